@@ -0,0 +1,105 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use nesoxyde::apu::Apu;
+use nesoxyde::bus::{MainBus, RamPattern, TestBus};
+use nesoxyde::cartridge::Cartridge;
+use nesoxyde::cpu::Cpu;
+use nesoxyde::ppu::frame::Region;
+use nesoxyde::resampler::{Resampler, ResamplerQuality};
+
+const SAMPLE_RATE: f64 = 44100.0;
+/// Cpu cycles in a NTSC frame
+const CYCLES_PER_FRAME: u64 = 29781;
+
+/// Cpu only, on a bus with nothing but memory
+fn cpu_loop(c: &mut Criterion) {
+    let code = [
+        0xE8, // INX
+        0xC8, // INY
+        0x69, 0x01, // ADC #$01
+        0x85, 0x10, // STA $10
+        0xB5, 0x10, // LDA $10,X
+        0x9D, 0x00, 0x02, // STA $0200,X
+        0xD0, 0xF3, // BNE $2000
+        0x4C, 0x00, 0x20, // JMP $2000
+    ];
+    // The TestBus maps the program from 0x2000 to the end of the address space
+    let mut program = vec![0; 0x10000 - 0x2000];
+    program[..code.len()].copy_from_slice(&code);
+    // Reset vector
+    program[0xFFFC - 0x2000] = 0x00;
+    program[0xFFFD - 0x2000] = 0x20;
+
+    let mut cpu = Cpu::new(TestBus::new(program));
+    cpu.power_on();
+    c.bench_function("cpu loop", |b| {
+        b.iter(|| black_box(cpu.run_cycles(CYCLES_PER_FRAME)))
+    });
+}
+
+/// Whole system running the nestest menu, one frame per iteration
+fn frame(c: &mut Criterion) {
+    let cartridge = Cartridge::from_bytes(include_bytes!("../roms/nestest.nes")).unwrap();
+    let bus = MainBus::new(
+        Rc::new(RefCell::new(cartridge)),
+        |frame| {
+            black_box(frame.pixels());
+        },
+        SAMPLE_RATE,
+        RamPattern::default(),
+    );
+    let mut cpu = Cpu::new(bus);
+    cpu.power_on();
+    c.bench_function("frame", |b| {
+        b.iter(|| {
+            cpu.run_until_frame();
+            // Don't let the audio samples pile up
+            black_box(cpu.samples());
+        })
+    });
+}
+
+/// Apu clocking, mixing and resampling of a frame of audio, all channels playing
+fn apu_mix(c: &mut Criterion) {
+    let mut apu = Apu::new(SAMPLE_RATE as f32, Region::Ntsc);
+    let mut resampler = Resampler::new(
+        CYCLES_PER_FRAME as f64 * 60.0,
+        SAMPLE_RATE,
+        ResamplerQuality::default(),
+    );
+    for (addr, data) in [
+        (0x4015, 0x0F),
+        (0x4000, 0xBF),
+        (0x4002, 0xFD),
+        (0x4003, 0x08),
+        (0x4004, 0x7F),
+        (0x4006, 0x7C),
+        (0x4007, 0x09),
+        (0x4008, 0xFF),
+        (0x400A, 0x40),
+        (0x400B, 0x08),
+        (0x400C, 0x3F),
+        (0x400E, 0x04),
+        (0x400F, 0x08),
+    ] {
+        apu.write(addr, data);
+    }
+
+    c.bench_function("apu mix", |b| {
+        b.iter(|| {
+            for _ in 0..CYCLES_PER_FRAME {
+                apu.clock();
+                let sample = apu.mix();
+                if let Some(sample) = resampler.push(sample) {
+                    black_box(apu.filter(sample));
+                }
+            }
+        })
+    });
+}
+
+criterion_group!(benches, cpu_loop, frame, apu_mix);
+criterion_main!(benches);
@@ -1,15 +1,15 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{Read, Write};
 
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 use dmc::Dmc;
 use noise::Noise;
 use square::Square;
 use triangle::Triangle;
 
-use crate::decay::Decay;
 use crate::filters::{Filter, HighPass, LowPass};
+use crate::ppu::frame::Region;
 use crate::savable::Savable;
 
 // http://wiki.nesdev.com/w/index.php/APU_Length_Counter
@@ -81,6 +81,7 @@ enum SequencerMode {
 pub struct Apu {
     cycles: u32,
     hz240_counter: u16,
+    frame_reset_delay: u8,
     irq_off: bool,
     pending_irq: Option<bool>,
 
@@ -92,45 +93,71 @@ pub struct Apu {
     sequencer: u8,
     mode: SequencerMode,
 
-    tri_decay: Decay,
     filters: Vec<Box<dyn Filter>>,
+
+    /// Register writes (cycle, address, data) captured for music ripping
+    write_log: Option<Vec<(u64, u16, u8)>>,
+    /// Cycles since the start of the capture
+    log_cycles: u64,
+}
+
+/// Channel state, for debug displays
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ChannelState {
+    /// Enable bit of 0x4015. For the DMC, if the sample is still playing
+    pub enabled: bool,
+    /// Length counter, or bytes left in the sample for the DMC
+    pub length_counter: u16,
+}
+
+/// Snapshot of the Apu channels, for debug displays
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ApuState {
+    pub square1: ChannelState,
+    pub square2: ChannelState,
+    pub triangle: ChannelState,
+    pub noise: ChannelState,
+    pub dmc: ChannelState,
 }
 
 impl Savable for Apu {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.cycles)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.hz240_counter)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.irq_off)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.pending_irq)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.sq1)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.sq2)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.tri)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.noise)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.dmc)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.sequencer)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.mode)?;
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.cycles)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.hz240_counter)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.frame_reset_delay)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_off)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.pending_irq)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.sq1)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.sq2)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.tri)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.noise)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.dmc)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.sequencer)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.mode)?;
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
-        self.cycles = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.hz240_counter = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.irq_off = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.pending_irq = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.sq1 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.sq2 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.tri = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.noise = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.dmc = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.sequencer = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.mode = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.filters.iter_mut().for_each(|f| f.reset());
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        self.cycles = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.hz240_counter = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.frame_reset_delay = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_off = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.pending_irq = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.sq1 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.sq2 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.tri = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.noise = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.dmc = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.sequencer = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.reset_filters();
         Ok(())
     }
 }
 
 impl Apu {
-    fn new_filters(sample_rate: f32) -> Vec<Box<dyn Filter>> {
+    /// Filter chain of the NES, running at `sample_rate`
+    pub fn default_filters(sample_rate: f32) -> Vec<Box<dyn Filter>> {
         vec![
             Box::new(HighPass::new(90.0, sample_rate, 2.0f32.sqrt())),
             // Box::new(HighPass::new(440.0, sample_rate, 2.0f32.sqrt())),
@@ -138,23 +165,27 @@ impl Apu {
         ]
     }
 
-    pub fn new(sample_rate: f32) -> Self {
+    /// `region`: Selects the timer periods of the channels
+    pub fn new(sample_rate: f32, region: Region) -> Self {
         Self {
             cycles: 0,
             hz240_counter: 0,
+            frame_reset_delay: 0,
             irq_off: false,
             pending_irq: None,
 
             sq1: Square::new(),
             sq2: Square::new(),
             tri: Triangle::new(),
-            noise: Noise::new(),
+            noise: Noise::new(region),
             dmc: Dmc::new(),
             sequencer: 0,
             mode: SequencerMode::FourStep,
 
-            tri_decay: Decay::new(0.1),
-            filters: Self::new_filters(sample_rate),
+            filters: Self::default_filters(sample_rate),
+
+            write_log: None,
+            log_cycles: 0,
         }
     }
 
@@ -163,8 +194,8 @@ impl Apu {
         match addr {
             SND_CHN => {
                 // Returns IF-D NT21
-                // I: DMC Interrupt requested and clears it if set
-                // F: Apu interrupt flag and clears it if set
+                // I: DMC interrupt flag. Reading doesn't clear it, only 0x4015 and 0x4010 writes do
+                // F: Frame interrupt flag and clears it if set
                 // D: 1 if DMC length counter > 0
                 // N: 1 if noise length counter > 0
                 // T: 1 if triangle length counter > 0
@@ -177,7 +208,7 @@ impl Apu {
                 let noise = (self.noise.length_counter() > 0) as u8;
                 let dmc = (self.dmc.length_counter() > 0) as u8;
                 let irq = self.pending_irq.take().is_some() as u8;
-                let dmc_irq = self.dmc.poll_irq() as u8;
+                let dmc_irq = self.dmc.irq_flag() as u8;
 
                 dmc_irq << 7 | irq << 6 | dmc << 4 | noise << 3 | tri << 2 | sq2 << 1 | sq1
             }
@@ -186,6 +217,10 @@ impl Apu {
     }
 
     pub fn write(&mut self, addr: u16, data: u8) {
+        if let Some(log) = &mut self.write_log {
+            log.push((self.log_cycles, addr, data));
+        }
+
         match addr {
             SQ1_VOL => self.sq1.write_vol(data),
             SQ1_SWEEP => self.sq1.write_sweep(data),
@@ -222,21 +257,32 @@ impl Apu {
             FRAME_COUNTER => {
                 // MI-- ---
                 // Sets the stepping based on M
-                self.mode = match data & 0x80 == 0 {
+                self.mode = match data & 0x80 != 0 {
                     true => SequencerMode::FiveStep,
                     false => SequencerMode::FourStep,
                 };
 
-                // Reset counter and sequencer
-                self.hz240_counter = 0;
-                self.sequencer = 0;
+                // The writes before this one happened on earlier cycles
+                self.clear_length_writes();
+
+                // Five step mode immediately clocks all the units
+                if self.mode == SequencerMode::FiveStep {
+                    self.tick_half_frame();
+                    self.tick_quarter_frame();
+                }
+
+                // The counter and sequencer are reset 3 or 4 cycles after the write,
+                // depending on if the write happens on an odd cycle or not
+                self.frame_reset_delay = match self.cycles % 2 {
+                    0 => 3,
+                    _ => 4,
+                };
 
                 // Sets the IRQ disable bit based on I
                 self.irq_off = data & 0x40 != 0;
 
-                // Clear the IRQ flag if set to disabled
+                // Clear the frame IRQ flag if set to disabled. The DMC flag stays
                 if self.irq_off {
-                    self.dmc.poll_irq();
                     self.pending_irq = None;
                 }
             }
@@ -244,10 +290,26 @@ impl Apu {
         }
     }
 
+    /// Starts capturing the register writes, to rip the music of a game. Restarts the capture
+    /// if it is already running
+    pub fn start_log(&mut self) {
+        self.write_log = Some(Vec::new());
+        self.log_cycles = 0;
+    }
+
+    /// Stops the capture. Returns the register writes as (cycle, address, data), the cycles
+    /// counted from the start of the capture
+    pub fn stop_log(&mut self) -> Vec<(u64, u16, u8)> {
+        self.write_log.take().unwrap_or_default()
+    }
+
     /// Clocks the Apu once
     pub fn clock(&mut self) {
         // Count the cycles
         self.cycles = self.cycles.wrapping_add(1);
+        if self.write_log.is_some() {
+            self.log_cycles += 1;
+        }
 
         // The triangle channel's timer is clocked at Cpu rate
         // The DMC rate counter is also clocked at Cpu rate
@@ -261,6 +323,15 @@ impl Apu {
             self.noise.tick_timer();
         }
 
+        // Reset counter and sequencer once the frame counter write delay is over
+        if self.frame_reset_delay > 0 {
+            self.frame_reset_delay -= 1;
+            if self.frame_reset_delay == 0 {
+                self.hz240_counter = 0;
+                self.sequencer = 0;
+            }
+        }
+
         // The frame counter runs at 240Hz.
         // We need to divide the cpu clock to get the right timing
         // (1,789,773Hz / 2) / 240Hz = ~14915
@@ -279,35 +350,58 @@ impl Apu {
                 self.pending_irq = Some(true);
             }
 
-            // Half tick happens on step 1 and 3
-            let half_tick = (self.hz240_counter & 0x5) == 1;
-            // Full tick happens on every step (Step 5 mode does nothing on last step)
-            let full_tick = self.sequencer < 4;
+            // The step is counted after the increment, the last step of a sequence is 0
+            // Four step: 1: quarter, 2: quarter + half, 3: quarter, 0: quarter + half (+ IRQ)
+            // Five step: 1: quarter, 2: quarter + half, 3: quarter, 4: nothing, 0: quarter + half
+            let (full_tick, half_tick) = match (&self.mode, self.sequencer) {
+                (_, 0) | (_, 2) => (true, true),
+                (SequencerMode::FiveStep, 4) => (false, false),
+                _ => (true, false),
+            };
 
-            // Sweep tick and length tick
             if half_tick {
-                self.sq1.tick_length();
-                self.sq2.tick_length();
-                self.sq1.tick_sweep(square::Channel::One);
-                self.sq2.tick_sweep(square::Channel::Two);
-                self.tri.tick_length();
-                self.noise.tick_length();
+                self.tick_half_frame();
             }
 
-            // Envelope and linear (triangle only) tick
             if full_tick {
-                self.sq1.tick_envelope();
-                self.sq2.tick_envelope();
-                self.noise.tick_envelope();
-                self.tri.tick_counter();
+                self.tick_quarter_frame();
             }
         }
+
+        self.clear_length_writes();
     }
 
-    /// Polls the IRQ flag
-    pub fn poll_irq(&mut self) -> bool {
-        // IRQ can be requested by the Apu or the DMC
-        self.pending_irq.take().is_some() | self.dmc.poll_irq()
+    /// Ends the cycle of the length counter writes
+    fn clear_length_writes(&mut self) {
+        self.sq1.clear_length_writes();
+        self.sq2.clear_length_writes();
+        self.tri.clear_length_writes();
+        self.noise.clear_length_writes();
+    }
+
+    /// Sweep tick and length tick
+    fn tick_half_frame(&mut self) {
+        self.sq1.tick_length();
+        self.sq2.tick_length();
+        self.sq1.tick_sweep(square::Channel::One);
+        self.sq2.tick_sweep(square::Channel::Two);
+        self.tri.tick_length();
+        self.noise.tick_length();
+    }
+
+    /// Envelope and linear (triangle only) tick
+    fn tick_quarter_frame(&mut self) {
+        self.sq1.tick_envelope();
+        self.sq2.tick_envelope();
+        self.noise.tick_envelope();
+        self.tri.tick_counter();
+    }
+
+    /// Returns if the IRQ line is held by the frame counter or the DMC
+    ///
+    /// The line stays active until the game acknowledges the flags, polling doesn't clear them
+    pub fn poll_irq(&self) -> bool {
+        self.pending_irq.is_some() || self.dmc.irq_flag()
     }
 
     /// Returns if the DMC needs a new audio sample or not
@@ -325,10 +419,32 @@ impl Apu {
         self.dmc.address()
     }
 
-    /// Resets the Apu and its channels
+    /// Soft resets the Apu (reset button)
+    ///
+    /// All channels are silenced but their registers keep their values
     pub fn reset(&mut self) {
+        self.write(SND_CHN, 0);
+        self.dmc.soft_reset();
+
+        // The frame counter restarts as if the last value was written again
+        let mut frame_counter = 0;
+        if self.mode == SequencerMode::FiveStep {
+            frame_counter |= 0x80;
+        }
+        if self.irq_off {
+            frame_counter |= 0x40;
+        }
+        self.write(FRAME_COUNTER, frame_counter);
+
+        // The old samples would ring in the filters after the reset
+        self.reset_filters();
+    }
+
+    /// Resets the Apu and its channels (power on)
+    pub fn power_on(&mut self) {
         self.cycles = 0;
         self.hz240_counter = 0;
+        self.frame_reset_delay = 0;
         self.sequencer = 0;
         self.pending_irq = None;
         self.mode = SequencerMode::FourStep;
@@ -337,10 +453,57 @@ impl Apu {
         self.tri.reset();
         self.noise.reset();
         self.dmc.reset();
+        self.reset_filters();
+    }
+
+    /// Changes the TV standard. Only the noise periods depend on it
+    pub fn set_region(&mut self, region: Region) {
+        self.noise.set_region(region);
+    }
+
+    /// Replaces the filter chain, applied in order. `default_filters` is the chain of the NES
+    pub fn set_filters(&mut self, filters: Vec<Box<dyn Filter>>) {
+        self.filters = filters;
+    }
+
+    /// Clears the history of the filters
+    fn reset_filters(&mut self) {
+        self.filters.iter_mut().for_each(|f| f.reset());
     }
 
-    /// Gets an audio sample
-    pub fn output(&mut self) -> f32 {
+    /// Returns which channels are enabled and their length counters
+    pub fn state(&self) -> ApuState {
+        let channel = |enabled, length_counter| ChannelState {
+            enabled,
+            length_counter,
+        };
+        ApuState {
+            square1: channel(self.sq1.enabled(), self.sq1.length_counter() as u16),
+            square2: channel(self.sq2.enabled(), self.sq2.length_counter() as u16),
+            triangle: channel(self.tri.enabled(), self.tri.length_counter() as u16),
+            noise: channel(self.noise.enabled(), self.noise.length_counter() as u16),
+            dmc: channel(self.dmc.length_counter() > 0, self.dmc.length_counter()),
+        }
+    }
+
+    /// State of the frame sequencer and the channels, for debug dumps
+    pub fn snapshot(&self) -> Value {
+        json!({
+            "cycles": self.cycles,
+            "sequencer": self.sequencer,
+            "mode": self.mode,
+            "irq_off": self.irq_off,
+            "pending_irq": self.pending_irq,
+            "square1": self.sq1,
+            "square2": self.sq2,
+            "triangle": self.tri,
+            "noise": self.noise,
+            "dmc": self.dmc,
+        })
+    }
+
+    /// Mixes the channels, without the filters. Called every clock
+    pub fn mix(&mut self) -> f32 {
         // Mix the audio according to NesDev
         // http://wiki.nesdev.com/w/index.php/APU_Mixer
 
@@ -348,16 +511,17 @@ impl Apu {
         let sq2 = self.sq2.output();
         let pulse = 95.88 / (100.0 + (8128.0 / (sq1 as f32 + sq2 as f32)));
 
-        // I apply a "decay" on the triangle channel to reduce audio pops
-        // Is only applied if the volume goes from a high value to zero
-        let tri = self.tri_decay.decay(self.tri.output() as f32);
+        let tri = self.tri.output() as f32;
         let noise = self.noise.output() as f32;
         let dmc = self.dmc.output() as f32;
         let tnd = 159.79
             / (100.0 + (1.0 / ((tri as f32 / 8227.0) + (noise / 12241.0) + (dmc / 22638.0))));
 
-        let sample = pulse + tnd;
+        pulse + tnd
+    }
 
+    /// Applies the filters to a sample at the audio sample rate
+    pub fn filter(&mut self, sample: f32) -> f32 {
         // Apply filters
         // The NES has 3 filters applied
         // High-pass at 90Hz
@@ -368,3 +532,245 @@ impl Apu {
             .fold(sample, |sample, filter| filter.filter(sample))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_five_step_immediate_clock() {
+        let mut apu = Apu::new(44100.0, Region::Ntsc);
+        apu.write(SND_CHN, 0x01);
+        // Length counter of 2, not halted
+        apu.write(SQ1_VOL, 0x00);
+        apu.write(SQ1_HI, 0x18);
+
+        // Four step mode doesn't clock on write
+        apu.write(FRAME_COUNTER, 0x00);
+        assert_eq!(apu.read(SND_CHN) & 0x1, 0x1);
+
+        // Each five step mode write clocks the length counter once
+        apu.write(FRAME_COUNTER, 0x80);
+        assert_eq!(apu.read(SND_CHN) & 0x1, 0x1);
+        apu.write(FRAME_COUNTER, 0x80);
+        assert_eq!(apu.read(SND_CHN) & 0x1, 0x0);
+    }
+
+    #[test]
+    fn test_sequencer_half_frames() {
+        // Two half frames per sequence in both modes
+        for &(mode, steps, length) in [(0x00, 4, 18), (0x80, 5, 17)].iter() {
+            let mut apu = Apu::new(44100.0, Region::Ntsc);
+            apu.write(SND_CHN, 0x01);
+            apu.write(SQ1_VOL, 0x00);
+            apu.write(SQ1_HI, 0x10);
+            apu.write(FRAME_COUNTER, mode);
+
+            for _ in 0..(steps * 7458 + 4) {
+                apu.clock();
+            }
+            assert_eq!(apu.sequencer, 0);
+            assert_eq!(apu.sq1.length_counter(), length);
+        }
+    }
+
+    #[test]
+    fn test_frame_counter_reset_delay() {
+        let mut apu = Apu::new(44100.0, Region::Ntsc);
+        for _ in 0..100 {
+            apu.clock();
+        }
+
+        apu.write(FRAME_COUNTER, 0x40);
+        for _ in 0..3 {
+            assert!(apu.hz240_counter > 2);
+            apu.clock();
+        }
+        // Reset on the third cycle, then clocked once
+        assert_eq!(apu.hz240_counter, 2);
+        assert_eq!(apu.sequencer, 0);
+    }
+
+    #[test]
+    fn test_state() {
+        let mut apu = Apu::new(44100.0, Region::Ntsc);
+        apu.write(DMC_LEN, 0x01);
+        apu.write(SND_CHN, 0x15);
+        apu.write(SQ1_HI, 0x18);
+
+        let state = apu.state();
+        assert_eq!(state.square1.length_counter, 2);
+        assert!(state.square1.enabled && state.triangle.enabled);
+        assert!(!state.square2.enabled && !state.noise.enabled);
+        assert_eq!(state.dmc.length_counter, 17);
+        assert!(state.dmc.enabled);
+    }
+
+    #[test]
+    fn test_length_writes() {
+        let mut apu = Apu::new(44100.0, Region::Ntsc);
+        // Not loaded while the channel is disabled
+        apu.write(SQ1_HI, 0x18);
+        apu.clock();
+        assert_eq!(apu.sq1.length_counter(), 0);
+
+        apu.write(SND_CHN, 0x01);
+        apu.write(SQ1_VOL, 0x00);
+        apu.write(SQ1_HI, 0x18);
+        apu.clock();
+        assert_eq!(apu.sq1.length_counter(), 2);
+
+        // Halting on the cycle of a half frame, the counter is still clocked
+        apu.hz240_counter = 14913;
+        apu.sequencer = 1;
+        apu.write(SQ1_VOL, 0x20);
+        apu.clock();
+        assert_eq!(apu.sequencer, 2);
+        assert_eq!(apu.sq1.length_counter(), 1);
+
+        // Reloading on the cycle of a half frame is ignored
+        apu.write(SQ1_VOL, 0x00);
+        apu.clock();
+        apu.hz240_counter = 14913;
+        apu.sequencer = 1;
+        apu.write(SQ1_HI, 0x08);
+        apu.clock();
+        assert_eq!(apu.sq1.length_counter(), 0);
+
+        // Unless the counter was 0
+        apu.hz240_counter = 14913;
+        apu.sequencer = 1;
+        apu.write(SQ1_HI, 0x08);
+        apu.clock();
+        assert_eq!(apu.sq1.length_counter(), 254);
+    }
+
+    #[test]
+    fn test_status_write_dmc() {
+        let mut apu = Apu::new(44100.0, Region::Ntsc);
+        apu.write(DMC_FREQ, 0x8F);
+        apu.write(DMC_LEN, 0x00);
+        apu.write(SND_CHN, 0x10);
+        assert!(apu.need_dmc_sample());
+
+        // The last byte raises the IRQ
+        apu.set_dmc_sample(0x00);
+        assert!(apu.poll_irq());
+
+        // Enabling restarts the sample once it is over
+        apu.write(SND_CHN, 0x10);
+        assert_eq!(apu.dmc.length_counter(), 1);
+        apu.set_dmc_sample(0x00);
+
+        // Writing 0x4015 acknowledges the IRQ
+        apu.write(SND_CHN, 0x10);
+        assert!(!apu.poll_irq());
+        assert_eq!(apu.dmc.length_counter(), 1);
+    }
+
+    #[test]
+    fn test_status_irq_flags() {
+        let mut apu = Apu::new(44100.0, Region::Ntsc);
+        apu.write(DMC_FREQ, 0x8F);
+        apu.write(DMC_LEN, 0x00);
+        apu.write(SND_CHN, 0x10);
+        apu.set_dmc_sample(0x00);
+
+        // Reading the status doesn't acknowledge the DMC IRQ, neither does polling
+        assert_eq!(apu.read(SND_CHN) & 0xC0, 0x80);
+        assert_eq!(apu.read(SND_CHN) & 0xC0, 0x80);
+        assert!(apu.poll_irq() && apu.poll_irq());
+
+        // Frame IRQ at the end of the four step sequence
+        apu.write(FRAME_COUNTER, 0x00);
+        let mut cycles = 0;
+        while apu.pending_irq.is_none() && cycles < 40000 {
+            apu.clock();
+            cycles += 1;
+        }
+        assert_eq!(apu.read(SND_CHN) & 0xC0, 0xC0);
+        // The read clears the frame flag only
+        assert_eq!(apu.read(SND_CHN) & 0xC0, 0x80);
+
+        // Disabling the frame IRQ doesn't acknowledge the DMC IRQ either
+        apu.write(FRAME_COUNTER, 0x40);
+        assert!(apu.poll_irq());
+        apu.write(SND_CHN, 0x00);
+        assert!(!apu.poll_irq());
+        assert_eq!(apu.read(SND_CHN) & 0xC0, 0x00);
+    }
+
+    #[test]
+    fn test_write_log() {
+        let mut apu = Apu::new(44100.0, Region::Ntsc);
+        apu.write(SQ1_VOL, 0x3F);
+        assert!(apu.stop_log().is_empty());
+
+        apu.clock();
+        apu.start_log();
+        apu.write(SND_CHN, 0x01);
+        apu.clock();
+        apu.clock();
+        apu.write(SQ1_HI, 0x18);
+        apu.clock();
+        apu.write(FRAME_COUNTER, 0x40);
+        assert_eq!(
+            apu.stop_log(),
+            vec![
+                (0, SND_CHN, 0x01),
+                (2, SQ1_HI, 0x18),
+                (3, FRAME_COUNTER, 0x40)
+            ]
+        );
+
+        // Nothing is captured once stopped
+        apu.write(SQ1_LO, 0x10);
+        assert!(apu.stop_log().is_empty());
+    }
+
+    #[test]
+    fn test_reset_filters() {
+        let mut apu = Apu::new(44100.0, Region::Ntsc);
+        for i in 0..100 {
+            apu.filter(if i % 2 == 0 { 0.5 } else { -0.5 });
+        }
+        assert_ne!(apu.filter(0.0), 0.0);
+
+        // Silence stays silent after a reset
+        for _ in 0..100 {
+            apu.filter(0.5);
+        }
+        apu.reset();
+        assert_eq!(apu.filter(0.0), 0.0);
+
+        apu.filter(0.5);
+        apu.power_on();
+        assert_eq!(apu.filter(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_set_filters() {
+        struct Gain(f32);
+
+        impl Filter for Gain {
+            fn filter(&mut self, input: f32) -> f32 {
+                input * self.0
+            }
+
+            fn reset(&mut self) {}
+        }
+
+        let mut apu = Apu::new(44100.0, Region::Ntsc);
+        apu.set_filters(vec![]);
+        assert_eq!(apu.filter(0.5), 0.5);
+
+        apu.set_filters(vec![Box::new(Gain(0.5)), Box::new(Gain(3.0))]);
+        assert_eq!(apu.filter(0.5), 0.75);
+
+        // Custom filters can extend the default chain
+        let mut filters = Apu::default_filters(44100.0);
+        filters.push(Box::new(Gain(0.0)));
+        apu.set_filters(filters);
+        assert_eq!(apu.filter(0.5), 0.0);
+    }
+}
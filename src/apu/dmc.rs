@@ -5,23 +5,30 @@ const RATE_TABLE: [u16; 16] = [
 ];
 
 /// Delta modulation channel
+///
+/// A memory reader fills the sample buffer while bytes are left, and the output unit plays the
+/// buffer one bit at a time. Both run independently, so the last byte still plays after the
+/// sample is over and a restart during it doesn't cut the playback
 #[derive(Serialize, Deserialize)]
 pub struct Dmc {
-    enabled: bool,
-
     irq: bool,
     pending_irq: Option<bool>,
     loop_flag: bool,
     rate: u16,
     rate_counter: u16,
 
-    pending_read: Option<bool>,
     address: u8,
     curr_address: u16,
-    buffer: u8,
-    phase: u8,
+    /// Byte waiting to be played. Empty until the memory reader fetches it
+    buffer: Option<u8>,
+    /// Byte being played, bit 0 first
+    shift: u8,
+    bits_remaining: u8,
+    /// Nothing to play, the output level stays the same
+    silence: bool,
 
     output_level: u8,
+    /// Bytes left to fetch
     length_counter: u16,
     pcm_length: u16,
 }
@@ -29,19 +36,18 @@ pub struct Dmc {
 impl Dmc {
     pub fn new() -> Self {
         Self {
-            enabled: false,
-
             irq: false,
             pending_irq: None,
             loop_flag: false,
-            rate: 0,
+            rate: RATE_TABLE[0],
             rate_counter: 0,
 
-            pending_read: None,
             address: 0,
             curr_address: 0xC000,
-            buffer: 0,
-            phase: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
 
             output_level: 0,
             length_counter: 0,
@@ -51,30 +57,22 @@ impl Dmc {
 
     /// Resets the channel state
     pub fn reset(&mut self) {
-        self.enabled = false;
-
-        self.irq = false;
-        self.pending_irq = None;
-        self.loop_flag = false;
-        self.rate = 0;
-        self.rate_counter = 0;
-
-        self.pending_read = None;
-        self.address = 0;
-        self.curr_address = 0xC000;
-        self.buffer = 0;
-        self.phase = 0;
+        *self = Self::new();
+    }
 
-        self.output_level = 0;
-        self.length_counter = 0;
-        self.pcm_length = 0;
+    /// Console reset. Only the low bit of the output level is kept
+    pub fn soft_reset(&mut self) {
+        self.output_level &= 1;
     }
 
     /// Enables or disables the channel
     pub fn set_enabled(&mut self, v: bool) {
-        self.enabled = v;
+        // Writing to 0x4015 acknowledges the IRQ
+        self.pending_irq = None;
         match v {
-            true if self.length_counter == 0 => self.length_counter = self.pcm_length * 16 + 1,
+            // Restarts the sample only if it is over. The byte being played keeps playing
+            true if self.length_counter == 0 => self.restart(),
+            // Stops fetching, but the buffer and the byte being played still play
             false => self.length_counter = 0,
             _ => {}
         }
@@ -89,6 +87,10 @@ impl Dmc {
         self.rate = RATE_TABLE[(data & 0xF) as usize];
         self.loop_flag = data & 0x40 != 0;
         self.irq = data & 0x80 != 0;
+        // Disabling the IRQ clears the flag
+        if !self.irq {
+            self.pending_irq = None;
+        }
     }
 
     /// Sets register 0x4011
@@ -102,17 +104,22 @@ impl Dmc {
     pub fn write_start(&mut self, data: u8) {
         // AAAA AAAA
         // A: Sample start address
+        // Used on the next restart
         self.address = data;
-        // The address is always calculated like below
-        self.curr_address = 0xC000 + (data as u16 * 64);
     }
 
     /// Sets register 0x4013
     pub fn write_len(&mut self, data: u8) {
         // LLLL LLLL
         // L: Sample length (how many samples to play)
+        // Used on the next restart
         self.pcm_length = data as u16;
-        // The number of samples to play is calculated like below
+    }
+
+    /// Starts the sample over, from the start address
+    fn restart(&mut self) {
+        // The address and length are always calculated like below
+        self.curr_address = 0xC000 + (self.address as u16 * 64);
         self.length_counter = self.pcm_length * 16 + 1;
     }
 
@@ -122,7 +129,8 @@ impl Dmc {
             // If the counter is 0, clock the timer and reset counter
             true => {
                 self.tick_timer();
-                self.rate_counter = self.rate;
+                // The rate is the period in Cpu cycles
+                self.rate_counter = self.rate - 1;
             }
             // Otherwise decrement
             false => self.rate_counter -= 1,
@@ -131,49 +139,28 @@ impl Dmc {
 
     /// Clocks the DMC timer
     fn tick_timer(&mut self) {
-        // If the phase == 0, the PCM or DPCM sample has been played
-        if self.phase == 0 {
-            // If the length counter == 0 (all the samples have been played)
-            // and the loop flag is set, we load the start address and
-            // reset the length counter
-            if self.length_counter == 0 && self.loop_flag {
-                self.length_counter = self.pcm_length * 16 + 1;
-                self.curr_address = 0xC000 + (self.address as u16 * 64);
-            }
-            match self.length_counter > 0 {
-                // If if is greater than 0, load the next sample,
-                // reset the phase to 8 (sample are only 7 bits)
-                // and decrement the counter
-                true => {
-                    self.pending_read = Some(true);
-                    self.phase = 8;
-                    self.length_counter -= 1;
-                }
-                // Otherwise, set the IRQ flag is enabled
-                false => match self.irq {
-                    true => self.pending_irq = Some(true),
-                    // Disable the channel if IRQ is disabled
-                    false => self.enabled = false,
-                },
+        if !self.silence {
+            // The output level is incremented or decremented by 2 based on the bit,
+            // only if it stays between 0 and 127
+            match self.shift & 1 != 0 {
+                true if self.output_level <= 0x7D => self.output_level += 2,
+                false if self.output_level >= 2 => self.output_level -= 2,
+                _ => {}
             }
         }
-        // Here, the current sample is not done playing
-        if self.phase != 0 {
-            // Decrement the phase
-            self.phase -= 1;
-            // Check the bit of the current phase
-            let delta = (self.buffer & (0x80 >> self.phase)) != 0;
-            // The new output volume is simply incremented or decremented
-            // by 2 based on the phase bit
-            let v = match delta {
-                // If the bit is set, add 2
-                true => self.output_level.wrapping_add(2),
-                // Otherwise, subtract 2
-                false => self.output_level.wrapping_sub(2),
-            };
-            // The output is only changed if it stays between 0 and 127
-            if (0..=0x7F).contains(&v) && self.enabled {
-                self.output_level = v;
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+
+        // The byte is done playing, start the next one
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(sample) => {
+                    self.shift = sample;
+                    self.silence = false;
+                }
+                // Nothing was fetched. The output level doesn't move
+                None => self.silence = true,
             }
         }
     }
@@ -185,20 +172,31 @@ impl Dmc {
 
     /// Sets the audio sample of the channel
     pub fn set_sample(&mut self, sample: u8) {
-        self.buffer = sample;
+        self.buffer = Some(sample);
         // Increments the address after updating the sample
         // Note that bit 15 is always set
         self.curr_address = self.curr_address.wrapping_add(1) | 0x8000;
+
+        self.length_counter = self.length_counter.saturating_sub(1);
+        if self.length_counter == 0 {
+            match (self.loop_flag, self.irq) {
+                (true, _) => self.restart(),
+                (false, true) => self.pending_irq = Some(true),
+                _ => {}
+            }
+        }
     }
 
     /// Returns if the channel needs a sample or not
+    ///
+    /// The memory reader fetches a byte as soon as the buffer is empty
     pub fn need_sample(&mut self) -> bool {
-        self.pending_read.take().is_some()
+        self.buffer.is_none() && self.length_counter > 0
     }
 
-    /// Polls the IRQ flag
-    pub fn poll_irq(&mut self) -> bool {
-        self.pending_irq.take().is_some()
+    /// IRQ flag. Stays set until a 0x4015 write or until the IRQ is disabled in 0x4010
+    pub fn irq_flag(&self) -> bool {
+        self.pending_irq.is_some()
     }
 
     /// Returns the length counter value
@@ -211,3 +209,112 @@ impl Dmc {
         self.output_level
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the memory reader like the bus, feeding `sample`
+    fn fetch(dmc: &mut Dmc, sample: u8) {
+        if dmc.need_sample() {
+            dmc.set_sample(sample);
+        }
+    }
+
+    /// Clocks until the output unit is done with a byte
+    fn play_byte(dmc: &mut Dmc) {
+        for _ in 0..8 {
+            dmc.tick_timer();
+        }
+    }
+
+    #[test]
+    fn test_last_byte_plays() {
+        let mut dmc = Dmc::new();
+        dmc.write_raw(0x40);
+        dmc.write_len(0);
+        dmc.set_enabled(true);
+        // 1 byte long sample
+        assert_eq!(dmc.length_counter(), 1);
+        fetch(&mut dmc, 0xFF);
+        assert_eq!(dmc.length_counter(), 0);
+        assert!(!dmc.need_sample());
+
+        // The first byte moves to the shifter after the silent one, then plays
+        play_byte(&mut dmc);
+        play_byte(&mut dmc);
+        assert_eq!(dmc.output(), 0x40 + 16);
+
+        // Then silence, the output level stays
+        play_byte(&mut dmc);
+        assert_eq!(dmc.output(), 0x40 + 16);
+    }
+
+    #[test]
+    fn test_restart_during_last_byte() {
+        let mut dmc = Dmc::new();
+        dmc.write_start(0x10);
+        dmc.write_len(0);
+        dmc.set_enabled(true);
+        fetch(&mut dmc, 0xFF);
+        play_byte(&mut dmc);
+        // The last byte is playing, the sample is over
+        dmc.tick_timer();
+        assert_eq!(dmc.length_counter(), 0);
+        assert!(!dmc.silence);
+
+        // Restarting fetches right away from the start address, without cutting the playback
+        dmc.set_enabled(true);
+        assert_eq!(dmc.address(), 0xC400);
+        assert!(dmc.need_sample());
+        fetch(&mut dmc, 0x00);
+        let level = dmc.output();
+        for _ in 0..7 {
+            dmc.tick_timer();
+        }
+        assert_eq!(dmc.output(), level + 14);
+        assert!(!dmc.silence);
+
+        // Enabling while bytes are left doesn't restart
+        dmc.write_len(1);
+        dmc.set_enabled(true);
+        assert_eq!(dmc.length_counter(), 17);
+        dmc.set_enabled(true);
+        assert_eq!(dmc.length_counter(), 17);
+    }
+
+    #[test]
+    fn test_disable_keeps_buffer() {
+        let mut dmc = Dmc::new();
+        dmc.write_len(1);
+        dmc.set_enabled(true);
+        fetch(&mut dmc, 0xFF);
+
+        dmc.set_enabled(false);
+        assert_eq!(dmc.length_counter(), 0);
+        assert!(!dmc.need_sample());
+        // The fetched byte still plays
+        play_byte(&mut dmc);
+        play_byte(&mut dmc);
+        assert_eq!(dmc.output(), 16);
+    }
+
+    #[test]
+    fn test_irq_and_loop() {
+        let mut dmc = Dmc::new();
+        dmc.write_freq(0x80);
+        dmc.set_enabled(true);
+        fetch(&mut dmc, 0);
+        assert!(dmc.irq_flag());
+
+        // Looping restarts instead
+        dmc.write_freq(0xC0);
+        dmc.write_start(0x01);
+        dmc.set_enabled(true);
+        dmc.buffer = None;
+        fetch(&mut dmc, 0);
+        assert!(!dmc.irq_flag());
+        assert_eq!(dmc.length_counter(), 1);
+        assert_eq!(dmc.address(), 0xC040);
+    }
+}
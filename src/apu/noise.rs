@@ -4,15 +4,25 @@
 use serde::{Deserialize, Serialize};
 
 use super::LENGTH_TABLE;
+use crate::ppu::frame::Region;
 
-/// Table of the different timer periods
+/// Table of the different timer periods (NTSC)
 const TIMER_TABLE: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
 ];
 
+/// Table of the different timer periods (PAL)
+const PAL_TIMER_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// The shift register is loaded with 1 at power on
+const SHIFT_SEED: u16 = 1;
+
 /// Audio noise channel
 #[derive(Serialize, Deserialize)]
 pub struct Noise {
+    region: Region,
     enabled: bool,
     mode: bool,
 
@@ -21,6 +31,10 @@ pub struct Noise {
 
     length_halt: bool,
     length_counter: u8,
+    /// Halt flag before a write on this cycle
+    halt_before_write: Option<bool>,
+    /// Length counter before a reload on this cycle
+    length_before_write: Option<u8>,
 
     constant_volume: bool,
     volume: u8,
@@ -32,8 +46,9 @@ pub struct Noise {
 }
 
 impl Noise {
-    pub fn new() -> Self {
+    pub fn new(region: Region) -> Self {
         Self {
+            region,
             enabled: false,
             mode: false,
 
@@ -42,6 +57,8 @@ impl Noise {
 
             length_halt: false,
             length_counter: 0,
+            halt_before_write: None,
+            length_before_write: None,
 
             constant_volume: false,
             volume: 0,
@@ -49,7 +66,7 @@ impl Noise {
             envelope_timer: 0,
             envelope_volume: 0,
 
-            shift: 1,
+            shift: SHIFT_SEED,
         }
     }
 
@@ -63,13 +80,20 @@ impl Noise {
 
         self.length_halt = false;
         self.length_counter = 0;
+        self.halt_before_write = None;
+        self.length_before_write = None;
 
         self.constant_volume = false;
         self.volume = 0;
 
         self.envelope_volume = 0;
 
-        self.shift = 1;
+        self.shift = SHIFT_SEED;
+    }
+
+    /// Changes the table of the timer periods, used by the next write to 0x400E
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
     }
 
     /// Enables or disables the channel
@@ -87,6 +111,7 @@ impl Noise {
         // L: Envelope loop / length counter halt
         // C: Output constant volume
         // V: Volume value / envelope period
+        self.halt_before_write.get_or_insert(self.length_halt);
         self.length_halt = data & 0x20 != 0;
         self.constant_volume = data & 0x10 != 0;
         self.volume = data & 0xF;
@@ -98,14 +123,22 @@ impl Noise {
         // M: Mode flag
         // P: Timer period table index
         self.mode = data & 0x80 != 0;
-        self.timer_period = TIMER_TABLE[(data & 0xF) as usize];
+        let table = match self.region {
+            Region::Ntsc => &TIMER_TABLE,
+            Region::Pal => &PAL_TIMER_TABLE,
+        };
+        self.timer_period = table[(data & 0xF) as usize];
     }
 
     /// Sets register 0x400F
     pub fn write_hi(&mut self, data: u8) {
         // LLLL L---
         // L: Length counter table index
-        self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        // The length counter is only loaded while the channel is enabled
+        if self.enabled {
+            self.length_before_write.get_or_insert(self.length_counter);
+            self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
         // Also restarts the envelope generator
         self.envelope_volume = 15;
         self.envelope_timer = self.volume + 1;
@@ -137,11 +170,24 @@ impl Noise {
 
         // If the length halt flag is not set and the counter is greater than
         // 0, decrement.
-        if !self.length_halt && self.length_counter > 0 {
-            self.length_counter -= 1;
+        // A halt flag written on this cycle only takes effect after the clock.
+        // A reload on this cycle is ignored if the counter is clocked while not 0
+        let halt = self.halt_before_write.take().unwrap_or(self.length_halt);
+        match self.length_before_write.take() {
+            _ if halt => {}
+            Some(0) => {}
+            Some(before) => self.length_counter = before - 1,
+            None if self.length_counter > 0 => self.length_counter -= 1,
+            None => {}
         }
     }
 
+    /// Forgets the writes of the last cycle, they no longer race with the length clock
+    pub fn clear_length_writes(&mut self) {
+        self.halt_before_write = None;
+        self.length_before_write = None;
+    }
+
     /// Clocks the envelope
     pub fn tick_envelope(&mut self) {
         match self.envelope_timer > 0 {
@@ -178,8 +224,45 @@ impl Noise {
         }
     }
 
+    /// Returns if the channel is enabled in 0x4015
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
     /// Returns the length counter value
     pub fn length_counter(&self) -> u8 {
         self.length_counter
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_period() {
+        let mut ntsc = Noise::new(Region::Ntsc);
+        let mut pal = Noise::new(Region::Pal);
+        ntsc.write_lo(0x0F);
+        pal.write_lo(0x0F);
+        assert_eq!(ntsc.timer_period, 4068);
+        assert_eq!(pal.timer_period, 3778);
+
+        ntsc.set_region(Region::Pal);
+        ntsc.write_lo(0x0F);
+        assert_eq!(ntsc.timer_period, 3778);
+    }
+
+    #[test]
+    fn test_shift_seed() {
+        let mut noise = Noise::new(Region::Ntsc);
+        noise.tick_timer();
+        assert_eq!(noise.shift, 0x4000);
+
+        // Same sequence after a reset
+        noise.reset();
+        assert_eq!(noise.shift, SHIFT_SEED);
+        noise.tick_timer();
+        assert_eq!(noise.shift, 0x4000);
+    }
+}
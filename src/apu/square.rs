@@ -30,6 +30,10 @@ pub struct Square {
 
     length_halt: bool,
     length_counter: u8,
+    /// Halt flag before a write on this cycle
+    halt_before_write: Option<bool>,
+    /// Length counter before a reload on this cycle
+    length_before_write: Option<u8>,
 
     constant_volume: bool,
     volume: u8,
@@ -62,6 +66,8 @@ impl Square {
 
             length_halt: false,
             length_counter: 0,
+            halt_before_write: None,
+            length_before_write: None,
 
             constant_volume: false,
             volume: 0,
@@ -90,6 +96,8 @@ impl Square {
 
         self.length_halt = false;
         self.length_counter = 0;
+        self.halt_before_write = None;
+        self.length_before_write = None;
 
         self.constant_volume = false;
         self.volume = 0;
@@ -123,6 +131,7 @@ impl Square {
         // C: Output constant volume
         // V: Volume value / envelope period
         self.duty = data >> 6;
+        self.halt_before_write.get_or_insert(self.length_halt);
         self.length_halt = data & 0x20 != 0;
         self.envelope_loop = self.length_halt;
         self.constant_volume = data & 0x10 != 0;
@@ -159,7 +168,11 @@ impl Square {
         // L: Length counter table index
         // T: Timer period high
         self.timer_period = ((data & 0x7) as u16) << 8 | (self.timer_period & 0xFF);
-        self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        // The length counter is only loaded while the channel is enabled
+        if self.enabled {
+            self.length_before_write.get_or_insert(self.length_counter);
+            self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
         // A write to this register resets the duty phase and the envelope volume + timer
         self.duty_phase = 0;
         self.envelope_volume = 15;
@@ -194,11 +207,24 @@ impl Square {
 
         // If the length halt flag is not set and the counter is greater than
         // 0, decrement.
-        if !self.length_halt && self.length_counter > 0 {
-            self.length_counter -= 1;
+        // A halt flag written on this cycle only takes effect after the clock.
+        // A reload on this cycle is ignored if the counter is clocked while not 0
+        let halt = self.halt_before_write.take().unwrap_or(self.length_halt);
+        match self.length_before_write.take() {
+            _ if halt => {}
+            Some(0) => {}
+            Some(before) => self.length_counter = before - 1,
+            None if self.length_counter > 0 => self.length_counter -= 1,
+            None => {}
         }
     }
 
+    /// Forgets the writes of the last cycle, they no longer race with the length clock
+    pub fn clear_length_writes(&mut self) {
+        self.halt_before_write = None;
+        self.length_before_write = None;
+    }
+
     /// Clocks the envelope
     pub fn tick_envelope(&mut self) {
         // The envelope generator controls the volume of the channel.
@@ -310,6 +336,11 @@ impl Square {
         }
     }
 
+    /// Returns if the channel is enabled in 0x4015
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
     /// Returns the length counter value
     pub fn length_counter(&self) -> u8 {
         self.length_counter
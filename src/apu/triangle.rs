@@ -11,9 +11,13 @@ const OUTPUT_TABLE: [u8; 32] = [
     13, 14, 15,
 ];
 
+/// Output of the ultrasonic periods, the middle of the wave
+const ULTRASONIC_OUTPUT: u8 = 7;
+
 /// Audio triangle channel
 #[derive(Serialize, Deserialize)]
 pub struct Triangle {
+    /// Set by 0x4015. Only gates the length counter loads, the output never depends on it
     enabled: bool,
     phase: u8,
 
@@ -22,6 +26,10 @@ pub struct Triangle {
 
     counter_halt: bool,
     length_counter: u8,
+    /// Halt flag before a write on this cycle
+    halt_before_write: Option<bool>,
+    /// Length counter before a reload on this cycle
+    length_before_write: Option<u8>,
 
     counter_reload: bool,
     counter_period: u8,
@@ -39,6 +47,8 @@ impl Triangle {
 
             counter_halt: false,
             length_counter: 0,
+            halt_before_write: None,
+            length_before_write: None,
 
             counter_reload: false,
             counter_period: 0,
@@ -56,6 +66,8 @@ impl Triangle {
 
         self.counter_halt = false;
         self.length_counter = 0;
+        self.halt_before_write = None;
+        self.length_before_write = None;
 
         self.counter_reload = false;
         self.counter_period = 0;
@@ -65,7 +77,7 @@ impl Triangle {
     /// Enables or disables the channel
     pub fn set_enabled(&mut self, v: bool) {
         self.enabled = v;
-        // If disabled, set the length counter to zero
+        // If disabled, set the length counter to zero. This halts the sequencer
         if !v {
             self.length_counter = 0;
         }
@@ -76,6 +88,7 @@ impl Triangle {
         // CRRR RRRR
         // C: Control flag (linear counter halt and length counter halt)
         // R: Linear counter period
+        self.halt_before_write.get_or_insert(self.counter_halt);
         self.counter_halt = data & 0x80 != 0;
         self.counter_period = data & 0x7F;
         if self.counter_halt {
@@ -96,7 +109,11 @@ impl Triangle {
         // L: Length counter table index
         // T: Timer period high
         self.timer_period = ((data & 0x7) as u16) << 8 | (self.timer_period & 0xFF);
-        self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        // The length counter is only loaded while the channel is enabled
+        if self.enabled {
+            self.length_before_write.get_or_insert(self.length_counter);
+            self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
         // A write to this register sets the linear reload flag
         self.counter_reload = true;
     }
@@ -130,11 +147,24 @@ impl Triangle {
 
         // If the length halt flag is not set and the counter is greater than
         // 0, decrement.
-        if !self.counter_halt && self.length_counter > 0 {
-            self.length_counter -= 1;
+        // A halt flag written on this cycle only takes effect after the clock.
+        // A reload on this cycle is ignored if the counter is clocked while not 0
+        let halt = self.halt_before_write.take().unwrap_or(self.counter_halt);
+        match self.length_before_write.take() {
+            _ if halt => {}
+            Some(0) => {}
+            Some(before) => self.length_counter = before - 1,
+            None if self.length_counter > 0 => self.length_counter -= 1,
+            None => {}
         }
     }
 
+    /// Forgets the writes of the last cycle, they no longer race with the length clock
+    pub fn clear_length_writes(&mut self) {
+        self.halt_before_write = None;
+        self.length_before_write = None;
+    }
+
     /// Clocks the linear counter
     pub fn tick_counter(&mut self) {
         // The triangle channel's linear counter is similar to the length counter
@@ -158,17 +188,74 @@ impl Triangle {
 
     /// Returns the output volume of the channel
     pub fn output(&self) -> u8 {
-        // All the conditions below silence the channel.
-        if !self.enabled || self.length_counter == 0 || self.linear_counter == 0 {
-            return 0;
+        // The channel is never muted. When the counters halt the sequencer,
+        // it keeps outputting the value of its phase
+
+        // The sequencer is not clocked with the ultrasonic periods. Games use them
+        // to silence the channel, which would be heard as a pop if the output
+        // stayed at the value of the phase
+        if self.timer_period < 2 {
+            return ULTRASONIC_OUTPUT;
         }
 
         // The output signal is based on fixed values, changing with the phase
         OUTPUT_TABLE[self.phase as usize]
     }
 
+    /// Returns if the channel is enabled in 0x4015
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
     /// Returns the length counter value
     pub fn length_counter(&self) -> u8 {
         self.length_counter
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_halted_output() {
+        let mut tri = Triangle::new();
+        tri.set_enabled(true);
+        tri.write_linear(0x81);
+        tri.write_lo(0x10);
+        tri.write_hi(0x08);
+        tri.tick_counter();
+        for _ in 0..(0x11 * 4) {
+            tri.tick_timer();
+        }
+        assert_eq!(tri.phase, 4);
+        assert_eq!(tri.output(), 11);
+
+        // Disabling the channel freezes the output at the current phase
+        tri.set_enabled(false);
+        for _ in 0..(0x11 * 4) {
+            tri.tick_timer();
+        }
+        assert_eq!(tri.phase, 4);
+        assert_eq!(tri.output(), 11);
+
+        // The length counter isn't loaded while disabled, the output stays the same
+        tri.write_hi(0x08);
+        assert_eq!(tri.length_counter(), 0);
+        assert_eq!(tri.output(), 11);
+    }
+
+    #[test]
+    fn test_ultrasonic_output() {
+        let mut tri = Triangle::new();
+        tri.set_enabled(true);
+        tri.write_linear(0x81);
+        tri.write_lo(0x01);
+        tri.write_hi(0x08);
+        tri.tick_counter();
+        for _ in 0..100 {
+            tri.tick_timer();
+            assert_eq!(tri.output(), ULTRASONIC_OUTPUT);
+        }
+    }
+}
@@ -1,9 +1,41 @@
 pub use main_bus::MainBus;
 pub use ppu_bus::PpuBus;
+pub use simple_bus::SimpleBus;
 pub use snake_bus::SnakeBus;
 pub use test_bus::TestBus;
 
 mod main_bus;
 mod ppu_bus;
+mod simple_bus;
 mod snake_bus;
 mod test_bus;
+
+/// Contents of the RAM at power on
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum RamPattern {
+    /// All bytes are 0x00
+    #[default]
+    Zeros,
+    /// All bytes are 0xFF
+    Ones,
+    /// Blocks of 4 bytes alternating between 0x00 and 0xFF, like a lot of consoles
+    PowerOn,
+}
+
+impl RamPattern {
+    /// Fills the memory with the pattern
+    pub fn fill(&self, ram: &mut [u8]) {
+        match self {
+            RamPattern::Zeros => ram.fill(0x00),
+            RamPattern::Ones => ram.fill(0xFF),
+            RamPattern::PowerOn => {
+                for (i, byte) in ram.iter_mut().enumerate() {
+                    *byte = match i & 0x4 {
+                        0 => 0x00,
+                        _ => 0xFF,
+                    };
+                }
+            }
+        }
+    }
+}
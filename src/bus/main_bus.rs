@@ -1,16 +1,19 @@
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::BufWriter;
+use std::io::{Read, Write};
 use std::rc::Rc;
 
-use super::PpuBus;
-use crate::apu::Apu;
+use serde_json::{json, Value};
+
+use super::{PpuBus, RamPattern};
+use crate::apu::{Apu, ApuState};
 use crate::cartridge::Cartridge;
 use crate::cpu::CpuInterface;
 use crate::cpu::Interface;
+use crate::filters::Filter;
 use crate::joypad::{Button, JoyPad, JoyPort};
-use crate::ppu::{Ppu, OAM_DATA};
+use crate::ppu::frame::{Frame, FrameInfo, Region};
+use crate::ppu::{Ppu, PpuState, OAM_DATA};
+use crate::resampler::{Resampler, ResamplerQuality};
 use crate::savable::Savable;
 
 /// Size of the RAM
@@ -24,6 +27,8 @@ const RAM_END: u16 = 0x1FFF;
 
 /// Mask for the Ppu addresses
 const PPU_MASK: u16 = 0x7;
+/// Ppu control register, once masked
+const PPU_CTRL: u16 = 0x0;
 /// First address of the Ppu registers memory space
 const PPU_REG_START: u16 = 0x2000;
 /// Last address of the Ppu registers memory space
@@ -34,6 +39,11 @@ const ROM_START: u16 = 0x4020;
 /// Last address of the ROM memory space
 const ROM_END: u16 = 0xFFFF;
 
+/// First address of the PRG RAM memory space
+const PRG_RAM_START: u16 = 0x6000;
+/// Last address of the PRG RAM memory space
+const PRG_RAM_END: u16 = 0x7FFF;
+
 /// Address of controller in port 1
 const JOY1: u16 = 0x4016;
 /// Address of controller in port 2
@@ -53,8 +63,20 @@ const APU_CH_ENABLE: u16 = 0x4015;
 /// Address of the Apu frame counter
 const APU_FRAME_COUNTER: u16 = 0x4017;
 
-/// How much time needs to pass between each audio samples (Apu is clocked at ~1.789 MHz)
-const TIME_PER_CLOCK: f64 = 1.0 / 1789773.0;
+/// Apu clock rate, the rate of the samples before resampling
+const CLOCK_RATE: f64 = 1789773.0;
+
+/// Scanline where the overclock cycles are inserted, right after the visible frame
+const OVERCLOCK_SCANLINE: i32 = 240;
+/// Ppu cycles in a scanline. The Cpu runs a third of them
+const DOTS_PER_SCANLINE: u32 = 341;
+/// Most extra scanlines given by the overclock, about 4 frames
+const MAX_OVERCLOCK: u32 = 1000;
+
+/// Called on Cpu reads. Returning `Some` overrides the value read
+type ReadHook<'a> = Box<dyn FnMut(u16) -> Option<u8> + 'a>;
+/// Called on Cpu writes with the address and data
+type WriteHook<'a> = Box<dyn FnMut(u16, u8) + 'a>;
 
 pub struct MainBus<'a> {
     ram: [u8; RAM_SIZE],
@@ -62,43 +84,73 @@ pub struct MainBus<'a> {
     apu: Apu,
     ppu: Ppu<'a>,
     joypads: [JoyPad; 2],
+    /// Controller read by the current instruction, with the cycles left before its read cycle
+    joypad_read: Option<(usize, u64)>,
+    /// PPUCTRL write of the current instruction, with the cycles left before its write cycle
+    ppu_ctrl_write: Option<(u8, u64)>,
+    /// Last byte on the Cpu data bus, read back from the unmapped addresses
+    cpu_open_bus: u8,
+    ram_pattern: RamPattern,
+    /// Extra scanlines of Cpu time every frame
+    overclock: u32,
+    /// Extra Cpu cycles left to run before the Ppu and Apu restart
+    overclock_cycles: u32,
+    read_hook: Option<ReadHook<'a>>,
+    write_hook: Option<WriteHook<'a>>,
 
-    audio_time: f64,
-    time_per_sample: f64,
+    sample_rate: f64,
+    resampler_quality: ResamplerQuality,
+    /// Emulation speed multiplier, the audio is pitched with it
+    speed: f64,
+    resampler: Resampler,
     samples: Vec<f32>,
 }
 
 impl CpuInterface for MainBus<'_> {}
 
 impl Savable for MainBus<'_> {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
         self.apu.save(output)?;
         self.ppu.save(output)?;
         self.cartridge.borrow().save(output)?;
         for i in 0..RAM_SIZE {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.ram[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram[i])?;
         }
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.audio_time)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.samples)?;
+        self.resampler.save(output)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.samples)?;
+        for joypad in self.joypads.iter() {
+            joypad.save(output)?;
+        }
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.joypad_read)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ppu_ctrl_write)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.cpu_open_bus)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.overclock_cycles)?;
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
         self.apu.load(input)?;
         self.ppu.load(input)?;
         self.cartridge.borrow_mut().load(input)?;
         for i in 0..RAM_SIZE {
-            self.ram[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+            self.ram[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        }
+        self.resampler.load(input)?;
+        self.samples = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        for joypad in self.joypads.iter_mut() {
+            joypad.load(input)?;
         }
-        self.audio_time = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.samples = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        self.joypad_read = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.ppu_ctrl_write = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.cpu_open_bus = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.overclock_cycles = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         Ok(())
     }
 }
 
 impl Interface for MainBus<'_> {
     fn read(&mut self, addr: u16) -> u8 {
-        match addr {
+        let data = match addr {
             // RAM memory space: mirror address and read from RAM
             RAM_START..=RAM_END => self.ram[(addr & RAM_MASK) as usize],
             // Ppu registers memory space: read from Ppu
@@ -107,19 +159,41 @@ impl Interface for MainBus<'_> {
                 let addr = addr & PPU_MASK;
                 self.ppu.read(addr)
             }
-            // Apu registers memory space: read from Apu
-            APU_REG_START..=APU_REG_END | APU_STATUS => self.apu.read(addr),
+            // Apu registers are write only
+            APU_REG_START..=APU_REG_END => self.cpu_open_bus,
+            // Bit 5 of the Apu status isn't driven
+            APU_STATUS => self.apu.read(addr) | (self.cpu_open_bus & 0x20),
             // Read controller port 1
-            JOY1 => self.joypads[0].read(),
+            JOY1 => {
+                self.joypad_read = Some((0, 0));
+                self.joypads[0].read() | self.joypad_upper_bits(addr)
+            }
             // Read controller port 2
-            JOY2 => self.joypads[1].read(),
+            JOY2 => {
+                self.joypad_read = Some((1, 0));
+                self.joypads[1].read() | self.joypad_upper_bits(addr)
+            }
             // ROM memory space: read from PRG ROM
             ROM_START..=ROM_END => self.cartridge.borrow_mut().read_prg(addr),
-            _ => 0,
-        }
+            // Nothing drives the bus, the last value is read back
+            _ => self.cpu_open_bus,
+        };
+
+        // The read still happens for its side effects, the hook can only change the value
+        let data = match &mut self.read_hook {
+            Some(hook) => hook(addr).unwrap_or(data),
+            None => data,
+        };
+        self.cpu_open_bus = data;
+        data
     }
 
     fn write(&mut self, addr: u16, data: u8) {
+        if let Some(hook) = &mut self.write_hook {
+            hook(addr, data);
+        }
+        self.cpu_open_bus = data;
+
         match addr {
             // RAM memory space: mirror address and write to RAM
             RAM_START..=RAM_END => self.ram[(addr & RAM_MASK) as usize] = data,
@@ -127,7 +201,17 @@ impl Interface for MainBus<'_> {
             PPU_REG_START..=PPU_REG_END => {
                 // Mirror address first
                 let addr = addr & PPU_MASK;
-                self.ppu.write(addr, data);
+                match addr {
+                    // Enabling or disabling the NMI depends on the exact write cycle, the write
+                    // waits for it in `tick`
+                    PPU_CTRL => {
+                        if let Some((pending, _)) = self.ppu_ctrl_write.take() {
+                            self.ppu.write(PPU_CTRL, pending);
+                        }
+                        self.ppu_ctrl_write = Some((data, 0));
+                    }
+                    _ => self.ppu.write(addr, data),
+                }
             }
             // Perform OAM DMA
             OAM_DMA => {
@@ -173,32 +257,94 @@ impl Interface for MainBus<'_> {
     }
 
     fn tick(&mut self, cycles: u64) {
+        // Some mappers need to know the Cpu timing
+        self.cartridge.borrow_mut().cpu_tick(cycles);
+
+        // The DMC fetches tick the bus too, they don't count down to the read cycle
+        let mut joypad_read = self.joypad_read.take();
+        let mut ppu_ctrl_write = self.ppu_ctrl_write.take();
+
         for _ in 0..cycles {
+            match ppu_ctrl_write {
+                Some((data, 0)) => {
+                    ppu_ctrl_write = None;
+                    self.ppu.write(PPU_CTRL, data);
+                }
+                Some((data, left)) => ppu_ctrl_write = Some((data, left - 1)),
+                None => {}
+            }
+            let read_port = match joypad_read {
+                Some((port, 0)) => {
+                    joypad_read = None;
+                    Some(port)
+                }
+                Some((port, left)) => {
+                    joypad_read = Some((port, left - 1));
+                    None
+                }
+                None => None,
+            };
+
+            // Overclocked cycles only run the Cpu. The Ppu and Apu are frozen, so the rendering
+            // and the audio timing don't see them
+            if self.overclock_cycles > 0 {
+                self.overclock_cycles -= 1;
+                continue;
+            }
+
             // Ppu is clocked at 3 times the speed of the Cpu
+            let scanline = self.ppu.scanline();
             for _ in 0..3 {
                 self.ppu.clock();
             }
 
-            // Apu is clocked at the same speed as the Cpu
+            // The post-render scanline is idle, it's a safe spot for the extra cycles
+            if scanline != OVERCLOCK_SCANLINE && self.ppu.scanline() == OVERCLOCK_SCANLINE {
+                self.overclock_cycles = self.overclock * DOTS_PER_SCANLINE / 3;
+            }
+
+            // Apu is clocked at the same speed as the Cpu, with the expansion audio of the
+            // cartridge
             self.apu.clock();
+            let expansion = self.cartridge.borrow_mut().clock_audio();
             // Check if DMC channel needs a new sample
-            self.update_dmc_sample();
-
-            // This next part is to keep the audio of the NES in sync
-            // Add the time per clock everytime the bus clocks
-            self.audio_time += TIME_PER_CLOCK;
-            // If enough time has passed to generate a new audio sample...
-            if self.audio_time >= self.time_per_sample {
-                // Substract the time per sample to the audio time.
-                // I do not reset it to 0 because it is possible that more than one sample
-                // needs to be generated.
-                self.audio_time -= self.time_per_sample;
-                // Generate a new sample
-                let sample = self.apu.output();
+            let dmc_fetch = self.update_dmc_sample();
+
+            // If the DMC fetch happens while the controller is read, the stalled Cpu
+            // reads the controller again and an extra bit is shifted out
+            if let Some(port) = read_port {
+                if dmc_fetch {
+                    self.joypads[port].read();
+                }
+            }
+
+            // The Apu output is resampled to the audio sample rate. The filters run at the
+            // sample rate, after the resampler
+            let sample = self.apu.mix() + expansion;
+            if let Some(sample) = self.resampler.push(sample) {
+                let sample = self.apu.filter(sample);
                 // Add it to the vec of samples
                 self.samples.push(sample);
             }
         }
+
+        // With Cpu::clock, the read cycle comes in a later tick
+        if joypad_read.is_some() {
+            self.joypad_read = joypad_read;
+        }
+        if ppu_ctrl_write.is_some() {
+            self.ppu_ctrl_write = ppu_ctrl_write;
+        }
+    }
+
+    fn instruction_cycles(&mut self, cycles: u64) {
+        // A controller read or a PPUCTRL write is always on the last cycle of the instruction
+        if let Some((port, _)) = self.joypad_read {
+            self.joypad_read = Some((port, cycles.saturating_sub(1)));
+        }
+        if let Some((data, _)) = self.ppu_ctrl_write {
+            self.ppu_ctrl_write = Some((data, cycles.saturating_sub(1)));
+        }
     }
 
     fn update_joypad(&mut self, button: Button, pressed: bool, port: JoyPort) {
@@ -208,14 +354,122 @@ impl Interface for MainBus<'_> {
         }
     }
 
+    fn set_joypad_state(&mut self, port: JoyPort, buttons: u8) {
+        match port {
+            JoyPort::Port1 => self.joypads[0].set_buttons(buttons),
+            JoyPort::Port2 => self.joypads[1].set_buttons(buttons),
+        }
+    }
+
     fn frame_count(&self) -> u128 {
         self.ppu.frame_count()
     }
 
+    fn frame(&self) -> Option<&Frame> {
+        Some(self.ppu.frame())
+    }
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "ppu": self.ppu.snapshot(),
+            "apu": self.apu.snapshot(),
+            "cartridge": self.cartridge.borrow().snapshot(),
+        })
+    }
+
+    fn joypad_buttons(&self, port: JoyPort) -> u8 {
+        match port {
+            JoyPort::Port1 => self.joypads[0].buttons(),
+            JoyPort::Port2 => self.joypads[1].buttons(),
+        }
+    }
+
+    fn ppu_state(&self) -> PpuState {
+        self.ppu.state()
+    }
+
+    fn apu_state(&self) -> ApuState {
+        self.apu.state()
+    }
+
+    fn start_apu_log(&mut self) {
+        self.apu.start_log();
+    }
+
+    fn stop_apu_log(&mut self) -> Vec<(u64, u16, u8)> {
+        self.apu.stop_log()
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM_START..=RAM_END => self.ram[(addr & RAM_MASK) as usize],
+            PPU_REG_START..=PPU_REG_END => self.ppu.peek_register(addr & PPU_MASK),
+            ROM_START..=ROM_END => self.cartridge.borrow_mut().peek_prg(addr),
+            // The Apu and controllers can't be read without side effects
+            _ => 0,
+        }
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        match addr {
+            RAM_START..=RAM_END => self.ram[(addr & RAM_MASK) as usize] = data,
+            // Higher addresses are mapper registers
+            PRG_RAM_START..=PRG_RAM_END => self.cartridge.borrow_mut().write_prg(addr, data),
+            _ => {}
+        }
+    }
+
+    fn peek_ppu(&self, addr: u16) -> u8 {
+        self.ppu.peek_vram(addr)
+    }
+
+    fn poke_ppu(&mut self, addr: u16, data: u8) {
+        self.ppu.poke_vram(addr, data);
+    }
+
+    fn set_sprite_limit(&mut self, enabled: bool) {
+        self.ppu.set_sprite_limit(enabled);
+    }
+
+    fn set_speed(&mut self, speed: f64) {
+        if speed > 0.0 && speed != self.speed {
+            self.speed = speed;
+            self.update_resampler();
+        }
+    }
+
+    fn set_chr_bank_capture(&mut self, enabled: bool) {
+        self.ppu.set_chr_bank_capture(enabled);
+    }
+
+    fn chr_banks_per_scanline(&self) -> Vec<[usize; 8]> {
+        self.ppu.debug_chr_banks_per_scanline()
+    }
+
     fn reset(&mut self) {
+        // The reset line only goes to the Cpu, Ppu and Apu. The RAM and cartridge are untouched
         self.ppu.reset();
         self.apu.reset();
+    }
+
+    fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.ppu.set_vs_palette(cartridge.vs_palette());
+        // The Ppu bus shares the cartridge, so both see the new one
+        *self.cartridge.borrow_mut() = cartridge;
+        // The audio of the old game isn't played
+        self.samples.clear();
+    }
+
+    fn power_on(&mut self) {
+        self.ram_pattern.fill(&mut self.ram);
+        self.ppu.power_on(self.ram_pattern);
+        self.apu.power_on();
         self.cartridge.borrow_mut().reset();
+        self.joypad_read = None;
+        self.ppu_ctrl_write = None;
+        self.cpu_open_bus = 0;
+        self.overclock_cycles = 0;
+        self.resampler.clear();
     }
 
     /// Returns the samples which are ready to be queued
@@ -230,27 +484,150 @@ impl Interface for MainBus<'_> {
 }
 
 impl<'a> MainBus<'a> {
-    pub fn new<F>(cartridge: Rc<RefCell<Cartridge>>, sdl_render_fn: F, sample_rate: f64) -> Self
+    /// `ram_pattern`: Contents of the RAM and VRAM at power on
+    pub fn new<F>(
+        cartridge: Rc<RefCell<Cartridge>>,
+        sdl_render_fn: F,
+        sample_rate: f64,
+        ram_pattern: RamPattern,
+    ) -> Self
     where
-        F: FnMut(&[u8]) + 'a,
+        F: FnMut(&Frame) + 'a,
     {
-        let ppu_bus = PpuBus::new(Rc::clone(&cartridge));
+        let ppu_bus = PpuBus::new(Rc::clone(&cartridge), ram_pattern);
+        let mut ppu = Ppu::new(Box::new(ppu_bus), Box::new(sdl_render_fn));
+        ppu.set_vs_palette(cartridge.borrow().vs_palette());
+        let mut ram = [0; RAM_SIZE];
+        ram_pattern.fill(&mut ram);
         Self {
-            ram: [0; RAM_SIZE],
+            ram,
             cartridge,
-            apu: Apu::new(sample_rate as f32),
-            ppu: Ppu::new(Box::new(ppu_bus), Box::new(sdl_render_fn)),
+            // The Ppu only has the NTSC timings
+            apu: Apu::new(sample_rate as f32, Region::Ntsc),
+            ppu,
             joypads: [JoyPad::new(); 2],
+            joypad_read: None,
+            ppu_ctrl_write: None,
+            cpu_open_bus: 0,
+            ram_pattern,
+            overclock: 0,
+            overclock_cycles: 0,
+            read_hook: None,
+            write_hook: None,
 
-            audio_time: 0.0,
-            time_per_sample: 1.0 / sample_rate,
+            sample_rate,
+            resampler_quality: ResamplerQuality::default(),
+            speed: 1.0,
+            resampler: Resampler::new(CLOCK_RATE, sample_rate, ResamplerQuality::default()),
             samples: Vec::new(),
         }
     }
 
-    fn update_dmc_sample(&mut self) {
+    /// Installs a function called on every Cpu read
+    ///
+    /// Returning `Some` overrides the value read, like a Game Genie
+    pub fn set_read_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(u16) -> Option<u8> + 'a,
+    {
+        self.read_hook = Some(Box::new(hook));
+    }
+
+    /// Installs a function called on every Cpu write
+    pub fn set_write_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(u16, u8) + 'a,
+    {
+        self.write_hook = Some(Box::new(hook));
+    }
+
+    /// Removes the read and write hooks
+    pub fn clear_hooks(&mut self) {
+        self.read_hook = None;
+        self.write_hook = None;
+    }
+
+    /// Also keep the palette index of each pixel in the frames sent to the render function
+    pub fn set_palette_indices(&mut self, enabled: bool) {
+        self.ppu.set_palette_indices(enabled);
+    }
+
+    /// Replaces the render function with one that also receives information about the frame
+    pub fn set_render_fn<F>(&mut self, render_fn: F)
+    where
+        F: FnMut(&Frame, FrameInfo) + 'a,
+    {
+        self.ppu.set_render_fn(render_fn);
+    }
+
+    /// Only render every `skip + 1` frames, for slow hosts
+    pub fn set_frame_skip(&mut self, skip: u32) {
+        self.ppu.set_frame_skip(skip);
+    }
+
+    /// Gives the Cpu `scanlines` extra scanlines of time every frame, to reduce slowdowns
+    ///
+    /// Games that count cycles to time things will run differently. Clamped to 1000 scanlines
+    pub fn set_overclock(&mut self, scanlines: u32) {
+        self.overclock = scanlines.min(MAX_OVERCLOCK);
+    }
+
+    /// Changes the quality of the resampling of the audio
+    pub fn set_resampler_quality(&mut self, quality: ResamplerQuality) {
+        self.resampler_quality = quality;
+        self.update_resampler();
+    }
+
+    /// The Apu output is resampled as if the clock ran `speed` times faster, so less or more
+    /// samples are made per frame and the pitch follows the speed
+    fn update_resampler(&mut self) {
+        self.resampler = Resampler::new(
+            CLOCK_RATE * self.speed,
+            self.sample_rate,
+            self.resampler_quality,
+        );
+    }
+
+    /// Changes the TV standard of the console. Only the Apu has the PAL timings for now
+    pub fn set_region(&mut self, region: Region) {
+        self.apu.set_region(region);
+    }
+
+    /// Replaces the filters of the audio. They run at the sample rate of the bus, after the
+    /// resampling. See `Apu::default_filters`
+    pub fn set_audio_filters(&mut self, filters: Vec<Box<dyn Filter>>) {
+        self.apu.set_filters(filters);
+    }
+
+    /// Bits 1-7 of a controller read. VS System boards drive some of them with the DIP switches
+    fn joypad_upper_bits(&self, addr: u16) -> u8 {
+        let cartridge = self.cartridge.borrow();
+        let dip_switches = cartridge.dip_switches();
+        match (cartridge.is_vs_system(), addr) {
+            // DIP switches 1 and 2 in bits 3-4. The service button (bit 2) and the coin slots
+            // (bits 5-6) aren't emulated, they read as not pressed. Bit 7 is 0 on the main Cpu
+            (true, JOY1) => (dip_switches & 0x3) << 3,
+            // DIP switches 3 to 8 in bits 2-7
+            (true, _) => dip_switches & 0xFC,
+            (false, _) => Self::joypad_open_bus(addr),
+        }
+    }
+
+    /// Upper bits of a controller read
+    ///
+    /// Only bits 0-4 are driven by the controller port. The rest is open bus, which still holds
+    /// the high byte of the address fetched by the instruction (0x40)
+    fn joypad_open_bus(addr: u16) -> u8 {
+        (addr >> 8) as u8 & 0xE0
+    }
+
+    /// Fetches a sample for the DMC if it needs one
+    ///
+    /// Returns if a sample was fetched
+    fn update_dmc_sample(&mut self) -> bool {
         // If DMC channel needs a new sample
-        if self.apu.need_dmc_sample() {
+        let fetch = self.apu.need_dmc_sample();
+        if fetch {
             // Read at the address
             let addr = self.apu.dmc_sample_address();
             let sample = self.read(addr);
@@ -259,5 +636,514 @@ impl<'a> MainBus<'a> {
             // The Cpu is stalled for 1-4 cycles, but I always use 4
             self.tick(4);
         }
+        fetch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use super::*;
+    use crate::cpu::{Cpu, Snapshot};
+
+    fn main_bus() -> MainBus<'static> {
+        let mut rom = vec![0; 16 + 0x4000 + 0x2000];
+        rom[..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+        let cartridge = Cartridge::from_bytes(&rom).unwrap();
+        MainBus::new(
+            Rc::new(RefCell::new(cartridge)),
+            |_| {},
+            44100.0,
+            RamPattern::default(),
+        )
+    }
+
+    #[test]
+    fn test_reset_and_power_on() {
+        let mut cpu = Cpu::new(main_bus());
+        cpu.power_on();
+        assert_eq!(cpu.s(), 0xFD);
+
+        // A soft reset keeps the RAM
+        cpu.mem_write(0x0010, 0x42);
+        cpu.reset();
+        assert_eq!(cpu.mem_read(0x0010), 0x42);
+        assert_eq!(cpu.s(), 0xFA);
+        assert_ne!(cpu.p() & 0x04, 0);
+
+        // Power on clears it
+        cpu.power_on();
+        assert_eq!(cpu.mem_read(0x0010), 0x00);
+        assert_eq!(cpu.s(), 0xFD);
+    }
+
+    #[test]
+    fn test_load_cartridge_clears_vram() {
+        let mut cpu = Cpu::new(main_bus());
+        cpu.power_on();
+        cpu.poke_ppu(0x2000, 0x42);
+        cpu.poke_ppu(0x3F01, 0x21);
+
+        let mut rom = vec![0; 16 + 0x4000 + 0x2000];
+        rom[..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+        cpu.load_cartridge(Cartridge::from_bytes(&rom).unwrap());
+
+        // The new game doesn't see the nametables and palettes of the old one
+        assert_eq!(cpu.peek_ppu(0x2000), 0x00);
+        assert_eq!(cpu.peek_ppu(0x3F01), 0x00);
+    }
+
+    #[test]
+    fn test_run_until_frame() {
+        let mut cpu = Cpu::new(main_bus());
+        cpu.power_on();
+        for frame in 1..=3 {
+            assert_eq!(cpu.run_until_frame(), frame);
+            // Stops at the start of vblank
+            assert_eq!(cpu.ppu_state().scanline, 241);
+        }
+        assert_eq!(cpu.frame().unwrap().pixels().len(), 256 * 240 * 3);
+        assert!(cpu.pixel_at(255, 239).is_some());
+        assert!(cpu.pixel_at(256, 0).is_none());
+        assert!(cpu.pixel_at(0, 240).is_none());
+
+        cpu.update_joypad(Button::Start, true, JoyPort::Port2);
+        assert_eq!(cpu.joypad_buttons(JoyPort::Port1), 0);
+        assert_eq!(cpu.joypad_buttons(JoyPort::Port2), 0x08);
+
+        cpu.set_joypad_state(JoyPort::Port1, 0xC3);
+        assert_eq!(cpu.joypad_buttons(JoyPort::Port1), 0xC3);
+        assert_eq!(cpu.joypad_buttons(JoyPort::Port2), 0x08);
+    }
+
+    #[test]
+    fn test_mmc1_rmw_write() {
+        // MMC1 with 32KB of PRG. INC $8000 at 0xC000, on a 0x7F in the ROM
+        let mut rom = vec![0; 16 + 0x8000 + 0x2000];
+        rom[..7].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 2, 1, 0x10]);
+        rom[16] = 0x7F;
+        rom[16 + 0x4000..16 + 0x4003].copy_from_slice(&[0xEE, 0x00, 0x80]);
+        rom[16 + 0x7FFD] = 0xC0;
+        let cartridge = Cartridge::from_bytes(&rom).unwrap();
+        let bus = MainBus::new(
+            Rc::new(RefCell::new(cartridge)),
+            |_| {},
+            44100.0,
+            RamPattern::default(),
+        );
+        let mut cpu = Cpu::new(bus);
+        cpu.power_on();
+        while cpu.pc() != 0xC003 {
+            cpu.clock();
+        }
+        while !cpu.instruction_done() {
+            cpu.clock();
+        }
+
+        // Only the first write (0x7F) reaches the shift register, the 0x80 reset is ignored
+        let registers = &cpu.snapshot().bus["cartridge"]["registers"];
+        assert_eq!(registers["shift_count"], 1);
+        assert_eq!(registers["shift"], 1);
+    }
+
+    #[test]
+    fn test_run_cycles() {
+        let mut cpu = Cpu::new(main_bus());
+        cpu.power_on();
+        let start = cpu.cycles();
+        assert_eq!(cpu.run_cycles(1000), 1000);
+        assert_eq!(cpu.cycles() - start, 1000);
+        assert_eq!(cpu.run_cycles(0), 0);
+    }
+
+    #[test]
+    fn test_save_in_memory() {
+        let mut cpu = Cpu::new(main_bus());
+        cpu.power_on();
+        cpu.mem_write(0x0010, 0x42);
+        cpu.run_until_frame();
+
+        // Any writer works, not only files
+        let mut state = Vec::new();
+        cpu.save(&mut state).unwrap();
+        let cycles = cpu.cycles();
+
+        cpu.mem_write(0x0010, 0x00);
+        cpu.run_until_frame();
+        cpu.load(&mut state.as_slice()).unwrap();
+        assert_eq!(cpu.cycles(), cycles);
+        assert_eq!(cpu.frame_count(), 1);
+        assert_eq!(cpu.mem_read(0x0010), 0x42);
+    }
+
+    #[test]
+    fn test_overclock() {
+        let cycles_per_frame = |overclock| {
+            let mut bus = main_bus();
+            bus.set_overclock(overclock);
+            let mut cpu = Cpu::new(bus);
+            cpu.power_on();
+            cpu.run_until_frame();
+            let start = cpu.cycles();
+            cpu.run_until_frame();
+            (cpu.cycles() - start) as i64
+        };
+
+        let normal = cycles_per_frame(0);
+        let overclocked = cycles_per_frame(20);
+        // A few cycles of slack for the instruction that overlaps the frame boundary
+        assert!((overclocked - normal - 20 * 341 / 3).abs() < 8);
+
+        // Huge values are clamped instead of overflowing
+        let clamped = cycles_per_frame(u32::MAX);
+        assert!((clamped - normal - MAX_OVERCLOCK as i64 * 341 / 3).abs() < 8);
+    }
+
+    #[test]
+    fn test_json_snapshot() {
+        let mut cpu = Cpu::new(main_bus());
+        cpu.power_on();
+        cpu.run_until_frame();
+
+        let json = cpu.to_json().unwrap();
+        let snapshot = Snapshot::from_json(&json).unwrap();
+        assert_eq!(snapshot, cpu.snapshot());
+        assert_eq!(snapshot.cpu.pc, cpu.pc());
+        assert_eq!(snapshot.bus["ppu"]["scanline"], 241);
+        assert_eq!(snapshot.bus["cartridge"]["mirror_mode"], "Horizontal");
+    }
+
+    #[test]
+    fn test_ram_pattern() {
+        let mut ram = [0x42; 16];
+        RamPattern::PowerOn.fill(&mut ram);
+        assert_eq!(&ram[..8], &[0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(&ram[8..], &ram[..8]);
+        RamPattern::Ones.fill(&mut ram);
+        assert!(ram.iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn test_hooks() {
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&writes);
+
+        let mut bus = main_bus();
+        bus.set_write_hook(move |addr, data| log.borrow_mut().push((addr, data)));
+        bus.set_read_hook(|addr| match addr {
+            0x8000 => Some(0xEA),
+            _ => None,
+        });
+
+        bus.write(0x0001, 0x42);
+        bus.write(0x0802, 0x43);
+        assert_eq!(*writes.borrow(), vec![(0x0001, 0x42), (0x0802, 0x43)]);
+
+        // Only the patched address is changed
+        assert_eq!(bus.read(0x8000), 0xEA);
+        assert_eq!(bus.read(0x8001), 0x00);
+        assert_eq!(bus.read(0x0001), 0x42);
+
+        bus.clear_hooks();
+        assert_eq!(bus.read(0x8000), 0x00);
+    }
+
+    #[test]
+    fn test_apu_log() {
+        let mut bus = main_bus();
+        bus.start_apu_log();
+        bus.write(0x4000, 0x3F);
+        bus.tick(10);
+        // The controllers and the test registers are not Apu registers
+        bus.write(0x4016, 0x01);
+        bus.write(0x4018, 0x01);
+        bus.write(0x4017, 0x40);
+        assert_eq!(
+            bus.stop_apu_log(),
+            vec![(0, 0x4000, 0x3F), (10, 0x4017, 0x40)]
+        );
+    }
+
+    #[test]
+    fn test_speed() {
+        // About 0.1s of emulation
+        let sample_count = |speed| {
+            let mut bus = main_bus();
+            bus.set_speed(speed);
+            bus.set_resampler_quality(ResamplerQuality::High);
+            bus.tick(178977);
+            bus.sample_count()
+        };
+        for (speed, expected) in [(1.0, 4410), (2.0, 2205), (0.5, 8820), (0.25, 17640)] {
+            let count = sample_count(speed);
+            assert!(count.abs_diff(expected) <= 1, "{}x: {}", speed, count);
+        }
+    }
+
+    #[test]
+    fn test_peek_poke() {
+        let mut bus = main_bus();
+        bus.poke(0x0010, 0x42);
+        assert_eq!(bus.peek(0x0810), 0x42);
+        assert_eq!(bus.read(0x0010), 0x42);
+
+        // Peeking the Ppu data doesn't move the Vram address
+        bus.write(0x2006, 0x20);
+        bus.write(0x2006, 0x00);
+        let v_addr = bus.ppu_state().v_addr;
+        bus.peek(0x2007);
+        bus.peek(0x2007);
+        assert_eq!(bus.ppu_state().v_addr, v_addr);
+
+        bus.poke_ppu(0x2000, 0x24);
+        assert_eq!(bus.peek_ppu(0x2000), 0x24);
+        assert_eq!(bus.ppu_state().v_addr, v_addr);
+    }
+
+    #[test]
+    fn test_vs_dip_switches() {
+        let mut rom = vec![0; 16 + 0x4000 + 0x2000];
+        // NES 2.0 VS System with a RP2C04-0001
+        rom[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1, 0, 0x09]);
+        rom[13] = 0x02;
+        let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+        assert!(cartridge.is_vs_system());
+        assert_eq!(cartridge.vs_palette(), Some(0));
+        cartridge.set_dip_switches(0b1010_0110);
+
+        let mut bus = MainBus::new(
+            Rc::new(RefCell::new(cartridge)),
+            |_| {},
+            44100.0,
+            RamPattern::default(),
+        );
+        // No open bus: the coin slots and the service button are driven as not pressed
+        assert_eq!(bus.read(JOY1), 0b10 << 3);
+        assert_eq!(bus.read(JOY2) & 0xFC, 0b1010_0100);
+
+        // Normal carts don't have them
+        let mut bus = main_bus();
+        assert_eq!(bus.read(JOY2) & 0xFC, 0x40);
+    }
+
+    #[test]
+    fn test_joypad_open_bus() {
+        let mut bus = main_bus();
+        bus.update_joypad(Button::A, true, JoyPort::Port1);
+        bus.write(JOY1, 1);
+        bus.write(JOY1, 0);
+
+        assert_eq!(bus.read(JOY1), 0x41);
+        assert_eq!(bus.read(JOY1), 0x40);
+        assert_eq!(bus.read(JOY2), 0x40);
+    }
+
+    #[test]
+    fn test_load_cartridge() {
+        let mut cpu = Cpu::new(main_bus());
+        cpu.power_on();
+        cpu.mem_write(0x0010, 0x42);
+        cpu.run_until_frame();
+        assert!(cpu.sample_count() > 0);
+
+        // Reset vector at 0x8123
+        let mut rom = vec![0; 16 + 0x4000 + 0x2000];
+        rom[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1, 0x01, 0]);
+        rom[16 + 0x3FFC] = 0x23;
+        rom[16 + 0x3FFD] = 0x81;
+        cpu.load_cartridge(Cartridge::from_bytes(&rom).unwrap());
+
+        assert_eq!(cpu.pc(), 0x8123);
+        assert_eq!(cpu.mem_read(0x0010), 0x00);
+        assert_eq!(cpu.frame_count(), 0);
+        assert_eq!(cpu.sample_count(), 0);
+        let snapshot = cpu.snapshot();
+        assert_eq!(snapshot.bus["cartridge"]["mirror_mode"], "Vertical");
+    }
+
+    #[test]
+    fn test_cpu_open_bus() {
+        let mut bus = main_bus();
+        bus.write(0x0010, 0x5A);
+        // Unmapped and write only addresses return the last value on the bus
+        assert_eq!(bus.read(0x4018), 0x5A);
+        assert_eq!(bus.read(0x401F), 0x5A);
+        assert_eq!(bus.read(OAM_DMA), 0x5A);
+        assert_eq!(bus.read(0x4000), 0x5A);
+
+        bus.write(0x0010, 0x20);
+        bus.read(0x0010);
+        assert_eq!(bus.read(0x4018), 0x20);
+        // Only bit 5 of the Apu status
+        assert_eq!(bus.read(APU_STATUS) & 0x20, 0x20);
+    }
+
+    /// Counts how many times the second controller read returns the third button
+    fn count_joypad_corruptions(bus: &mut MainBus) -> usize {
+        // A and Select pressed, so B reads as 0 and Select as 1
+        bus.update_joypad(Button::A, true, JoyPort::Port1);
+        bus.update_joypad(Button::Select, true, JoyPort::Port1);
+
+        (0..2000)
+            .filter(|_| {
+                bus.write(JOY1, 1);
+                bus.write(JOY1, 0);
+                // Like LDA $4016
+                bus.read(JOY1);
+                bus.instruction_cycles(4);
+                bus.tick(4);
+                let b = bus.read(JOY1) & 0x1;
+                // Odd length so the DMC fetches don't always land on the same cycle
+                bus.tick(3);
+                b == 1
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_dmc_joypad_conflict() {
+        let mut bus = main_bus();
+        assert_eq!(count_joypad_corruptions(&mut bus), 0);
+
+        // Looping DMC sample at the fastest rate
+        let mut bus = main_bus();
+        bus.write(0x4010, 0x4F);
+        bus.write(0x4013, 0xFF);
+        bus.write(0x4015, 0x10);
+        assert!(count_joypad_corruptions(&mut bus) > 0);
+    }
+
+    #[test]
+    fn test_dmc_joypad_conflict_clock() {
+        // Starts a looping DMC sample at the fastest rate, then reads the B button of
+        // controller 1 into 0x0300-0x03FF
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x4F, 0x8D, 0x10, 0x40, // LDA #$4F, STA $4010
+            0xA9, 0xFF, 0x8D, 0x13, 0x40, // LDA #$FF, STA $4013
+            0xA9, 0x10, 0x8D, 0x15, 0x40, // LDA #$10, STA $4015
+            0xA9, 0x01, 0x8D, 0x16, 0x40, // loop: LDA #1, STA $4016
+            0xA9, 0x00, 0x8D, 0x16, 0x40, // LDA #0, STA $4016
+            0xAD, 0x16, 0x40, // LDA $4016
+            0xAD, 0x16, 0x40, // LDA $4016
+            0x9D, 0x00, 0x03, // STA $0300,X
+            0xE8, 0xD0, 0xEA, // INX, BNE loop
+            0x02, // KIL
+        ];
+        let run = |clock: bool| {
+            let mut rom = vec![0; 16 + 0x4000 + 0x2000];
+            rom[..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+            rom[16..16 + program.len()].copy_from_slice(&program);
+            rom[16 + 0x3FFD] = 0xC0;
+            let cartridge = Cartridge::from_bytes(&rom).unwrap();
+            let bus = MainBus::new(
+                Rc::new(RefCell::new(cartridge)),
+                |_| {},
+                44100.0,
+                RamPattern::default(),
+            );
+            let mut cpu = Cpu::new(bus);
+            cpu.power_on();
+            // B reads as 0 and Select as 1
+            cpu.update_joypad(Button::A, true, JoyPort::Port1);
+            cpu.update_joypad(Button::Select, true, JoyPort::Port1);
+            while !cpu.is_jammed() {
+                match clock {
+                    true => cpu.clock(),
+                    false => {
+                        cpu.execute();
+                    }
+                }
+            }
+            (0x0300..0x0400)
+                .map(|addr| cpu.mem_read(addr) & 0x1)
+                .collect::<Vec<_>>()
+        };
+
+        // Cycle by cycle, the read lands on the same cycle as when the instruction is
+        // ticked all at once
+        let reads = run(true);
+        assert!(reads.contains(&1));
+        assert_eq!(reads, run(false));
+    }
+
+    #[test]
+    fn test_ppu_ctrl_nmi_write_cycle() {
+        // Runs until an instruction ends in the NOP loop with the Ppu on `scanline`, in `dots`
+        fn run_to(cpu: &mut Cpu<MainBus>, scanline: i32, dots: RangeInclusive<usize>) {
+            loop {
+                cpu.clock();
+                let state = cpu.ppu_state();
+                if cpu.instruction_done()
+                    && (0x0002..0x00F0).contains(&cpu.pc())
+                    && state.scanline == scanline
+                    && dots.contains(&state.cycle)
+                {
+                    break;
+                }
+            }
+        }
+
+        // The NMI handler counts the NMIs in 0x0300
+        let mut rom = vec![0; 16 + 0x4000 + 0x2000];
+        rom[..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+        rom[16..20].copy_from_slice(&[0xEE, 0x00, 0x03, 0x40]);
+        rom[16 + 0x3FFA..16 + 0x4000].copy_from_slice(&[0x00, 0x80, 0x00, 0x00, 0x00, 0x80]);
+        let bus = MainBus::new(
+            Rc::new(RefCell::new(Cartridge::from_bytes(&rom).unwrap())),
+            |_| {},
+            44100.0,
+            RamPattern::default(),
+        );
+        let mut cpu = Cpu::new(bus);
+        cpu.power_on();
+
+        // LDA #$80, NOPs, JMP $0002
+        (0x0000..0x00F0).for_each(|addr| cpu.poke(addr, 0xEA));
+        [0xA9, 0x80, 0xEA]
+            .iter()
+            .zip(0x0000..)
+            .for_each(|(&b, addr)| cpu.poke(addr, b));
+        [0x4C, 0x02, 0x00]
+            .iter()
+            .zip(0x00F0..)
+            .for_each(|(&b, addr)| cpu.poke(addr, b));
+        // STX $2000 (X is 0) and STA $2000, back to the loop
+        #[rustfmt::skip]
+        let writes = [
+            0x8E, 0x00, 0x20, 0x4C, 0x02, 0x00,
+            0x8D, 0x00, 0x20, 0x4C, 0x02, 0x00,
+        ];
+        writes
+            .iter()
+            .zip(0x0400..)
+            .for_each(|(&b, addr)| cpu.poke(addr, b));
+        let (disable, enable) = (0x0400, 0x0406);
+
+        // Past the warm up of the Ppu
+        cpu.run_until_frame();
+        cpu.run_until_frame();
+        run_to(&mut cpu, 100, 0..=340);
+        cpu.set_pc(enable);
+        run_to(&mut cpu, 250, 0..=340);
+        assert_eq!(cpu.peek(0x0300), 1);
+
+        // The vblank flag is set during the first cycles of the STX. The NMI is withdrawn on
+        // the write cycle, before the Cpu sees it
+        run_to(&mut cpu, 240, 334..=340);
+        cpu.set_pc(disable);
+        run_to(&mut cpu, 250, 0..=340);
+        assert_eq!(cpu.peek(0x0300), 1);
+
+        // The vblank flag is cleared before the write cycle of the STA, there is no NMI
+        run_to(&mut cpu, 260, 334..=340);
+        cpu.set_pc(enable);
+        run_to(&mut cpu, 10, 0..=340);
+        assert_eq!(cpu.peek(0x0300), 1);
+
+        // The next vblank has one
+        run_to(&mut cpu, 250, 0..=340);
+        assert_eq!(cpu.peek(0x0300), 2);
     }
 }
@@ -1,8 +1,8 @@
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{Read, Write};
 use std::rc::Rc;
 
+use super::RamPattern;
 use crate::cartridge::{Cartridge, MirrorMode};
 use crate::ppu::{self, PpuInterface};
 use crate::savable::Savable;
@@ -14,8 +14,8 @@ const ROM_END: u16 = 0x1FFF;
 
 /// Size of one nametable
 const NTA_SIZE: u16 = 0x400;
-/// Size of the VRAM (Doubled to handle some games which use 4screen mapping)
-const VRAM_SIZE: usize = 0x800 * 2;
+/// Size of the VRAM. Four screen cartridges have the extra nametables
+const VRAM_SIZE: usize = 0x800;
 /// First address of the VRAM memory space
 const VRAM_START: u16 = 0x2000;
 /// Last address of the VRAM memory space
@@ -38,22 +38,22 @@ pub struct PpuBus {
 impl PpuInterface for PpuBus {}
 
 impl Savable for PpuBus {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
         for i in 0..PALETTE_RAM_SIZE {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.pal_ram[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.pal_ram[i])?;
         }
         for i in 0..VRAM_SIZE {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.vram[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.vram[i])?;
         }
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
         for i in 0..PALETTE_RAM_SIZE {
-            self.pal_ram[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+            self.pal_ram[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         }
         for i in 0..VRAM_SIZE {
-            self.vram[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+            self.vram[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         }
         Ok(())
     }
@@ -68,9 +68,13 @@ impl ppu::Interface for PpuBus {
             ROM_START..=ROM_END => self.cartridge.borrow_mut().read_chr(addr),
             // VRAM memory space: read from VRAM
             VRAM_START..=VRAM_END => {
-                // Mirror the address first
-                let index = self.mirrored_vaddr(addr) as usize;
-                self.vram[index]
+                let mode = self.mirror_mode();
+                match mode {
+                    // The nametables are on the cartridge
+                    MirrorMode::FourScreen => self.cartridge.borrow().read_nametable(addr),
+                    // Mirror the address first
+                    _ => self.vram[self.mirrored_vaddr(addr, mode) as usize],
+                }
             }
             // Palette RAM memory space:
             PALETTE_START..=PALETTE_END => {
@@ -99,9 +103,15 @@ impl ppu::Interface for PpuBus {
             ROM_START..=ROM_END => self.cartridge.borrow_mut().write_chr(addr, data),
             // VRAM memory space: read from VRAM
             VRAM_START..=VRAM_END => {
-                // Mirror the address first
-                let index = self.mirrored_vaddr(addr) as usize;
-                self.vram[index] = data;
+                let mode = self.mirror_mode();
+                match mode {
+                    // The nametables are on the cartridge
+                    MirrorMode::FourScreen => {
+                        self.cartridge.borrow_mut().write_nametable(addr, data)
+                    }
+                    // Mirror the address first
+                    _ => self.vram[self.mirrored_vaddr(addr, mode) as usize] = data,
+                }
             }
             // Palette RAM memory space:
             PALETTE_START..=PALETTE_END => {
@@ -126,26 +136,59 @@ impl ppu::Interface for PpuBus {
     fn inc_scanline(&mut self) {
         self.cartridge.borrow_mut().inc_scanline()
     }
+
+    fn power_on(&mut self, ram_pattern: RamPattern) {
+        ram_pattern.fill(&mut self.vram);
+        self.pal_ram = [0; PALETTE_RAM_SIZE];
+    }
+
+    fn chr_bank_layout(&self) -> Option<[usize; 8]> {
+        self.cartridge.borrow().chr_bank_layout()
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        match addr {
+            // Some mappers switch CHR banks on reads
+            ROM_START..=ROM_END => self.cartridge.borrow_mut().peek_chr(addr),
+            _ => self.read(addr),
+        }
+    }
 }
 
 impl PpuBus {
-    pub fn new(cartridge: Rc<RefCell<Cartridge>>) -> Self {
-        Self {
+    pub fn new(cartridge: Rc<RefCell<Cartridge>>, ram_pattern: RamPattern) -> Self {
+        let mut bus = Self {
             cartridge,
             pal_ram: [0; PALETTE_RAM_SIZE],
             vram: [0; VRAM_SIZE],
+        };
+        ppu::Interface::power_on(&mut bus, ram_pattern);
+        bus
+    }
+
+    /// Mirroring of the nametables. The debug override of the cartridge wins over the mapper.
+    /// Four screen without the extra nametables on the cartridge uses vertical mirroring
+    fn mirror_mode(&self) -> MirrorMode {
+        let cartridge = self.cartridge.borrow();
+        match cartridge
+            .mirror_override()
+            .unwrap_or_else(|| cartridge.mirror_mode())
+        {
+            MirrorMode::FourScreen if !cartridge.has_nametables() => MirrorMode::Vertical,
+            mode => mode,
         }
     }
 
-    /// Returns the address mirrored based on the current mirroring mode
-    fn mirrored_vaddr(&self, addr: u16) -> u16 {
+    /// Returns the address mirrored based on the mirroring mode
+    fn mirrored_vaddr(&self, addr: u16, mode: MirrorMode) -> u16 {
         // Mask because 0x2000 - 0x2FFF mirrors 0x3000 - 0x3EFF
         let addr = addr & 0x2FFF;
         // Substract the memory map offset to have real memory index
         let index = addr - VRAM_START;
         // Calculate which nametable we are in
         let nta = index / NTA_SIZE;
-        match self.cartridge.borrow().mirror_mode() {
+        match mode {
             // |---------|---------|  |---------|---------|
             // |         |         |  |         |         |
             // |  0 - A  |  1 - B  |  |    0    |    1    | The hardware has space for only 2 nametables
@@ -215,9 +258,37 @@ impl PpuBus {
             // |  2 - C  |  3 - D  |  |    2    |    3    | The extra nametables were on the cartridge PCB
             // |         |         |  |         |         |
             // |---------|---------|  |---------|---------|
-            // Real hardware would use memory on the cartridge but, I simply
-            // allocated a Vec of twice the size of VRAM and use the index directly
-            MirrorMode::FourScreen => index,
+            // The cartridge handles these, see `Cartridge::read_nametable`
+            MirrorMode::FourScreen => unreachable!("Four screen nametables are on the cartridge"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::Interface;
+
+    #[test]
+    fn test_four_screen_without_nametables() {
+        let mut bytes = vec![0; 16 + 0x4000 + 0x2000];
+        bytes[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1, 0, 0]);
+        let mut state = vec![];
+        Cartridge::from_bytes(&bytes)
+            .unwrap()
+            .save(&mut state)
+            .unwrap();
+
+        // A state without the extra nametables loaded in a four screen cartridge
+        bytes[6] |= 0x8;
+        let cartridge = Rc::new(RefCell::new(Cartridge::from_bytes(&bytes).unwrap()));
+        cartridge.borrow_mut().load(&mut state.as_slice()).unwrap();
+        assert!(!cartridge.borrow().has_nametables());
+
+        let mut bus = PpuBus::new(cartridge, RamPattern::Zeros);
+        bus.write(0x2800, 0x42);
+        bus.write(0x2400, 0x43);
+        assert_eq!(bus.read(0x2000), 0x42);
+        assert_eq!(bus.read(0x2C00), 0x43);
+    }
+}
@@ -0,0 +1,144 @@
+use std::io::{Read, Write};
+
+use crate::cpu::CpuInterface;
+use crate::cpu::Interface;
+use crate::savable::Savable;
+
+/// Size of the address space of the Cpu
+const MEMORY_SIZE: usize = 0x10000;
+const RESET_VECTOR: u16 = 0xFFFC;
+
+/// Flat 64KB of RAM, to run the Cpu without the rest of the NES
+///
+/// For 6502 experiments and fuzzing. There is no Ppu, Apu or controller, so no interrupts
+/// are raised and every address reads back what was written
+///
+/// ```
+/// use nesoxyde::bus::SimpleBus;
+/// use nesoxyde::cpu::Cpu;
+///
+/// // LDA #$05, ADC #$03, STA $10
+/// let bus = SimpleBus::with_program(0x8000, &[0xA9, 0x05, 0x69, 0x03, 0x85, 0x10]);
+/// let mut cpu = Cpu::new(bus);
+/// cpu.power_on();
+/// assert_eq!(cpu.pc(), 0x8000);
+///
+/// for _ in 0..3 {
+///     cpu.execute();
+/// }
+/// assert_eq!(cpu.a(), 0x08);
+/// assert_eq!(cpu.peek(0x0010), 0x08);
+/// assert_eq!(cpu.pc(), 0x8006);
+/// ```
+pub struct SimpleBus {
+    memory: Vec<u8>,
+}
+
+impl Interface for SimpleBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        self.write(addr, data);
+    }
+}
+
+impl CpuInterface for SimpleBus {}
+
+impl Savable for SimpleBus {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.memory)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        self.memory = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        Ok(())
+    }
+}
+
+impl Default for SimpleBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimpleBus {
+    /// Memory filled with zeros
+    pub fn new() -> Self {
+        Self {
+            memory: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    /// Memory with `program` copied at `addr`, the reset vector pointing to it
+    ///
+    /// A program reaching the reset vector overwrites it. Panics if it goes past 0xFFFF
+    pub fn with_program(addr: u16, program: &[u8]) -> Self {
+        let mut bus = Self::new();
+        bus.set_ram(RESET_VECTOR, addr as u8);
+        bus.set_ram(RESET_VECTOR + 1, (addr >> 8) as u8);
+        bus.load(addr, program);
+        bus
+    }
+
+    /// Copies bytes to the memory, starting at `addr`. Panics if they go past 0xFFFF
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) {
+        let start = addr as usize;
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Sets the byte at `addr`
+    pub fn set_ram(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+
+    /// Byte at `addr`
+    pub fn get_ram(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_simple_bus() {
+        let mut bus = SimpleBus::with_program(0xFFF0, &[0xEA; 0x10]);
+        // The program overwrote the reset vector
+        assert_eq!(bus.get_ram(0xFFFC), 0xEA);
+        bus.set_ram(0xFFFF, 0x42);
+        assert_eq!(bus.read(0xFFFF), 0x42);
+
+        // JSR $1234, the return address is pushed on the stack
+        let mut bus = SimpleBus::with_program(0x0400, &[0x20, 0x34, 0x12]);
+        bus.load(0x1234, &[0xE8]);
+        let mut cpu = Cpu::new(bus);
+        cpu.power_on();
+        cpu.execute();
+        cpu.execute();
+        assert_eq!((cpu.pc(), cpu.x(), cpu.s()), (0x1235, 1, 0xFB));
+        assert_eq!((cpu.peek(0x01FD), cpu.peek(0x01FC)), (0x04, 0x02));
+
+        // Save states keep the whole memory
+        let mut state = vec![];
+        let mut bus = SimpleBus::new();
+        bus.set_ram(0xBEEF, 0x12);
+        bus.save(&mut state).unwrap();
+        let mut loaded = SimpleBus::new();
+        loaded.load(0, &[0xFF]);
+        Savable::load(&mut loaded, &mut state.as_slice()).unwrap();
+        assert_eq!((loaded.get_ram(0xBEEF), loaded.get_ram(0)), (0x12, 0));
+    }
+}
@@ -1,42 +1,125 @@
-#![allow(dead_code)]
-
-use std::usize;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::cpu::CpuInterface;
 use crate::cpu::Interface;
 use crate::savable::Savable;
 
 const RESET_VECTOR: u16 = 0xFFFC;
+/// The program is loaded here
+const PROGRAM_START: u16 = 0x600;
+/// Reads return a new random byte
+const RANDOM_ADDR: u16 = 0xFE;
 
 // Bus only used with the snake game
 pub struct SnakeBus {
-    memory: [u8; 0xFFFF],
+    memory: Vec<u8>,
+    /// Xorshift state, never 0
+    rng: u64,
 }
 
 impl Interface for SnakeBus {
     fn read(&mut self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+        match addr {
+            RANDOM_ADDR => self.next_random(),
+            _ => self.memory[addr as usize],
+        }
     }
 
     fn write(&mut self, addr: u16, data: u8) {
         self.memory[addr as usize] = data
     }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        self.write(addr, data);
+    }
 }
 
 impl CpuInterface for SnakeBus {}
 
+impl Default for SnakeBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SnakeBus {
+    /// Biggest program that fits in memory, up to the reset vector
+    pub const MAX_PROGRAM_SIZE: usize = (RESET_VECTOR - PROGRAM_START) as usize;
+
+    /// Bus with a random seed, a different game every time
     pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::with_seed(seed)
+    }
+
+    /// Bus where the random bytes always come in the same order, for reproducible games
+    pub fn with_seed(seed: u64) -> Self {
         Self {
-            memory: [0; 0xFFFF],
+            memory: vec![0; 0x10000],
+            // Xorshift gets stuck on 0
+            rng: seed.max(1),
         }
     }
 
+    /// Loads the program at 0x0600. Panics if it is bigger than `MAX_PROGRAM_SIZE`
     pub fn load(&mut self, program: Vec<u8>) {
-        self.memory[0x600..(0x600 + program.len())].copy_from_slice(&program[..]);
-        self.write(RESET_VECTOR, 0x00);
-        self.write(RESET_VECTOR + 1, 0x06);
+        assert!(program.len() <= Self::MAX_PROGRAM_SIZE);
+        let start = PROGRAM_START as usize;
+        self.memory[start..(start + program.len())].copy_from_slice(&program[..]);
+        self.write(RESET_VECTOR, PROGRAM_START as u8);
+        self.write(RESET_VECTOR + 1, (PROGRAM_START >> 8) as u8);
+    }
+
+    /// Next byte of the xorshift64 generator
+    fn next_random(&mut self) -> u8 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 32) as u8
     }
 }
 
 impl Savable for SnakeBus {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed() {
+        let random = |seed| {
+            let mut bus = SnakeBus::with_seed(seed);
+            (0..16).map(|_| bus.read(RANDOM_ADDR)).collect::<Vec<_>>()
+        };
+        assert_eq!(random(42), random(42));
+        assert_ne!(random(42), random(43));
+        assert_ne!(random(0), vec![0; 16]);
+
+        // Peeking doesn't draw a number
+        let mut bus = SnakeBus::with_seed(42);
+        bus.peek(RANDOM_ADDR);
+        assert_eq!(bus.read(RANDOM_ADDR), random(42)[0]);
+
+        // The rest is plain memory
+        bus.write(0x00FF, b'w');
+        assert_eq!(bus.read(0x00FF), b'w');
+    }
+
+    #[test]
+    fn test_load() {
+        let mut bus = SnakeBus::with_seed(42);
+        bus.load(vec![0xEA; SnakeBus::MAX_PROGRAM_SIZE]);
+        assert_eq!(bus.read(0xFFFB), 0xEA);
+        assert_eq!(
+            (bus.read(RESET_VECTOR), bus.read(RESET_VECTOR + 1)),
+            (0x00, 0x06)
+        );
+    }
+}
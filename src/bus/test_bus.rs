@@ -1,5 +1,3 @@
-#![allow(dead_code)]
-
 use crate::cpu::CpuInterface;
 use crate::cpu::Interface;
 use crate::savable::Savable;
@@ -8,6 +6,8 @@ use crate::savable::Savable;
 pub struct TestBus {
     ram: [u8; 0x800],
     program: Vec<u8>,
+    cycles: u64,
+    nmi_cycle: Option<u64>,
 }
 
 impl Interface for TestBus {
@@ -24,6 +24,28 @@ impl Interface for TestBus {
             _ => self.program[(addr - 0x2000) as usize] = data,
         }
     }
+
+    fn poll_nmi(&mut self) -> bool {
+        match self.nmi_cycle {
+            Some(cycle) if self.cycles >= cycle => self.nmi_cycle.take().is_some(),
+            _ => false,
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.ram[(addr & 0x7FF) as usize],
+            _ => self.program[(addr - 0x2000) as usize],
+        }
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        self.write(addr, data);
+    }
 }
 
 impl CpuInterface for TestBus {}
@@ -33,9 +55,16 @@ impl TestBus {
         Self {
             ram: [0; 0x800],
             program,
+            cycles: 0,
+            nmi_cycle: None,
         }
     }
 
+    /// Raises a NMI once the bus has been clocked `cycle` times
+    pub fn set_nmi_at(&mut self, cycle: u64) {
+        self.nmi_cycle = Some(cycle);
+    }
+
     /// Sets a value at a RAM address
     pub fn set_ram(&mut self, addr: u16, data: u8) {
         self.ram[(addr & 0x7FF) as usize] = data;
@@ -1,15 +1,31 @@
-use core::panic;
-use std::fs::File;
-use std::io::{self, BufWriter};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
 use std::path::Path;
-use std::{fmt::Display, io::BufReader};
 
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use zip::result::ZipError;
+use zip::ZipArchive;
 
+use crate::ppu::frame::Region;
 use crate::savable::Savable;
-use mappers::{Mapper, Mapper0, Mapper1, Mapper10, Mapper2, Mapper3, Mapper4, Mapper7, Mapper9};
-use rom::Rom;
+use game_genie::GameGenie;
+pub use game_genie::{Code, GameGenieError};
+pub use mappers::mapper_name;
+use mappers::{
+    Mapper, Mapper0, Mapper1, Mapper10, Mapper11, Mapper2, Mapper24, Mapper3, Mapper4, Mapper5,
+    Mapper66, Mapper69, Mapper7, Mapper9,
+};
+pub use rom::ConsoleType;
+use rom::{INesHeader, Rom, CHR_PAGE_SIZE, PRG_PAGE_SIZE, TRAINER_ADDR};
 
+/// Size of the VRAM on four screen cartridges
+const FOUR_SCREEN_VRAM_SIZE: usize = 0x1000;
+/// Largest ROM extracted from a zip archive, well over the biggest iNES files
+const MAX_ROM_SIZE: usize = 16 * 1024 * 1024;
+
+mod game_genie;
 mod mappers;
 mod rom;
 
@@ -23,39 +39,281 @@ pub enum MirrorMode {
     FourScreen,
 }
 
+/// Errors while loading a cartridge
+#[derive(Debug)]
+pub enum CartridgeError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The file does not start with the iNES tag
+    BadMagic,
+    /// The header has no PRG ROM banks
+    NoPrgRom,
+    /// The file is smaller than what the header describes
+    Truncated { expected: usize, found: usize },
+    /// The mapper is not implemented
+    UnsupportedMapper(u16),
+    /// The zip archive could not be read
+    Zip(ZipError),
+    /// The zip archive has no files
+    EmptyArchive,
+    /// The zip archive has no .nes file
+    NoRomInArchive,
+    /// The .nes file of the zip archive is bigger than `MAX_ROM_SIZE`
+    RomTooLarge,
+}
+
+impl Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "{}", e),
+            CartridgeError::BadMagic => write!(f, "Not iNES file format"),
+            CartridgeError::NoPrgRom => write!(f, "The header has no PRG ROM"),
+            CartridgeError::Truncated { expected, found } => write!(
+                f,
+                "File is truncated: expected {} bytes, found {}",
+                expected, found
+            ),
+            CartridgeError::UnsupportedMapper(id) => write!(f, "Mapper {} not supported", id),
+            CartridgeError::Zip(e) => write!(f, "Bad zip archive: {}", e),
+            CartridgeError::EmptyArchive => write!(f, "Zip archive is empty"),
+            CartridgeError::NoRomInArchive => write!(f, "No .nes file in the zip archive"),
+            CartridgeError::RomTooLarge => {
+                write!(f, "The .nes file of the zip archive is too large")
+            }
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
+
+impl From<ZipError> for CartridgeError {
+    fn from(e: ZipError) -> Self {
+        CartridgeError::Zip(e)
+    }
+}
+
 pub trait RomMapper: Mapper + Savable {}
 
+/// What the header says about a cartridge, to show the ROM information
+#[derive(Debug, Clone, Copy)]
+pub struct CartridgeInfo {
+    /// Sizes in bytes. No CHR ROM means the cartridge has CHR RAM
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub chr_ram_size: usize,
+    pub mapper: u16,
+    pub mapper_name: &'static str,
+    /// NES 2.0 only
+    pub submapper: Option<u8>,
+    /// Mirroring wired on the board. Most mappers can change it
+    pub mirror_mode: MirrorMode,
+    pub battery: bool,
+    /// None for the multi-region and Dendy games
+    pub region: Option<Region>,
+    pub console_type: ConsoleType,
+    /// The header uses the NES 2.0 format
+    pub nes2: bool,
+}
+
 /// NES ROM cartridge
 pub struct Cartridge {
+    header: INesHeader,
     mapper: Box<dyn RomMapper>,
     filename: Option<String>,
+    game_genie: GameGenie,
+    /// Settings of a VS System board
+    dip_switches: u8,
+    /// Nametable memory of four screen cartridges. Empty on the others
+    vram: Vec<u8>,
+    /// Debug mirroring forced over the one of the mapper
+    mirror_override: Option<MirrorMode>,
+    /// CRC32 of the PRG and CHR ROM
+    rom_hash: u32,
+}
+
+/// CRC-32 of some bytes (polynomial 0xEDB88320)
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = match crc & 0x1 != 0 {
+                true => (crc >> 1) ^ 0xEDB88320,
+                false => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+/// Extracts the first .nes file of a zip archive
+fn unzip_rom(bytes: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    let mut archive = ZipArchive::new(io::Cursor::new(bytes))?;
+    if archive.is_empty() {
+        return Err(CartridgeError::EmptyArchive);
+    }
+
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        if file.is_file() && file.name().to_ascii_lowercase().ends_with(".nes") {
+            // The size in the archive can't be trusted, the read stops a byte after the limit
+            let mut rom = Vec::new();
+            file.take(MAX_ROM_SIZE as u64 + 1).read_to_end(&mut rom)?;
+            return match rom.len() > MAX_ROM_SIZE {
+                true => Err(CartridgeError::RomTooLarge),
+                false => Ok(rom),
+            };
+        }
+    }
+    Err(CartridgeError::NoRomInArchive)
 }
 
 impl Cartridge {
-    pub fn new<P: AsRef<Path> + Display>(romfile: P) -> io::Result<Self> {
+    /// Loads a cartridge from the content of an iNES file, or of a zip archive holding one.
+    /// The path only names the save files and tells the zip archives apart, reading the file
+    /// is up to the frontend
+    pub fn new<P: AsRef<Path>>(romfile: P, bytes: &[u8]) -> Result<Self, CartridgeError> {
+        let romfile = romfile.as_ref();
         let filename = romfile
-            .as_ref()
             .file_stem()
             .map(|name| name.to_string_lossy().to_string());
+        let is_zip = romfile
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
 
-        let rom = Rom::new(romfile)?;
-        let mapper: Box<dyn RomMapper> = match rom.header.mapper_id() {
+        let cartridge = match is_zip {
+            true => Self::from_bytes(&unzip_rom(bytes)?)?,
+            false => Self::from_bytes(bytes)?,
+        };
+        Ok(Self {
+            filename,
+            ..cartridge
+        })
+    }
+
+    /// Loads a cartridge from an iNES file already in memory
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        let rom = Rom::from_bytes(bytes)?;
+        let rom_hash = rom.hash();
+        Ok(Self {
+            header: rom.header,
+            vram: Self::new_vram(&rom.header),
+            mapper: Self::new_mapper(rom)?,
+            filename: None,
+            game_genie: GameGenie::new(),
+            dip_switches: 0,
+            mirror_override: None,
+            rom_hash,
+        })
+    }
+
+    /// Creates the mapper of the ROM
+    fn new_mapper(mut rom: Rom) -> Result<Box<dyn RomMapper>, CartridgeError> {
+        let trainer = rom.trainer.take();
+        let mut mapper: Box<dyn RomMapper> = match rom.header.mapper_number() {
             0 => Box::new(Mapper0::new(rom)),
             1 => Box::new(Mapper1::new(rom)),
             2 => Box::new(Mapper2::new(rom)),
             3 => Box::new(Mapper3::new(rom)),
             4 => Box::new(Mapper4::new(rom)),
+            5 => Box::new(Mapper5::new(rom)),
             7 => Box::new(Mapper7::new(rom)),
             9 => Box::new(Mapper9::new(rom)),
             10 => Box::new(Mapper10::new(rom)),
-            _ => panic!("Unimplemented mapper: {}", rom.header.mapper_id()),
+            11 => Box::new(Mapper11::new(rom)),
+            24 => Box::new(Mapper24::new(rom, false)),
+            26 => Box::new(Mapper24::new(rom, true)),
+            66 => Box::new(Mapper66::new(rom)),
+            69 => Box::new(Mapper69::new(rom)),
+            id => return Err(CartridgeError::UnsupportedMapper(id)),
         };
 
-        Ok(Self { mapper, filename })
+        // Copy the trainer in PRG RAM. Only works with mappers where the RAM is writable at power on
+        if let Some(trainer) = trainer {
+            for (i, &byte) in trainer.iter().enumerate() {
+                mapper.write_prg(TRAINER_ADDR + i as u16, byte);
+            }
+        }
+
+        Ok(mapper)
+    }
+
+    /// Allocates the extra nametable memory if the cartridge has it
+    fn new_vram(header: &INesHeader) -> Vec<u8> {
+        match header.four_screen() {
+            true => vec![0; FOUR_SCREEN_VRAM_SIZE],
+            false => Vec::new(),
+        }
     }
 
     pub fn read_prg(&mut self, addr: u16) -> u8 {
-        self.mapper.read_prg(addr)
+        let data = self.mapper.read_prg(addr);
+        self.game_genie.patch(addr, data)
+    }
+
+    /// Reads PRG without side effects
+    pub fn peek_prg(&mut self, addr: u16) -> u8 {
+        let data = self.mapper.peek_prg(addr);
+        self.game_genie.patch(addr, data)
+    }
+
+    /// Reads CHR without side effects
+    pub fn peek_chr(&mut self, addr: u16) -> u8 {
+        self.mapper.peek_chr(addr)
+    }
+
+    /// Mirroring and mapper registers, for debug dumps
+    pub fn snapshot(&self) -> Value {
+        json!({
+            "mirror_mode": self.mapper.mirror_mode(),
+            "mirror_override": self.mirror_override,
+            "registers": self.mapper.registers(),
+        })
+    }
+
+    /// Runs on a VS System arcade board
+    pub fn is_vs_system(&self) -> bool {
+        self.header.console_type() == ConsoleType::VsSystem
+    }
+
+    /// Which RP2C04 palette the VS System Ppu uses (0-3), if it has a scrambled one
+    ///
+    /// The RP2C03 and RC2C05 Ppus use the normal palette order
+    pub fn vs_palette(&self) -> Option<usize> {
+        match self.header.vs_ppu() {
+            // RP2C04-0001 to RP2C04-0004
+            Some(ppu @ 0x2..=0x5) => Some(ppu as usize - 2),
+            _ => None,
+        }
+    }
+
+    /// Sets the 8 DIP switches of a VS System board, switch 1 in bit 0
+    ///
+    /// Games read them on the controller ports for settings like the difficulty or the price
+    pub fn set_dip_switches(&mut self, switches: u8) {
+        self.dip_switches = switches;
+    }
+
+    /// Returns the DIP switches of a VS System board
+    pub fn dip_switches(&self) -> u8 {
+        self.dip_switches
+    }
+
+    /// Activates a Game Genie code
+    pub fn add_game_genie_code(&mut self, code: &str) -> Result<Code, GameGenieError> {
+        self.game_genie.add_code(code)
+    }
+
+    /// Deactivates all the Game Genie codes
+    pub fn clear_game_genie_codes(&mut self) {
+        self.game_genie.clear();
     }
 
     pub fn write_prg(&mut self, addr: u16, data: u8) {
@@ -70,10 +328,54 @@ impl Cartridge {
         self.mapper.write_chr(addr, data);
     }
 
+    /// Mirroring set by the mapper. Doesn't include the debug override
     pub fn mirror_mode(&self) -> MirrorMode {
         self.mapper.mirror_mode()
     }
 
+    /// Debug mirroring used instead of the one of the mapper, if any
+    pub fn mirror_override(&self) -> Option<MirrorMode> {
+        self.mirror_override
+    }
+
+    /// Forces a mirroring mode, to check if a glitch comes from the mirroring. `None` gives
+    /// the control back to the mapper
+    pub fn set_mirror_override(&mut self, mode: Option<MirrorMode>) {
+        // Four screen needs the extra nametables, even on cartridges without them
+        if let Some(MirrorMode::FourScreen) = mode {
+            if self.vram.is_empty() {
+                self.vram = vec![0; FOUR_SCREEN_VRAM_SIZE];
+            }
+        }
+        self.mirror_override = mode;
+    }
+
+    /// Has the extra nametables used in four screen mode
+    pub fn has_nametables(&self) -> bool {
+        !self.vram.is_empty()
+    }
+
+    /// Reads a byte of the nametables in four screen mode. Reads 0 if the cartridge doesn't
+    /// have the extra nametables
+    pub fn read_nametable(&self, addr: u16) -> u8 {
+        if self.vram.is_empty() {
+            return 0;
+        }
+        let index = self.mapper.map_nametable(addr);
+        self.vram[index % self.vram.len()]
+    }
+
+    /// Writes a byte of the nametables in four screen mode. Does nothing if the cartridge
+    /// doesn't have the extra nametables
+    pub fn write_nametable(&mut self, addr: u16, data: u8) {
+        if self.vram.is_empty() {
+            return;
+        }
+        let index = self.mapper.map_nametable(addr) % self.vram.len();
+        self.vram[index] = data;
+    }
+
+    /// Puts the mapper in its power on state. Not done by the reset button
     pub fn reset(&mut self) {
         self.mapper.reset();
     }
@@ -82,10 +384,62 @@ impl Cartridge {
         self.mapper.inc_scanline();
     }
 
+    /// CHR banks mapped in the pattern tables, in 1KB units, if the mapper reports them
+    pub fn chr_bank_layout(&self) -> Option<[usize; 8]> {
+        self.mapper.chr_bank_layout()
+    }
+
     pub fn poll_irq(&mut self) -> bool {
         self.mapper.poll_irq()
     }
 
+    pub fn cpu_tick(&mut self, cycles: u64) {
+        self.mapper.cpu_tick(cycles);
+    }
+
+    /// Clocks the expansion audio once. Returns 0 for the cartridges without it
+    pub fn clock_audio(&mut self) -> f32 {
+        self.mapper.clock_audio()
+    }
+
+    /// Enables or disables the logging of the mapper bank switches
+    pub fn set_mapper_logging(&mut self, enabled: bool) {
+        if let Some(logger) = self.mapper.logger() {
+            logger.set_enabled(enabled);
+        }
+    }
+
+    /// Sets where the mapper bank switches are logged. Stdout by default
+    pub fn set_mapper_log_sink<F: FnMut(&str) + 'static>(&mut self, sink: F) {
+        if let Some(logger) = self.mapper.logger() {
+            logger.set_sink(sink);
+        }
+    }
+
+    /// CRC32 of the PRG and CHR ROM, as listed by the ROM databases. Identifies the dump
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    /// Sizes, mapper, mirroring and other information from the header
+    pub fn info(&self) -> CartridgeInfo {
+        let header = &self.header;
+        CartridgeInfo {
+            prg_rom_size: header.prg_count() * PRG_PAGE_SIZE,
+            chr_rom_size: header.chr_count() * CHR_PAGE_SIZE,
+            prg_ram_size: header.prg_ram_size(),
+            chr_ram_size: header.chr_ram_size(),
+            mapper: header.mapper_number(),
+            mapper_name: mapper_name(header.mapper_number()),
+            submapper: header.submapper(),
+            mirror_mode: header.mirror_mode(),
+            battery: header.has_battery(),
+            region: header.region(),
+            console_type: header.console_type(),
+            nes2: header.is_nes2(),
+        }
+    }
+
     pub fn filename(&self) -> String {
         match self.filename {
             Some(ref name) => name.clone(),
@@ -93,11 +447,283 @@ impl Cartridge {
         }
     }
 
-    pub fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
-        self.mapper.save(output)
+    pub fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        self.mapper.save(output)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.vram)?;
+        Ok(())
+    }
+
+    pub fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        self.mapper.load(input)?;
+        self.vram = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        // The state may come from before four screen was forced
+        self.set_mirror_override(self.mirror_override);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ines(mapper: u8, prg_count: u8, size: usize) -> Vec<u8> {
+        let mut bytes = vec![0; size];
+        bytes[..8].copy_from_slice(&[
+            b'N',
+            b'E',
+            b'S',
+            0x1A,
+            prg_count,
+            1,
+            mapper << 4,
+            mapper & 0xF0,
+        ]);
+        bytes
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let size = 16 + 0x4000 + 0x2000;
+        assert!(Cartridge::from_bytes(&ines(0, 1, size)).is_ok());
+
+        let mut bad_magic = ines(0, 1, size);
+        bad_magic[3] = 0;
+        assert!(matches!(
+            Cartridge::from_bytes(&bad_magic),
+            Err(CartridgeError::BadMagic)
+        ));
+
+        assert!(matches!(
+            Cartridge::from_bytes(&ines(0, 0, size)),
+            Err(CartridgeError::NoPrgRom)
+        ));
+
+        assert!(matches!(
+            Cartridge::from_bytes(&ines(0, 2, size)),
+            Err(CartridgeError::Truncated { expected, found }) if expected == size + 0x4000 && found == size
+        ));
+        assert!(matches!(
+            Cartridge::from_bytes(b"NES"),
+            Err(CartridgeError::Truncated {
+                expected: 16,
+                found: 3
+            })
+        ));
+
+        let unsupported = Cartridge::from_bytes(&ines(210, 1, size));
+        assert!(matches!(
+            unsupported,
+            Err(CartridgeError::UnsupportedMapper(210))
+        ));
+        assert_eq!(
+            unsupported.err().unwrap().to_string(),
+            "Mapper 210 not supported"
+        );
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        for mapper in [0, 1, 2, 3, 4, 5, 7, 9, 10, 11, 24, 66, 69] {
+            // Writes to CHR ROM are ignored
+            let mut cartridge =
+                Cartridge::from_bytes(&ines(mapper, 2, 16 + 0x8000 + 0x2000)).unwrap();
+            let rom = cartridge.read_chr(0x0010);
+            cartridge.write_chr(0x0010, !rom);
+            assert_eq!(cartridge.read_chr(0x0010), rom, "mapper {}", mapper);
+
+            // and go through on CHR RAM
+            let mut bytes = ines(mapper, 2, 16 + 0x8000);
+            bytes[5] = 0;
+            let mut cartridge = Cartridge::from_bytes(&bytes).unwrap();
+            cartridge.write_chr(0x0010, 0xAB);
+            assert_eq!(cartridge.read_chr(0x0010), 0xAB, "mapper {}", mapper);
+        }
+    }
+
+    #[test]
+    fn test_info() {
+        // MMC1 with CHR RAM and a battery
+        let mut bytes = ines(1, 2, 16 + 0x8000);
+        bytes[5] = 0;
+        bytes[6] |= 0x3;
+        let info = Cartridge::from_bytes(&bytes).unwrap().info();
+        assert_eq!(
+            (info.prg_rom_size, info.chr_rom_size, info.chr_ram_size),
+            (0x8000, 0, 0x2000)
+        );
+        assert_eq!((info.mapper, info.mapper_name), (1, "MMC1"));
+        assert!(matches!(info.mirror_mode, MirrorMode::Vertical));
+        assert!(info.battery && !info.nes2);
+        assert_eq!((info.submapper, info.region), (None, Some(Region::Ntsc)));
+
+        // NES 2.0: submapper, RAM sizes and region
+        let mut bytes = ines(4, 2, 16 + 0x8000 + 0x2000);
+        bytes[7] |= 0x08;
+        bytes[8] = 0x10;
+        bytes[10] = 0x70;
+        bytes[12] = 0x01;
+        let info = Cartridge::from_bytes(&bytes).unwrap().info();
+        assert_eq!((info.mapper, info.submapper), (4, Some(1)));
+        assert_eq!((info.prg_ram_size, info.chr_ram_size), (0x2000, 0));
+        assert_eq!(info.region, Some(Region::Pal));
+        assert_eq!(info.console_type, ConsoleType::Nes);
+
+        // The NES 2.0 mapper numbers go above 255
+        bytes[8] = 0x01;
+        assert!(matches!(
+            Cartridge::from_bytes(&bytes),
+            Err(CartridgeError::UnsupportedMapper(260))
+        ));
+        assert_eq!(mapper_name(260), "Unknown");
+    }
+
+    #[test]
+    fn test_rom_hash() {
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+
+        // Only the PRG and CHR ROM are hashed
+        let size = 16 + 0x4000 + 0x2000;
+        let mut bytes = ines(0, 1, size);
+        bytes[16] = 0x4C;
+        let hash = Cartridge::from_bytes(&bytes).unwrap().rom_hash();
+        assert_eq!(hash, crc32(&bytes[16..]));
+        bytes[9] = 1;
+        bytes.push(0xFF);
+        assert_eq!(Cartridge::from_bytes(&bytes).unwrap().rom_hash(), hash);
+
+        // CHR RAM is not part of the ROM
+        let mut bytes = ines(0, 1, 16 + 0x4000);
+        bytes[5] = 0;
+        let hash = Cartridge::from_bytes(&bytes).unwrap().rom_hash();
+        assert_eq!(hash, crc32(&bytes[16..]));
     }
 
-    pub fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
-        self.mapper.load(input)
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, bytes) in files {
+            let options = zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated);
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(bytes).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_zip() {
+        let mut rom = ines(0, 1, 16 + 0x4000 + 0x2000);
+        rom[16] = 0x4C;
+        let hash = Cartridge::from_bytes(&rom).unwrap().rom_hash();
+
+        // The first .nes file is loaded
+        let archive = zip(&[("readme.txt", b"hello"), ("Game.NES", &rom), ("b.nes", &[])]);
+        let cartridge = Cartridge::new("roms/game.zip", &archive).unwrap();
+        assert_eq!(cartridge.rom_hash(), hash);
+        assert_eq!(cartridge.filename(), "game");
+
+        // Only the .zip files are extracted
+        assert!(matches!(
+            Cartridge::new("game.nes", &archive),
+            Err(CartridgeError::BadMagic)
+        ));
+        assert!(matches!(
+            Cartridge::new("game.zip", &zip(&[])),
+            Err(CartridgeError::EmptyArchive)
+        ));
+        assert!(matches!(
+            Cartridge::new("game.zip", &zip(&[("readme.txt", b"hello")])),
+            Err(CartridgeError::NoRomInArchive)
+        ));
+        assert!(matches!(
+            Cartridge::new("game.zip", &rom),
+            Err(CartridgeError::Zip(_))
+        ));
+        let huge = vec![0; MAX_ROM_SIZE + 1];
+        assert!(matches!(
+            Cartridge::new("game.zip", &zip(&[("huge.nes", &huge)])),
+            Err(CartridgeError::RomTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_four_screen() {
+        let mut bytes = ines(0, 1, 16 + 0x4000 + 0x2000);
+        bytes[6] |= 0x8;
+
+        let mut cartridge = Cartridge::from_bytes(&bytes).unwrap();
+        assert!(matches!(cartridge.mirror_mode(), MirrorMode::FourScreen));
+        for nta in 0..4 {
+            cartridge.write_nametable(0x2000 + nta * 0x400, nta as u8 + 1);
+        }
+        for nta in 0..4 {
+            assert_eq!(
+                cartridge.read_nametable(0x2000 + nta * 0x400),
+                nta as u8 + 1
+            );
+        }
+        // 0x3000-0x3EFF mirrors 0x2000-0x2EFF
+        assert_eq!(cartridge.read_nametable(0x3800), 3);
+
+        // Without the extra nametables, nothing is stored
+        let mut cartridge = Cartridge::from_bytes(&ines(0, 1, 16 + 0x4000 + 0x2000)).unwrap();
+        cartridge.write_nametable(0x2C00, 0x42);
+        assert_eq!(cartridge.read_nametable(0x2C00), 0);
+    }
+
+    #[test]
+    fn test_mirror_override() {
+        let mut cartridge = Cartridge::from_bytes(&ines(0, 1, 16 + 0x4000 + 0x2000)).unwrap();
+        assert!(matches!(cartridge.mirror_mode(), MirrorMode::Horizontal));
+        assert!(cartridge.mirror_override().is_none());
+
+        cartridge.set_mirror_override(Some(MirrorMode::OneScreenHi));
+        assert!(matches!(
+            cartridge.mirror_override(),
+            Some(MirrorMode::OneScreenHi)
+        ));
+        // The mapper still reports its own mirroring
+        assert!(matches!(cartridge.mirror_mode(), MirrorMode::Horizontal));
+
+        // Forcing four screen adds the missing nametables
+        cartridge.set_mirror_override(Some(MirrorMode::FourScreen));
+        cartridge.write_nametable(0x2C00, 0x42);
+        assert_eq!(cartridge.read_nametable(0x2C00), 0x42);
+
+        cartridge.set_mirror_override(None);
+        assert!(cartridge.mirror_override().is_none());
+    }
+
+    #[test]
+    fn test_mirror_override_state() {
+        let mut cartridge = Cartridge::from_bytes(&ines(0, 1, 16 + 0x4000 + 0x2000)).unwrap();
+        let mut state = vec![];
+        cartridge.save(&mut state).unwrap();
+
+        // A state without the extra nametables keeps them when four screen is forced
+        cartridge.set_mirror_override(Some(MirrorMode::FourScreen));
+        cartridge.load(&mut state.as_slice()).unwrap();
+        cartridge.write_nametable(0x2C00, 0x42);
+        assert_eq!(cartridge.read_nametable(0x2C00), 0x42);
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut bytes = ines(0, 1, 16 + 512 + 0x4000 + 0x2000);
+        bytes[6] |= 0x4;
+        for (i, byte) in bytes[16..16 + 512].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        bytes[16 + 512] = 0x42;
+        bytes[16 + 512 + 0x3FFF] = 0x43;
+
+        let mut cartridge = Cartridge::from_bytes(&bytes).unwrap();
+        assert_eq!(cartridge.read_prg(0x7000), 0x00);
+        assert_eq!(cartridge.read_prg(0x71FF), 0xFF);
+        assert_eq!(cartridge.read_prg(0x7200), 0x00);
+
+        // The PRG ROM starts after the trainer
+        assert_eq!(cartridge.read_prg(0x8000), 0x42);
+        assert_eq!(cartridge.read_prg(0xBFFF), 0x43);
     }
 }
@@ -0,0 +1,180 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+
+/// Letters of the Game Genie codes, in the order of the values they encode
+const LETTERS: [char; 16] = [
+    'A', 'P', 'Z', 'L', 'G', 'I', 'T', 'Y', 'E', 'O', 'X', 'U', 'K', 'S', 'V', 'N',
+];
+
+/// Errors while decoding a Game Genie code
+#[derive(Debug, PartialEq)]
+pub enum GameGenieError {
+    /// Codes are 6 or 8 letters long
+    BadLength(usize),
+    /// The letter is not part of the Game Genie alphabet
+    BadLetter(char),
+}
+
+impl Display for GameGenieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameGenieError::BadLength(len) => {
+                write!(f, "Code must be 6 or 8 letters long, found {}", len)
+            }
+            GameGenieError::BadLetter(c) => write!(f, "Letter {} is not a Game Genie letter", c),
+        }
+    }
+}
+
+impl Error for GameGenieError {}
+
+/// A decoded Game Genie code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Code {
+    /// Patched address (0x8000-0xFFFF)
+    pub addr: u16,
+    /// Value read instead of the ROM byte
+    pub value: u8,
+    /// 8 letters codes only patch the address when the ROM byte has this value
+    pub compare: Option<u8>,
+}
+
+impl Code {
+    /// Decodes a 6 or 8 letters code
+    pub fn parse(code: &str) -> Result<Self, GameGenieError> {
+        let n = code
+            .trim()
+            .chars()
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                match LETTERS.iter().position(|&l| l == c) {
+                    Some(value) => Ok(value as u16),
+                    None => Err(GameGenieError::BadLetter(c)),
+                }
+            })
+            .collect::<Result<Vec<u16>, _>>()?;
+
+        if n.len() != 6 && n.len() != 8 {
+            return Err(GameGenieError::BadLength(n.len()));
+        }
+
+        // The bits of the address and value are scrambled between the letters
+        // http://wiki.nesdev.com/w/index.php/Game_Genie
+        let addr = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+
+        let code = match n.len() {
+            6 => Self {
+                addr,
+                value: (value | (n[5] & 8)) as u8,
+                compare: None,
+            },
+            _ => Self {
+                addr,
+                value: (value | (n[7] & 8)) as u8,
+                compare: Some(
+                    (((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8)) as u8,
+                ),
+            },
+        };
+
+        Ok(code)
+    }
+}
+
+/// Patches the PRG ROM reads with Game Genie codes
+pub struct GameGenie {
+    codes: Vec<Code>,
+}
+
+impl GameGenie {
+    pub fn new() -> Self {
+        Self { codes: Vec::new() }
+    }
+
+    /// Decodes and activates a code
+    pub fn add_code(&mut self, code: &str) -> Result<Code, GameGenieError> {
+        let code = Code::parse(code)?;
+        self.codes.push(code);
+        Ok(code)
+    }
+
+    /// Deactivates all the codes
+    pub fn clear(&mut self) {
+        self.codes.clear();
+    }
+
+    /// Returns the value of a PRG read at `addr` once the codes are applied
+    pub fn patch(&self, addr: u16, data: u8) -> u8 {
+        // Nothing to do most of the time
+        if self.codes.is_empty() {
+            return data;
+        }
+
+        self.codes
+            .iter()
+            .find(|code| code.addr == addr && code.compare.unwrap_or(data) == data)
+            .map_or(data, |code| code.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // Super Mario Bros. infinite lives
+        assert_eq!(
+            Code::parse("SXIOPO"),
+            Ok(Code {
+                addr: 0x91D9,
+                value: 0xAD,
+                compare: None
+            })
+        );
+        assert_eq!(
+            Code::parse("gossip"),
+            Ok(Code {
+                addr: 0xD1DD,
+                value: 0x14,
+                compare: None
+            })
+        );
+        assert_eq!(
+            Code::parse("ZEXPYGLA"),
+            Ok(Code {
+                addr: 0x94A7,
+                value: 0x02,
+                compare: Some(0x03)
+            })
+        );
+
+        assert_eq!(Code::parse("SXIOP"), Err(GameGenieError::BadLength(5)));
+        assert_eq!(Code::parse("SXIOPB"), Err(GameGenieError::BadLetter('B')));
+    }
+
+    #[test]
+    fn test_patch() {
+        let mut gg = GameGenie::new();
+        gg.add_code("SXIOPO").unwrap();
+        gg.add_code("ZEXPYGLA").unwrap();
+
+        assert_eq!(gg.patch(0x91D9, 0xCE), 0xAD);
+        assert_eq!(gg.patch(0x91DA, 0xCE), 0xCE);
+
+        // The compare value has to match
+        assert_eq!(gg.patch(0x94A7, 0x03), 0x02);
+        assert_eq!(gg.patch(0x94A7, 0x04), 0x04);
+
+        gg.clear();
+        assert_eq!(gg.patch(0x91D9, 0xCE), 0xCE);
+    }
+}
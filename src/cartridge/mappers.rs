@@ -1,23 +1,66 @@
 pub use mapper0::Mapper0;
 pub use mapper1::Mapper1;
 pub use mapper10::Mapper10;
+pub use mapper11::Mapper11;
 pub use mapper2::Mapper2;
+pub use mapper24::Mapper24;
 pub use mapper3::Mapper3;
 pub use mapper4::Mapper4;
+pub use mapper5::Mapper5;
+pub use mapper66::Mapper66;
+pub use mapper69::Mapper69;
 pub use mapper7::Mapper7;
 pub use mapper9::Mapper9;
 
+use std::fmt::Arguments;
+
+use serde_json::Value;
+
+use super::rom::{Rom, CHR_PAGE_SIZE};
 use super::MirrorMode;
 
 mod mapper0;
 mod mapper1;
 mod mapper10;
+mod mapper11;
 mod mapper2;
+mod mapper24;
 mod mapper3;
 mod mapper4;
+mod mapper5;
+mod mapper66;
+mod mapper69;
 mod mapper7;
 mod mapper9;
 
+/// Common name of the board of a mapper number
+pub fn mapper_name(id: u16) -> &'static str {
+    match id {
+        0 => "NROM",
+        1 => "MMC1",
+        2 => "UxROM",
+        3 => "CNROM",
+        4 => "MMC3",
+        5 => "MMC5",
+        7 => "AxROM",
+        9 => "MMC2",
+        10 => "MMC4",
+        11 => "Color Dreams",
+        13 => "CPROM",
+        19 => "Namco 163",
+        21 | 23 | 25 => "VRC2/VRC4",
+        22 => "VRC2",
+        24 | 26 => "VRC6",
+        34 => "BNROM/NINA-001",
+        66 => "GxROM",
+        69 => "Sunsoft FME-7",
+        71 => "Camerica",
+        85 => "VRC7",
+        206 => "Namco 118",
+        _ => "Unknown",
+    }
+}
+
 /// Cartridge ROM Mapping
 pub trait Mapper {
     /// Reads a byte from PRG ROM
@@ -32,10 +75,46 @@ pub trait Mapper {
     /// Writes a byte to CHR ROM
     fn write_chr(&mut self, addr: u16, data: u8);
 
+    /// Reads a byte from PRG without side effects, for debuggers
+    ///
+    /// Only mappers with registers that change on reads need to override this
+    fn peek_prg(&mut self, addr: u16) -> u8 {
+        self.read_prg(addr)
+    }
+
+    /// Reads a byte from CHR without side effects, for debuggers
+    ///
+    /// Only mappers with latches that change on reads need to override this
+    fn peek_chr(&mut self, addr: u16) -> u8 {
+        self.read_chr(addr)
+    }
+
     /// Returns the current mirroring mode
     fn mirror_mode(&self) -> MirrorMode;
 
-    /// Resets the mapper
+    /// Returns the index in the cartridge VRAM of a nametable address, in four screen mode
+    ///
+    /// The 4 nametables are mapped in order by default. Mappers that bank the VRAM override this
+    fn map_nametable(&self, addr: u16) -> usize {
+        (addr & 0x0FFF) as usize
+    }
+
+    /// Returns the registers of the mapper, for debug dumps
+    fn registers(&self) -> Value {
+        Value::Null
+    }
+
+    /// Returns the 1KB CHR bank mapped at each 0x400 bytes of the pattern tables, for debuggers
+    ///
+    /// Only the mappers switching CHR banks mid-frame report it
+    fn chr_bank_layout(&self) -> Option<[usize; 8]> {
+        None
+    }
+
+    /// Puts the mapper in its power on state, with the reset vector in the last PRG bank
+    ///
+    /// The reset button doesn't reach the cartridge, so this is only done on power on. The
+    /// registers and the banks stay as they are on a soft reset
     fn reset(&mut self);
 
     /// Tells the mapper a new scanline was rendered
@@ -49,4 +128,87 @@ pub trait Mapper {
     fn poll_irq(&mut self) -> bool {
         false
     }
+
+    /// Tells the mapper how many Cpu cycles passed
+    ///
+    /// Used by Mapper1 to ignore writes on consecutive cycles and by Mapper24 and Mapper69 for
+    /// their IRQ counter
+    fn cpu_tick(&mut self, _cycles: u64) {}
+
+    /// Clocks the expansion audio of the cartridge once and returns its output, mixed with
+    /// the Apu. Called every Apu clock
+    ///
+    /// Only Mapper24 (VRC6) has expansion audio for now
+    fn clock_audio(&mut self) -> f32 {
+        0.0
+    }
+
+    /// Returns the bank switching logger of the mapper
+    ///
+    /// Mappers without bank registers don't have one
+    fn logger(&mut self) -> Option<&mut BankLogger> {
+        None
+    }
+}
+
+/// Reads from a switchable 32KB PRG bank
+///
+/// Used by the discrete logic mappers
+fn read_prg_32k(rom: &Rom, bank: usize, addr: u16) -> u8 {
+    let index = bank * 0x8000 + (addr & 0x7FFF) as usize;
+    rom.prg[index % rom.prg.len()]
+}
+
+/// Reads from a switchable 8KB CHR bank
+///
+/// Used by the discrete logic mappers
+fn read_chr_8k(rom: &Rom, bank: usize, addr: u16) -> u8 {
+    if rom.chr_is_ram() {
+        return rom.read_chr(addr as usize);
+    }
+
+    let index = bank * CHR_PAGE_SIZE + addr as usize;
+    rom.read_chr(index)
+}
+
+/// Logs the bank register writes of a mapper
+///
+/// Used to debug mappers. Disabled by default
+pub struct BankLogger {
+    id: u8,
+    enabled: bool,
+    sink: Box<dyn FnMut(&str)>,
+}
+
+impl BankLogger {
+    pub fn new(id: u8) -> Self {
+        Self {
+            id,
+            enabled: false,
+            sink: Box::new(|line| println!("{}", line)),
+        }
+    }
+
+    /// Enables or disables the logging
+    pub fn set_enabled(&mut self, v: bool) {
+        self.enabled = v;
+    }
+
+    /// Replaces the output of the logger (stdout by default)
+    pub fn set_sink<F: FnMut(&str) + 'static>(&mut self, sink: F) {
+        self.sink = Box::new(sink);
+    }
+
+    /// Logs a register write and the resulting bank offsets
+    ///
+    /// The message is only formatted if the logging is enabled
+    pub fn log(&mut self, addr: u16, data: u8, banks: Arguments) {
+        if self.enabled {
+            let line = format!(
+                "[Mapper {}] ${:04X} <- ${:02X} | {}",
+                self.id, addr, data, banks
+            );
+            (self.sink)(&line);
+        }
+    }
 }
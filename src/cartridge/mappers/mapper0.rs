@@ -1,5 +1,4 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{Read, Write};
 
 use super::Mapper;
 use crate::cartridge::{MirrorMode, Rom, RomMapper};
@@ -22,18 +21,18 @@ impl Mapper0 {
 impl RomMapper for Mapper0 {}
 
 impl Savable for Mapper0 {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
         self.rom.save(output)?;
         for i in 0..0x2000 {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.ram[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram[i])?;
         }
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
         self.rom.load(input)?;
         for i in 0..0x2000 {
-            self.ram[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+            self.ram[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         }
         Ok(())
     }
@@ -60,13 +59,11 @@ impl Mapper for Mapper0 {
     }
 
     fn read_chr(&mut self, addr: u16) -> u8 {
-        self.rom.chr[addr as usize]
+        self.rom.read_chr(addr as usize)
     }
 
     fn write_chr(&mut self, addr: u16, data: u8) {
-        if self.rom.header.chr_count() == 0 {
-            self.rom.chr[addr as usize] = data;
-        }
+        self.rom.write_chr(addr as usize, data);
     }
 
     fn mirror_mode(&self) -> MirrorMode {
@@ -1,7 +1,8 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{Read, Write};
 
-use super::Mapper;
+use serde_json::{json, Value};
+
+use super::{BankLogger, Mapper};
 use crate::cartridge::{MirrorMode, Rom, RomMapper};
 use crate::savable::Savable;
 
@@ -14,6 +15,11 @@ pub struct Mapper1 {
     prg_lo: u8,
     prg_hi: u8,
     prg_32k: u8,
+    /// PRG bank register. The banks above depend on it and on the PRG mode
+    prg_bank: u8,
+    /// First CHR bank register. Also selects the 256KB PRG page and the PRG RAM bank on
+    /// the boards with more memory (SUROM, SOROM and SXROM)
+    chr_bank: u8,
 
     control: u8,
     count: u8,
@@ -21,66 +27,133 @@ pub struct Mapper1 {
 
     ram: Vec<u8>,
     mirror_mode: MirrorMode,
+
+    cycles: u64,
+    last_write: Option<u64>,
+
+    logger: BankLogger,
 }
 
 impl Mapper1 {
     pub fn new(rom: Rom) -> Self {
-        let prg_hi = (rom.header.prg_count() - 1) as u8;
-        Self {
+        // 8KB, 16KB (SOROM) or 32KB (SXROM)
+        let ram_size = match rom.header.prg_ram_size() {
+            size if !rom.chr_is_ram() || size < 0x4000 => 0x2000,
+            size if size < 0x8000 => 0x4000,
+            _ => 0x8000,
+        };
+
+        let mut mapper = Self {
             rom,
 
             chr_lo: 0,
             chr_hi: 0,
             chr_8k: 0,
             prg_lo: 0,
-            prg_hi,
+            prg_hi: 0,
             prg_32k: 0,
+            prg_bank: 0,
+            chr_bank: 0,
 
             control: 0x0C,
             count: 0,
             load: 0,
 
-            ram: vec![0; 0x2000],
+            ram: vec![0; ram_size],
             mirror_mode: MirrorMode::Vertical,
+
+            cycles: 0,
+            last_write: None,
+
+            logger: BankLogger::new(1),
+        };
+        mapper.update_prg_banks();
+        mapper
+    }
+}
+
+impl Mapper1 {
+    /// Maps the PRG banks from the bank register and the PRG mode
+    ///
+    /// Done on every write to either of them. Switching to a mode with the last bank fixed at
+    /// 0xC000 brings it back right away, so the reset vector stays reachable
+    fn update_prg_banks(&mut self) {
+        // 512KB boards (SUROM, SXROM) switch between two 256KB pages with bit 4 of the CHR
+        // bank, the bank register and the fixed bank stay in the page
+        let page = match self.rom.header.prg_count() > 16 {
+            true => self.chr_bank & 0x10,
+            false => 0,
+        };
+        let last = (self.rom.header.prg_count().min(16) - 1) as u8;
+
+        match (self.control >> 2) & 0x3 {
+            0 | 1 => self.prg_32k = (page | (self.prg_bank & 0xE)) >> 1,
+            2 => {
+                self.prg_lo = page;
+                self.prg_hi = page | (self.prg_bank & 0xF);
+            }
+            _ => {
+                self.prg_lo = page | (self.prg_bank & 0xF);
+                self.prg_hi = page | last;
+            }
         }
     }
+
+    /// Index of a PRG RAM address. The bigger RAMs are switched in 8KB banks with bits 2-3 of
+    /// the CHR bank
+    fn ram_index(&self, addr: u16) -> usize {
+        let bank = match self.ram.len() {
+            0x8000 => (self.chr_bank >> 2) & 0x3,
+            0x4000 => (self.chr_bank >> 3) & 0x1,
+            _ => 0,
+        };
+        bank as usize * 0x2000 + (addr & 0x1FFF) as usize
+    }
 }
 
 impl RomMapper for Mapper1 {}
 
 impl Savable for Mapper1 {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
         self.rom.save(output)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.chr_lo)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.chr_hi)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.chr_8k)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.prg_lo)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.prg_hi)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.prg_32k)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.control)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.count)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.load)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.mirror_mode)?;
-        for i in 0..0x2000 {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.ram[i])?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_lo)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_hi)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_8k)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_lo)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_hi)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_32k)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_bank)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_bank)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.control)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.count)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.load)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.mirror_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.cycles)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.last_write)?;
+        for i in 0..self.ram.len() {
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram[i])?;
         }
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
         self.rom.load(input)?;
-        self.chr_lo = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.chr_hi = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.chr_8k = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.prg_lo = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.prg_hi = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.prg_32k = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.control = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.count = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.load = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.mirror_mode = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        for i in 0..0x2000 {
-            self.ram[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        self.chr_lo = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_hi = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_8k = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_lo = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_hi = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_32k = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_bank = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_bank = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.control = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.count = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.load = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.mirror_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.cycles = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.last_write = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        for i in 0..self.ram.len() {
+            self.ram[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         }
         Ok(())
     }
@@ -89,7 +162,7 @@ impl Savable for Mapper1 {
 impl Mapper for Mapper1 {
     fn read_prg(&mut self, addr: u16) -> u8 {
         match addr {
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize],
+            0x6000..=0x7FFF => self.ram[self.ram_index(addr)],
             0x8000..=0xFFFF => {
                 let prg_16k_mode = self.control & 0x8 != 0;
 
@@ -109,61 +182,84 @@ impl Mapper for Mapper1 {
 
     fn write_prg(&mut self, addr: u16, data: u8) {
         match addr {
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize] = data,
-            0x8000..=0xFFFF => match data & 0x80 != 0 {
-                true => {
-                    self.control |= 0x0C;
-                    self.count = 0;
-                    self.load = 0;
+            0x6000..=0x7FFF => {
+                let index = self.ram_index(addr);
+                self.ram[index] = data;
+            }
+            0x8000..=0xFFFF => {
+                // The MMC1 ignores a write on the cycle right after another one.
+                // RMW instructions write twice in a row and only the first write counts
+                if let Some(cycle) = self.last_write {
+                    if self.cycles.wrapping_sub(cycle) <= 1 {
+                        return;
+                    }
                 }
-                false => {
-                    self.load |= (data & 0x1) << self.count;
-                    self.count += 1;
-
-                    if self.count == 5 {
-                        let target = (addr >> 13) & 0x3;
-                        let chr_4k_mode = self.control & 0x10 != 0;
-                        match target {
-                            0 => {
-                                self.control = self.load & 0x1F;
-                                self.mirror_mode = match self.control & 0x3 {
-                                    0 => MirrorMode::OneScreenLo,
-                                    1 => MirrorMode::OneScreenHi,
-                                    2 => MirrorMode::Vertical,
-                                    _ => MirrorMode::Horizontal,
-                                };
-                            }
-                            1 => match chr_4k_mode {
-                                true => self.chr_lo = self.load & 0x1F,
-                                false => self.chr_8k = (self.load & 0x1E) >> 1,
-                            },
-                            2 => {
-                                if chr_4k_mode {
-                                    self.chr_hi = self.load & 0x1F;
+                self.last_write = Some(self.cycles);
+
+                match data & 0x80 != 0 {
+                    true => {
+                        self.control |= 0x0C;
+                        self.update_prg_banks();
+                        self.count = 0;
+                        self.load = 0;
+                    }
+                    false => {
+                        self.load |= (data & 0x1) << self.count;
+                        self.count += 1;
+
+                        if self.count == 5 {
+                            let target = (addr >> 13) & 0x3;
+                            let chr_4k_mode = self.control & 0x10 != 0;
+                            match target {
+                                0 => {
+                                    self.control = self.load & 0x1F;
+                                    self.mirror_mode = match self.control & 0x3 {
+                                        0 => MirrorMode::OneScreenLo,
+                                        1 => MirrorMode::OneScreenHi,
+                                        2 => MirrorMode::Vertical,
+                                        _ => MirrorMode::Horizontal,
+                                    };
+                                    self.update_prg_banks();
                                 }
-                            }
-                            _ => {
-                                let prg_mode = (self.control >> 2) & 0x3;
-
-                                match prg_mode {
-                                    0 | 1 => self.prg_32k = (self.load & 0xE) >> 1,
-                                    2 => {
-                                        self.prg_lo = 0;
-                                        self.prg_hi = self.load & 0xF;
+                                1 => {
+                                    match chr_4k_mode {
+                                        true => self.chr_lo = self.load & 0x1F,
+                                        false => self.chr_8k = (self.load & 0x1E) >> 1,
                                     }
-                                    _ => {
-                                        self.prg_lo = self.load & 0xF;
-                                        self.prg_hi = (self.rom.header.prg_count() - 1) as u8;
+                                    self.chr_bank = self.load & 0x1F;
+                                    self.update_prg_banks();
+                                }
+                                2 => {
+                                    if chr_4k_mode {
+                                        self.chr_hi = self.load & 0x1F;
                                     }
                                 }
+                                _ => {
+                                    self.prg_bank = self.load & 0xF;
+                                    self.update_prg_banks();
+                                }
                             }
-                        }
 
-                        self.count = 0;
-                        self.load = 0;
+                            self.logger.log(
+                                addr,
+                                self.load,
+                                format_args!(
+                                    "PRG: lo {} hi {} 32k {} CHR: lo {} hi {} 8k {}",
+                                    self.prg_lo,
+                                    self.prg_hi,
+                                    self.prg_32k,
+                                    self.chr_lo,
+                                    self.chr_hi,
+                                    self.chr_8k
+                                ),
+                            );
+
+                            self.count = 0;
+                            self.load = 0;
+                        }
                     }
                 }
-            },
+            }
             _ => {}
         }
     }
@@ -171,8 +267,8 @@ impl Mapper for Mapper1 {
     fn read_chr(&mut self, addr: u16) -> u8 {
         let chr_4k_mode = self.control & 0x10 != 0;
 
-        if self.rom.header.chr_count() == 0 {
-            return self.rom.chr[addr as usize];
+        if self.rom.chr_is_ram() {
+            return self.rom.read_chr(addr as usize);
         }
 
         let index = match chr_4k_mode {
@@ -183,24 +279,176 @@ impl Mapper for Mapper1 {
             },
             false => self.chr_8k as usize * 0x2000 + (addr & 0x1FFF) as usize,
         };
-        self.rom.chr[index]
+        self.rom.read_chr(index)
     }
 
     fn write_chr(&mut self, addr: u16, data: u8) {
-        if self.rom.header.chr_count() == 0 {
-            self.rom.chr[addr as usize] = data;
-        }
+        self.rom.write_chr(addr as usize, data);
     }
 
     fn mirror_mode(&self) -> MirrorMode {
         self.mirror_mode
     }
 
+    fn registers(&self) -> Value {
+        json!({
+            "control": self.control,
+            "shift_count": self.count,
+            "shift": self.load,
+            "chr_lo": self.chr_lo,
+            "chr_hi": self.chr_hi,
+            "chr_8k": self.chr_8k,
+            "prg_lo": self.prg_lo,
+            "prg_hi": self.prg_hi,
+            "prg_32k": self.prg_32k,
+            "prg_bank": self.prg_bank,
+            "chr_bank": self.chr_bank,
+        })
+    }
+
     fn reset(&mut self) {
         self.mirror_mode = MirrorMode::Vertical;
         self.control = 0x0C;
         self.count = 0;
         self.load = 0;
-        self.prg_hi = (self.rom.header.prg_count() - 1) as u8;
+        self.chr_lo = 0;
+        self.chr_hi = 0;
+        self.chr_8k = 0;
+        self.prg_32k = 0;
+        self.prg_bank = 0;
+        self.chr_bank = 0;
+        self.update_prg_banks();
+        self.last_write = None;
+    }
+
+    fn cpu_tick(&mut self, cycles: u64) {
+        self.cycles = self.cycles.wrapping_add(cycles);
+    }
+
+    fn logger(&mut self) -> Option<&mut BankLogger> {
+        Some(&mut self.logger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rom::INesHeader;
+
+    fn mapper() -> Mapper1 {
+        let header = INesHeader::new([
+            b'N', b'E', b'S', 0x1A, 4, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        // Each byte holds the number of its 16KB bank
+        let prg = (0..0x10000).map(|i| (i / 0x4000) as u8).collect();
+        Mapper1::new(Rom {
+            header,
+            prg,
+            chr: vec![0; 0x2000],
+            trainer: None,
+        })
+    }
+
+    /// Writes a value to a register serially, with enough cycles between the writes
+    fn write_register(mapper: &mut Mapper1, addr: u16, data: u8) {
+        for i in 0..5 {
+            mapper.write_prg(addr, (data >> i) & 0x1);
+            mapper.cpu_tick(4);
+        }
+    }
+
+    #[test]
+    fn test_consecutive_writes() {
+        let mut mapper = mapper();
+        write_register(&mut mapper, 0xE000, 0x02);
+        assert_eq!(mapper.prg_lo, 0x02);
+
+        // Like an INC on the register: writes the old value then the new one on the next cycle
+        mapper.write_prg(0xE000, 0x80);
+        mapper.write_prg(0xE000, 0x01);
+        mapper.cpu_tick(1);
+        mapper.write_prg(0xE000, 0x01);
+        mapper.cpu_tick(6);
+
+        // Only the reset is accepted
+        assert_eq!(mapper.count, 0);
+        assert_eq!(mapper.control & 0x0C, 0x0C);
+
+        write_register(&mut mapper, 0xE000, 0x03);
+        assert_eq!(mapper.prg_lo, 0x03);
+    }
+
+    #[test]
+    fn test_fixed_last_bank() {
+        let mut mapper = mapper();
+        assert_eq!(mapper.read_prg(0xFFFC), 3);
+
+        // Mode 2 switches the bank at 0xC000
+        write_register(&mut mapper, 0x8000, 0x08);
+        write_register(&mut mapper, 0xE000, 0x01);
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xFFFC)), (0, 1));
+
+        // The reset bit fixes the last bank again, the bank register moves to 0x8000
+        mapper.write_prg(0x8000, 0x80);
+        mapper.cpu_tick(4);
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xFFFC)), (1, 3));
+
+        // Same when changing the mode with the control register
+        write_register(&mut mapper, 0x8000, 0x08);
+        write_register(&mut mapper, 0xE000, 0x02);
+        write_register(&mut mapper, 0x8000, 0x0C);
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xFFFC)), (2, 3));
+
+        // Power on state
+        write_register(&mut mapper, 0x8000, 0x08);
+        write_register(&mut mapper, 0xA000, 0x01);
+        mapper.reset();
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xFFFC)), (0, 3));
+        assert_eq!(mapper.chr_8k, 0);
+    }
+
+    #[test]
+    fn test_512k_prg() {
+        // 512KB of PRG ROM, CHR RAM and 32KB of PRG RAM (SXROM)
+        let header = INesHeader::new([
+            b'N', b'E', b'S', 0x1A, 32, 0, 0x10, 0, 4, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        let prg = (0..0x80000).map(|i| (i / 0x4000) as u8).collect();
+        let mut mapper = Mapper1::new(Rom {
+            header,
+            prg,
+            chr: vec![0; 0x2000],
+            trainer: None,
+        });
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xFFFC)), (0, 15));
+
+        // Bit 4 of the CHR bank selects the second 256KB, with its own fixed bank
+        write_register(&mut mapper, 0xE000, 0x03);
+        write_register(&mut mapper, 0xA000, 0x10);
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xFFFC)), (19, 31));
+
+        // 32KB mode
+        write_register(&mut mapper, 0x8000, 0x00);
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xC000)), (18, 19));
+
+        // Bits 2-3 select the PRG RAM bank
+        mapper.write_prg(0x6000, 0x42);
+        write_register(&mut mapper, 0xA000, 0x14);
+        assert_eq!(mapper.read_prg(0x6000), 0x00);
+        mapper.write_prg(0x6000, 0x43);
+        write_register(&mut mapper, 0xA000, 0x00);
+        assert_eq!(mapper.read_prg(0x6000), 0x42);
+        assert_eq!(mapper.ram.len(), 0x8000);
+    }
+
+    #[test]
+    fn test_256k_prg() {
+        // The CHR bank only switches CHR on the smaller boards
+        let mut mapper = mapper();
+        write_register(&mut mapper, 0xA000, 0x1C);
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xFFFC)), (0, 3));
+        mapper.write_prg(0x6000, 0x42);
+        write_register(&mut mapper, 0xA000, 0x00);
+        assert_eq!(mapper.read_prg(0x6000), 0x42);
     }
 }
@@ -0,0 +1,564 @@
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{BankLogger, Mapper};
+use crate::cartridge::{MirrorMode, Rom, RomMapper};
+use crate::savable::Savable;
+
+/// Cpu cycles of a scanline, times 3. The IRQ prescaler counts down by 3 every Cpu cycle
+const IRQ_PRESCALER: i16 = 341;
+/// Level of one step of the expansion audio. A VRC6 pulse at full volume is about as loud as
+/// an Apu pulse
+const AUDIO_STEP: f32 = 0.15 / 15.0;
+
+/// Konami VRC6 pulse channel
+#[derive(Default, Serialize, Deserialize)]
+struct Pulse {
+    volume: u8,
+    /// The step where the output goes low, 0 to 7
+    duty: u8,
+    /// Ignores the duty and outputs the volume all the time
+    constant: bool,
+    enabled: bool,
+    period: u16,
+    counter: u16,
+    /// Counts down from 15
+    step: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.constant = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0x7;
+                self.volume = data & 0x0F;
+            }
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (data as u16 & 0x0F) << 8;
+                self.enabled = data & 0x80 != 0;
+                // Disabling restarts the duty cycle
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        match self.counter {
+            0 => {
+                self.counter = self.period >> shift;
+                self.step = self.step.wrapping_sub(1) & 0x0F;
+            }
+            _ => self.counter -= 1,
+        }
+    }
+
+    fn output(&self) -> u8 {
+        match self.enabled && (self.constant || self.step <= self.duty) {
+            true => self.volume,
+            false => 0,
+        }
+    }
+}
+
+/// Konami VRC6 sawtooth channel
+#[derive(Default, Serialize, Deserialize)]
+struct Sawtooth {
+    /// Added to the accumulator every other step
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    counter: u16,
+    /// 0 to 13, the accumulator is cleared on step 14, after 6 additions
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (data as u16 & 0x0F) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.counter > 0 {
+            self.counter -= 1;
+            return;
+        }
+
+        self.counter = self.period >> shift;
+        self.step += 1;
+        match self.step {
+            14 => {
+                self.step = 0;
+                self.accumulator = 0;
+            }
+            step if step % 2 == 0 => self.accumulator = self.accumulator.wrapping_add(self.rate),
+            _ => {}
+        }
+    }
+
+    /// Top 5 bits of the accumulator
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+/// Konami VRC6, with its expansion audio. Mapper 26 swaps the 2 lowest address lines
+pub struct Mapper24 {
+    rom: Rom,
+    swap_lines: bool,
+
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_banks: [u8; 8],
+    ram_enable: bool,
+    mirror_mode: MirrorMode,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_enable: bool,
+    /// IRQ enabled again after an acknowledge
+    irq_enable_after_ack: bool,
+    /// Counts Cpu cycles instead of scanlines
+    irq_cycle_mode: bool,
+    pending_irq: bool,
+
+    pulses: [Pulse; 2],
+    saw: Sawtooth,
+    /// Stops the channel timers
+    audio_halt: bool,
+    /// Shift of the channel periods, to speed up the 3 channels
+    period_shift: u8,
+
+    ram: Vec<u8>,
+
+    logger: BankLogger,
+}
+
+impl Mapper24 {
+    pub fn new(rom: Rom, swap_lines: bool) -> Self {
+        Self {
+            rom,
+            swap_lines,
+
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            ram_enable: false,
+            mirror_mode: MirrorMode::Vertical,
+
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_prescaler: IRQ_PRESCALER,
+            irq_enable: false,
+            irq_enable_after_ack: false,
+            irq_cycle_mode: false,
+            pending_irq: false,
+
+            pulses: [Pulse::default(), Pulse::default()],
+            saw: Sawtooth::default(),
+            audio_halt: false,
+            period_shift: 0,
+
+            ram: vec![0; 0x2000],
+
+            logger: BankLogger::new(if swap_lines { 26 } else { 24 }),
+        }
+    }
+
+    /// Gets the offset of the address in PRG ROM
+    fn prg_addr(&self, addr: u16) -> usize {
+        let index = match addr {
+            0x8000..=0xBFFF => self.prg_16k as usize * 0x4000 + (addr & 0x3FFF) as usize,
+            0xC000..=0xDFFF => self.prg_8k as usize * 0x2000 + (addr & 0x1FFF) as usize,
+            // Fixed to the last bank
+            _ => self.rom.prg.len() - 0x2000 + (addr & 0x1FFF) as usize,
+        };
+        index % self.rom.prg.len()
+    }
+
+    /// Clocks the IRQ counter. It fires when it wraps and reloads from the latch
+    fn clock_irq_counter(&mut self) {
+        match self.irq_counter {
+            0xFF => {
+                self.irq_counter = self.irq_latch;
+                self.pending_irq = true;
+            }
+            _ => self.irq_counter += 1,
+        }
+    }
+}
+
+impl RomMapper for Mapper24 {}
+
+impl Savable for Mapper24 {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        self.rom.save(output)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_16k)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_8k)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_banks)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram_enable)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.mirror_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_latch)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_counter)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_prescaler)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_enable)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_enable_after_ack)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_cycle_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.pending_irq)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.pulses)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.saw)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.audio_halt)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.period_shift)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        self.rom.load(input)?;
+        self.prg_16k = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_8k = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_banks = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.ram_enable = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.mirror_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_latch = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_counter = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_prescaler = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_enable = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_enable_after_ack = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_cycle_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.pending_irq = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.pulses = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.saw = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.audio_halt = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.period_shift = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.ram = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper24 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => match self.ram_enable {
+                true => self.ram[(addr & 0x1FFF) as usize],
+                false => 0,
+            },
+            0x8000..=0xFFFF => self.rom.prg[self.prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            if self.ram_enable {
+                self.ram[(addr & 0x1FFF) as usize] = data;
+            }
+            return;
+        }
+
+        // Only A0, A1 and the top 4 bits are decoded
+        let reg = match self.swap_lines {
+            true => (addr & 0x1) << 1 | (addr & 0x2) >> 1,
+            false => addr & 0x3,
+        };
+        match addr & 0xF000 {
+            0x8000 => self.prg_16k = data & 0x0F,
+            0x9000 if reg == 3 => {
+                self.audio_halt = data & 0x1 != 0;
+                self.period_shift = match data & 0x6 {
+                    0 => 0,
+                    2 => 4,
+                    // Shifting by 8 takes precedence
+                    _ => 8,
+                };
+            }
+            0x9000 => self.pulses[0].write(reg, data),
+            0xA000 if reg != 3 => self.pulses[1].write(reg, data),
+            0xB000 if reg != 3 => self.saw.write(reg, data),
+            0xB000 => {
+                // Only the standard mode with 1KB CHR banks is supported
+                self.ram_enable = data & 0x80 != 0;
+                self.mirror_mode = match data & 0x0C {
+                    0x0 => MirrorMode::Vertical,
+                    0x4 => MirrorMode::Horizontal,
+                    0x8 => MirrorMode::OneScreenLo,
+                    _ => MirrorMode::OneScreenHi,
+                };
+            }
+            0xC000 => self.prg_8k = data & 0x1F,
+            0xD000 => self.chr_banks[reg as usize] = data,
+            0xE000 => self.chr_banks[reg as usize + 4] = data,
+            0xF000 => match reg {
+                0 => self.irq_latch = data,
+                1 => {
+                    self.irq_enable_after_ack = data & 0x1 != 0;
+                    self.irq_enable = data & 0x2 != 0;
+                    self.irq_cycle_mode = data & 0x4 != 0;
+                    if self.irq_enable {
+                        self.irq_counter = self.irq_latch;
+                        self.irq_prescaler = IRQ_PRESCALER;
+                    }
+                    self.pending_irq = false;
+                }
+                2 => {
+                    self.pending_irq = false;
+                    self.irq_enable = self.irq_enable_after_ack;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        if matches!(addr & 0xF000, 0x8000 | 0xB000 | 0xC000 | 0xD000 | 0xE000) {
+            self.logger.log(
+                addr,
+                data,
+                format_args!(
+                    "PRG: {:02X} {:02X} CHR: {:02X?} RAM: {}",
+                    self.prg_16k, self.prg_8k, self.chr_banks, self.ram_enable as u8
+                ),
+            );
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        if self.rom.chr_is_ram() {
+            return self.rom.read_chr(addr as usize);
+        }
+
+        let bank = self.chr_banks[(addr / 0x400) as usize] as usize;
+        let index = bank * 0x400 + (addr & 0x3FF) as usize;
+        self.rom.read_chr(index)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.rom.write_chr(addr as usize, data);
+    }
+
+    fn mirror_mode(&self) -> MirrorMode {
+        self.mirror_mode
+    }
+
+    fn registers(&self) -> Value {
+        json!({
+            "prg_16k": self.prg_16k,
+            "prg_8k": self.prg_8k,
+            "chr_banks": self.chr_banks,
+            "ram_enable": self.ram_enable,
+            "irq_latch": self.irq_latch,
+            "irq_counter": self.irq_counter,
+            "irq_enable": self.irq_enable,
+            "irq_cycle_mode": self.irq_cycle_mode,
+            "pending_irq": self.pending_irq,
+            "pulses": self.pulses,
+            "saw": self.saw,
+        })
+    }
+
+    fn chr_bank_layout(&self) -> Option<[usize; 8]> {
+        Some(self.chr_banks.map(|bank| bank as usize))
+    }
+
+    fn reset(&mut self) {
+        self.prg_16k = 0;
+        self.prg_8k = 0;
+        self.chr_banks = [0; 8];
+        self.ram_enable = false;
+        self.mirror_mode = MirrorMode::Vertical;
+
+        self.irq_latch = 0;
+        self.irq_counter = 0;
+        self.irq_prescaler = IRQ_PRESCALER;
+        self.irq_enable = false;
+        self.irq_enable_after_ack = false;
+        self.irq_cycle_mode = false;
+        self.pending_irq = false;
+
+        self.pulses = [Pulse::default(), Pulse::default()];
+        self.saw = Sawtooth::default();
+        self.audio_halt = false;
+        self.period_shift = 0;
+    }
+
+    fn cpu_tick(&mut self, cycles: u64) {
+        if !self.irq_enable {
+            return;
+        }
+
+        for _ in 0..cycles {
+            if self.irq_cycle_mode {
+                self.clock_irq_counter();
+                continue;
+            }
+            // The prescaler clocks the counter about once per scanline
+            self.irq_prescaler -= 3;
+            if self.irq_prescaler <= 0 {
+                self.irq_prescaler += IRQ_PRESCALER;
+                self.clock_irq_counter();
+            }
+        }
+    }
+
+    fn poll_irq(&mut self) -> bool {
+        // The IRQ line stays asserted until acknowledged
+        self.pending_irq
+    }
+
+    fn clock_audio(&mut self) -> f32 {
+        if !self.audio_halt {
+            self.pulses[0].clock(self.period_shift);
+            self.pulses[1].clock(self.period_shift);
+            self.saw.clock(self.period_shift);
+        }
+
+        // The channels are summed by a 6 bit DAC
+        let sum = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        sum as f32 * AUDIO_STEP
+    }
+
+    fn logger(&mut self) -> Option<&mut BankLogger> {
+        Some(&mut self.logger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rom::INesHeader;
+
+    fn mapper(swap_lines: bool) -> Mapper24 {
+        let header = INesHeader::new([
+            b'N', b'E', b'S', 0x1A, 16, 16, 0x80, 0x10, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        // Each 8KB PRG bank is filled with its number
+        let prg = (0..0x40000).map(|i| (i / 0x2000) as u8).collect();
+        // Each 1KB CHR bank is filled with its number
+        let chr = (0..0x20000).map(|i| (i / 0x400) as u8).collect();
+        Mapper24::new(
+            Rom {
+                header,
+                prg,
+                chr,
+                trainer: None,
+            },
+            swap_lines,
+        )
+    }
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = mapper(false);
+        mapper.write_prg(0x8000, 0x03);
+        mapper.write_prg(0xC000, 0x09);
+        assert_eq!(mapper.read_prg(0x8000), 0x06);
+        assert_eq!(mapper.read_prg(0xA000), 0x07);
+        assert_eq!(mapper.read_prg(0xC000), 0x09);
+        assert_eq!(mapper.read_prg(0xE000), 0x1F);
+
+        mapper.write_prg(0xD002, 0x42);
+        mapper.write_prg(0xE003, 0x7F);
+        assert_eq!(mapper.read_chr(0x0800), 0x42);
+        assert_eq!(mapper.read_chr(0x1FFF), 0x7F);
+
+        // PRG RAM and mirroring
+        mapper.write_prg(0xB003, 0x84);
+        mapper.write_prg(0x6000, 0x55);
+        assert_eq!(mapper.read_prg(0x6000), 0x55);
+        assert!(matches!(mapper.mirror_mode(), MirrorMode::Horizontal));
+
+        // Mapper 26 swaps A0 and A1
+        let mut swapped = self::mapper(true);
+        swapped.write_prg(0xD001, 0x11);
+        swapped.write_prg(0xD002, 0x22);
+        assert_eq!(swapped.read_chr(0x0400), 0x22);
+        assert_eq!(swapped.read_chr(0x0800), 0x11);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = mapper(false);
+        // Cycle mode, 16 cycles before the IRQ
+        mapper.write_prg(0xF000, 0xF0);
+        mapper.write_prg(0xF001, 0x07);
+        mapper.cpu_tick(15);
+        assert!(!mapper.poll_irq());
+        mapper.cpu_tick(1);
+        assert!(mapper.poll_irq());
+        assert_eq!(mapper.irq_counter, 0xF0);
+
+        // The acknowledge keeps the IRQ enabled with bit 0 set
+        mapper.write_prg(0xF002, 0);
+        assert!(!mapper.poll_irq());
+        mapper.cpu_tick(16);
+        assert!(mapper.poll_irq());
+
+        // Scanline mode: the counter is clocked every 113.67 cycles
+        mapper.write_prg(0xF000, 0xFE);
+        mapper.write_prg(0xF001, 0x02);
+        assert!(!mapper.poll_irq());
+        mapper.cpu_tick(227);
+        assert!(!mapper.poll_irq());
+        mapper.cpu_tick(1);
+        assert!(mapper.poll_irq());
+
+        // Without bit 0, the acknowledge disables the IRQ
+        mapper.write_prg(0xF002, 0);
+        mapper.cpu_tick(1000);
+        assert!(!mapper.poll_irq());
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut mapper = mapper(false);
+        assert_eq!(mapper.clock_audio(), 0.0);
+
+        // Pulse 1 at volume 15 and 50% duty, period of 2 cycles
+        mapper.write_prg(0x9000, 0x7F);
+        mapper.write_prg(0x9001, 0x01);
+        mapper.write_prg(0x9002, 0x80);
+        let output: Vec<f32> = (0..32).map(|_| mapper.clock_audio()).collect();
+        let high = output.iter().filter(|&&s| s > 0.0).count();
+        assert_eq!(high, 16);
+        assert!(output.iter().all(|&s| s == 0.0 || s == 15.0 * AUDIO_STEP));
+
+        // Halted, the output stays where it is
+        mapper.write_prg(0x9003, 0x01);
+        let level = mapper.clock_audio();
+        assert!((0..8).all(|_| mapper.clock_audio() == level));
+        mapper.write_prg(0x9003, 0x00);
+        mapper.write_prg(0x9002, 0x00);
+
+        // Sawtooth at rate 8, restarting after 6 additions
+        mapper.write_prg(0xB000, 0x08);
+        mapper.write_prg(0xB001, 0x00);
+        mapper.write_prg(0xB002, 0x80);
+        let levels: Vec<u8> = (0..14)
+            .map(|_| (mapper.clock_audio() / AUDIO_STEP).round() as u8)
+            .collect();
+        assert_eq!(levels, [0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0]);
+    }
+}
@@ -1,33 +1,41 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{Read, Write};
+
+use serde_json::{json, Value};
 
 use crate::cartridge::rom::CHR_PAGE_SIZE;
 use crate::cartridge::{MirrorMode, Rom, RomMapper};
 use crate::savable::Savable;
 
-use super::Mapper;
+use super::{BankLogger, Mapper};
 
 pub struct Mapper3 {
     rom: Rom,
     bank: usize,
+
+    logger: BankLogger,
 }
 
 impl Mapper3 {
     pub fn new(rom: Rom) -> Self {
-        Self { rom, bank: 0 }
+        Self {
+            rom,
+            bank: 0,
+
+            logger: BankLogger::new(3),
+        }
     }
 }
 
 impl RomMapper for Mapper3 {}
 
 impl Savable for Mapper3 {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
         self.rom.save(output)?;
         bincode::serialize_into(output, &self.bank)?;
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
         self.rom.load(input)?;
         self.bank = bincode::deserialize_from(input)?;
         Ok(())
@@ -47,30 +55,44 @@ impl Mapper for Mapper3 {
     fn write_prg(&mut self, addr: u16, data: u8) {
         if let 0x8000..=0xFFFF = addr {
             self.bank = (data & 0x3) as usize;
+            self.logger.log(
+                addr,
+                data,
+                format_args!(
+                    "CHR bank {} at {:#07X}",
+                    self.bank,
+                    self.bank * CHR_PAGE_SIZE
+                ),
+            );
         }
     }
 
     fn read_chr(&mut self, addr: u16) -> u8 {
-        if self.rom.header.chr_count() == 0 {
-            return self.rom.chr[addr as usize];
+        if self.rom.chr_is_ram() {
+            return self.rom.read_chr(addr as usize);
         }
 
-        let mask = self.rom.header.chr_count() * CHR_PAGE_SIZE - 1;
         let index = self.bank * CHR_PAGE_SIZE + addr as usize;
-        self.rom.chr[index & mask]
+        self.rom.read_chr(index)
     }
 
     fn write_chr(&mut self, addr: u16, data: u8) {
-        if self.rom.header.chr_count() == 0 {
-            self.rom.chr[addr as usize] = data;
-        }
+        self.rom.write_chr(addr as usize, data);
     }
 
     fn mirror_mode(&self) -> MirrorMode {
         self.rom.header.mirror_mode()
     }
 
+    fn registers(&self) -> Value {
+        json!({ "bank": self.bank })
+    }
+
     fn reset(&mut self) {
         self.bank = 0;
     }
+
+    fn logger(&mut self) -> Option<&mut BankLogger> {
+        Some(&mut self.logger)
+    }
 }
@@ -1,10 +1,11 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{Read, Write};
+
+use serde_json::{json, Value};
 
 use crate::cartridge::{MirrorMode, Rom, RomMapper};
 use crate::savable::Savable;
 
-use super::Mapper;
+use super::{BankLogger, Mapper};
 
 pub struct Mapper4 {
     rom: Rom,
@@ -24,6 +25,8 @@ pub struct Mapper4 {
     pending_irq: Option<bool>,
 
     ram: Vec<u8>,
+
+    logger: BankLogger,
 }
 
 impl Mapper4 {
@@ -46,6 +49,8 @@ impl Mapper4 {
             pending_irq: None,
 
             ram: vec![0; 0x2000],
+
+            logger: BankLogger::new(4),
         }
     }
 }
@@ -53,48 +58,48 @@ impl Mapper4 {
 impl RomMapper for Mapper4 {}
 
 impl Savable for Mapper4 {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
         self.rom.save(output)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.target)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.prg_mode)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.chr_invert)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.mirror_mode)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.irq_reload)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.irq_counter)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.irq_enable)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.pending_irq)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.target)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_invert)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.mirror_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_reload)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_counter)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_enable)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.pending_irq)?;
         for i in 0..8 {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.registers[i])?;
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.chr_banks[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.registers[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_banks[i])?;
         }
         for i in 0..4 {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.prg_banks[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_banks[i])?;
         }
         for i in 0..0x2000 {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.ram[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram[i])?;
         }
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
         self.rom.load(input)?;
-        self.target = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.prg_mode = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.chr_invert = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.mirror_mode = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.irq_reload = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.irq_counter = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.irq_enable = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.pending_irq = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        self.target = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_invert = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.mirror_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_reload = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_counter = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_enable = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.pending_irq = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         for i in 0..8 {
-            self.registers[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-            self.chr_banks[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+            self.registers[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+            self.chr_banks[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         }
         for i in 0..4 {
-            self.prg_banks[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+            self.prg_banks[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         }
         for i in 0..0x2000 {
-            self.ram[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+            self.ram[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         }
         Ok(())
     }
@@ -127,6 +132,14 @@ impl Mapper for Mapper4 {
                 self.target = data & 0x7;
                 self.prg_mode = data & 0x40 != 0;
                 self.chr_invert = data & 0x80 != 0;
+                self.logger.log(
+                    addr,
+                    data,
+                    format_args!(
+                        "Bank select: R{} PRG mode {} CHR invert {}",
+                        self.target, self.prg_mode as u8, self.chr_invert as u8
+                    ),
+                );
             }
             0x8000..=0x9FFF => {
                 self.registers[self.target as usize] = data;
@@ -166,6 +179,15 @@ impl Mapper for Mapper4 {
                 }
 
                 self.prg_banks[1] = (self.registers[7] & 0x3F) as usize * 0x2000;
+
+                self.logger.log(
+                    addr,
+                    data,
+                    format_args!(
+                        "R{} | PRG: {:05X?} CHR: {:05X?}",
+                        self.target, self.prg_banks, self.chr_banks
+                    ),
+                );
             }
             0xA000..=0xBFFF if even => match data & 0x1 != 0 {
                 true => self.mirror_mode = MirrorMode::Horizontal,
@@ -183,8 +205,8 @@ impl Mapper for Mapper4 {
     }
 
     fn read_chr(&mut self, addr: u16) -> u8 {
-        if self.rom.header.chr_count() == 0 {
-            return self.rom.chr[addr as usize];
+        if self.rom.chr_is_ram() {
+            return self.rom.read_chr(addr as usize);
         }
 
         let reg_index = match addr {
@@ -199,13 +221,11 @@ impl Mapper for Mapper4 {
             _ => 0,
         };
         let index = self.chr_banks[reg_index] + (addr & 0x3FF) as usize;
-        self.rom.chr[index]
+        self.rom.read_chr(index)
     }
 
     fn write_chr(&mut self, addr: u16, data: u8) {
-        if self.rom.header.chr_count() == 0 {
-            self.rom.chr[addr as usize] = data;
-        }
+        self.rom.write_chr(addr as usize, data);
     }
 
     fn mirror_mode(&self) -> MirrorMode {
@@ -215,6 +235,33 @@ impl Mapper for Mapper4 {
         }
     }
 
+    fn registers(&self) -> Value {
+        json!({
+            "target": self.target,
+            "prg_mode": self.prg_mode,
+            "chr_invert": self.chr_invert,
+            "registers": self.registers,
+            "prg_banks": self.prg_banks,
+            "chr_banks": self.chr_banks,
+            "irq_reload": self.irq_reload,
+            "irq_counter": self.irq_counter,
+            "irq_enable": self.irq_enable,
+            "pending_irq": self.pending_irq,
+        })
+    }
+
+    fn chr_bank_layout(&self) -> Option<[usize; 8]> {
+        if self.rom.chr_is_ram() {
+            return None;
+        }
+
+        let mut layout = [0; 8];
+        for (bank, &offset) in layout.iter_mut().zip(self.chr_banks.iter()) {
+            *bank = offset / 0x400;
+        }
+        Some(layout)
+    }
+
     fn reset(&mut self) {
         self.target = 0;
         self.prg_mode = false;
@@ -249,4 +296,66 @@ impl Mapper for Mapper4 {
     fn poll_irq(&mut self) -> bool {
         self.pending_irq.take().is_some()
     }
+
+    fn logger(&mut self) -> Option<&mut BankLogger> {
+        Some(&mut self.logger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cartridge::rom::INesHeader;
+
+    fn mapper() -> Mapper4 {
+        let header = INesHeader::new([
+            b'N', b'E', b'S', 0x1A, 2, 1, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        Mapper4::new(Rom {
+            header,
+            prg: vec![0; 0x8000],
+            chr: vec![0; 0x2000],
+            trainer: None,
+        })
+    }
+
+    #[test]
+    fn test_bank_logging() {
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let sink = lines.clone();
+        let mut mapper = mapper();
+        let logger = mapper.logger().unwrap();
+        logger.set_sink(move |line| sink.borrow_mut().push(line.to_string()));
+
+        mapper.write_prg(0x8000, 0x06);
+        assert!(lines.borrow().is_empty());
+
+        mapper.logger().unwrap().set_enabled(true);
+        mapper.write_prg(0x8000, 0x46);
+        mapper.write_prg(0x8001, 0x01);
+
+        let lines = lines.borrow();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("[Mapper 4] $8000 <- $46"));
+        assert!(lines[0].contains("R6 PRG mode 1"));
+        assert!(lines[1].contains("PRG: [04000, 00000, 02000, 00000]"));
+    }
+
+    #[test]
+    fn test_chr_bank_layout() {
+        let mut mapper = mapper();
+        mapper.write_prg(0x8000, 0x00);
+        mapper.write_prg(0x8001, 0x07);
+        mapper.write_prg(0x8000, 0x02);
+        mapper.write_prg(0x8001, 0x03);
+        assert_eq!(mapper.chr_bank_layout(), Some([6, 7, 0, 1, 3, 0, 0, 0]));
+
+        // The 2KB banks move to 0x1000
+        mapper.write_prg(0x8000, 0x82);
+        mapper.write_prg(0x8001, 0x05);
+        assert_eq!(mapper.chr_bank_layout(), Some([5, 0, 0, 0, 6, 7, 0, 1]));
+    }
 }
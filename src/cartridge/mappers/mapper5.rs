@@ -0,0 +1,431 @@
+use std::io::{Read, Write};
+
+use serde_json::{json, Value};
+
+use super::{BankLogger, Mapper};
+use crate::cartridge::{MirrorMode, Rom, RomMapper};
+use crate::savable::Savable;
+
+/// Size of the PRG RAM (the biggest MMC5 board)
+const PRG_RAM_SIZE: usize = 0x10000;
+/// Size of the extended RAM
+const EXRAM_SIZE: usize = 0x400;
+
+/// MMC5 baseline
+///
+/// Supports the PRG and CHR banking modes, the extended RAM and the scanline IRQ.
+/// Split screen, fill mode and the extra audio channels are not emulated
+pub struct Mapper5 {
+    rom: Rom,
+
+    prg_mode: u8,
+    chr_mode: u8,
+    ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametables: u8,
+
+    ram_bank: u8,
+    prg_banks: [u8; 4],
+    chr_banks: [u16; 12],
+    chr_upper: u8,
+    chr_use_b: bool,
+
+    irq_target: u8,
+    irq_enable: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    ram: Vec<u8>,
+    exram: Vec<u8>,
+
+    logger: BankLogger,
+}
+
+impl Mapper5 {
+    pub fn new(rom: Rom) -> Self {
+        Self {
+            rom,
+
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+
+            ram_bank: 0,
+            prg_banks: [0xFF; 4],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            chr_use_b: false,
+
+            irq_target: 0,
+            irq_enable: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+
+            ram: vec![0; PRG_RAM_SIZE],
+            exram: vec![0; EXRAM_SIZE],
+
+            logger: BankLogger::new(5),
+        }
+    }
+
+    /// PRG RAM is only writable with the right values in both protect registers
+    fn ram_writable(&self) -> bool {
+        self.ram_protect == [0x2, 0x1]
+    }
+
+    /// Gets if the address is mapped to ROM and its offset in ROM or RAM
+    fn prg_addr(&self, addr: u16) -> (bool, usize) {
+        let (reg, size) = match (self.prg_mode, addr) {
+            (0, _) => (self.prg_banks[3], 0x8000),
+            (1, 0x8000..=0xBFFF) => (self.prg_banks[1], 0x4000),
+            (1, _) => (self.prg_banks[3], 0x4000),
+            (2, 0x8000..=0xBFFF) => (self.prg_banks[1], 0x4000),
+            (2, 0xC000..=0xDFFF) => (self.prg_banks[2], 0x2000),
+            (2, _) => (self.prg_banks[3], 0x2000),
+            (_, 0x8000..=0x9FFF) => (self.prg_banks[0], 0x2000),
+            (_, 0xA000..=0xBFFF) => (self.prg_banks[1], 0x2000),
+            (_, 0xC000..=0xDFFF) => (self.prg_banks[2], 0x2000),
+            _ => (self.prg_banks[3], 0x2000),
+        };
+
+        // Bit 7 selects ROM. The last bank register is always ROM
+        let rom = reg & 0x80 != 0 || addr >= 0xE000;
+        // Banks are numbered in 8KB, the low bits are ignored for the bigger banks
+        let bank = (reg & 0x7F) as usize & !(size / 0x2000 - 1);
+        (rom, bank * 0x2000 + (addr as usize & (size - 1)))
+    }
+
+    /// Gets the offset of the address in CHR ROM
+    fn chr_addr(&self, addr: u16) -> usize {
+        let (index, size) = match self.chr_mode {
+            0 => (7, 0x2000),
+            1 => (3 + 4 * (addr as usize / 0x1000), 0x1000),
+            2 => (1 + 2 * (addr as usize / 0x800), 0x800),
+            _ => (addr as usize / 0x400, 0x400),
+        };
+
+        // The B set (0x5128-0x512B) is the same for both pattern tables
+        let index = match self.chr_use_b {
+            true => 8 + (index & 0x3),
+            false => index,
+        };
+
+        self.chr_banks[index] as usize * size + (addr as usize & (size - 1))
+    }
+}
+
+impl RomMapper for Mapper5 {}
+
+impl Savable for Mapper5 {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        self.rom.save(output)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram_protect)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.exram_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.nametables)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram_bank)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_banks)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_banks)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_upper)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_use_b)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_target)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_enable)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_pending)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.in_frame)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.scanline)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.multiplicand)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.multiplier)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.exram)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        self.rom.load(input)?;
+        self.prg_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.ram_protect = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.exram_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.nametables = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.ram_bank = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_banks = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_banks = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_upper = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_use_b = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_target = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_enable = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_pending = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.in_frame = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.scanline = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.multiplicand = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.multiplier = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.ram = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.exram = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper5 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x5204 => {
+                // Reading the status acknowledges the IRQ
+                let data = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending = false;
+                data
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            // Extended RAM is only readable in modes 2 and 3
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[(addr & 0x3FF) as usize],
+            0x6000..=0x7FFF => {
+                let index = self.ram_bank as usize * 0x2000 + (addr & 0x1FFF) as usize;
+                self.ram[index % PRG_RAM_SIZE]
+            }
+            0x8000..=0xFFFF => match self.prg_addr(addr) {
+                (true, index) => self.rom.prg[index % self.rom.prg.len()],
+                (false, index) => self.ram[index % PRG_RAM_SIZE],
+            },
+            _ => 0,
+        }
+    }
+
+    fn peek_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            // Reading the status doesn't acknowledge the IRQ
+            0x5204 => (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6,
+            _ => self.read_prg(addr),
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5100 => self.prg_mode = data & 0x3,
+            0x5101 => self.chr_mode = data & 0x3,
+            0x5102 => self.ram_protect[0] = data & 0x3,
+            0x5103 => self.ram_protect[1] = data & 0x3,
+            0x5104 => self.exram_mode = data & 0x3,
+            0x5105 => self.nametables = data,
+            0x5113 => self.ram_bank = data & 0x7,
+            0x5114..=0x5117 => self.prg_banks[(addr - 0x5114) as usize] = data,
+            0x5120..=0x512B => {
+                let index = (addr - 0x5120) as usize;
+                self.chr_banks[index] = (self.chr_upper as u16) << 8 | data as u16;
+                // Use the last set written to
+                self.chr_use_b = index >= 8;
+            }
+            0x5130 => self.chr_upper = data & 0x3,
+            0x5203 => self.irq_target = data,
+            0x5204 => self.irq_enable = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            // Extended RAM is read only in mode 3
+            0x5C00..=0x5FFF if self.exram_mode != 3 => self.exram[(addr & 0x3FF) as usize] = data,
+            0x6000..=0x7FFF if self.ram_writable() => {
+                let index = self.ram_bank as usize * 0x2000 + (addr & 0x1FFF) as usize;
+                self.ram[index % PRG_RAM_SIZE] = data;
+            }
+            0x8000..=0xDFFF if self.ram_writable() => {
+                if let (false, index) = self.prg_addr(addr) {
+                    self.ram[index % PRG_RAM_SIZE] = data;
+                }
+            }
+            _ => {}
+        }
+
+        if let 0x5100..=0x5130 = addr {
+            self.logger.log(
+                addr,
+                data,
+                format_args!(
+                    "PRG mode {}: {:02X?} CHR mode {}: {:03X?}",
+                    self.prg_mode, self.prg_banks, self.chr_mode, self.chr_banks
+                ),
+            );
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        if self.rom.chr_is_ram() {
+            return self.rom.read_chr(addr as usize);
+        }
+
+        let index = self.chr_addr(addr);
+        self.rom.read_chr(index)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.rom.write_chr(addr as usize, data);
+    }
+
+    fn mirror_mode(&self) -> MirrorMode {
+        // Each nametable can be mapped separately. Only the mappings matching a standard
+        // mirroring are supported, the extended RAM and fill mode nametables are not
+        match self.nametables {
+            0x00 => MirrorMode::OneScreenLo,
+            0x55 => MirrorMode::OneScreenHi,
+            0x44 => MirrorMode::Vertical,
+            _ => MirrorMode::Horizontal,
+        }
+    }
+
+    fn registers(&self) -> Value {
+        json!({
+            "prg_mode": self.prg_mode,
+            "chr_mode": self.chr_mode,
+            "ram_protect": self.ram_protect,
+            "exram_mode": self.exram_mode,
+            "nametables": self.nametables,
+            "ram_bank": self.ram_bank,
+            "prg_banks": self.prg_banks,
+            "chr_banks": self.chr_banks,
+            "chr_upper": self.chr_upper,
+            "chr_use_b": self.chr_use_b,
+            "irq_target": self.irq_target,
+            "irq_enable": self.irq_enable,
+            "irq_pending": self.irq_pending,
+            "in_frame": self.in_frame,
+            "scanline": self.scanline,
+            "multiplicand": self.multiplicand,
+            "multiplier": self.multiplier,
+        })
+    }
+
+    fn reset(&mut self) {
+        self.prg_mode = 3;
+        self.chr_mode = 0;
+        self.ram_protect = [0; 2];
+        self.exram_mode = 0;
+        self.nametables = 0;
+
+        self.ram_bank = 0;
+        self.prg_banks = [0xFF; 4];
+        self.chr_banks = [0; 12];
+        self.chr_upper = 0;
+        self.chr_use_b = false;
+
+        self.irq_target = 0;
+        self.irq_enable = false;
+        self.irq_pending = false;
+        self.in_frame = false;
+        self.scanline = 0;
+    }
+
+    fn inc_scanline(&mut self) {
+        // The first scanline of the frame (pre-render) starts the counter
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+            return;
+        }
+
+        self.scanline += 1;
+        if self.scanline == self.irq_target {
+            self.irq_pending = true;
+        }
+
+        // The frame is over after the last visible scanline
+        if self.scanline >= 240 {
+            self.in_frame = false;
+        }
+    }
+
+    fn poll_irq(&mut self) -> bool {
+        // The IRQ line stays asserted until the status is read
+        self.irq_pending && self.irq_enable
+    }
+
+    fn logger(&mut self) -> Option<&mut BankLogger> {
+        Some(&mut self.logger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rom::INesHeader;
+
+    fn mapper() -> Mapper5 {
+        let header = INesHeader::new([
+            b'N', b'E', b'S', 0x1A, 8, 16, 0x50, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        // Each 8KB PRG bank and 1KB CHR bank is filled with its number
+        let prg = (0..0x20000).map(|i| (i / 0x2000) as u8).collect();
+        let chr = (0..0x20000).map(|i| (i / 0x400) as u8).collect();
+        let mut mapper = Mapper5::new(Rom {
+            header,
+            prg,
+            chr,
+            trainer: None,
+        });
+        mapper.reset();
+        mapper
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut mapper = mapper();
+        // Last bank at power on
+        assert_eq!(mapper.read_prg(0xE000), 0x0F);
+
+        mapper.write_prg(0x5114, 0x83);
+        mapper.write_prg(0x5115, 0x85);
+        assert_eq!(mapper.read_prg(0x8000), 0x03);
+        assert_eq!(mapper.read_prg(0xA000), 0x05);
+
+        // 16KB banks ignore the low bit
+        mapper.write_prg(0x5100, 0x1);
+        assert_eq!(mapper.read_prg(0x8000), 0x04);
+        assert_eq!(mapper.read_prg(0xA000), 0x05);
+        assert_eq!(mapper.read_prg(0xC000), 0x0E);
+    }
+
+    #[test]
+    fn test_chr_banking() {
+        let mut mapper = mapper();
+        mapper.write_prg(0x5101, 0x3);
+        mapper.write_prg(0x5120, 0x10);
+        mapper.write_prg(0x5127, 0x17);
+        assert_eq!(mapper.read_chr(0x0000), 0x10);
+        assert_eq!(mapper.read_chr(0x1C00), 0x17);
+
+        mapper.write_prg(0x5101, 0x1);
+        mapper.write_prg(0x512B, 0x08);
+        assert_eq!(mapper.read_chr(0x0000), 0x20);
+        assert_eq!(mapper.read_chr(0x1000), 0x20);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mapper = mapper();
+        mapper.write_prg(0x5203, 10);
+        mapper.write_prg(0x5204, 0x80);
+
+        // Pre-render scanline
+        mapper.inc_scanline();
+        assert_eq!(mapper.read_prg(0x5204), 0x40);
+
+        for _ in 0..9 {
+            mapper.inc_scanline();
+            assert!(!mapper.poll_irq());
+        }
+        mapper.inc_scanline();
+        assert!(mapper.poll_irq());
+        assert!(mapper.poll_irq());
+
+        // Reading the status acknowledges the IRQ
+        assert_eq!(mapper.read_prg(0x5204), 0xC0);
+        assert!(!mapper.poll_irq());
+    }
+}
@@ -0,0 +1,124 @@
+use std::io::{Read, Write};
+
+use serde_json::{json, Value};
+
+use crate::cartridge::{MirrorMode, Rom, RomMapper};
+use crate::savable::Savable;
+
+use super::{read_chr_8k, read_prg_32k, BankLogger, Mapper};
+
+/// GxROM
+pub struct Mapper66 {
+    rom: Rom,
+    prg_bank: usize,
+    chr_bank: usize,
+
+    logger: BankLogger,
+}
+
+impl Mapper66 {
+    pub fn new(rom: Rom) -> Self {
+        Self {
+            rom,
+            prg_bank: 0,
+            chr_bank: 0,
+
+            logger: BankLogger::new(66),
+        }
+    }
+}
+
+impl RomMapper for Mapper66 {}
+
+impl Savable for Mapper66 {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        self.rom.save(output)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_bank)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_bank)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        self.rom.load(input)?;
+        self.prg_bank = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_bank = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper66 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => read_prg_32k(&self.rom, self.prg_bank, addr),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x8000..=0xFFFF = addr {
+            // --PP --CC
+            // P: 32KB PRG bank
+            // C: 8KB CHR bank
+            self.prg_bank = ((data >> 4) & 0x3) as usize;
+            self.chr_bank = (data & 0x3) as usize;
+            self.logger.log(
+                addr,
+                data,
+                format_args!("PRG bank {} CHR bank {}", self.prg_bank, self.chr_bank),
+            );
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        read_chr_8k(&self.rom, self.chr_bank, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.rom.write_chr(addr as usize, data);
+    }
+
+    fn mirror_mode(&self) -> MirrorMode {
+        self.rom.header.mirror_mode()
+    }
+
+    fn registers(&self) -> Value {
+        json!({ "prg_bank": self.prg_bank, "chr_bank": self.chr_bank })
+    }
+
+    fn reset(&mut self) {
+        self.prg_bank = 0;
+        self.chr_bank = 0;
+    }
+
+    fn logger(&mut self) -> Option<&mut BankLogger> {
+        Some(&mut self.logger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rom::INesHeader;
+
+    #[test]
+    fn test_banking() {
+        let header = INesHeader::new([
+            b'N', b'E', b'S', 0x1A, 8, 4, 0x20, 0x40, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        // Each 32KB PRG bank and 8KB CHR bank is filled with its number
+        let prg = (0..0x20000).map(|i| (i / 0x8000) as u8).collect();
+        let chr = (0..0x8000).map(|i| (i / 0x2000) as u8).collect();
+        let mut mapper = Mapper66::new(Rom {
+            header,
+            prg,
+            chr,
+            trainer: None,
+        });
+
+        mapper.write_prg(0x8000, 0x23);
+        assert_eq!(mapper.read_prg(0x8000), 0x02);
+        assert_eq!(mapper.read_prg(0xFFFF), 0x02);
+        assert_eq!(mapper.read_chr(0x0000), 0x03);
+        assert_eq!(mapper.read_chr(0x1FFF), 0x03);
+    }
+}
@@ -0,0 +1,295 @@
+use std::io::{Read, Write};
+
+use serde_json::{json, Value};
+
+use super::{BankLogger, Mapper};
+use crate::cartridge::{MirrorMode, Rom, RomMapper};
+use crate::savable::Savable;
+
+/// Sunsoft FME-7
+pub struct Mapper69 {
+    rom: Rom,
+
+    command: u8,
+    prg_banks: [u8; 4],
+    chr_banks: [u8; 8],
+    ram_select: bool,
+    ram_enable: bool,
+    mirror_mode: MirrorMode,
+
+    irq_enable: bool,
+    counter_enable: bool,
+    irq_counter: u16,
+    pending_irq: bool,
+
+    ram: Vec<u8>,
+
+    logger: BankLogger,
+}
+
+impl Mapper69 {
+    pub fn new(rom: Rom) -> Self {
+        Self {
+            rom,
+
+            command: 0,
+            prg_banks: [0; 4],
+            chr_banks: [0; 8],
+            ram_select: false,
+            ram_enable: false,
+            mirror_mode: MirrorMode::Vertical,
+
+            irq_enable: false,
+            counter_enable: false,
+            irq_counter: 0,
+            pending_irq: false,
+
+            ram: vec![0; 0x2000],
+
+            logger: BankLogger::new(69),
+        }
+    }
+
+    /// Gets the offset of the address in PRG ROM
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x6000..=0x7FFF => self.prg_banks[0] as usize,
+            0x8000..=0x9FFF => self.prg_banks[1] as usize,
+            0xA000..=0xBFFF => self.prg_banks[2] as usize,
+            0xC000..=0xDFFF => self.prg_banks[3] as usize,
+            // Fixed to the last bank
+            _ => self.rom.header.prg_count() * 2 - 1,
+        };
+        (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.rom.prg.len()
+    }
+}
+
+impl RomMapper for Mapper69 {}
+
+impl Savable for Mapper69 {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        self.rom.save(output)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.command)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_banks)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_banks)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram_select)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram_enable)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.mirror_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_enable)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.counter_enable)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.irq_counter)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.pending_irq)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        self.rom.load(input)?;
+        self.command = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_banks = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_banks = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.ram_select = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.ram_enable = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.mirror_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_enable = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.counter_enable = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.irq_counter = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.pending_irq = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.ram = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper69 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.ram_select => match self.ram_enable {
+                true => self.ram[(addr & 0x1FFF) as usize],
+                false => 0,
+            },
+            0x6000..=0xFFFF => self.rom.prg[self.prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.ram_select && self.ram_enable => {
+                self.ram[(addr & 0x1FFF) as usize] = data
+            }
+            // Command register
+            0x8000..=0x9FFF => self.command = data & 0xF,
+            // Parameter register
+            0xA000..=0xBFFF => {
+                match self.command {
+                    0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+                    0x8 => {
+                        self.ram_enable = data & 0x80 != 0;
+                        self.ram_select = data & 0x40 != 0;
+                        self.prg_banks[0] = data & 0x3F;
+                    }
+                    0x9..=0xB => self.prg_banks[(self.command - 0x8) as usize] = data & 0x3F,
+                    0xC => {
+                        self.mirror_mode = match data & 0x3 {
+                            0 => MirrorMode::Vertical,
+                            1 => MirrorMode::Horizontal,
+                            2 => MirrorMode::OneScreenLo,
+                            _ => MirrorMode::OneScreenHi,
+                        }
+                    }
+                    0xD => {
+                        self.irq_enable = data & 0x1 != 0;
+                        self.counter_enable = data & 0x80 != 0;
+                        // Writing the IRQ control acknowledges the IRQ
+                        self.pending_irq = false;
+                    }
+                    0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+                    _ => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+                }
+
+                if self.command <= 0xC {
+                    self.logger.log(
+                        addr,
+                        data,
+                        format_args!(
+                            "Command {:X} | PRG: {:02X?} CHR: {:02X?} RAM: {}",
+                            self.command, self.prg_banks, self.chr_banks, self.ram_select as u8
+                        ),
+                    );
+                }
+            }
+            // 0xC000-0xFFFF is the audio expansion, not emulated
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        if self.rom.chr_is_ram() {
+            return self.rom.read_chr(addr as usize);
+        }
+
+        let bank = self.chr_banks[(addr / 0x400) as usize] as usize;
+        let index = bank * 0x400 + (addr & 0x3FF) as usize;
+        self.rom.read_chr(index)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.rom.write_chr(addr as usize, data);
+    }
+
+    fn mirror_mode(&self) -> MirrorMode {
+        self.mirror_mode
+    }
+
+    fn registers(&self) -> Value {
+        json!({
+            "command": self.command,
+            "prg_banks": self.prg_banks,
+            "chr_banks": self.chr_banks,
+            "ram_select": self.ram_select,
+            "ram_enable": self.ram_enable,
+            "irq_enable": self.irq_enable,
+            "counter_enable": self.counter_enable,
+            "irq_counter": self.irq_counter,
+            "pending_irq": self.pending_irq,
+        })
+    }
+
+    fn reset(&mut self) {
+        self.command = 0;
+        self.prg_banks = [0; 4];
+        self.chr_banks = [0; 8];
+        self.ram_select = false;
+        self.ram_enable = false;
+        self.mirror_mode = MirrorMode::Vertical;
+
+        self.irq_enable = false;
+        self.counter_enable = false;
+        self.irq_counter = 0;
+        self.pending_irq = false;
+    }
+
+    fn cpu_tick(&mut self, cycles: u64) {
+        if !self.counter_enable {
+            return;
+        }
+
+        // The counter is decremented every Cpu cycle. The IRQ fires when it wraps from 0 to 0xFFFF
+        if self.irq_enable && cycles > self.irq_counter as u64 {
+            self.pending_irq = true;
+        }
+        self.irq_counter = self.irq_counter.wrapping_sub(cycles as u16);
+    }
+
+    fn poll_irq(&mut self) -> bool {
+        // The IRQ line stays asserted until acknowledged
+        self.pending_irq
+    }
+
+    fn logger(&mut self) -> Option<&mut BankLogger> {
+        Some(&mut self.logger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rom::INesHeader;
+
+    fn mapper() -> Mapper69 {
+        let header = INesHeader::new([
+            b'N', b'E', b'S', 0x1A, 8, 16, 0x50, 0x40, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        // Each 8KB PRG bank is filled with its number
+        let prg = (0..0x20000).map(|i| (i / 0x2000) as u8).collect();
+        Mapper69::new(Rom {
+            header,
+            prg,
+            chr: vec![0; 0x20000],
+            trainer: None,
+        })
+    }
+
+    fn write_command(mapper: &mut Mapper69, command: u8, data: u8) {
+        mapper.write_prg(0x8000, command);
+        mapper.write_prg(0xA000, data);
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut mapper = mapper();
+        write_command(&mut mapper, 0x9, 0x03);
+        write_command(&mut mapper, 0xB, 0x07);
+        assert_eq!(mapper.read_prg(0x8000), 0x03);
+        assert_eq!(mapper.read_prg(0xC000), 0x07);
+        assert_eq!(mapper.read_prg(0xE000), 0x0F);
+
+        // PRG RAM at 0x6000
+        write_command(&mut mapper, 0x8, 0xC0);
+        mapper.write_prg(0x6000, 0x42);
+        assert_eq!(mapper.read_prg(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_cycle_irq() {
+        let mut mapper = mapper();
+        write_command(&mut mapper, 0xE, 100);
+        write_command(&mut mapper, 0xF, 0);
+        write_command(&mut mapper, 0xD, 0x81);
+
+        mapper.cpu_tick(100);
+        assert!(!mapper.poll_irq());
+        mapper.cpu_tick(1);
+        assert!(mapper.poll_irq());
+        assert_eq!(mapper.irq_counter, 0xFFFF);
+
+        // Writing the control register acknowledges the IRQ
+        write_command(&mut mapper, 0xD, 0x80);
+        assert!(!mapper.poll_irq());
+
+        // The counter keeps going but doesn't fire with the IRQ disabled
+        mapper.cpu_tick(0x10000);
+        assert!(!mapper.poll_irq());
+        assert_eq!(mapper.irq_counter, 0xFFFF);
+    }
+}
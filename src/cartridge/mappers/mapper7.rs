@@ -1,16 +1,19 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{Read, Write};
+
+use serde_json::{json, Value};
 
 use crate::cartridge::{MirrorMode, Rom, RomMapper};
 use crate::savable::Savable;
 
-use super::Mapper;
+use super::{BankLogger, Mapper};
 
 pub struct Mapper7 {
     rom: Rom,
 
     bank: usize,
     mirror_mode: MirrorMode,
+
+    logger: BankLogger,
 }
 
 impl Mapper7 {
@@ -19,6 +22,8 @@ impl Mapper7 {
             rom,
             bank: 0,
             mirror_mode: MirrorMode::OneScreenLo,
+
+            logger: BankLogger::new(7),
         }
     }
 }
@@ -26,17 +31,17 @@ impl Mapper7 {
 impl RomMapper for Mapper7 {}
 
 impl Savable for Mapper7 {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
         self.rom.save(output)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.bank)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.mirror_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.bank)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.mirror_mode)?;
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
         self.rom.load(input)?;
-        self.bank = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.mirror_mode = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        self.bank = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.mirror_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         Ok(())
     }
 }
@@ -54,25 +59,41 @@ impl Mapper for Mapper7 {
                 true => self.mirror_mode = MirrorMode::OneScreenHi,
                 false => self.mirror_mode = MirrorMode::OneScreenLo,
             }
+            self.logger.log(
+                addr,
+                data,
+                format_args!(
+                    "PRG bank {} at {:#07X} Mirroring: {:?}",
+                    self.bank,
+                    self.bank * 0x8000,
+                    self.mirror_mode
+                ),
+            );
         }
     }
 
     fn read_chr(&mut self, addr: u16) -> u8 {
-        self.rom.chr[addr as usize]
+        self.rom.read_chr(addr as usize)
     }
 
     fn write_chr(&mut self, addr: u16, data: u8) {
-        if self.rom.header.chr_count() == 0 {
-            self.rom.chr[addr as usize] = data;
-        }
+        self.rom.write_chr(addr as usize, data);
     }
 
     fn mirror_mode(&self) -> crate::cartridge::MirrorMode {
         self.mirror_mode
     }
 
+    fn registers(&self) -> Value {
+        json!({ "bank": self.bank })
+    }
+
     fn reset(&mut self) {
         self.bank = 0;
         self.mirror_mode = MirrorMode::OneScreenLo;
     }
+
+    fn logger(&mut self) -> Option<&mut BankLogger> {
+        Some(&mut self.logger)
+    }
 }
@@ -1,10 +1,11 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{Read, Write};
+
+use serde_json::{json, Value};
 
 use crate::cartridge::{MirrorMode, Rom, RomMapper};
 use crate::savable::Savable;
 
-use super::Mapper;
+use super::{BankLogger, Mapper};
 
 pub struct Mapper9 {
     rom: Rom,
@@ -25,6 +26,8 @@ pub struct Mapper9 {
     mirror_mode: MirrorMode,
 
     ram: Vec<u8>,
+
+    logger: BankLogger,
 }
 
 impl Mapper9 {
@@ -52,6 +55,8 @@ impl Mapper9 {
             mirror_mode: MirrorMode::Vertical,
 
             ram: vec![0; 0x2000],
+
+            logger: BankLogger::new(9),
         }
     }
 }
@@ -59,40 +64,40 @@ impl Mapper9 {
 impl RomMapper for Mapper9 {}
 
 impl Savable for Mapper9 {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
         self.rom.save(output)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.latch0)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.latch1)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.prg_bank)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.prg_fixed0)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.prg_fixed1)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.prg_fixed2)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.chr_lo_fd)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.chr_lo_fe)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.chr_hi_fd)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.chr_hi_fe)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.mirror_mode)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.latch0)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.latch1)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_bank)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_fixed0)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_fixed1)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.prg_fixed2)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_lo_fd)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_lo_fe)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_hi_fd)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.chr_hi_fe)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.mirror_mode)?;
         for i in 0..0x2000 {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.ram[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.ram[i])?;
         }
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
         self.rom.load(input)?;
-        self.latch0 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.latch1 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.prg_bank = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.prg_fixed0 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.prg_fixed1 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.prg_fixed2 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.chr_lo_fd = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.chr_lo_fe = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.chr_hi_fd = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.chr_hi_fe = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.mirror_mode = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        self.latch0 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.latch1 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_bank = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_fixed0 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_fixed1 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.prg_fixed2 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_lo_fd = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_lo_fe = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_hi_fd = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.chr_hi_fe = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.mirror_mode = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         for i in 0..0x2000 {
-            self.ram[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+            self.ram[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         }
         Ok(())
     }
@@ -128,7 +133,18 @@ impl Mapper for Mapper9 {
                 true => self.mirror_mode = MirrorMode::Horizontal,
                 false => self.mirror_mode = MirrorMode::Vertical,
             },
-            _ => {}
+            _ => return,
+        }
+
+        if addr >= 0xA000 {
+            self.logger.log(
+                addr,
+                data,
+                format_args!(
+                    "PRG bank {} CHR banks: FD {} {} FE {} {}",
+                    self.prg_bank, self.chr_lo_fd, self.chr_hi_fd, self.chr_lo_fe, self.chr_hi_fe
+                ),
+            );
         }
     }
 
@@ -156,18 +172,46 @@ impl Mapper for Mapper9 {
             _ => 0,
         };
         let index = bank * 0x1000 + (addr & 0xFFF) as usize;
-        self.rom.chr[index]
+        self.rom.read_chr(index)
     }
 
-    fn write_chr(&mut self, _addr: u16, _data: u8) {}
+    fn peek_chr(&mut self, addr: u16) -> u8 {
+        // Reading doesn't flip the latches
+        let latch0 = self.latch0;
+        let latch1 = self.latch1;
+        let data = self.read_chr(addr);
+        self.latch0 = latch0;
+        self.latch1 = latch1;
+        data
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.rom.write_chr(addr as usize, data);
+    }
 
     fn mirror_mode(&self) -> crate::cartridge::MirrorMode {
         self.mirror_mode
     }
 
+    fn registers(&self) -> Value {
+        json!({
+            "latch0": self.latch0,
+            "latch1": self.latch1,
+            "prg_bank": self.prg_bank,
+            "chr_lo_fd": self.chr_lo_fd,
+            "chr_lo_fe": self.chr_lo_fe,
+            "chr_hi_fd": self.chr_hi_fd,
+            "chr_hi_fe": self.chr_hi_fe,
+        })
+    }
+
     fn reset(&mut self) {
         self.mirror_mode = MirrorMode::Vertical;
         self.latch0 = false;
         self.latch1 = false;
     }
+
+    fn logger(&mut self) -> Option<&mut BankLogger> {
+        Some(&mut self.logger)
+    }
 }
@@ -1,11 +1,9 @@
-use std::fmt::Display;
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom};
-use std::path::Path;
+use std::io::{Read, Write};
 
+use crate::ppu::frame::Region;
 use crate::savable::Savable;
 
-use super::MirrorMode;
+use super::{crc32, CartridgeError, MirrorMode};
 
 /// Size of one PRG bank
 pub const PRG_PAGE_SIZE: usize = 0x4000;
@@ -13,9 +11,24 @@ pub const PRG_PAGE_SIZE: usize = 0x4000;
 pub const CHR_PAGE_SIZE: usize = 0x2000;
 /// Size of the iNES header
 const HEADER_SIZE: usize = 16;
+/// Size of the trainer data, between the header and the PRG ROM
+const TRAINER_SIZE: usize = 512;
+/// The trainer is loaded in PRG RAM at this address
+pub const TRAINER_ADDR: u16 = 0x7000;
 /// iNES header tag. Must be at the start of the file
 const NES_TAG: [u8; 4] = [b'N', b'E', b'S', 0x1A];
 
+/// Console the ROM runs on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    /// Nintendo VS System arcade board
+    VsSystem,
+    PlayChoice10,
+    /// NES 2.0 extended console type
+    Extended,
+}
+
 /// Header of the iNES file format
 #[derive(Clone, Copy)]
 pub struct INesHeader {
@@ -33,12 +46,25 @@ impl INesHeader {
 
     /// PRG bank count
     pub fn prg_count(&self) -> usize {
-        self.bytes[4] as usize
+        self.size_msb(self.bytes[9] & 0x0F) << 8 | self.bytes[4] as usize
     }
 
     /// CHR bank count
     pub fn chr_count(&self) -> usize {
-        self.bytes[5] as usize
+        self.size_msb(self.bytes[9] >> 4) << 8 | self.bytes[5] as usize
+    }
+
+    /// Upper bits of a NES 2.0 bank count. The exponent notation (0xF) isn't supported
+    fn size_msb(&self, nibble: u8) -> usize {
+        match self.is_nes2() && nibble != 0xF {
+            true => nibble as usize,
+            false => 0,
+        }
+    }
+
+    /// Has battery backed memory
+    pub fn has_battery(&self) -> bool {
+        self.bytes[6] & 0x2 != 0
     }
 
     /// Contains trainer data or not
@@ -48,9 +74,10 @@ impl INesHeader {
 
     /// Hardware mirror mode
     pub fn mirror_mode(&self) -> MirrorMode {
-        match self.bytes[6] & 0x1 != 0 {
-            true => MirrorMode::Vertical,
-            false => MirrorMode::Horizontal,
+        match (self.four_screen(), self.bytes[6] & 0x1 != 0) {
+            (true, _) => MirrorMode::FourScreen,
+            (false, true) => MirrorMode::Vertical,
+            (false, false) => MirrorMode::Horizontal,
         }
     }
 
@@ -63,6 +90,91 @@ impl INesHeader {
     pub fn mapper_id(&self) -> u8 {
         (self.bytes[7] & 0xF0) | (self.bytes[6] >> 4)
     }
+
+    /// Mapper number, with the 4 upper bits of NES 2.0
+    pub fn mapper_number(&self) -> u16 {
+        match self.is_nes2() {
+            true => (self.bytes[8] as u16 & 0x0F) << 8 | self.mapper_id() as u16,
+            false => self.mapper_id() as u16,
+        }
+    }
+
+    /// Board variant of the mapper. Only in NES 2.0 headers
+    pub fn submapper(&self) -> Option<u8> {
+        match self.is_nes2() {
+            true => Some(self.bytes[8] >> 4),
+            false => None,
+        }
+    }
+
+    /// Uses the NES 2.0 format
+    pub fn is_nes2(&self) -> bool {
+        self.bytes[7] & 0x0C == 0x08
+    }
+
+    /// Console type. iNES uses the same bits for the VS System and PlayChoice-10 flags
+    pub fn console_type(&self) -> ConsoleType {
+        match self.bytes[7] & 0x3 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::PlayChoice10,
+            _ => ConsoleType::Extended,
+        }
+    }
+
+    /// PRG RAM size in bytes, battery backed or not. At least 8KB, the old headers often leave
+    /// it at 0
+    pub fn prg_ram_size(&self) -> usize {
+        let size = match self.is_nes2() {
+            // Volatile and battery backed sizes, as shift counts of 64 bytes
+            true => [self.bytes[10] & 0x0F, self.bytes[10] >> 4]
+                .iter()
+                .filter(|&&shift| shift != 0)
+                .map(|&shift| 64 << shift)
+                .sum(),
+            // 8KB units
+            false => self.bytes[8] as usize * 0x2000,
+        };
+        size.max(0x2000)
+    }
+
+    /// CHR RAM size in bytes. 8KB without CHR ROM if the header doesn't say
+    pub fn chr_ram_size(&self) -> usize {
+        match (self.is_nes2(), self.chr_count()) {
+            // Volatile and battery backed sizes, as shift counts of 64 bytes
+            (true, _) => [self.bytes[11] & 0x0F, self.bytes[11] >> 4]
+                .iter()
+                .filter(|&&shift| shift != 0)
+                .map(|&shift| 64 << shift)
+                .sum(),
+            (false, 0) => CHR_PAGE_SIZE,
+            (false, _) => 0,
+        }
+    }
+
+    /// TV system of the game. None for the multi-region and Dendy games
+    pub fn region(&self) -> Option<Region> {
+        match self.is_nes2() {
+            true => match self.bytes[12] & 0x3 {
+                0 => Some(Region::Ntsc),
+                1 => Some(Region::Pal),
+                _ => None,
+            },
+            // Rarely set by the iNES dumps
+            false => match self.bytes[9] & 0x1 {
+                0 => Some(Region::Ntsc),
+                _ => Some(Region::Pal),
+            },
+        }
+    }
+
+    /// Ppu type of a VS System game. Only in NES 2.0 headers
+    pub fn vs_ppu(&self) -> Option<u8> {
+        match self.is_nes2() && self.console_type() == ConsoleType::VsSystem {
+            true => Some(self.bytes[13] & 0x0F),
+            false => None,
+        }
+    }
 }
 
 /// Game ROM data
@@ -70,18 +182,20 @@ pub struct Rom {
     pub header: INesHeader,
     pub prg: Vec<u8>,
     pub chr: Vec<u8>,
+    /// Data to copy in PRG RAM before running the game
+    pub trainer: Option<Vec<u8>>,
 }
 
 impl Savable for Rom {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
-        if self.header.chr_count() == 0 {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        if self.chr_is_ram() {
             bincode::serialize_into(output, &self.chr)?;
         }
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
-        if self.header.chr_count() == 0 {
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        if self.chr_is_ram() {
             self.chr = bincode::deserialize_from(input)?;
         }
         Ok(())
@@ -89,62 +203,87 @@ impl Savable for Rom {
 }
 
 impl Rom {
-    pub fn new<P: AsRef<Path> + Display>(romfile: P) -> io::Result<Self> {
-        let mut file = File::open(&romfile)?;
+    /// Parses an iNES file already in memory
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(CartridgeError::Truncated {
+                expected: HEADER_SIZE,
+                found: bytes.len(),
+            });
+        }
 
         let mut buf = [0; HEADER_SIZE];
-        file.read_exact(&mut buf)?;
+        buf.copy_from_slice(&bytes[..HEADER_SIZE]);
         let header = INesHeader::new(buf);
 
         if !header.is_valid() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Not iNES file format",
-            ));
+            return Err(CartridgeError::BadMagic);
         }
-
-        if header.has_trainer() {
-            file.seek(SeekFrom::Current(512))?;
+        // The mappers can't map anything without PRG ROM
+        if header.prg_count() == 0 {
+            return Err(CartridgeError::NoPrgRom);
         }
 
+        let trainer_size = match header.has_trainer() {
+            true => TRAINER_SIZE,
+            false => 0,
+        };
+
         let prg_size = PRG_PAGE_SIZE * header.prg_count();
-        let prg_start = 0;
+        let prg_start = HEADER_SIZE + trainer_size;
         let chr_size = CHR_PAGE_SIZE * header.chr_count();
         let chr_start = prg_start + prg_size;
 
-        println!(
-            "PRG Size: {} * {:#06X} = {:#06X} ({} KB)",
-            header.prg_count(),
-            PRG_PAGE_SIZE,
-            prg_size,
-            header.prg_count() * 16,
-        );
-        if header.chr_count() == 0 {
-            println!(
-                "CHR Size (RAM): 1 * {:#06X} = {:#06X} (8 KB)",
-                CHR_PAGE_SIZE, CHR_PAGE_SIZE,
-            );
-        } else {
-            println!(
-                "CHR Size: {} * {:#06X} = {:#06X} ({} KB)",
-                header.chr_count(),
-                CHR_PAGE_SIZE,
-                chr_size,
-                header.chr_count() * 8
-            );
+        if bytes.len() < chr_start + chr_size {
+            return Err(CartridgeError::Truncated {
+                expected: chr_start + chr_size,
+                found: bytes.len(),
+            });
         }
-        println!("Mapper ID: {}", header.mapper_id());
-
-        let mut rom_bytes = Vec::new();
-        file.read_to_end(&mut rom_bytes)?;
 
-        let prg = rom_bytes[prg_start..(prg_start + prg_size)].to_vec();
+        let trainer = match header.has_trainer() {
+            true => Some(bytes[HEADER_SIZE..prg_start].to_vec()),
+            false => None,
+        };
+        let prg = bytes[prg_start..(prg_start + prg_size)].to_vec();
         let chr = if header.chr_count() == 0 {
             vec![0; CHR_PAGE_SIZE]
         } else {
-            rom_bytes[chr_start..(chr_start + chr_size)].to_vec()
+            bytes[chr_start..(chr_start + chr_size)].to_vec()
         };
 
-        Ok(Self { header, prg, chr })
+        Ok(Self {
+            header,
+            prg,
+            chr,
+            trainer,
+        })
+    }
+
+    /// True when the cartridge has CHR RAM instead of CHR ROM
+    pub fn chr_is_ram(&self) -> bool {
+        self.header.chr_count() == 0
+    }
+
+    /// Reads the CHR memory. Indexes past the end wrap around
+    pub fn read_chr(&self, index: usize) -> u8 {
+        self.chr[index % self.chr.len()]
+    }
+
+    /// Writes the CHR memory. Does nothing on CHR ROM, the mappers don't have to check
+    pub fn write_chr(&mut self, index: usize, data: u8) {
+        if self.chr_is_ram() {
+            let len = self.chr.len();
+            self.chr[index % len] = data;
+        }
+    }
+
+    /// CRC32 of the PRG and CHR ROM. Leaves out the header, the trainer and the CHR RAM
+    pub fn hash(&self) -> u32 {
+        let mut data = self.prg.clone();
+        if !self.chr_is_ram() {
+            data.extend_from_slice(&self.chr);
+        }
+        crc32(&data)
     }
 }
@@ -1,17 +1,24 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{Read, Write};
 
 use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
+use crate::apu::ApuState;
+use crate::cartridge::Cartridge;
 use crate::joypad::{Button, JoyPort};
-use crate::savable::Savable;
+use crate::ppu::frame::{Frame, Thumbnail, HEIGHT, WIDTH};
+use crate::ppu::{PpuState, Rgb};
+use crate::savable::{self, Savable, THUMBNAIL_TAG};
 
 pub use addr_modes::AddrMode;
+use instructions::Op;
 pub use instructions::OPTABLE;
 
 mod addr_modes;
+pub mod disasm;
 mod instructions;
+pub mod trace;
 
 /// Memory page of the cpu stack
 const STACK_PAGE: u16 = 0x0100;
@@ -25,6 +32,10 @@ const NMI_VECTOR: u16 = 0xFFFA;
 const RESET_VECTOR: u16 = 0xFFFC;
 /// Interrupt request vector
 const IRQ_VECTOR: u16 = 0xFFFE;
+/// Opcode of JSR, stepped over by the debugger
+const JSR_OPCODE: u8 = 0x20;
+/// Cycles a debugger step runs before giving up, about ten NTSC frames
+const STEP_CYCLE_LIMIT: u64 = 29781 * 10;
 
 pub trait CpuInterface: Interface + Savable {}
 
@@ -53,19 +64,100 @@ pub trait Interface {
     /// Performs one clock tick on the bus
     fn tick(&mut self, _cycles: u64) {}
 
+    /// Tells the length of the instruction that just ran, before its cycles are ticked.
+    /// The instruction runs all at once, the bus places its accesses in those cycles
+    fn instruction_cycles(&mut self, _cycles: u64) {}
+
     /// Updates a controller's state
     ///
     /// Used with SDL2 keyboard events
     fn update_joypad(&mut self, _button: Button, _pressed: bool, _port: JoyPort) {}
 
+    /// Sets all the buttons of a controller, A in bit 0 up to Right in bit 7
+    ///
+    /// Used by the replays, where the input is stored as bytes
+    fn set_joypad_state(&mut self, _port: JoyPort, _buttons: u8) {}
+
     /// Returns the number of frame rendered by the Ppu
     fn frame_count(&self) -> u128 {
         0
     }
 
-    /// Resets the bus and its components
+    /// Returns the frame of the Ppu
+    fn frame(&self) -> Option<&Frame> {
+        None
+    }
+
+    /// Returns the buttons held on a controller
+    fn joypad_buttons(&self, _port: JoyPort) -> u8 {
+        0
+    }
+
+    /// Returns the state of the components on the bus, for debug dumps
+    fn snapshot(&self) -> Value {
+        Value::Null
+    }
+
+    /// Reads a byte from `addr` without side effects
+    fn peek(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    /// Writes a byte to `addr` without side effects. Only memory is written, not registers
+    fn poke(&mut self, _addr: u16, _data: u8) {}
+
+    /// Reads a byte from the Ppu memory without side effects
+    fn peek_ppu(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    /// Writes a byte to the Ppu memory
+    fn poke_ppu(&mut self, _addr: u16, _data: u8) {}
+
+    /// Enables or disables the limit of 8 sprites per scanline of the Ppu
+    fn set_sprite_limit(&mut self, _enabled: bool) {}
+
+    /// Changes the emulation speed multiplier. The audio samples are made `speed` times faster,
+    /// with the pitch changed
+    fn set_speed(&mut self, _speed: f64) {}
+
+    /// Enables or disables the capture of the CHR banks of each scanline
+    fn set_chr_bank_capture(&mut self, _enabled: bool) {}
+
+    /// Returns the CHR banks of each scanline of the last frame
+    fn chr_banks_per_scanline(&self) -> Vec<[usize; 8]> {
+        Vec::new()
+    }
+
+    /// Starts capturing the Apu register writes
+    fn start_apu_log(&mut self) {}
+
+    /// Stops the capture of the Apu register writes and returns them
+    fn stop_apu_log(&mut self) -> Vec<(u64, u16, u8)> {
+        Vec::new()
+    }
+
+    /// Returns the state of the Ppu
+    fn ppu_state(&self) -> PpuState {
+        PpuState::default()
+    }
+
+    /// Returns the state of the Apu channels
+    fn apu_state(&self) -> ApuState {
+        ApuState::default()
+    }
+
+    /// Soft resets the bus and its components (reset button)
     fn reset(&mut self) {}
 
+    /// Replaces the cartridge
+    fn load_cartridge(&mut self, _cartridge: Cartridge) {}
+
+    /// Powers on the bus and its components, clearing all the state
+    fn power_on(&mut self) {
+        self.reset();
+    }
+
     /// Gets audio samples from the Apu
     fn samples(&mut self) -> Vec<f32> {
         vec![]
@@ -77,6 +169,67 @@ pub trait Interface {
     }
 }
 
+/// Registers of the Cpu in a `Snapshot`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CpuSnapshot {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub pc: u16,
+    pub cycles: u64,
+    pub jammed: bool,
+}
+
+/// Human readable dump of the whole machine, to compare with other emulators
+///
+/// Can't be loaded back in the emulator, use the save states for that
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub cpu: CpuSnapshot,
+    /// Ppu, Apu and mapper state
+    pub bus: Value,
+}
+
+impl Snapshot {
+    /// Parses a dump made by `Cpu::to_json`
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+/// Why a debugger step stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// The step is complete
+    Done,
+    /// The next instruction is on a breakpoint
+    Breakpoint,
+    /// The Cpu is halted by a KIL opcode
+    Jammed,
+    /// The step ran for about ten frames without completing, like a call that never returns.
+    /// The next step continues it
+    Limit,
+}
+
+/// Where a debugger step stops
+#[derive(Clone, Copy)]
+enum StepTarget {
+    /// The instruction after a call, at the same stack level
+    After { pc: u16, s: u8 },
+    /// The stack pointer goes above this level
+    Return(u8),
+}
+
+/// Sources of the interrupt sequence
+#[derive(Clone, Copy)]
+enum Interrupt {
+    Brk,
+    Irq,
+    Nmi,
+}
+
 bitflags! {
     /// Cpu Flags
     #[derive(Serialize, Deserialize)]
@@ -101,7 +254,7 @@ bitflags! {
 }
 
 /// 2A03 Cpu
-pub struct Cpu<'a> {
+pub struct Cpu<B> {
     /// Accumulator
     a: u8,
     /// Index X
@@ -116,46 +269,86 @@ pub struct Cpu<'a> {
     pc: u16,
 
     /// Memory bus
-    bus: Box<dyn CpuInterface + 'a>,
+    bus: B,
     /// Current instruction duration in cycles
     ins_cycles: u64,
     /// Cycles elapsed
     cycles: u64,
+    /// Cpu halted by a KIL opcode
+    jammed: bool,
+    /// BCD arithmetic when the D flag is set. The 2A03 doesn't have it
+    decimal_mode: bool,
+    /// Addresses where the debugger steps stop
+    breakpoints: Vec<u16>,
+    /// Target of a step stopped by the cycle limit
+    step_target: Option<StepTarget>,
+}
+
+impl<B: CpuInterface> Savable for Cpu<B> {
+    /// Starts with the magic number and the version of the format
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        savable::write_header(output)?;
+        self.save_state(output)
+    }
+
+    /// Fails on states of another format version. Skips the thumbnail block if there is one
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        savable::check_header(input)?;
+        let mut tag = [0; THUMBNAIL_TAG.len()];
+        input.read_exact(&mut tag)?;
+        if tag == THUMBNAIL_TAG {
+            bincode::deserialize_from::<&mut dyn Read, Thumbnail>(input)?;
+            return self.load_state(input);
+        }
+        // Put the bytes back in front of the state
+        self.load_state(&mut (&tag[..]).chain(input))
+    }
 }
 
-impl Savable for Cpu<'_> {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
+impl<B: CpuInterface> Cpu<B> {
+    /// Saves the state with a thumbnail in front, for the save slot menus. `load` skips it and
+    /// `savable::read_thumbnail` reads it alone
+    pub fn save_with_thumbnail(
+        &self,
+        output: &mut dyn Write,
+        thumbnail: &Thumbnail,
+    ) -> bincode::Result<()> {
+        savable::write_header(output)?;
+        savable::write_thumbnail(output, thumbnail)?;
+        self.save_state(output)
+    }
+
+    fn save_state(&self, output: &mut dyn Write) -> bincode::Result<()> {
         self.bus.save(output)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.a)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.x)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.y)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.s)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.p)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.pc)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.ins_cycles)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.cycles)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.a)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.x)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.y)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.s)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.p)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.pc)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ins_cycles)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.cycles)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.jammed)?;
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load_state(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
         self.bus.load(input)?;
-        self.a = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.x = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.y = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.s = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.p = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.pc = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.ins_cycles = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.cycles = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        self.a = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.x = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.y = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.s = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.p = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.pc = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.ins_cycles = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.cycles = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.jammed = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         Ok(())
     }
 }
 
-impl<'a> Cpu<'a> {
-    pub fn new<I>(bus: I) -> Self
-    where
-        I: CpuInterface + 'a,
-    {
+impl<B: CpuInterface> Cpu<B> {
+    pub fn new(bus: B) -> Self {
         Self {
             a: 0,
             x: 0,
@@ -164,9 +357,13 @@ impl<'a> Cpu<'a> {
             p: Flags::from_bits_truncate(STATUS_RESET),
             pc: 0,
 
-            bus: Box::new(bus),
+            bus,
             ins_cycles: 0,
             cycles: 0,
+            jammed: false,
+            decimal_mode: false,
+            breakpoints: vec![],
+            step_target: None,
         }
     }
 
@@ -174,6 +371,14 @@ impl<'a> Cpu<'a> {
         self.pc
     }
 
+    /// Starts the next instruction at `addr` instead of the reset vector
+    ///
+    /// For test ROMs with a fixed entry point, like nestest's automation mode at 0xC000.
+    /// Call it after `power_on` or `reset`, between two instructions
+    pub fn set_pc(&mut self, addr: u16) {
+        self.pc = addr;
+    }
+
     pub fn a(&self) -> u8 {
         self.a
     }
@@ -199,25 +404,186 @@ impl<'a> Cpu<'a> {
         self.cycles
     }
 
+    /// Enables the decimal mode of the generic 6502
+    ///
+    /// Disabled by default since the NES doesn't support it
+    pub fn set_decimal_mode(&mut self, v: bool) {
+        self.decimal_mode = v;
+    }
+
+    /// Is the Cpu halted by a KIL opcode
+    ///
+    /// Only a reset gets it out of this state
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
     /// Ppu frames rendered
     pub fn frame_count(&self) -> u128 {
         self.bus.frame_count()
     }
 
-    /// Resets the NES
-    pub fn reset(&mut self) {
-        self.bus.reset();
+    /// Clocks until the Ppu completes a frame. Returns the new frame count
+    pub fn run_until_frame(&mut self) -> u128 {
+        let frame_count = self.frame_count();
+        while self.frame_count() == frame_count {
+            self.clock();
+        }
+        self.frame_count()
+    }
+
+    /// Clocks until at least `cycles` Cpu cycles passed. Returns how many cycles were run
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        let start = self.cycles;
+        while self.cycles.wrapping_sub(start) < cycles {
+            self.clock();
+        }
+        self.cycles.wrapping_sub(start)
+    }
+
+    /// Last completed frame, right after `run_until_frame`
+    pub fn frame(&self) -> Option<&Frame> {
+        self.bus.frame()
+    }
+
+    /// Color of a pixel of the last completed frame. `None` outside of the frame
+    pub fn pixel_at(&self, x: usize, y: usize) -> Option<Rgb> {
+        if x >= WIDTH as usize || y >= HEIGHT as usize {
+            return None;
+        }
+        self.bus.frame().map(|f| f.get_pixel(x, y))
+    }
+
+    /// Buttons held on a controller, A in bit 0 up to Right in bit 7
+    pub fn joypad_buttons(&self, port: JoyPort) -> u8 {
+        self.bus.joypad_buttons(port)
+    }
+
+    /// Reads memory without side effects, for debuggers
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+
+    /// Writes memory (RAM or PRG RAM) without side effects, for debuggers
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        self.bus.poke(addr, data);
+    }
+
+    /// Reads the Ppu memory without side effects, for debuggers
+    pub fn peek_ppu(&self, addr: u16) -> u8 {
+        self.bus.peek_ppu(addr)
+    }
+
+    /// Writes the Ppu memory, for debuggers
+    pub fn poke_ppu(&mut self, addr: u16, data: u8) {
+        self.bus.poke_ppu(addr, data);
+    }
+
+    /// Enables or disables the limit of 8 sprites per scanline
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.bus.set_sprite_limit(enabled);
+    }
+
+    /// Changes the emulation speed multiplier, 1.0 is the normal speed
+    pub fn set_speed(&mut self, speed: f64) {
+        self.bus.set_speed(speed);
+    }
+
+    /// Records the CHR banks of the cartridge at the start of each visible scanline
+    pub fn set_chr_bank_capture(&mut self, enabled: bool) {
+        self.bus.set_chr_bank_capture(enabled);
+    }
+
+    /// CHR banks (1KB units) of each scanline of the last frame, to debug raster bank switches
+    ///
+    /// Empty if the capture is disabled or the mapper doesn't report its banks
+    pub fn debug_chr_banks_per_scanline(&self) -> Vec<[usize; 8]> {
+        self.bus.chr_banks_per_scanline()
+    }
+
+    /// Takes a snapshot of the whole machine
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            cpu: CpuSnapshot {
+                a: self.a,
+                x: self.x,
+                y: self.y,
+                s: self.s,
+                p: self.p.bits(),
+                pc: self.pc,
+                cycles: self.cycles,
+                jammed: self.jammed,
+            },
+            bus: self.bus.snapshot(),
+        }
+    }
+
+    /// Dumps the whole machine state as JSON
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&self.snapshot())
+    }
+
+    /// Ppu timing and scroll, for debug displays
+    pub fn ppu_state(&self) -> PpuState {
+        self.bus.ppu_state()
+    }
+
+    /// Apu channels, for debug displays
+    pub fn apu_state(&self) -> ApuState {
+        self.bus.apu_state()
+    }
+
+    /// Starts capturing the writes to the Apu registers (0x4000-0x4013, 0x4015 and 0x4017),
+    /// to rip the music of a game
+    pub fn start_apu_log(&mut self) {
+        self.bus.start_apu_log();
+    }
+
+    /// Stops the capture. Returns the writes as (cycle, address, data), the cycles counted
+    /// from `start_apu_log`. Overclocked cycles are not counted, like for the audio
+    pub fn stop_apu_log(&mut self) -> Vec<(u64, u16, u8)> {
+        self.bus.stop_apu_log()
+    }
+
+    /// Powers on the NES. The memory and all the state is cleared
+    pub fn power_on(&mut self) {
+        self.bus.power_on();
         self.a = 0;
         self.x = 0;
         self.y = 0;
         self.s = STACK_RESET;
         self.p = Flags::from_bits_truncate(STATUS_RESET);
+        self.cycles = 0;
+        self.reset_sequence();
+    }
+
+    /// Presses the reset button of the NES
+    ///
+    /// The memory and registers are kept. Like an interrupt without the writes,
+    /// the stack pointer goes down by 3 and interrupts are disabled
+    pub fn reset(&mut self) {
+        self.bus.reset();
+        self.s = self.s.wrapping_sub(3);
+        self.p.insert(Flags::I);
+        self.reset_sequence();
+    }
+
+    /// Swaps the cartridge and powers on the console, like changing games
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.bus.load_cartridge(cartridge);
+        self.power_on();
+    }
+
+    /// Jumps to the reset vector
+    fn reset_sequence(&mut self) {
         // Set pc to value at reset vector
         self.pc = self.mem_read_word(RESET_VECTOR);
         self.ins_cycles = 0;
+        self.jammed = false;
+        self.step_target = None;
         // Reset takes 7 cycles
         self.bus.tick(7);
-        self.cycles = 7;
+        self.cycles += 7;
     }
 
     /// Gets audio samples from the Apu
@@ -232,49 +598,57 @@ impl<'a> Cpu<'a> {
 
     /// Non-maskable interrupt
     fn nmi(&mut self) {
-        // Push the program counter
-        self.push_word(self.pc);
-        // Push the status register without the Break flag
-        self.push_byte((self.p & !Flags::B).bits());
-        // Set disable interrupt
-        self.p.insert(Flags::I);
-        // Set pc to value at NMI vector
-        self.pc = self.mem_read_word(NMI_VECTOR);
-        // NMI takes 7 cycles
-        self.cycles += 7;
-        self.ins_cycles = 7;
+        self.interrupt(Interrupt::Nmi);
     }
 
     /// Interrupt request
     fn irq(&mut self) {
         // Don't execute if disable interrupt is set
-        if !self.p.contains(Flags::I) {
-            // Push the program counter
-            self.push_word(self.pc);
-            // Push the status register without the Break flag
-            self.push_byte((self.p & !Flags::B).bits());
-            // Set disable interrupt
-            self.p.insert(Flags::I);
-            // Set pc to value at IRQ vector
-            self.pc = self.mem_read_word(IRQ_VECTOR);
-            // IRQ takes 7 cycles
-            self.cycles += 7;
-            self.ins_cycles = 7;
-        } else {
-            self.ins_cycles = 0;
+        match self.p.contains(Flags::I) {
+            true => self.ins_cycles = 0,
+            false => self.interrupt(Interrupt::Irq),
         }
     }
 
-    /// Executes an instruction and runs a callback function in a loop
+    /// Interrupt sequence shared by BRK, IRQ and NMI. Takes 7 cycles
+    ///
+    /// The vector is only fetched on the last 2 cycles. If a NMI is raised while a BRK or an IRQ
+    /// is pushing its state on the stack, it hijacks the sequence and the NMI vector is used instead
+    fn interrupt(&mut self, kind: Interrupt) {
+        // Push the program counter
+        self.push_word(self.pc);
+        // Push the status register. The Break flag is only set by BRK
+        let p = match kind {
+            Interrupt::Brk => self.p | Flags::B,
+            _ => self.p & !Flags::B,
+        };
+        self.push_byte(p.bits());
+        // Set disable interrupt
+        self.p.insert(Flags::I);
+
+        // Clock the bus up to the vector fetch
+        self.bus.tick(5);
+        self.cycles = self.cycles.wrapping_add(5);
+
+        // Check for a NMI right before fetching the vector
+        let vector = match kind {
+            Interrupt::Nmi => NMI_VECTOR,
+            _ if self.bus.poll_nmi() => NMI_VECTOR,
+            _ => IRQ_VECTOR,
+        };
+        self.pc = self.mem_read_word(vector);
+        // The 2 cycles of the vector fetch are left
+        self.ins_cycles = 2;
+    }
+
+    /// Runs a callback function before each instruction, until it returns `false`
     ///
     /// Used with the trace debug module
-    #[allow(dead_code)]
     pub fn run_with_callback<F>(&mut self, mut callback: F)
     where
-        F: FnMut(&mut Self),
+        F: FnMut(&mut Self) -> bool,
     {
-        loop {
-            callback(self);
+        while callback(self) {
             self.execute();
         }
     }
@@ -282,15 +656,21 @@ impl<'a> Cpu<'a> {
     /// Executes a full instruction
     ///
     /// Returns how many cycles were executed
-    #[allow(dead_code)]
     pub fn execute(&mut self) -> u64 {
-        let mut nmi_cycles = 0;
+        // A jammed Cpu does nothing, but the rest of the system keeps running
+        if self.jammed {
+            self.bus.tick(1);
+            self.cycles = self.cycles.wrapping_add(1);
+            return 1;
+        }
+
+        let start = self.cycles;
         // If Ppu has requested a NMI, do it
         if self.bus.poll_nmi() {
             self.nmi();
-            // Clock the bus for the NMI cycles duration (7)
+            // Clock the bus for the rest of the NMI
             self.bus.tick(self.ins_cycles);
-            nmi_cycles = self.ins_cycles;
+            self.cycles = self.cycles.wrapping_add(self.ins_cycles);
         }
 
         // Get next instruction opcode
@@ -300,32 +680,112 @@ impl<'a> Cpu<'a> {
         let ins = *OPTABLE.get(&opcode).unwrap();
         // Set the current instruction cycle duration
         self.ins_cycles = ins.cycles;
-        // Call the instruction function
-        (ins.cpu_fn)(self, ins.mode);
+        // Run the instruction
+        self.run_op(ins.op, ins.mode);
+        self.bus.instruction_cycles(self.ins_cycles);
 
         // Clock the bus for the instruction's cycles duration
         self.bus.tick(self.ins_cycles);
+        self.cycles = self.cycles.wrapping_add(self.ins_cycles);
 
-        let mut irq_cycles = 0;
         // If Apu has requested an interrupt, do it
         if self.bus.poll_irq() {
             self.irq();
             self.bus.tick(self.ins_cycles);
-            irq_cycles = self.ins_cycles;
+            self.cycles = self.cycles.wrapping_add(self.ins_cycles);
         }
 
-        // Count cycles
-        self.cycles = self
-            .cycles
-            .wrapping_add(nmi_cycles + irq_cycles + self.ins_cycles);
+        self.cycles.wrapping_sub(start)
+    }
+
+    /// Stops `step_over` and `run_to_return` before the instruction at `addr`
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.retain(|&bp| bp != addr);
+    }
+
+    /// Executes one instruction, but a JSR runs with its whole subroutine and stops at the
+    /// instruction after the call
+    ///
+    /// Continues the previous step instead if it stopped at the cycle limit
+    pub fn step_over(&mut self) -> StepResult {
+        if let Some(target) = self.step_target.take() {
+            return self.run_until(target);
+        }
+        let (pc, s) = (self.pc, self.s);
+        let call = self.peek(pc) == JSR_OPCODE;
+        self.execute();
+        match (call, self.jammed) {
+            // Recursive calls come back to the same address, but deeper in the stack
+            (true, _) => self.run_until(StepTarget::After {
+                pc: pc.wrapping_add(3),
+                s,
+            }),
+            (false, true) => StepResult::Jammed,
+            (false, false) => StepResult::Done,
+        }
+    }
+
+    /// Runs until the current subroutine returns, when the stack pointer goes above its
+    /// current level
+    ///
+    /// Never returns in the main loop of a game, the step stops at the cycle limit. Continues
+    /// the previous step instead if it stopped there
+    pub fn run_to_return(&mut self) -> StepResult {
+        if let Some(target) = self.step_target.take() {
+            return self.run_until(target);
+        }
+        let s = self.s;
+        self.execute();
+        self.run_until(StepTarget::Return(s))
+    }
+
+    /// Executes instructions until `target`, a breakpoint, a KIL opcode or the cycle limit
+    fn run_until(&mut self, target: StepTarget) -> StepResult {
+        let start = self.cycles;
+        loop {
+            if self.jammed {
+                return StepResult::Jammed;
+            }
+            let done = match target {
+                StepTarget::After { pc, s } => self.pc == pc && self.s == s,
+                StepTarget::Return(s) => self.s > s,
+            };
+            if done {
+                return StepResult::Done;
+            }
+            if self.breakpoints.contains(&self.pc) {
+                return StepResult::Breakpoint;
+            }
+            if self.cycles.wrapping_sub(start) >= STEP_CYCLE_LIMIT {
+                self.step_target = Some(target);
+                return StepResult::Limit;
+            }
+            self.execute();
+        }
+    }
 
-        nmi_cycles + irq_cycles + self.ins_cycles
+    /// The next clock starts a new instruction
+    pub fn instruction_done(&self) -> bool {
+        self.ins_cycles == 0 && !self.jammed
     }
 
     /// Clocks the Cpu once
     ///
     /// This function is not cycle accurate. I execute the instruction in one cycle and then do nothing for the remaining cycles
     pub fn clock(&mut self) {
+        // A jammed Cpu does nothing, but the rest of the system keeps running
+        if self.jammed {
+            self.bus.tick(1);
+            self.cycles = self.cycles.wrapping_add(1);
+            return;
+        }
+
         // If current instruction is done and a NMI is requested, do it
         if self.ins_cycles == 0 && self.bus.poll_nmi() {
             self.nmi();
@@ -345,7 +805,8 @@ impl<'a> Cpu<'a> {
             let ins = *OPTABLE.get(&opcode).unwrap();
 
             self.ins_cycles = ins.cycles;
-            (ins.cpu_fn)(self, ins.mode);
+            self.run_op(ins.op, ins.mode);
+            self.bus.instruction_cycles(self.ins_cycles);
         }
 
         // Tick once
@@ -356,6 +817,92 @@ impl<'a> Cpu<'a> {
         self.ins_cycles -= 1;
     }
 
+    /// Runs the operation of an instruction
+    fn run_op(&mut self, op: Op, mode: AddrMode) {
+        match op {
+            Op::Adc => self.adc(mode),
+            Op::Ahx => self.ahx(mode),
+            Op::Alr => self.alr(mode),
+            Op::Anc => self.anc(mode),
+            Op::And => self.and(mode),
+            Op::Arr => self.arr(mode),
+            Op::AslAcc => self.asl_acc(mode),
+            Op::AslMem => self.asl_mem(mode),
+            Op::Axs => self.axs(mode),
+            Op::Bcc => self.bcc(mode),
+            Op::Bcs => self.bcs(mode),
+            Op::Beq => self.beq(mode),
+            Op::Bit => self.bit(mode),
+            Op::Bmi => self.bmi(mode),
+            Op::Bne => self.bne(mode),
+            Op::Bpl => self.bpl(mode),
+            Op::Brk => self.brk(mode),
+            Op::Bvc => self.bvc(mode),
+            Op::Bvs => self.bvs(mode),
+            Op::Clc => self.clc(mode),
+            Op::Cld => self.cld(mode),
+            Op::Cli => self.cli(mode),
+            Op::Clv => self.clv(mode),
+            Op::Cpa => self.cpa(mode),
+            Op::Cpx => self.cpx(mode),
+            Op::Cpy => self.cpy(mode),
+            Op::Dcp => self.dcp(mode),
+            Op::Dec => self.dec(mode),
+            Op::Dex => self.dex(mode),
+            Op::Dey => self.dey(mode),
+            Op::Eor => self.eor(mode),
+            Op::Inc => self.inc(mode),
+            Op::Inx => self.inx(mode),
+            Op::Iny => self.iny(mode),
+            Op::Isb => self.isb(mode),
+            Op::JmpAbs => self.jmp_abs(mode),
+            Op::JmpInd => self.jmp_ind(mode),
+            Op::Jsr => self.jsr(mode),
+            Op::Kil => self.kil(mode),
+            Op::Las => self.las(mode),
+            Op::Lax => self.lax(mode),
+            Op::Lda => self.lda(mode),
+            Op::Ldx => self.ldx(mode),
+            Op::Ldy => self.ldy(mode),
+            Op::LsrAcc => self.lsr_acc(mode),
+            Op::LsrMem => self.lsr_mem(mode),
+            Op::Nop => self.nop(mode),
+            Op::Ora => self.ora(mode),
+            Op::Pha => self.pha(mode),
+            Op::Php => self.php(mode),
+            Op::Pla => self.pla(mode),
+            Op::Plp => self.plp(mode),
+            Op::Rla => self.rla(mode),
+            Op::RolAcc => self.rol_acc(mode),
+            Op::RolMem => self.rol_mem(mode),
+            Op::RorAcc => self.ror_acc(mode),
+            Op::RorMem => self.ror_mem(mode),
+            Op::Rra => self.rra(mode),
+            Op::Rti => self.rti(mode),
+            Op::Rts => self.rts(mode),
+            Op::Sax => self.sax(mode),
+            Op::Sbc => self.sbc(mode),
+            Op::Sec => self.sec(mode),
+            Op::Sed => self.sed(mode),
+            Op::Sei => self.sei(mode),
+            Op::Shx => self.shx(mode),
+            Op::Shy => self.shy(mode),
+            Op::Slo => self.slo(mode),
+            Op::Sre => self.sre(mode),
+            Op::Sta => self.sta(mode),
+            Op::Stx => self.stx(mode),
+            Op::Sty => self.sty(mode),
+            Op::Tas => self.tas(mode),
+            Op::Tax => self.tax(mode),
+            Op::Tay => self.tay(mode),
+            Op::Tsx => self.tsx(mode),
+            Op::Txa => self.txa(mode),
+            Op::Txs => self.txs(mode),
+            Op::Tya => self.tya(mode),
+            Op::Xxa => self.xxa(mode),
+        }
+    }
+
     /// Updates a controller's state
     ///
     /// Used with SDL2 keyboard events
@@ -363,6 +910,13 @@ impl<'a> Cpu<'a> {
         self.bus.update_joypad(button, pressed, port);
     }
 
+    /// Sets all the buttons of a controller, A in bit 0 up to Right in bit 7
+    ///
+    /// Used by the replays, where the input is stored as bytes
+    pub fn set_joypad_state(&mut self, port: JoyPort, buttons: u8) {
+        self.bus.set_joypad_state(port, buttons);
+    }
+
     /// Reads a byte at addr
     pub fn mem_read(&mut self, addr: u16) -> u8 {
         self.bus.read(addr)
@@ -730,12 +1284,20 @@ impl<'a> Cpu<'a> {
         self.p.insert(Flags::I);
     }
 
+    /// Writes the result of a read-modify-write instruction. Like the real Cpu, the value
+    /// read is written back first, on the cycle before the result
+    fn rmw_write(&mut self, addr: u16, old: u8, result: u8) {
+        self.mem_write(addr, old);
+        self.mem_write(addr, result);
+    }
+
     /// Increment memory
     fn inc(&mut self, mode: AddrMode) {
         let addr = self.operand_addr(mode);
-        let v = self.fetch_operand(addr, mode).wrapping_add(1);
+        let old = self.fetch_operand(addr, mode);
+        let v = old.wrapping_add(1);
         self.set_z_n(v);
-        self.mem_write(addr, v);
+        self.rmw_write(addr, old, v);
     }
 
     /// Increment X register
@@ -751,9 +1313,10 @@ impl<'a> Cpu<'a> {
     /// Decrement memory
     fn dec(&mut self, mode: AddrMode) {
         let addr = self.operand_addr(mode);
-        let v = self.fetch_operand(addr, mode).wrapping_sub(1);
+        let old = self.fetch_operand(addr, mode);
+        let v = old.wrapping_sub(1);
         self.set_z_n(v);
-        self.mem_write(addr, v);
+        self.rmw_write(addr, old, v);
     }
 
     /// Decrement X register
@@ -850,13 +1413,9 @@ impl<'a> Cpu<'a> {
 
     /// Break
     fn brk(&mut self, _mode: AddrMode) {
-        if !self.p.contains(Flags::I) {
-            self.increment_pc();
-            self.push_word(self.pc);
-            self.push_byte((self.p | Flags::B).bits());
-            self.p.insert(Flags::I);
-            self.pc = self.mem_read_word(IRQ_VECTOR);
-        }
+        // Skip the padding byte
+        self.increment_pc();
+        self.interrupt(Interrupt::Brk);
     }
 
     /// Push accumulator
@@ -963,7 +1522,7 @@ impl<'a> Cpu<'a> {
         let addr = self.operand_addr(mode);
         let v = self.fetch_operand(addr, mode);
         let result = self.asl(v);
-        self.mem_write(addr, result);
+        self.rmw_write(addr, v, result);
     }
 
     /// Logical shift right
@@ -985,7 +1544,7 @@ impl<'a> Cpu<'a> {
         let addr = self.operand_addr(mode);
         let v = self.fetch_operand(addr, mode);
         let result = self.lsr(v);
-        self.mem_write(addr, result);
+        self.rmw_write(addr, v, result);
     }
 
     /// Rotate left
@@ -1009,7 +1568,7 @@ impl<'a> Cpu<'a> {
         let addr = self.operand_addr(mode);
         let v = self.fetch_operand(addr, mode);
         let result = self.rol(v);
-        self.mem_write(addr, result);
+        self.rmw_write(addr, v, result);
     }
 
     /// Rotate right
@@ -1033,11 +1592,19 @@ impl<'a> Cpu<'a> {
         let addr = self.operand_addr(mode);
         let v = self.fetch_operand(addr, mode);
         let result = self.ror(v);
-        self.mem_write(addr, result);
+        self.rmw_write(addr, v, result);
     }
 
     /// Performs addition with on accumulator value
     fn add(&mut self, v: u8) {
+        match self.decimal_mode && self.p.contains(Flags::D) {
+            true => self.add_bcd(v),
+            false => self.add_binary(v),
+        }
+    }
+
+    /// Performs binary addition on accumulator with value
+    fn add_binary(&mut self, v: u8) {
         let c = self.p.contains(Flags::C);
         let sum = self.a() as u16 + v as u16 + c as u16;
         let result = sum as u8;
@@ -1052,7 +1619,58 @@ impl<'a> Cpu<'a> {
     ///
     /// Substraction is adding with all the bits flipped
     fn sub(&mut self, v: u8) {
-        self.add(!v);
+        match self.decimal_mode && self.p.contains(Flags::D) {
+            true => self.sub_bcd(v),
+            false => self.add_binary(!v),
+        }
+    }
+
+    /// Performs BCD addition on accumulator with value
+    ///
+    /// Like the NMOS 6502, Z is set from the binary result and N and V are set from
+    /// the result before the high nibble is adjusted
+    fn add_bcd(&mut self, v: u8) {
+        let a = self.a() as u16;
+        let c = self.p.contains(Flags::C) as u16;
+
+        let mut lo = (a & 0xF) + (v as u16 & 0xF) + c;
+        if lo >= 0xA {
+            lo = ((lo + 0x6) & 0xF) + 0x10;
+        }
+        let mut sum = (a & 0xF0) + (v as u16 & 0xF0) + lo;
+
+        let binary = (a + v as u16 + c) as u8;
+        self.p.set(Flags::Z, binary == 0);
+        self.p.set(Flags::N, sum & 0x80 != 0);
+        self.p
+            .set(Flags::V, (v as u16 ^ sum) & (sum ^ a) & 0x80 != 0);
+
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+        self.p.set(Flags::C, sum > 0xFF);
+        self.a = sum as u8;
+    }
+
+    /// Performs BCD substraction on accumulator with value
+    ///
+    /// Like the NMOS 6502, the flags are the same as the binary substraction
+    fn sub_bcd(&mut self, v: u8) {
+        let a = self.a() as i16;
+        let c = self.p.contains(Flags::C) as i16;
+
+        let mut lo = (a & 0xF) - (v as i16 & 0xF) + c - 1;
+        if lo < 0 {
+            lo = ((lo - 0x6) & 0xF) - 0x10;
+        }
+        let mut diff = (a & 0xF0) - (v as i16 & 0xF0) + lo;
+        if diff < 0 {
+            diff -= 0x60;
+        }
+
+        // Flags are set by the binary substraction
+        self.add_binary(!v);
+        self.a = diff as u8;
     }
 
     /// Add with carry
@@ -1073,7 +1691,10 @@ impl<'a> Cpu<'a> {
 
     /// Illegal operation which halts the cpu
     fn kil(&mut self, _mode: AddrMode) {
-        panic!("KIL opcode called");
+        // Halt the Cpu on the KIL opcode until the next reset
+        self.jammed = true;
+        self.pc = self.pc.wrapping_sub(1);
+        self.ins_cycles = 1;
     }
 
     /// ASL & ORA
@@ -1083,7 +1704,7 @@ impl<'a> Cpu<'a> {
 
         let result = self.asl(v);
         self.set_a(self.a() | result);
-        self.mem_write(addr, result);
+        self.rmw_write(addr, v, result);
     }
 
     /// ROL & AND
@@ -1093,7 +1714,7 @@ impl<'a> Cpu<'a> {
 
         let result = self.rol(v);
         self.set_a(self.a() & result);
-        self.mem_write(addr, result);
+        self.rmw_write(addr, v, result);
     }
 
     /// LSR & EOR
@@ -1103,7 +1724,7 @@ impl<'a> Cpu<'a> {
 
         let result = self.lsr(v);
         self.set_a(self.a() ^ result);
-        self.mem_write(addr, result);
+        self.rmw_write(addr, v, result);
     }
 
     /// ROR & ADC
@@ -1113,7 +1734,7 @@ impl<'a> Cpu<'a> {
 
         let result = self.ror(v);
         self.add(result);
-        self.mem_write(addr, result);
+        self.rmw_write(addr, v, result);
     }
 
     /// STA & STX
@@ -1141,19 +1762,21 @@ impl<'a> Cpu<'a> {
     /// DEC & CMP
     fn dcp(&mut self, mode: AddrMode) {
         let addr = self.operand_addr(mode);
-        let v = self.fetch_operand(addr, mode).wrapping_sub(1);
+        let old = self.fetch_operand(addr, mode);
+        let v = old.wrapping_sub(1);
 
         self.cmp(self.a(), v);
-        self.mem_write(addr, v);
+        self.rmw_write(addr, old, v);
     }
 
     /// INC & SBC
     fn isb(&mut self, mode: AddrMode) {
         let addr = self.operand_addr(mode);
-        let v = self.fetch_operand(addr, mode).wrapping_add(1);
+        let old = self.fetch_operand(addr, mode);
+        let v = old.wrapping_add(1);
 
         self.sub(v);
-        self.mem_write(addr, v);
+        self.rmw_write(addr, old, v);
     }
 
     /// AND with Carry flag
@@ -1239,9 +1862,9 @@ impl<'a> Cpu<'a> {
 mod tests {
     use super::*;
 
-    use crate::bus::TestBus;
+    use crate::bus::{SimpleBus, TestBus};
 
-    fn get_test_cpu(program: Vec<u8>, ram: Vec<u8>) -> Cpu<'static> {
+    fn get_test_cpu(program: Vec<u8>, ram: Vec<u8>) -> Cpu<TestBus> {
         let mut bus = TestBus::new(program);
         for (addr, data) in ram.iter().enumerate() {
             bus.set_ram(addr as u16, *data);
@@ -1251,7 +1874,7 @@ mod tests {
         cpu
     }
 
-    fn get_test_cpu_from_bus(bus: TestBus) -> Cpu<'static> {
+    fn get_test_cpu_from_bus(bus: TestBus) -> Cpu<TestBus> {
         let mut cpu = Cpu::new(bus);
         cpu.pc = 0x2000;
         cpu
@@ -2414,4 +3037,271 @@ mod tests {
         assert!(!cpu.p.contains(Flags::V));
         assert_eq!(cpu.a, 0x00u8.wrapping_sub(0x02));
     }
+
+    #[test]
+    fn test_02() {
+        let mut cpu = get_test_cpu(vec![0x02, 0xA9, 0x05], vec![]);
+        cpu.execute();
+
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.pc, 0x2000);
+
+        cpu.execute();
+        cpu.clock();
+
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.pc, 0x2000);
+        assert_eq!(cpu.a, 0x00);
+    }
+
+    #[test]
+    fn test_69_decimal() {
+        // (a, value, carry in, result, carry out)
+        let vectors = [
+            (0x12, 0x34, false, 0x46, false),
+            (0x09, 0x01, false, 0x10, false),
+            (0x58, 0x46, true, 0x05, true),
+            (0x81, 0x92, false, 0x73, true),
+        ];
+        for &(a, v, c, result, carry) in vectors.iter() {
+            let mut cpu = get_test_cpu(vec![0x69, v], vec![]);
+            cpu.set_decimal_mode(true);
+            cpu.p.insert(Flags::D);
+            cpu.p.set(Flags::C, c);
+            cpu.a = a;
+            cpu.execute();
+
+            assert_eq!(cpu.a, result);
+            assert_eq!(cpu.p.contains(Flags::C), carry);
+        }
+
+        // Z is set from the binary result
+        let mut cpu = get_test_cpu(vec![0x69, 0x01], vec![]);
+        cpu.set_decimal_mode(true);
+        cpu.p.insert(Flags::D);
+        cpu.a = 0x99;
+        cpu.execute();
+
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.p.contains(Flags::C));
+        assert!(!cpu.p.contains(Flags::Z));
+        assert!(cpu.p.contains(Flags::N));
+
+        // Decimal mode disabled ignores the D flag
+        let mut cpu = get_test_cpu(vec![0x69, 0x01], vec![]);
+        cpu.p.insert(Flags::D);
+        cpu.a = 0x09;
+        cpu.execute();
+
+        assert_eq!(cpu.a, 0x0A);
+    }
+
+    #[test]
+    fn test_e9_decimal() {
+        // (a, value, carry in, result, carry out)
+        let vectors = [
+            (0x46, 0x12, true, 0x34, true),
+            (0x40, 0x13, true, 0x27, true),
+            (0x32, 0x02, false, 0x29, true),
+            (0x12, 0x21, true, 0x91, false),
+            (0x21, 0x34, true, 0x87, false),
+        ];
+        for &(a, v, c, result, carry) in vectors.iter() {
+            let mut cpu = get_test_cpu(vec![0xE9, v], vec![]);
+            cpu.set_decimal_mode(true);
+            cpu.p.insert(Flags::D);
+            cpu.p.set(Flags::C, c);
+            cpu.a = a;
+            cpu.execute();
+
+            assert_eq!(cpu.a, result);
+            assert_eq!(cpu.p.contains(Flags::C), carry);
+        }
+    }
+
+    fn get_interrupt_test_bus() -> TestBus {
+        // BRK at 0x2000, IRQ handler at 0x3000 and NMI handler at 0x4000
+        let mut program = vec![0xEA; 0xE000];
+        program[0] = 0x00;
+        program[(NMI_VECTOR - 0x2000) as usize] = 0x00;
+        program[(NMI_VECTOR - 0x2000 + 1) as usize] = 0x40;
+        program[(IRQ_VECTOR - 0x2000) as usize] = 0x00;
+        program[(IRQ_VECTOR - 0x2000 + 1) as usize] = 0x30;
+        TestBus::new(program)
+    }
+
+    #[test]
+    fn test_00() {
+        let mut cpu = get_test_cpu_from_bus(get_interrupt_test_bus());
+        cpu.p.insert(Flags::I);
+        let cycles = cpu.execute();
+
+        // BRK ignores the disable interrupt flag
+        assert_eq!(cycles, 7);
+        assert_eq!(cpu.pc, 0x3000);
+        assert_eq!(cpu.s, 0xFA);
+        assert_eq!(cpu.mem_read_word(0x01FC), 0x2002);
+        assert!(Flags::from_bits_truncate(cpu.mem_read(0x01FB)).contains(Flags::B));
+    }
+
+    #[test]
+    fn test_brk_nmi_hijack() {
+        // NMI raised while BRK pushes on the stack
+        let mut bus = get_interrupt_test_bus();
+        bus.set_nmi_at(3);
+        let mut cpu = get_test_cpu_from_bus(bus);
+        cpu.execute();
+
+        assert_eq!(cpu.pc, 0x4000);
+        assert!(cpu.p.contains(Flags::I));
+        // The pushed status still has the Break flag
+        assert!(Flags::from_bits_truncate(cpu.mem_read(0x01FB)).contains(Flags::B));
+
+        // NMI raised after the vector fetch is serviced on the next instruction
+        let mut bus = get_interrupt_test_bus();
+        bus.set_nmi_at(6);
+        let mut cpu = get_test_cpu_from_bus(bus);
+        cpu.execute();
+
+        assert_eq!(cpu.pc, 0x3000);
+
+        cpu.execute();
+
+        assert_eq!(cpu.pc, 0x4001);
+        assert_eq!(cpu.mem_read_word(0x01F9), 0x3000);
+    }
+
+    #[test]
+    fn test_step_over() {
+        let mut program = vec![0xEA; 0x30];
+        // Main: JSR $2010, NOP, KIL
+        program[0x00..0x05].copy_from_slice(&[0x20, 0x10, 0x20, 0xEA, 0x02]);
+        // JSR $2020, INX, RTS
+        program[0x10..0x15].copy_from_slice(&[0x20, 0x20, 0x20, 0xE8, 0x60]);
+        // INY, RTS
+        program[0x20..0x22].copy_from_slice(&[0xC8, 0x60]);
+        let mut cpu = get_test_cpu(program.clone(), vec![0]);
+
+        // The whole call is one step
+        assert_eq!(cpu.step_over(), StepResult::Done);
+        assert_eq!((cpu.pc, cpu.s, cpu.x, cpu.y), (0x2003, STACK_RESET, 1, 1));
+        assert_eq!(cpu.step_over(), StepResult::Done);
+        assert_eq!(cpu.pc, 0x2004);
+        assert_eq!(cpu.step_over(), StepResult::Jammed);
+
+        // Breakpoints inside the subroutine stop the step
+        let mut cpu = get_test_cpu(program, vec![0]);
+        cpu.add_breakpoint(0x2013);
+        assert_eq!(cpu.step_over(), StepResult::Breakpoint);
+        assert_eq!((cpu.pc, cpu.x, cpu.y), (0x2013, 0, 1));
+
+        // The instruction on the breakpoint runs, then up to the end of the subroutine
+        assert_eq!(cpu.run_to_return(), StepResult::Done);
+        assert_eq!((cpu.pc, cpu.s, cpu.x), (0x2003, STACK_RESET, 1));
+
+        cpu.remove_breakpoint(0x2013);
+        cpu.pc = 0x2000;
+        assert_eq!(cpu.step_over(), StepResult::Done);
+        assert_eq!((cpu.pc, cpu.x, cpu.y), (0x2003, 2, 2));
+
+        // Nested calls return to their caller only
+        cpu.pc = 0x2010;
+        cpu.s = 0xFB;
+        cpu.execute();
+        assert_eq!(cpu.run_to_return(), StepResult::Done);
+        assert_eq!((cpu.pc, cpu.s), (0x2013, 0xFB));
+
+        // No return in the main loop
+        cpu.pc = 0x2003;
+        assert_eq!(cpu.run_to_return(), StepResult::Jammed);
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut program = vec![0xEA; 0x20];
+        // JSR $2010, then JMP * in the subroutine
+        program[0x00..0x03].copy_from_slice(&[0x20, 0x10, 0x20]);
+        program[0x10..0x13].copy_from_slice(&[0x4C, 0x10, 0x20]);
+        let mut cpu = get_test_cpu(program, vec![0]);
+
+        let start = cpu.cycles;
+        assert_eq!(cpu.step_over(), StepResult::Limit);
+        assert_eq!(cpu.pc, 0x2010);
+        assert!(cpu.cycles - start >= STEP_CYCLE_LIMIT);
+
+        let start = cpu.cycles;
+        assert_eq!(cpu.run_to_return(), StepResult::Limit);
+        assert_eq!(cpu.pc, 0x2010);
+        assert!(cpu.cycles - start >= STEP_CYCLE_LIMIT);
+
+        let mut program = vec![0xEA; 0x20];
+        // JSR $2010, then a subroutine running for a bit more than the limit
+        program[0x00..0x03].copy_from_slice(&[0x20, 0x10, 0x20]);
+        // LDY #0, LDX #0, DEX, BNE -3, DEY, BNE -8, RTS
+        program[0x10..0x1B].copy_from_slice(&[
+            0xA0, 0x00, 0xA2, 0x00, 0xCA, 0xD0, 0xFD, 0x88, 0xD0, 0xF8, 0x60,
+        ]);
+        let mut cpu = get_test_cpu(program, vec![0]);
+
+        assert_eq!(cpu.step_over(), StepResult::Limit);
+        assert!((0x2010..0x201A).contains(&cpu.pc));
+        // The next step finishes the call instead of stepping inside the subroutine
+        assert_eq!(cpu.step_over(), StepResult::Done);
+        assert_eq!((cpu.pc, cpu.s), (0x2003, STACK_RESET));
+    }
+
+    #[test]
+    fn test_thumbnail_state() {
+        let mut cpu = Cpu::new(SimpleBus::with_program(0x8000, &[0xE8]));
+        cpu.power_on();
+        cpu.execute();
+        let thumbnail = Frame::new().thumbnail(4);
+
+        let mut plain = vec![];
+        cpu.save(&mut plain).unwrap();
+        let mut with_thumbnail = vec![];
+        cpu.save_with_thumbnail(&mut with_thumbnail, &thumbnail)
+            .unwrap();
+        // The header comes first in both, then the thumbnail
+        let mut header = vec![];
+        savable::write_header(&mut header).unwrap();
+        assert!(plain.starts_with(&header) && with_thumbnail.starts_with(&header));
+        assert!(with_thumbnail.ends_with(&plain[header.len()..]));
+
+        // Both load, the thumbnail is skipped
+        for state in [plain.as_slice(), with_thumbnail.as_slice()] {
+            let mut loaded = Cpu::new(SimpleBus::new());
+            loaded.load(&mut &state[..]).unwrap();
+            assert_eq!((loaded.pc(), loaded.x()), (0x8001, 1));
+        }
+
+        assert_eq!(
+            savable::read_thumbnail(&mut with_thumbnail.as_slice()).unwrap(),
+            Some(thumbnail)
+        );
+        assert_eq!(
+            savable::read_thumbnail(&mut plain.as_slice()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_state_version() {
+        let cpu = Cpu::new(SimpleBus::with_program(0x8000, &[0xE8]));
+        let mut state = vec![];
+        cpu.save(&mut state).unwrap();
+
+        let mut loaded = Cpu::new(SimpleBus::new());
+        assert!(loaded.load(&mut state.as_slice()).is_ok());
+
+        // Another version of the format
+        state[savable::STATE_MAGIC.len()] += 1;
+        assert!(loaded.load(&mut state.as_slice()).is_err());
+        assert!(savable::read_thumbnail(&mut state.as_slice()).is_err());
+
+        // No header at all
+        assert!(loaded
+            .load(&mut &state[savable::STATE_MAGIC.len()..])
+            .is_err());
+    }
 }
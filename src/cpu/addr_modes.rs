@@ -19,6 +19,29 @@ pub enum AddrMode {
     IzyW, // Indirect with Y (Write)
 }
 
+impl AddrMode {
+    /// Bytes of operand following the opcode
+    pub fn operand_len(&self) -> u16 {
+        match *self {
+            AddrMode::None | AddrMode::Imp => 0,
+            AddrMode::Imm
+            | AddrMode::Zp0
+            | AddrMode::Zpx
+            | AddrMode::Zpy
+            | AddrMode::Rel
+            | AddrMode::Izx
+            | AddrMode::Izy
+            | AddrMode::IzyW => 1,
+            AddrMode::Abs
+            | AddrMode::Abx
+            | AddrMode::AbxW
+            | AddrMode::Aby
+            | AddrMode::AbyW
+            | AddrMode::Ind => 2,
+        }
+    }
+}
+
 impl std::fmt::Display for AddrMode {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
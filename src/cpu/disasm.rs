@@ -0,0 +1,139 @@
+//! Disassembler for debuggers. Only peeks at memory, so nothing changes while decoding
+
+use std::fmt::{self, Display};
+
+use super::{AddrMode, Cpu, CpuInterface, Op, OPTABLE};
+
+/// An instruction decoded from memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassembledInstruction {
+    /// Address of the opcode
+    pub addr: u16,
+    /// Opcode and operand bytes
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    /// Operand in assembly syntax, like `$10,X`. Empty if there is none
+    pub operand: String,
+}
+
+impl DisassembledInstruction {
+    /// Address of the instruction after this one
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+impl Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "{:04X}  {:<8}  {}",
+            self.addr,
+            bytes.join(" "),
+            self.mnemonic
+        )?;
+        if !self.operand.is_empty() {
+            write!(f, " {}", self.operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the instruction at `addr`
+pub fn disassemble<B: CpuInterface>(cpu: &Cpu<B>, addr: u16) -> DisassembledInstruction {
+    let ins = OPTABLE.get(&cpu.peek(addr)).unwrap();
+    let bytes: Vec<u8> = (0..=ins.mode.operand_len())
+        .map(|i| cpu.peek(addr.wrapping_add(i)))
+        .collect();
+    let word = || u16::from_le_bytes([bytes[1], bytes[2]]);
+
+    let operand = match ins.mode {
+        AddrMode::None => String::new(),
+        AddrMode::Imp => match ins.op {
+            Op::AslAcc | Op::LsrAcc | Op::RolAcc | Op::RorAcc => "A".to_string(),
+            _ => String::new(),
+        },
+        AddrMode::Imm => format!("#${:02X}", bytes[1]),
+        AddrMode::Zp0 => format!("${:02X}", bytes[1]),
+        AddrMode::Zpx => format!("${:02X},X", bytes[1]),
+        AddrMode::Zpy => format!("${:02X},Y", bytes[1]),
+        // Shows the branch target
+        AddrMode::Rel => {
+            let target = addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16);
+            format!("${:04X}", target)
+        }
+        AddrMode::Abs => format!("${:04X}", word()),
+        AddrMode::Abx | AddrMode::AbxW => format!("${:04X},X", word()),
+        AddrMode::Aby | AddrMode::AbyW => format!("${:04X},Y", word()),
+        AddrMode::Ind => format!("(${:04X})", word()),
+        AddrMode::Izx => format!("(${:02X},X)", bytes[1]),
+        AddrMode::Izy | AddrMode::IzyW => format!("(${:02X}),Y", bytes[1]),
+    };
+
+    DisassembledInstruction {
+        addr,
+        bytes,
+        mnemonic: ins.mnemonic,
+        operand,
+    }
+}
+
+/// Decodes `count` instructions, one after the other, starting at `start`
+pub fn disassemble_range<B: CpuInterface>(
+    cpu: &Cpu<B>,
+    start: u16,
+    count: usize,
+) -> Vec<DisassembledInstruction> {
+    let mut addr = start;
+    (0..count)
+        .map(|_| {
+            let ins = disassemble(cpu, addr);
+            addr = ins.next_addr();
+            ins
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::TestBus;
+
+    #[test]
+    fn test_disassemble_range() {
+        let code = [
+            0xA9, 0x10, // LDA #$10
+            0x0A, // ASL A
+            0x9D, 0x00, 0x02, // STA $0200,X
+            0xB1, 0x20, // LDA ($20),Y
+            0xD0, 0xF6, // BNE $2000
+            0x6C, 0x34, 0x12, // JMP ($1234)
+        ];
+        let mut program = vec![0; 0x10000 - 0x2000];
+        program[..code.len()].copy_from_slice(&code);
+        let cpu = Cpu::new(TestBus::new(program));
+
+        let lines: Vec<String> = disassemble_range(&cpu, 0x2000, 6)
+            .iter()
+            .map(|ins| ins.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "2000  A9 10     LDA #$10",
+                "2002  0A        ASL A",
+                "2003  9D 00 02  STA $0200,X",
+                "2006  B1 20     LDA ($20),Y",
+                "2008  D0 F6     BNE $2000",
+                "200A  6C 34 12  JMP ($1234)",
+            ]
+        );
+
+        let ins = disassemble(&cpu, 0x2003);
+        assert_eq!(ins.bytes, [0x9D, 0x00, 0x02]);
+        assert_eq!(ins.mnemonic, "STA");
+        assert_eq!(ins.operand, "$0200,X");
+        assert_eq!(ins.next_addr(), 0x2006);
+    }
+}
@@ -1,67 +1,72 @@
 // Debug module used to compare my cpu with Nintendulator's log of the nestest rom
 
-#![allow(dead_code)]
+use super::{AddrMode, Cpu, CpuInterface, OPTABLE};
 
-use crate::cpu::{AddrMode, Cpu, OPTABLE};
+impl<B: CpuInterface> Cpu<B> {
+    /// Peeks a word (2 bytes) at addr
+    fn peek_word(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.peek(addr), self.peek(addr.wrapping_add(1))])
+    }
 
-impl<'a> Cpu<'a> {
     /// Gets the operand addr without changing the program counter. Used in trace module
-    fn operand_addr_peek(&mut self, mode: AddrMode, pc: u16) -> u16 {
+    fn operand_addr_peek(&self, mode: AddrMode, pc: u16) -> u16 {
         match mode {
             AddrMode::None | AddrMode::Imp => 0,
             AddrMode::Imm | AddrMode::Rel => pc,
-            AddrMode::Zp0 => self.mem_read(pc) as u16,
+            AddrMode::Zp0 => self.peek(pc) as u16,
             AddrMode::Zpx => {
-                let base = self.mem_read(pc);
+                let base = self.peek(pc);
                 base.wrapping_add(self.x()) as u16
             }
             AddrMode::Zpy => {
-                let base = self.mem_read(pc);
+                let base = self.peek(pc);
                 base.wrapping_add(self.y()) as u16
             }
-            AddrMode::Abs | AddrMode::Ind => self.mem_read_word(pc),
+            AddrMode::Abs | AddrMode::Ind => self.peek_word(pc),
             AddrMode::Abx => {
-                let base = self.mem_read_word(pc);
+                let base = self.peek_word(pc);
                 base.wrapping_add(self.x() as u16)
             }
             AddrMode::AbxW => {
-                let base = self.mem_read_word(pc);
+                let base = self.peek_word(pc);
                 base.wrapping_add(self.x() as u16)
             }
             AddrMode::Aby => {
-                let base = self.mem_read_word(pc);
+                let base = self.peek_word(pc);
                 base.wrapping_add(self.y() as u16)
             }
             AddrMode::AbyW => {
-                let base = self.mem_read_word(pc);
+                let base = self.peek_word(pc);
                 base.wrapping_add(self.y() as u16)
             }
             AddrMode::Izx => {
-                let base = self.mem_read(pc);
+                let base = self.peek(pc);
                 let ptr = base.wrapping_add(self.x());
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+                let lo = self.peek(ptr as u16);
+                let hi = self.peek(ptr.wrapping_add(1) as u16);
                 u16::from_le_bytes([lo, hi])
             }
             AddrMode::Izy => {
-                let ptr = self.mem_read(pc);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+                let ptr = self.peek(pc);
+                let lo = self.peek(ptr as u16);
+                let hi = self.peek(ptr.wrapping_add(1) as u16);
                 u16::from_le_bytes([lo, hi]).wrapping_add(self.y() as u16)
             }
             AddrMode::IzyW => {
-                let ptr = self.mem_read(pc);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+                let ptr = self.peek(pc);
+                let lo = self.peek(ptr as u16);
+                let hi = self.peek(ptr.wrapping_add(1) as u16);
                 u16::from_le_bytes([lo, hi]).wrapping_add(self.y() as u16)
             }
         }
     }
 }
 
-/// Traces execution of the NES
-pub fn trace(cpu: &mut Cpu) -> String {
-    let code = cpu.mem_read(cpu.pc());
+/// Traces execution of the NES, in the format of Nintendulator without the Ppu position
+///
+/// Memory is only peeked, so tracing doesn't change how the game runs
+pub fn trace<B: CpuInterface>(cpu: &Cpu<B>) -> String {
+    let code = cpu.peek(cpu.pc());
     let ins = *OPTABLE.get(&code).unwrap();
 
     let begin = cpu.pc();
@@ -71,7 +76,7 @@ pub fn trace(cpu: &mut Cpu) -> String {
         AddrMode::Imm | AddrMode::None | AddrMode::Imp => (0, 0),
         _ => {
             let addr = cpu.operand_addr_peek(ins.mode, begin + 1);
-            (addr, cpu.mem_read(addr))
+            (addr, cpu.peek(addr))
         }
     };
 
@@ -88,7 +93,7 @@ pub fn trace(cpu: &mut Cpu) -> String {
         | AddrMode::Izy
         | AddrMode::IzyW
         | AddrMode::Rel => {
-            let address: u8 = cpu.mem_read(begin + 1);
+            let address: u8 = cpu.peek(begin + 1);
             hex_dump.push(address);
 
             match ins.mode {
@@ -135,12 +140,12 @@ pub fn trace(cpu: &mut Cpu) -> String {
         | AddrMode::Aby
         | AddrMode::AbyW
         | AddrMode::Ind => {
-            let address_lo = cpu.mem_read(begin + 1);
-            let address_hi = cpu.mem_read(begin + 2);
+            let address_lo = cpu.peek(begin + 1);
+            let address_hi = cpu.peek(begin + 2);
             hex_dump.push(address_lo);
             hex_dump.push(address_hi);
 
-            let address = cpu.mem_read_word(begin + 1);
+            let address = cpu.peek_word(begin + 1);
 
             match ins.mode {
                 AddrMode::Ind | AddrMode::Abs
@@ -149,11 +154,11 @@ pub fn trace(cpu: &mut Cpu) -> String {
                     if ins.opcode == 0x6C {
                         //jmp indirect
                         let jmp_addr = if address & 0x00FF == 0x00FF {
-                            let lo = cpu.mem_read(address);
-                            let hi = cpu.mem_read(address & 0xFF00);
+                            let lo = cpu.peek(address);
+                            let hi = cpu.peek(address & 0xFF00);
                             (hi as u16) << 8 | (lo as u16)
                         } else {
-                            cpu.mem_read_word(address)
+                            cpu.peek_word(address)
                         };
 
                         format!("(${:04x}) = {:04x}", address, jmp_addr)
@@ -199,3 +204,54 @@ pub fn trace(cpu: &mut Cpu) -> String {
     )
     .to_ascii_uppercase()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::bus::{MainBus, RamPattern};
+    use crate::cartridge::Cartridge;
+
+    /// Nintendulator log of nestest in automation mode
+    const NESTEST_LOG: &str = include_str!("../../roms/nestest.log");
+    /// The last lines write to the Apu registers, which Nintendulator shows as 0xFF
+    const NESTEST_LINES: usize = 8980;
+
+    #[test]
+    fn test_nestest() {
+        let cartridge = Cartridge::from_bytes(include_bytes!("../../roms/nestest.nes")).unwrap();
+        let bus = MainBus::new(
+            Rc::new(RefCell::new(cartridge)),
+            |_| {},
+            44100.0,
+            RamPattern::default(),
+        );
+        let mut cpu = Cpu::new(bus);
+        cpu.power_on();
+        // Automation mode, without the Ppu
+        cpu.set_pc(0xC000);
+
+        let mut expected = NESTEST_LOG.lines().take(NESTEST_LINES).enumerate();
+        cpu.run_with_callback(|cpu| {
+            let (count, line) = match expected.next() {
+                Some(line) => line,
+                None => return false,
+            };
+            // The Ppu position isn't traced
+            let ppu = line.find("PPU:").unwrap();
+            let cyc = line.find("CYC:").unwrap();
+            let line = format!("{}{}", &line[..ppu], &line[cyc..]);
+
+            let actual = trace(cpu);
+            assert_eq!(
+                actual, line,
+                "Mismatch after {} instructions\nexpected: {}\nactual:   {}",
+                count, line, actual
+            );
+            true
+        });
+    }
+}
@@ -8,5 +8,6 @@ mod lowpass;
 pub trait Filter {
     /// Filters an audio signal
     fn filter(&mut self, input: f32) -> f32;
+    /// Forgets the previous samples, like a filter that only got silence
     fn reset(&mut self);
 }
@@ -37,6 +37,7 @@ impl Filter for HighPass {
 }
 
 impl HighPass {
+    /// `freq`: Cutoff frequency in Hz. `resonance`: sqrt(2) for a flat response
     pub fn new(freq: f32, sample_rate: f32, resonance: f32) -> Self {
         let c = (PI * freq / sample_rate).tan();
         let a1 = 1.0 / (1.0 + resonance * c + c * c);
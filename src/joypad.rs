@@ -1,5 +1,9 @@
+use std::io::{Read, Write};
+
 use bitflags::bitflags;
 
+use crate::savable::Savable;
+
 bitflags! {
     /// State of the controller buttons
     struct State: u8 {
@@ -15,13 +19,14 @@ bitflags! {
 }
 
 /// Controller port of the NES
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum JoyPort {
     Port1,
     Port2,
 }
 
 /// Buttons on the NES controller
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Button {
     A,
     B,
@@ -39,6 +44,32 @@ pub struct JoyPad {
     strobe: bool,
     state: State,
     snapshot: u8,
+    post_read: u8,
+}
+
+impl Savable for JoyPad {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        // The buttons held are live input, loading a state keeps the current ones
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.strobe)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.snapshot)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.post_read)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        self.strobe = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.snapshot = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.post_read = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        // A strobing controller sees the buttons held now
+        self.reload();
+        Ok(())
+    }
+}
+
+impl Default for JoyPad {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl JoyPad {
@@ -47,18 +78,31 @@ impl JoyPad {
             strobe: false,
             state: State::from_bits_truncate(0),
             snapshot: 0,
+            post_read: 1,
         }
     }
 
+    /// Sets the value returned once the 8 buttons have been shifted out
+    ///
+    /// Standard controllers return 1, but some revisions return 0
+    pub fn set_post_read_value(&mut self, v: bool) {
+        self.post_read = v as u8;
+    }
+
     /// Strobes the controller
     ///
     /// If bit 0 is set, the controller continuously latches the current state of the buttons.
     /// If bit 0 is clear, stops latching
     pub fn strobe(&mut self, v: u8) {
+        self.strobe = v & 0x1 != 0;
+        self.reload();
+    }
+
+    /// While strobing, the shift register is reloaded with the live state of the buttons
+    fn reload(&mut self) {
         if self.strobe {
             self.snapshot = self.state.bits();
         }
-        self.strobe = v & 0x1 != 0;
     }
 
     /// Reads the controller input data
@@ -67,16 +111,31 @@ impl JoyPad {
     ///
     /// Buttons are always read in the order: A, B, Select, Start, Up, Down, Left, Right
     pub fn read(&mut self) -> u8 {
-        if self.strobe {
-            self.state.contains(State::A) as u8
-        } else {
-            let output = self.snapshot & 0x1;
-            self.snapshot >>= 1;
-            self.snapshot |= 0x80;
-            output
+        match self.strobe {
+            // The register is reloaded right away, so it never shifts
+            true => self.snapshot & 0x1,
+            false => {
+                let output = self.snapshot & 0x1;
+                self.snapshot >>= 1;
+                self.snapshot |= self.post_read << 7;
+                output
+            }
         }
     }
 
+    /// Returns the buttons held, A in bit 0 up to Right in bit 7
+    pub fn buttons(&self) -> u8 {
+        self.state.bits()
+    }
+
+    /// Sets all the buttons at once, A in bit 0 up to Right in bit 7
+    ///
+    /// Same layout as the movie files and the netplay packets
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.state = State::from_bits_truncate(buttons);
+        self.reload();
+    }
+
     /// Updates the state of the buttons
     ///
     /// This function is used to update the buttons from SDL2 keyboard events
@@ -91,5 +150,129 @@ impl JoyPad {
             Button::Left => self.state.set(State::LEFT, pressed),
             Button::Right => self.state.set(State::RIGHT, pressed),
         }
+        self.reload();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(joypad: &mut JoyPad) -> Vec<u8> {
+        joypad.strobe(1);
+        joypad.strobe(0);
+        (0..12).map(|_| joypad.read()).collect()
+    }
+
+    #[test]
+    fn test_post_read_one() {
+        let mut joypad = JoyPad::new();
+        joypad.update(Button::A, true);
+        joypad.update(Button::Right, true);
+        let reads = read_all(&mut joypad);
+        assert_eq!(reads, vec![1, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_ninth_read() {
+        // Nothing held, the 1 can only come from the shift register filling up
+        let mut joypad = JoyPad::new();
+        joypad.strobe(1);
+        joypad.strobe(0);
+        for _ in 0..8 {
+            assert_eq!(joypad.read(), 0);
+        }
+        assert_eq!(joypad.read(), 1);
+
+        // Until the next strobe
+        joypad.strobe(1);
+        joypad.strobe(0);
+        assert_eq!(joypad.read(), 0);
+    }
+
+    #[test]
+    fn test_strobe_high() {
+        let mut joypad = JoyPad::new();
+        joypad.strobe(1);
+        assert_eq!(joypad.read(), 0);
+
+        // The reads follow the A button while strobing
+        joypad.update(Button::A, true);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1);
+        joypad.update(Button::A, false);
+        joypad.update(Button::B, true);
+        assert_eq!(joypad.read(), 0);
+
+        // The state when the strobe goes low is shifted out
+        joypad.update(Button::A, true);
+        joypad.strobe(0);
+        joypad.update(Button::A, false);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 0);
+    }
+
+    #[test]
+    fn test_set_buttons() {
+        let mut joypad = JoyPad::new();
+        joypad.set_buttons(0x81);
+        assert_eq!(joypad.buttons(), 0x81);
+        assert_eq!(
+            read_all(&mut joypad),
+            vec![1, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1]
+        );
+
+        // Same as pressing the buttons one by one
+        let mut pressed = JoyPad::new();
+        pressed.update(Button::Select, true);
+        pressed.update(Button::Up, true);
+        joypad.set_buttons(0x14);
+        assert_eq!(joypad.buttons(), pressed.buttons());
+
+        // Reloads the shift register while strobing
+        joypad.strobe(1);
+        joypad.set_buttons(0x01);
+        assert_eq!(joypad.read(), 1);
+    }
+
+    #[test]
+    fn test_post_read_zero() {
+        let mut joypad = JoyPad::new();
+        joypad.set_post_read_value(false);
+        joypad.update(Button::A, true);
+        joypad.update(Button::Right, true);
+        let reads = read_all(&mut joypad);
+        assert_eq!(reads, vec![1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_load_keeps_buttons() {
+        let mut joypad = JoyPad::new();
+        joypad.update(Button::Start, true);
+        joypad.strobe(1);
+        let mut state = Vec::new();
+        joypad.save(&mut state).unwrap();
+
+        // No keys held when the state is loaded
+        let mut loaded = JoyPad::new();
+        loaded.load(&mut &state[..]).unwrap();
+        assert_eq!(loaded.buttons(), 0);
+        assert_eq!(
+            read_all(&mut loaded),
+            vec![0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1]
+        );
+
+        // The shift register is restored mid read
+        joypad.strobe(0);
+        joypad.read();
+        let mut state = Vec::new();
+        joypad.save(&mut state).unwrap();
+        let mut loaded = JoyPad::new();
+        loaded.load(&mut &state[..]).unwrap();
+        assert_eq!(
+            (0..3).map(|_| loaded.read()).collect::<Vec<_>>(),
+            vec![0, 0, 1]
+        );
     }
 }
@@ -0,0 +1,14 @@
+//! NES emulation core, without the SDL2 frontend
+//!
+//! Also used by the benchmarks
+
+pub mod apu;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod filters;
+pub mod joypad;
+pub mod lockstep;
+pub mod ppu;
+pub mod resampler;
+pub mod savable;
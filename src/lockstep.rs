@@ -0,0 +1,172 @@
+//! Deterministic core for netplay
+//!
+//! Runs exactly one frame per step with the input of both players, and saves and restores the
+//! whole machine in memory for rollbacks. The same ROM, RAM pattern and inputs always give the
+//! same frames. The network part is left to the frontend
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bus::{MainBus, RamPattern};
+use crate::cartridge::Cartridge;
+use crate::cpu::Cpu;
+use crate::joypad::JoyPort;
+use crate::ppu::frame::Frame;
+use crate::savable::Savable;
+
+/// Console stepped one frame at a time
+pub struct Lockstep<'a> {
+    cpu: Cpu<MainBus<'a>>,
+}
+
+impl<'a> Lockstep<'a> {
+    /// Powers on the console. All the peers need the same ROM and RAM pattern
+    pub fn new(cartridge: Cartridge, sample_rate: f64, ram_pattern: RamPattern) -> Self {
+        let bus = MainBus::new(
+            Rc::new(RefCell::new(cartridge)),
+            |_| {},
+            sample_rate,
+            ram_pattern,
+        );
+        let mut cpu = Cpu::new(bus);
+        cpu.power_on();
+        Self { cpu }
+    }
+
+    /// Runs one frame with the buttons of both players, A in bit 0 up to Right in bit 7
+    ///
+    /// Each step stops at the start of vblank, so the NMI at the start of the next step reads
+    /// the buttons given to it
+    ///
+    /// Returns the audio samples of the frame. They can be dropped while replaying frames
+    /// after a rollback
+    pub fn step_frame(&mut self, p1_input: u8, p2_input: u8) -> Vec<f32> {
+        self.cpu.set_joypad_state(JoyPort::Port1, p1_input);
+        self.cpu.set_joypad_state(JoyPort::Port2, p2_input);
+        self.cpu.run_until_frame();
+        self.cpu.samples()
+    }
+
+    /// Frames run since power on
+    pub fn frame_count(&self) -> u128 {
+        self.cpu.frame_count()
+    }
+
+    /// Last frame, complete after each step
+    pub fn frame(&self) -> Option<&Frame> {
+        self.cpu.frame()
+    }
+
+    /// The console, to read the memory or the registers
+    pub fn cpu(&self) -> &Cpu<MainBus<'a>> {
+        &self.cpu
+    }
+
+    /// Saves the whole machine in memory
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut state = vec![];
+        // Writing in a Vec doesn't fail and the state is always serializable
+        self.cpu
+            .save(&mut state)
+            .expect("Error while saving the state in memory");
+        state
+    }
+
+    /// Goes back to a snapshot. Only the audio filters aren't restored, the emulation is
+    /// the same
+    pub fn restore(&mut self, state: &[u8]) -> bincode::Result<()> {
+        self.cpu.load(&mut &state[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Enables the NMI, then reads the first controller in every NMI and adds the buttons to
+    /// 0x00. 0x01 counts the NMIs
+    fn lockstep() -> Lockstep<'static> {
+        let mut rom = vec![0; 16 + 0x4000 + 0x2000];
+        rom[..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+        let prg = &mut rom[16..16 + 0x4000];
+        // LDA #$80, STA $2000 in a loop, the writes are ignored until the Ppu is warmed up
+        prg[..8].copy_from_slice(&[0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x00, 0x80]);
+        prg[0x10..0x2F].copy_from_slice(&[
+            0xA9, 0x01, 0x8D, 0x16, 0x40, // Strobe
+            0xA9, 0x00, 0x8D, 0x16, 0x40, //
+            0xA2, 0x08, // 8 buttons
+            0xAD, 0x16, 0x40, 0x4A, 0x26, 0x02, 0xCA, 0xD0, 0xF7, // Shift them in 0x02
+            0xA5, 0x02, 0x18, 0x65, 0x00, 0x85, 0x00, // Add to 0x00
+            0xE6, 0x01, // Count
+            0x40, // RTI
+        ]);
+        // NMI and reset vectors
+        prg[0x3FFA..0x3FFE].copy_from_slice(&[0x10, 0x80, 0x00, 0x80]);
+        Lockstep::new(
+            Cartridge::from_bytes(&rom).unwrap(),
+            44100.0,
+            RamPattern::default(),
+        )
+    }
+
+    fn inputs(frame: u8) -> (u8, u8) {
+        (frame.wrapping_mul(37), frame ^ 0x5A)
+    }
+
+    #[test]
+    fn test_step_frame() {
+        let mut a = lockstep();
+        let mut b = lockstep();
+        for frame in 0..10 {
+            let (p1, p2) = inputs(frame);
+            a.step_frame(p1, p2);
+            b.step_frame(p1, p2);
+            assert_eq!(a.frame_count(), frame as u128 + 1);
+        }
+        // The game ran and read the input
+        assert!(a.cpu().peek(0x0001) > 0);
+        assert_ne!(a.cpu().peek(0x0000), 0);
+        // Same inputs, same machine
+        assert_eq!(a.snapshot(), b.snapshot());
+
+        // Another input changes the game
+        a.step_frame(0x01, 0);
+        b.step_frame(0x02, 0);
+        a.step_frame(0, 0);
+        b.step_frame(0, 0);
+        assert_ne!(a.cpu().peek(0x0000), b.cpu().peek(0x0000));
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut lockstep = lockstep();
+        for frame in 0..5 {
+            let (p1, p2) = inputs(frame);
+            lockstep.step_frame(p1, p2);
+        }
+        let state = lockstep.snapshot();
+
+        let run = |lockstep: &mut Lockstep| {
+            (5..15)
+                .map(|frame| {
+                    let (p1, p2) = inputs(frame);
+                    lockstep.step_frame(p1, p2);
+                    (
+                        lockstep.frame().unwrap().hash(),
+                        lockstep.cpu().peek(0x0000),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = run(&mut lockstep);
+        let end = lockstep.snapshot();
+
+        // Replaying from the snapshot gives the same frames and the same end state
+        lockstep.restore(&state).unwrap();
+        assert_eq!(lockstep.frame_count(), 5);
+        assert_eq!(run(&mut lockstep), first);
+        assert_eq!(lockstep.snapshot(), end);
+
+        assert!(lockstep.restore(&state[..10]).is_err());
+    }
+}
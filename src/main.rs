@@ -1,58 +1,265 @@
 use sdl2::keyboard::Keycode;
 
-use cartridge::Cartridge;
-use joypad::{Button, JoyPort};
-use nes::Mode;
-
-mod apu;
-mod bus;
-mod cartridge;
-mod cpu;
-mod decay;
-mod filters;
-mod joypad;
+use nes::{AudioConfig, Mode, Overscan, Replay, Settings, Tracer};
+use nesoxyde::bus::RamPattern;
+use nesoxyde::cartridge::Cartridge;
+use nesoxyde::joypad::{Button, JoyPort};
+use nesoxyde::resampler::ResamplerQuality;
+
 mod nes;
-mod ppu;
 mod reverb;
-mod savable;
 mod timer;
 
+/// Program arguments
+struct Args<'a> {
+    /// Emulation sync mode, overrides the saved one
+    mode: Option<Mode>,
+    /// Borders cropped from the frame, overrides the saved ones
+    overscan: Option<Overscan>,
+    /// iNES file to run
+    rom: &'a String,
+    /// Replay script to run headlessly
+    script: Option<&'a String>,
+    /// Frames to run headlessly
+    frames: Option<u128>,
+    /// PNG file for the last frame of a headless run
+    screenshot: Option<&'a String>,
+    /// Game Genie codes to activate
+    codes: Vec<&'a String>,
+    /// Contents of the RAM at power on
+    ram_pattern: RamPattern,
+    /// Frames skipped between 2 rendered frames
+    frame_skip: u32,
+    /// Quality of the audio resampling
+    resampler: ResamplerQuality,
+    /// Extra scanlines of Cpu time per frame
+    overclock: u32,
+    /// DIP switches of a VS System game
+    dip_switches: u8,
+    /// File for the trace of the last instructions
+    trace: Option<&'a String>,
+    /// The file is a 6502 snake game, not an iNES file
+    snake: bool,
+    /// Seed of the random numbers of the snake game
+    seed: Option<u64>,
+}
+
+/// Prints the usage and exits
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [-A | -V] [--replay <script>] [--frames <n>] [--screenshot <png>] [--gg <code>]... [--ram <zeros|ones|pattern>] [--frameskip <n>] [--resampler <nearest|low|high>] [--overclock <scanlines>] [--dip <switches>] [--overscan <top,bottom,left,right>] [-T <logfile>] <iNES File>\n       {} --snake [--seed <n>] <6502 program>",
+        program, program
+    );
+    std::process::exit(0);
+}
+
 /// Parses program arguments
-fn parse_args(args: &[String]) -> (Mode, &String) {
-    if args.len() != 2 && args.len() != 3 {
-        eprintln!("Usage: {} [-V] <iNES File>", args[0]);
-        std::process::exit(0);
-    }
+fn parse_args(args: &[String]) -> Args<'_> {
+    let mut mode = None;
+    let mut overscan = None;
+    let mut rom = None;
+    let mut script = None;
+    let mut frames = None;
+    let mut screenshot = None;
+    let mut codes = Vec::new();
+    let mut ram_pattern = RamPattern::default();
+    let mut frame_skip = 0;
+    let mut resampler = ResamplerQuality::default();
+    let mut overclock = 0;
+    let mut dip_switches = 0;
+    let mut trace = None;
+    let mut snake = false;
+    let mut seed = None;
 
-    match args.len() {
-        // Default to AudioSync
-        2 => (nes::Mode::AudioSync, &args[1]),
-        3 => match args[1].as_str() {
-            "-V" => (nes::Mode::VideoSync, &args[2]),
-            flag => {
-                eprintln!("Bad option flag: {}. Use -V for video sync", flag);
-                std::process::exit(0);
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-V" => mode = Some(Mode::VideoSync),
+            "-A" => mode = Some(Mode::AudioSync),
+            "--replay" => match iter.next() {
+                Some(path) => script = Some(path),
+                None => usage(&args[0]),
+            },
+            "--frames" => match iter.next().map(|s| s.parse()) {
+                Some(Ok(n)) if n > 0 => frames = Some(n),
+                _ => usage(&args[0]),
+            },
+            "--screenshot" => match iter.next() {
+                Some(path) => screenshot = Some(path),
+                None => usage(&args[0]),
+            },
+            "--gg" => match iter.next() {
+                Some(code) => codes.push(code),
+                None => usage(&args[0]),
+            },
+            "--ram" => match iter.next().map(|s| s.as_str()) {
+                Some("zeros") => ram_pattern = RamPattern::Zeros,
+                Some("ones") => ram_pattern = RamPattern::Ones,
+                Some("pattern") => ram_pattern = RamPattern::PowerOn,
+                _ => usage(&args[0]),
+            },
+            "--frameskip" => match iter.next().map(|s| s.parse()) {
+                Some(Ok(skip)) => frame_skip = skip,
+                _ => usage(&args[0]),
+            },
+            "--resampler" => match iter.next().map(|s| s.as_str()) {
+                Some("nearest") => resampler = ResamplerQuality::Nearest,
+                Some("low") => resampler = ResamplerQuality::Low,
+                Some("high") => resampler = ResamplerQuality::High,
+                _ => usage(&args[0]),
+            },
+            "--overclock" => match iter.next().map(|s| s.parse()) {
+                Some(Ok(scanlines)) => overclock = scanlines,
+                _ => usage(&args[0]),
+            },
+            // Switch 1 first, for example 01000000
+            "--dip" => match iter
+                .next()
+                .map(|s| u8::from_str_radix(&s.chars().rev().collect::<String>(), 2))
+            {
+                Some(Ok(switches)) => dip_switches = switches,
+                _ => usage(&args[0]),
+            },
+            "--overscan" => match iter.next().map(|s| Overscan::parse(s)) {
+                Some(Some(margins)) => overscan = Some(margins),
+                _ => usage(&args[0]),
+            },
+            "-T" => match iter.next() {
+                Some(path) => trace = Some(path),
+                None => usage(&args[0]),
+            },
+            "--snake" => snake = true,
+            "--seed" => match iter.next().map(|s| s.parse()) {
+                Some(Ok(n)) => seed = Some(n),
+                _ => usage(&args[0]),
+            },
+            flag if flag.starts_with('-') => {
+                eprintln!("Bad option flag: {}", flag);
+                usage(&args[0]);
             }
-        },
-        count => {
-            eprintln!("Bad argument count: {}, expected 2 or 3", count);
-            std::process::exit(0);
+            _ if rom.is_none() => rom = Some(arg),
+            _ => usage(&args[0]),
         }
     }
+
+    // Screenshots are only taken by the headless runs
+    if screenshot.is_some() && script.is_none() && frames.is_none() {
+        usage(&args[0]);
+    }
+    // The seed is only for the snake game
+    if seed.is_some() && !snake {
+        usage(&args[0]);
+    }
+
+    match rom {
+        Some(rom) => Args {
+            mode,
+            overscan,
+            rom,
+            script,
+            frames,
+            screenshot,
+            codes,
+            ram_pattern,
+            frame_skip,
+            resampler,
+            overclock,
+            dip_switches,
+            trace,
+            snake,
+            seed,
+        },
+        None => usage(&args[0]),
+    }
 }
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    let (mode, rom) = parse_args(&args);
+    let Args {
+        mode,
+        overscan,
+        rom,
+        script,
+        frames,
+        screenshot,
+        codes,
+        ram_pattern,
+        frame_skip,
+        resampler,
+        overclock,
+        dip_switches,
+        trace,
+        snake,
+        seed,
+    } = parse_args(&args);
+
+    // Run the snake game instead of a NES game
+    if snake {
+        let result = match std::fs::read(rom) {
+            Ok(program) => nes::run_snake(&program, seed),
+            Err(e) => {
+                eprintln!("Problem while loading program \"{}\" -> {}", rom, e);
+                return;
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Problem while starting the snake game -> {}", e);
+        }
+        return;
+    }
 
     // Load the rom from iNES file
-    let cartridge = match Cartridge::new(rom) {
+    let bytes = match std::fs::read(rom) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Problem while loading ROM \"{}\" -> {}", rom, e);
+            std::process::exit(0);
+        }
+    };
+    let mut cartridge = match Cartridge::new(rom, &bytes) {
         Ok(cart) => cart,
         Err(e) => {
             eprintln!("Problem while loading ROM \"{}\" -> {}", rom, e);
             std::process::exit(0);
         }
     };
+    nes::print_rom_info(&cartridge);
+
+    if cartridge.is_vs_system() {
+        println!(
+            "VS System DIP switches: {:08b}",
+            dip_switches.reverse_bits()
+        );
+    }
+    cartridge.set_dip_switches(dip_switches);
+
+    // Activate the Game Genie codes
+    for code in codes {
+        match cartridge.add_game_genie_code(code) {
+            Ok(decoded) => println!("Game Genie {} -> {:X?}", code, decoded),
+            Err(e) => {
+                eprintln!("Bad Game Genie code \"{}\" -> {}", code, e);
+                std::process::exit(0);
+            }
+        }
+    }
+
+    // Run headlessly, with the inputs of the replay script
+    if script.is_some() || frames.is_some() {
+        let replay = match script {
+            Some(script) => match Replay::from_file(script) {
+                Ok(replay) => replay,
+                Err(e) => {
+                    eprintln!("Problem while loading replay \"{}\" -> {}", script, e);
+                    return;
+                }
+            },
+            None => Replay::new(),
+        };
+        let screenshot = screenshot.map(|s| s.as_str());
+        nes::run_headless(cartridge, replay, ram_pattern, frames, screenshot);
+        return;
+    }
 
     // Closure which maps keycodes to NES buttons
     let map_key = |key: Keycode, port: JoyPort| match port {
@@ -82,6 +289,31 @@ fn main() {
         },
     };
 
+    // The settings of the last run and the profile of the game, with the mode of the command line
+    let mut settings = Settings::load();
+    settings.apply_profile(cartridge.rom_hash());
+    if let Some(mode) = mode {
+        settings.mode = mode;
+    }
+    if let Some(overscan) = overscan {
+        settings.overscan = overscan;
+    }
+
     // Run the game
-    nes::run(cartridge, map_key, mode);
+    let result = nes::run(
+        cartridge,
+        map_key,
+        settings,
+        AudioConfig {
+            resampler,
+            ..AudioConfig::default()
+        },
+        ram_pattern,
+        frame_skip,
+        overclock,
+        trace.map(|path| Tracer::new(path)),
+    );
+    if let Err(e) = result {
+        eprintln!("Problem while starting the emulator -> {}", e);
+    }
 }
@@ -1,76 +1,188 @@
 use sdl2::audio::AudioSpecDesired;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::TextureValueError;
+use sdl2::video::{FullscreenType, WindowBuildError};
+use sdl2::IntegerOrSdlError;
 use spin_sleep::SpinSleeper;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::fmt::{self, Display};
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
 use std::rc::Rc;
 use std::time::Duration;
 
-use crate::bus::MainBus;
-use crate::cartridge::Cartridge;
-use crate::cpu::Cpu;
-use crate::joypad::{Button, JoyPort};
-use crate::reverb::Reverb;
-use crate::savable::Savable;
 use crate::timer::Timer;
+use nesoxyde::bus::{MainBus, RamPattern, SnakeBus};
+use nesoxyde::cartridge::{Cartridge, CartridgeError, MirrorMode};
+use nesoxyde::cpu::Cpu;
+use nesoxyde::joypad::{Button, JoyPort};
+pub use nesoxyde::ppu::frame::{HEIGHT, WIDTH};
+use nesoxyde::savable::Savable;
+use serde::{Deserialize, Serialize};
 
 /// Time between each frame (at 60fps)
 const SECS_PER_FRAME: f64 = 1.0 / 60.0;
+/// Frame rate of the real NTSC console, 100% speed in the stats
+const NTSC_FPS: f64 = 60.0988;
 
 static WINDOW_TITLE: &str = "NesOxyde";
-/// NES screen width
-pub const WIDTH: u32 = 256;
-/// NES screen height
-pub const HEIGHT: u32 = 240;
 
 /// Step when adjusting volume
 const VOLUME_STEP: f32 = 0.05;
+/// Bounds of the audio buffers queued ahead in audio sync mode
+const MIN_AUDIO_BUFFERS: u32 = 1;
+const MAX_AUDIO_BUFFERS: u32 = 16;
+/// The thumbnails of the save states are 64x60
+const THUMBNAIL_FACTOR: u32 = 4;
+/// Emulation speed multipliers, from slowest to fastest
+const SPEEDS: [f64; 4] = [0.25, 0.5, 1.0, 2.0];
 
-mod trace;
+mod audio;
+mod display;
+mod gamepad;
+mod ntsc;
+mod png;
+mod replay;
+mod settings;
+mod snake;
+mod tracer;
+
+pub use audio::AudioConfig;
+pub use display::Overscan;
+use gamepad::Gamepads;
+use ntsc::{Ntsc, NTSC_WIDTH};
+pub use replay::Replay;
+pub use settings::Settings;
+pub use snake::run_snake;
+pub use tracer::Tracer;
 
 /// Emulation sync mode
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Mode {
     VideoSync,
     AudioSync,
 }
 
+/// Errors while starting the frontend
+#[derive(Debug)]
+pub enum NesError {
+    /// SDL or one of its subsystems failed to start
+    Sdl(String),
+    /// The window could not be created
+    Window(WindowBuildError),
+    /// The renderer could not be created
+    Canvas(IntegerOrSdlError),
+    /// The textures of the frame could not be created
+    Texture(TextureValueError),
+    /// The audio device could not be opened
+    Audio(String),
+    /// The snake program doesn't fit in memory, with its size in bytes
+    ProgramTooBig(usize),
+}
+
+impl Display for NesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NesError::Sdl(e) => write!(f, "SDL init failed: {}", e),
+            NesError::Window(e) => write!(f, "Window creation failed: {}", e),
+            NesError::Canvas(e) => write!(f, "Renderer creation failed: {}", e),
+            NesError::Texture(e) => write!(f, "Texture creation failed: {}", e),
+            NesError::Audio(e) => write!(f, "Audio device unavailable: {}", e),
+            NesError::ProgramTooBig(size) => write!(
+                f,
+                "Program too big: {} bytes, at most {} fit in memory",
+                size,
+                SnakeBus::MAX_PROGRAM_SIZE
+            ),
+        }
+    }
+}
+
+impl Error for NesError {}
+
+impl From<WindowBuildError> for NesError {
+    fn from(e: WindowBuildError) -> Self {
+        NesError::Window(e)
+    }
+}
+
+impl From<IntegerOrSdlError> for NesError {
+    fn from(e: IntegerOrSdlError) -> Self {
+        NesError::Canvas(e)
+    }
+}
+
+impl From<TextureValueError> for NesError {
+    fn from(e: TextureValueError) -> Self {
+        NesError::Texture(e)
+    }
+}
+
 /// Runs the emulation
-pub fn run<KeyMap>(cartridge: Cartridge, map_key: KeyMap, mode: Mode)
+///
+/// Fails if the window or the audio device can't be opened
+#[allow(clippy::too_many_arguments)]
+pub fn run<KeyMap>(
+    cartridge: Cartridge,
+    map_key: KeyMap,
+    mut settings: Settings,
+    audio: AudioConfig,
+    ram_pattern: RamPattern,
+    frame_skip: u32,
+    overclock: u32,
+    mut tracer: Option<Tracer>,
+) -> Result<(), NesError>
 where
     KeyMap: Fn(Keycode, JoyPort) -> Option<Button>,
 {
     // SDL2 init ----------------->
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
-    let filename = cartridge.filename();
-    let savestate_file = format!("{}.save", &filename);
-    let formated_name = if filename.is_empty() {
-        "".to_string()
-    } else {
-        format!(" - {}", &filename)
-    };
+    let sdl_context = sdl2::init().map_err(NesError::Sdl)?;
+    let video_subsystem = sdl_context.video().map_err(NesError::Sdl)?;
+    let audio_subsystem = sdl_context.audio().map_err(NesError::Audio)?;
+    // Controllers already plugged in are added by the first events
+    let mut gamepads = Gamepads::new(sdl_context.game_controller().map_err(NesError::Sdl)?);
+    let mut filename = cartridge.filename();
+    // Shift + a number key selects the slot
+    let mut save_slot = 1;
+    let mut savestate_file = savestate_path(&filename, save_slot);
+    let mut dump_file = format!("{}.json", &filename);
+    // The scaling is shared with the render function, which resizes the window
+    let scaling = Rc::new(Cell::new(settings.scaling()));
+    let resize = Rc::new(Cell::new(false));
+    let toggle_fullscreen = Rc::new(Cell::new(false));
+    let ntsc_enabled = Rc::new(Cell::new(settings.ntsc_filter));
+    // New window title, set by the render function
+    let title = Rc::new(Cell::new(None::<String>));
+    let mut base_title = window_title(&filename);
+    let (window_w, window_h) = scaling.get().window_size();
     let window = video_subsystem
-        .window(
-            &format!("{}{}", WINDOW_TITLE, &formated_name),
-            WIDTH * 2,
-            HEIGHT * 2,
-        )
+        .window(&base_title, window_w, window_h)
         .position_centered()
         .resizable()
-        .build()
-        .unwrap();
+        .build()?;
 
-    let mut canvas = window.into_canvas().build().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut canvas = window.into_canvas().build()?;
+    let mut event_pump = sdl_context.event_pump().map_err(NesError::Sdl)?;
     let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
-        .unwrap();
+    // The filtering is picked when the textures are created
+    let create_textures = || -> Result<_, TextureValueError> {
+        let texture =
+            creator.create_texture_target(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)?;
+        let ntsc_texture =
+            creator.create_texture_target(PixelFormatEnum::RGB24, NTSC_WIDTH, HEIGHT)?;
+        Ok((texture, ntsc_texture))
+    };
+    let linear_filter = Rc::new(Cell::new(settings.linear_filter));
+    let mut texture_filter = settings.linear_filter;
+    set_texture_filter(texture_filter);
+    let (mut texture, mut ntsc_texture) = create_textures()?;
+    // The texture doesn't hold the last frame, it has to be uploaded even if it didn't change
+    let mut texture_stale = true;
+    let mut ntsc = Ntsc::new();
 
     let buffer_size = 1024;
     let sample_rate = 44100;
@@ -79,35 +191,144 @@ where
         channels: Some(1),
         samples: Some(buffer_size),
     };
-    let queue = audio_subsystem.open_queue::<f32, _>(None, &spec).unwrap();
+    let queue = audio_subsystem
+        .open_queue::<f32, _>(None, &spec)
+        .map_err(NesError::Audio)?;
     queue.resume();
 
     let mut samples = vec![0.0; 1024];
-    let mut volume = 0.5;
+    let mut volume = settings.volume.clamp(0.0, 1.0);
+    let mut audio_buffers = settings
+        .audio_buffers
+        .clamp(MIN_AUDIO_BUFFERS, MAX_AUDIO_BUFFERS);
+    let latency_ms = |buffers: u32| buffers as usize * buffer_size as usize * 1000 / sample_rate;
+    // Unknown speeds in the settings go back to 1x
+    let mut speed = SPEEDS
+        .iter()
+        .position(|&s| s == settings.speed)
+        .unwrap_or(2);
 
-    let mut reverbs = [
-        Reverb::new(330, sample_rate, 0.15),
-        Reverb::new(150, sample_rate, 0.1),
-        Reverb::new(285, sample_rate, 0.05),
-    ];
+    let mut reverbs = audio.new_reverbs(sample_rate);
+    let mut reverb = settings.reverb;
+    let mut limiter = settings.limiter;
+    let mut low_input_lag = settings.low_input_lag;
+    let mode = settings.mode;
 
     println!("Audio driver: {}", audio_subsystem.current_audio_driver());
     println!("Emulation mode: {:?}", &mode);
+    if frame_skip > 0 {
+        println!("Frame skip: {}", frame_skip);
+    }
+    if overclock > 0 {
+        println!("Overclock: {} scanlines", overclock);
+    }
     println!("Vol: {:.0}", volume * 100.0);
+    println!("Reverb: {}", if reverb { "on" } else { "off" });
+    println!("Limiter: {}", if limiter { "on" } else { "off" });
+    if low_input_lag {
+        println!("Low input lag: on");
+    }
+    println!("Resampler: {:?}", audio.resampler);
+    println!(
+        "Audio latency: {} buffers ({} ms)",
+        audio_buffers,
+        latency_ms(audio_buffers)
+    );
+    if SPEEDS[speed] != 1.0 {
+        println!("Speed: {}x", SPEEDS[speed]);
+    }
     // >----------------- SDL2 init
 
-    let bus = MainBus::new(
-        Rc::new(RefCell::new(cartridge)),
-        move |frame| {
-            texture.update(None, frame, (WIDTH * 3) as usize).unwrap();
-            canvas.copy(&texture, None, None).unwrap();
-            canvas.present();
-        },
+    let render_scaling = Rc::clone(&scaling);
+    let render_resize = Rc::clone(&resize);
+    let render_fullscreen = Rc::clone(&toggle_fullscreen);
+    let render_ntsc = Rc::clone(&ntsc_enabled);
+    let render_filter = Rc::clone(&linear_filter);
+    let render_title = Rc::clone(&title);
+    // Kept to change the mirroring while debugging
+    let cartridge = Rc::new(RefCell::new(cartridge));
+    let mut bus = MainBus::new(
+        Rc::clone(&cartridge),
+        |_| {},
         sample_rate as f64,
+        ram_pattern,
     );
+    bus.set_render_fn(move |frame, info| {
+        let scaling = render_scaling.get();
+        // Resize the window if the scale changed
+        if render_resize.take() {
+            let (w, h) = scaling.window_size();
+            canvas.window_mut().set_size(w, h).unwrap();
+        }
+        // Switch between windowed and fullscreen
+        if render_fullscreen.take() {
+            let window = canvas.window_mut();
+            let state = match window.fullscreen_state() {
+                FullscreenType::Off => FullscreenType::Desktop,
+                _ => FullscreenType::Off,
+            };
+            if let Err(e) = window.set_fullscreen(state) {
+                println!("Error while toggling fullscreen: {}", e);
+            }
+        }
+        // Recreate the textures if the filtering changed
+        if render_filter.get() != texture_filter {
+            texture_filter = render_filter.get();
+            set_texture_filter(texture_filter);
+            match create_textures() {
+                Ok(textures) => {
+                    (texture, ntsc_texture) = textures;
+                    texture_stale = true;
+                }
+                Err(e) => println!("Error while changing the texture filtering: {}", e),
+            }
+        }
+        // Show the stats in the title
+        if let Some(title) = render_title.take() {
+            canvas.window_mut().set_title(&title).unwrap();
+        }
+
+        // Letterbox the frame in the window
+        let (x, y, w, h) = scaling.dest_rect(canvas.output_size().unwrap());
+        let dest = Rect::new(x, y, w, h);
+        // Hide the overscan
+        let src = |width| {
+            let (x, y, w, h) = scaling.overscan.src_rect(width);
+            Some(Rect::new(x, y, w, h))
+        };
+        canvas.clear();
+        match (render_ntsc.get(), frame.indices()) {
+            (true, Some(indices)) => {
+                let pixels = ntsc.filter(indices, info.frame_count);
+                ntsc_texture
+                    .update(None, pixels, (NTSC_WIDTH * 3) as usize)
+                    .unwrap();
+                canvas.copy(&ntsc_texture, src(NTSC_WIDTH), dest).unwrap();
+                texture_stale = true;
+            }
+            _ => {
+                // Static screens don't need to be uploaded again
+                if frame.changed_since_last() || texture_stale {
+                    texture
+                        .update(None, frame.pixels(), (WIDTH * 3) as usize)
+                        .unwrap();
+                    texture_stale = false;
+                }
+                canvas.copy(&texture, src(WIDTH), dest).unwrap();
+            }
+        }
+        canvas.present();
+    });
+    // The NTSC filter works on the palette indices
+    bus.set_palette_indices(true);
+    bus.set_resampler_quality(audio.resampler);
+    bus.set_frame_skip(frame_skip);
+    bus.set_overclock(overclock);
+    bus.set_region(settings.region);
 
     let mut cpu = Cpu::new(bus);
-    cpu.reset();
+    cpu.set_speed(SPEEDS[speed]);
+    cpu.power_on();
 
     let update_vol = |vol, step| {
         let old = (vol * 100.0) as u32;
@@ -123,6 +344,10 @@ where
         out
     };
 
+    let mut jammed = false;
+    let mut sprite_limit = true;
+    let mut fullscreen = false;
+    let mut show_stats = false;
     let mut timer = Timer::new();
     let spin_sleeper = SpinSleeper::default();
     // Main loop
@@ -136,6 +361,19 @@ where
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'nes,
+                // Select the save state slot, before the other number keys
+                Event::KeyDown {
+                    keycode: Some(key),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD)
+                    && slot_number(key).is_some() =>
+                {
+                    save_slot = slot_number(key).unwrap();
+                    savestate_file = savestate_path(&filename, save_slot);
+                    println!("Save slot: {}", save_slot);
+                }
                 // Volume down
                 Event::KeyDown {
                     keycode: Some(Keycode::Num1),
@@ -146,11 +384,134 @@ where
                     keycode: Some(Keycode::Num2),
                     ..
                 } => volume = update_vol(volume, VOLUME_STEP),
+                // Audio latency down and up
+                Event::KeyDown {
+                    keycode: Some(key @ (Keycode::Num3 | Keycode::Num4)),
+                    ..
+                } => {
+                    let buffers = match key {
+                        Keycode::Num3 => audio_buffers.saturating_sub(1),
+                        _ => audio_buffers + 1,
+                    }
+                    .clamp(MIN_AUDIO_BUFFERS, MAX_AUDIO_BUFFERS);
+                    if buffers != audio_buffers {
+                        audio_buffers = buffers;
+                        println!(
+                            "Audio latency: {} buffers ({} ms)",
+                            audio_buffers,
+                            latency_ms(audio_buffers)
+                        );
+                    }
+                }
+                // Slower and faster emulation
+                Event::KeyDown {
+                    keycode: Some(key @ (Keycode::Num5 | Keycode::Num6)),
+                    ..
+                } => {
+                    let index = match key {
+                        Keycode::Num5 => speed.saturating_sub(1),
+                        _ => (speed + 1).min(SPEEDS.len() - 1),
+                    };
+                    if index != speed {
+                        speed = index;
+                        cpu.set_speed(SPEEDS[speed]);
+                        println!("Speed: {}x", SPEEDS[speed]);
+                    }
+                }
                 // Reset
                 Event::KeyDown {
                     keycode: Some(Keycode::R),
                     ..
                 } => cpu.reset(),
+                // Window scale down / up
+                Event::KeyDown {
+                    keycode: Some(key @ Keycode::Minus),
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(key @ Keycode::Equals),
+                    ..
+                } => {
+                    let mut s = scaling.get();
+                    let step = if key == Keycode::Minus { -1 } else { 1 };
+                    if s.step_scale(step) {
+                        println!("Scale: x{}", s.scale);
+                        scaling.set(s);
+                        resize.set(true);
+                    }
+                }
+                // Toggle integer scaling
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => {
+                    let mut s = scaling.get();
+                    s.integer = !s.integer;
+                    println!("Integer scaling: {}", if s.integer { "on" } else { "off" });
+                    scaling.set(s);
+                }
+                // Toggle linear filtering
+                Event::KeyDown {
+                    keycode: Some(Keycode::L),
+                    ..
+                } => {
+                    linear_filter.set(!linear_filter.get());
+                    let state = if linear_filter.get() { "on" } else { "off" };
+                    println!("Linear filtering: {}", state);
+                }
+                // Toggle the input polling on every frame
+                Event::KeyDown {
+                    keycode: Some(Keycode::I),
+                    ..
+                } => {
+                    low_input_lag = !low_input_lag;
+                    println!(
+                        "Low input lag: {}",
+                        if low_input_lag { "on" } else { "off" }
+                    );
+                }
+                // Toggle 8:7 pixel aspect ratio
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => {
+                    let mut s = scaling.get();
+                    s.aspect = !s.aspect;
+                    println!("8:7 pixel aspect: {}", if s.aspect { "on" } else { "off" });
+                    scaling.set(s);
+                    resize.set(true);
+                }
+                // Toggle NTSC filter
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    ntsc_enabled.set(!ntsc_enabled.get());
+                    let state = if ntsc_enabled.get() { "on" } else { "off" };
+                    println!("NTSC filter: {}", state);
+                }
+                // Toggle reverb
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    reverb = !reverb;
+                    // Don't play the old echoes when turning it back on
+                    reverbs.iter_mut().for_each(|r| r.clear());
+                    println!("Reverb: {}", if reverb { "on" } else { "off" });
+                }
+                // Toggle fullscreen
+                Event::KeyDown {
+                    keycode: Some(key @ (Keycode::F11 | Keycode::Return)),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if key == Keycode::F11 || keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    fullscreen = !fullscreen;
+                    toggle_fullscreen.set(true);
+                    // Nothing uses the mouse, hide the cursor over the frame
+                    sdl_context.mouse().show_cursor(!fullscreen);
+                }
                 // Save state
                 Event::KeyDown {
                     keycode: Some(Keycode::F1),
@@ -158,8 +519,14 @@ where
                 } => match File::create(&savestate_file) {
                     Ok(file) => {
                         let mut buf = BufWriter::new(file);
-                        match cpu.save(&mut buf) {
-                            Ok(_) => println!("State saved!"),
+                        // With a small picture of the game for the save slot menus
+                        let result = match cpu.frame() {
+                            Some(frame) => cpu
+                                .save_with_thumbnail(&mut buf, &frame.thumbnail(THUMBNAIL_FACTOR)),
+                            None => cpu.save(&mut buf),
+                        };
+                        match result {
+                            Ok(_) => println!("State saved in slot {}!", save_slot),
                             Err(e) => println!("Error while saving state: {}", e),
                         }
                     }
@@ -174,7 +541,7 @@ where
                         let mut buf = BufReader::new(file);
                         match cpu.load(&mut buf) {
                             Ok(_) => {
-                                println!("State loaded!");
+                                println!("State loaded from slot {}!", save_slot);
                                 samples.clear();
                                 queue.clear();
                                 reverbs.iter_mut().for_each(|r| r.clear());
@@ -184,6 +551,89 @@ where
                     }
                     Err(e) => println!("Error while loading state: {} -> {}", e, &savestate_file),
                 },
+                // Dump the state as JSON
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => match cpu.to_json() {
+                    Ok(json) => match std::fs::write(&dump_file, json) {
+                        Ok(_) => println!("State dumped to {}", &dump_file),
+                        Err(e) => println!("Error while dumping state: {} -> {}", e, &dump_file),
+                    },
+                    Err(e) => println!("Error while dumping state: {}", e),
+                },
+                // Toggle sprite limit
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => {
+                    sprite_limit = !sprite_limit;
+                    cpu.set_sprite_limit(sprite_limit);
+                    println!("Sprite limit: {}", if sprite_limit { "on" } else { "off" });
+                }
+                // Toggle limiter
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => {
+                    limiter = !limiter;
+                    println!("Limiter: {}", if limiter { "on" } else { "off" });
+                }
+                // Load the ROM dropped on the window, in place of the current one
+                Event::DropFile { filename: path, .. } => {
+                    let new_cartridge = std::fs::read(&path)
+                        .map_err(CartridgeError::Io)
+                        .and_then(|bytes| Cartridge::new(&path, &bytes));
+                    match new_cartridge {
+                        Ok(new_cartridge) => {
+                            print_rom_info(&new_cartridge);
+                            filename = new_cartridge.filename();
+                            savestate_file = savestate_path(&filename, save_slot);
+                            dump_file = format!("{}.json", &filename);
+                            base_title = window_title(&filename);
+                            title.set(Some(base_title.clone()));
+
+                            cpu.load_cartridge(new_cartridge);
+                            samples.clear();
+                            queue.clear();
+                            reverbs.iter_mut().for_each(|r| r.clear());
+                            timer.reset_fps(cpu.frame_count());
+                        }
+                        Err(e) => println!("Problem while loading ROM \"{}\" -> {}", path, e),
+                    }
+                }
+                // Toggle the FPS, speed and audio queue stats
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    show_stats = !show_stats;
+                    timer.reset_fps(cpu.frame_count());
+                    if !show_stats {
+                        title.set(Some(base_title.clone()));
+                    }
+                    println!("Stats: {}", if show_stats { "on" } else { "off" });
+                }
+                // Cycle the debug mirroring override
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } => {
+                    let mut cartridge = cartridge.borrow_mut();
+                    let mode = match cartridge.mirror_override() {
+                        None => Some(MirrorMode::Vertical),
+                        Some(MirrorMode::Vertical) => Some(MirrorMode::Horizontal),
+                        Some(MirrorMode::Horizontal) => Some(MirrorMode::OneScreenLo),
+                        Some(MirrorMode::OneScreenLo) => Some(MirrorMode::OneScreenHi),
+                        Some(MirrorMode::OneScreenHi) => Some(MirrorMode::FourScreen),
+                        Some(MirrorMode::FourScreen) => None,
+                    };
+                    cartridge.set_mirror_override(mode);
+                    match mode {
+                        Some(mode) => println!("Mirroring: {:?} (forced)", mode),
+                        None => println!("Mirroring: {:?} (mapper)", cartridge.mirror_mode()),
+                    }
+                }
                 Event::KeyDown {
                     keycode: Some(key),
                     repeat,
@@ -210,50 +660,281 @@ where
                         cpu.update_joypad(button, false, JoyPort::Port2)
                     }
                 }
+                // Game controllers
+                Event::ControllerDeviceAdded { which, .. } => gamepads.add(which),
+                Event::ControllerDeviceRemoved { which, .. } => gamepads.remove(which),
+                Event::ControllerButtonDown { which, button, .. } => {
+                    if let (Some(port), Some(button)) =
+                        (gamepads.port(which), gamepad::map_button(button))
+                    {
+                        cpu.update_joypad(button, true, port)
+                    }
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if let (Some(port), Some(button)) =
+                        (gamepads.port(which), gamepad::map_button(button))
+                    {
+                        cpu.update_joypad(button, false, port)
+                    }
+                }
+                // The left stick works like the D-pad
+                Event::ControllerAxisMotion {
+                    which, axis, value, ..
+                } => {
+                    if let (Some(port), Some(buttons)) = (
+                        gamepads.port(which),
+                        gamepad::map_axis(axis, value, settings.dead_zone),
+                    ) {
+                        for (button, pressed) in buttons {
+                            cpu.update_joypad(button, pressed, port)
+                        }
+                    }
+                }
                 _ => {}
             }
         }
 
         match mode {
-            // Sync emulation at 60 fps
+            // Sync emulation at 60 fps, times the speed
             Mode::VideoSync => {
-                let frame_count = cpu.frame_count();
                 // Clock until a new frame is rendered
-                while cpu.frame_count() == frame_count {
-                    cpu.clock();
+                match tracer.as_mut() {
+                    Some(tracer) => {
+                        let frame = cpu.frame_count();
+                        while cpu.frame_count() == frame {
+                            tracer.clock(&mut cpu);
+                        }
+                    }
+                    None => {
+                        cpu.run_until_frame();
+                    }
                 }
                 // Wait if not enough time has passed
-                timer.wait(Duration::from_secs_f64(SECS_PER_FRAME));
+                timer.wait(Duration::from_secs_f64(SECS_PER_FRAME / SPEEDS[speed]));
                 timer.reset();
             }
             // Sync emulation with the audio sample rate
             Mode::AudioSync => {
                 // While theres too many samples in the queue, wait a bit
-                while queue.size() > buffer_size as u32 * 4 {
+                while queue.size() > buffer_size as u32 * audio_buffers {
                     spin_sleeper.sleep(Duration::from_micros(256));
                 }
 
-                // Clock until enough samples are generated
+                // Clock until enough samples are generated. With low input lag, also stop when
+                // vblank starts so the events are polled before the NMI reads the joypads
+                let frame = cpu.frame_count();
                 while cpu.sample_count() < buffer_size as usize {
-                    cpu.clock();
+                    match tracer.as_mut() {
+                        Some(tracer) => tracer.clock(&mut cpu),
+                        None => cpu.clock(),
+                    }
+                    if low_input_lag && cpu.frame_count() != frame {
+                        break;
+                    }
                 }
             }
         }
 
+        // Refresh the stats every second
+        if show_stats {
+            if let Some(fps) = timer.measure_fps(cpu.frame_count()) {
+                let queued = queue.size() as usize / std::mem::size_of::<f32>();
+                title.set(Some(format!(
+                    "{} | {:.1} fps | {:.0}% | Audio queue: {}",
+                    base_title,
+                    fps,
+                    fps / NTSC_FPS * 100.0,
+                    queued
+                )));
+            }
+        }
+
+        // Notify once when the Cpu gets jammed
+        if cpu.is_jammed() && !jammed {
+            println!("Cpu jammed at ${:04X}! Press R to reset", cpu.pc());
+            if let Some(tracer) = &tracer {
+                dump_trace(tracer);
+            }
+        }
+        jammed = cpu.is_jammed();
+
         // Add the samples to a buffer
         samples.append(&mut cpu.samples());
 
         // Apply reverb to the samples
-        for r in reverbs.iter_mut() {
-            r.apply(&mut samples);
+        if reverb {
+            for r in reverbs.iter_mut() {
+                r.apply(&mut samples);
+            }
         }
 
         // Adjust the volume
         samples.iter_mut().for_each(|s| *s *= volume);
 
+        // Keep the peaks in range
+        if limiter {
+            samples.iter_mut().for_each(|s| *s = audio::soft_clip(*s));
+        }
+
         // Add the samples to the SDL audio queue
         queue.queue(&samples);
         // Empty the samples buffer
         samples.clear();
     }
+
+    if let Some(tracer) = &tracer {
+        dump_trace(tracer);
+    }
+
+    // Remember the settings for the next run
+    let s = scaling.get();
+    settings.volume = volume;
+    settings.audio_buffers = audio_buffers;
+    settings.speed = SPEEDS[speed];
+    settings.scale = s.scale;
+    settings.integer_scaling = s.integer;
+    settings.pixel_aspect = s.aspect;
+    settings.ntsc_filter = ntsc_enabled.get();
+    settings.linear_filter = linear_filter.get();
+    settings.reverb = reverb;
+    settings.limiter = limiter;
+    settings.low_input_lag = low_input_lag;
+    if let Err(e) = settings.save() {
+        println!("Error while saving settings: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Sets the scaling of the textures created after this call, smooth or sharp pixels
+fn set_texture_filter(linear: bool) {
+    let quality = match linear {
+        true => "linear",
+        false => "nearest",
+    };
+    sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", quality);
+}
+
+/// Save state file of a slot, `rom.slot1.save` for example
+fn savestate_path(filename: &str, slot: u8) -> String {
+    format!("{}.slot{}.save", filename, slot)
+}
+
+/// Slot of a number key, 1 to 9 and 0 for the tenth one
+fn slot_number(key: Keycode) -> Option<u8> {
+    match key {
+        Keycode::Num1 => Some(1),
+        Keycode::Num2 => Some(2),
+        Keycode::Num3 => Some(3),
+        Keycode::Num4 => Some(4),
+        Keycode::Num5 => Some(5),
+        Keycode::Num6 => Some(6),
+        Keycode::Num7 => Some(7),
+        Keycode::Num8 => Some(8),
+        Keycode::Num9 => Some(9),
+        Keycode::Num0 => Some(0),
+        _ => None,
+    }
+}
+
+/// Title of the window for a ROM
+fn window_title(filename: &str) -> String {
+    match filename.is_empty() {
+        true => WINDOW_TITLE.to_string(),
+        false => format!("{} - {}", WINDOW_TITLE, filename),
+    }
+}
+
+/// Prints the file, header sizes, mapper and CRC32 of a ROM when it is loaded
+pub fn print_rom_info(cartridge: &Cartridge) {
+    let info = cartridge.info();
+    println!(
+        "ROM: {} -> Mapper {} ({}), CRC32 {:08X}",
+        cartridge.filename(),
+        info.mapper,
+        info.mapper_name,
+        cartridge.rom_hash()
+    );
+    match info.chr_rom_size {
+        0 => println!(
+            "PRG ROM: {} KB, CHR RAM: {} KB",
+            info.prg_rom_size / 1024,
+            info.chr_ram_size / 1024
+        ),
+        size => println!(
+            "PRG ROM: {} KB, CHR ROM: {} KB",
+            info.prg_rom_size / 1024,
+            size / 1024
+        ),
+    }
+}
+
+/// Writes the last instructions to the trace file
+fn dump_trace(tracer: &Tracer) {
+    match tracer.dump() {
+        Ok(_) => println!("Trace written to {}", tracer.path()),
+        Err(e) => println!("Error while writing trace: {} -> {}", e, tracer.path()),
+    }
+}
+
+/// Runs the emulation without video or audio, feeding the inputs of a replay script
+///
+/// `frames`: Frames to run, by default until the script is over
+///
+/// `screenshot`: PNG file for the last frame
+///
+/// Prints a hash of the last frame and the state of the Cpu at the end
+pub fn run_headless(
+    cartridge: Cartridge,
+    mut replay: Replay,
+    ram_pattern: RamPattern,
+    frames: Option<u128>,
+    screenshot: Option<&str>,
+) {
+    let bus = MainBus::new(
+        Rc::new(RefCell::new(cartridge)),
+        |_| {},
+        44100.0,
+        ram_pattern,
+    );
+
+    let mut cpu = Cpu::new(bus);
+    cpu.power_on();
+
+    // Run one more frame so the last inputs have an effect
+    let frames = frames.unwrap_or(replay.last_frame() + 2);
+    while cpu.frame_count() < frames {
+        let frame_count = cpu.frame_count();
+        replay.apply(frame_count, |port, buttons| {
+            cpu.set_joypad_state(port, buttons)
+        });
+
+        cpu.run_until_frame();
+        // The samples are not played, drop them
+        cpu.samples();
+    }
+
+    let frame = match cpu.frame() {
+        Some(frame) => frame,
+        None => return,
+    };
+    let pixels = frame.pixels();
+    println!("Frames: {}", cpu.frame_count());
+    println!("Frame hash: {:016X}", frame.hash());
+    if let Some(path) = screenshot {
+        match png::write(path, pixels, WIDTH, HEIGHT) {
+            Ok(_) => println!("Screenshot written to {}", path),
+            Err(e) => eprintln!("Error while writing screenshot: {} -> {}", e, path),
+        }
+    }
+    println!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        cpu.pc(),
+        cpu.a(),
+        cpu.x(),
+        cpu.y(),
+        cpu.p(),
+        cpu.s(),
+        cpu.cycles()
+    );
 }
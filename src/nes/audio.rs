@@ -0,0 +1,86 @@
+use crate::reverb::Reverb;
+use nesoxyde::resampler::ResamplerQuality;
+
+/// Samples under this level go through the limiter untouched
+const LIMITER_THRESHOLD: f32 = 0.8;
+
+/// Settings of a reverb pass
+#[derive(Debug, Clone, Copy)]
+pub struct ReverbConfig {
+    /// Delay of the echo in ms
+    pub delay_ms: usize,
+    /// Strength of the echo
+    pub decay: f32,
+}
+
+/// Post-processing applied to the audio
+#[derive(Debug, Clone)]
+pub struct AudioConfig {
+    /// Reverb passes applied one after the other
+    pub reverbs: Vec<ReverbConfig>,
+    /// Quality of the conversion to the audio sample rate
+    pub resampler: ResamplerQuality,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            reverbs: vec![
+                ReverbConfig {
+                    delay_ms: 330,
+                    decay: 0.15,
+                },
+                ReverbConfig {
+                    delay_ms: 150,
+                    decay: 0.1,
+                },
+                ReverbConfig {
+                    delay_ms: 285,
+                    decay: 0.05,
+                },
+            ],
+            resampler: ResamplerQuality::default(),
+        }
+    }
+}
+
+impl AudioConfig {
+    /// Creates the reverb passes for a sample rate
+    pub fn new_reverbs(&self, sample_rate: usize) -> Vec<Reverb> {
+        self.reverbs
+            .iter()
+            .map(|r| Reverb::new(r.delay_ms, sample_rate, r.decay))
+            .collect()
+    }
+}
+
+/// Compresses the samples above the threshold so they never go past 1.0
+///
+/// The curve is smooth, so loud parts get quieter instead of crackling
+pub fn soft_clip(sample: f32) -> f32 {
+    let level = sample.abs();
+    if level <= LIMITER_THRESHOLD {
+        return sample;
+    }
+    let headroom = 1.0 - LIMITER_THRESHOLD;
+    let level = LIMITER_THRESHOLD + headroom * ((level - LIMITER_THRESHOLD) / headroom).tanh();
+    level.copysign(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soft_clip() {
+        // Quiet samples are untouched
+        assert_eq!(soft_clip(0.5), 0.5);
+        assert_eq!(soft_clip(-0.8), -0.8);
+
+        // Loud ones stay under 1.0 and keep their order
+        assert!(soft_clip(1.0) < 1.0);
+        assert!(soft_clip(3.0) <= 1.0);
+        assert!(soft_clip(3.0) > soft_clip(1.0));
+        assert_eq!(soft_clip(-1.5), -soft_clip(1.5));
+    }
+}
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+
+use super::{HEIGHT, WIDTH};
+
+/// Smallest window scale
+const MIN_SCALE: u32 = 1;
+/// Biggest window scale
+const MAX_SCALE: u32 = 8;
+/// The NES pixels are slightly wider than they are tall
+const PIXEL_ASPECT: f64 = 8.0 / 7.0;
+
+/// Lines and columns hidden on each side of the frame, like the borders of a TV
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Overscan {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Overscan {
+    /// Parses `top,bottom,left,right`, for example `8,8,0,0`
+    ///
+    /// The margins must leave at least one line and one column
+    pub fn parse(margins: &str) -> Option<Self> {
+        let margins = margins
+            .split(',')
+            .map(|m| m.trim().parse().ok())
+            .collect::<Option<Vec<u32>>>()?;
+        match margins[..] {
+            [top, bottom, left, right] => {
+                let height = top.checked_add(bottom)?;
+                let width = left.checked_add(right)?;
+                match height < HEIGHT && width < WIDTH {
+                    true => Some(Self {
+                        top,
+                        bottom,
+                        left,
+                        right,
+                    }),
+                    false => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Margins cut down to leave at least one line and one column
+    ///
+    /// The settings file doesn't go through `parse`, so its margins can be anything
+    fn clamped(&self) -> Self {
+        let top = self.top.min(HEIGHT - 1);
+        let left = self.left.min(WIDTH - 1);
+        Self {
+            top,
+            bottom: self.bottom.min(HEIGHT - 1 - top),
+            left,
+            right: self.right.min(WIDTH - 1 - left),
+        }
+    }
+
+    /// Size of the frame once cropped
+    pub fn size(&self) -> (u32, u32) {
+        let margins = self.clamped();
+        (
+            WIDTH - margins.left - margins.right,
+            HEIGHT - margins.top - margins.bottom,
+        )
+    }
+
+    /// Part of the frame to draw, in a texture `width` pixels wide
+    ///
+    /// The NTSC filter makes wider textures, the columns are scaled to match
+    pub fn src_rect(&self, width: u32) -> (i32, i32, u32, u32) {
+        let margins = self.clamped();
+        let (w, h) = margins.size();
+        let x = margins.left * width / WIDTH;
+        let w = (w * width / WIDTH).max(1);
+        (x as i32, margins.top as i32, w, h)
+    }
+}
+
+/// Scaling of the NES frame in the window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scaling {
+    /// Window size multiplier
+    pub scale: u32,
+    /// Only scale by whole numbers to keep the pixels crisp
+    pub integer: bool,
+    /// Use the 8:7 pixel aspect ratio of the NES
+    pub aspect: bool,
+    /// Borders cropped from the frame
+    pub overscan: Overscan,
+}
+
+impl Default for Scaling {
+    fn default() -> Self {
+        Self {
+            scale: 2,
+            integer: false,
+            aspect: false,
+            overscan: Overscan::default(),
+        }
+    }
+}
+
+impl Scaling {
+    /// Size of the cropped NES frame before scaling
+    fn frame_size(&self) -> (f64, f64) {
+        let (w, h) = self.overscan.size();
+        match self.aspect {
+            true => (w as f64 * PIXEL_ASPECT, h as f64),
+            false => (w as f64, h as f64),
+        }
+    }
+
+    /// Window size for the current scale
+    pub fn window_size(&self) -> (u32, u32) {
+        let (w, h) = self.frame_size();
+        (
+            (w * self.scale as f64).round() as u32,
+            (h * self.scale as f64).round() as u32,
+        )
+    }
+
+    /// Changes the scale by `step`. Returns if the scale changed
+    pub fn step_scale(&mut self, step: i32) -> bool {
+        let scale = (self.scale as i32 + step).clamp(MIN_SCALE as i32, MAX_SCALE as i32) as u32;
+        let changed = scale != self.scale;
+        self.scale = scale;
+        changed
+    }
+
+    /// Where to draw the frame in a window of size `output`
+    ///
+    /// The frame is centered and letterboxed instead of stretched
+    pub fn dest_rect(&self, output: (u32, u32)) -> (i32, i32, u32, u32) {
+        let (w, h) = self.frame_size();
+        let (out_w, out_h) = (output.0 as f64, output.1 as f64);
+
+        let mut factor = f64::min(out_w / w, out_h / h);
+        if self.integer {
+            factor = factor.floor().max(1.0);
+        }
+
+        let w = (w * factor).round();
+        let h = (h * factor).round();
+        let x = ((out_w - w) / 2.0).floor();
+        let y = ((out_h - h) / 2.0).floor();
+        (x as i32, y as i32, w as u32, h as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dest_rect() {
+        let mut scaling = Scaling::default();
+        assert_eq!(scaling.dest_rect((512, 480)), (0, 0, 512, 480));
+
+        // Stretched to fit, but keeps the ratio
+        assert_eq!(scaling.dest_rect((800, 480)), (144, 0, 512, 480));
+        assert_eq!(scaling.dest_rect((700, 600)), (30, 0, 640, 600));
+
+        // Integer scaling letterboxes
+        scaling.integer = true;
+        assert_eq!(scaling.dest_rect((700, 600)), (94, 60, 512, 480));
+
+        // 8:7 pixels
+        scaling.integer = false;
+        scaling.aspect = true;
+        assert_eq!(scaling.window_size(), (585, 480));
+        assert_eq!(scaling.dest_rect((585, 480)), (0, 0, 585, 480));
+    }
+
+    #[test]
+    fn test_step_scale() {
+        let mut scaling = Scaling::default();
+        assert!(scaling.step_scale(1));
+        assert_eq!(scaling.window_size(), (768, 720));
+        assert!(scaling.step_scale(-2));
+        assert!(!scaling.step_scale(-1));
+        assert_eq!(scaling.scale, MIN_SCALE);
+    }
+
+    #[test]
+    fn test_overscan() {
+        assert_eq!(
+            Overscan::parse("8, 8,0,0").map(|o| o.size()),
+            Some((256, 224))
+        );
+        assert_eq!(Overscan::parse("8,8,0"), None);
+        assert_eq!(Overscan::parse("120,120,0,0"), None);
+        assert_eq!(Overscan::parse("0,0,-1,0"), None);
+        assert_eq!(Overscan::parse("4294967295,1,0,0"), None);
+        assert_eq!(Overscan::parse("0,0,1,4294967295"), None);
+
+        // Margins from the settings file aren't checked by parse
+        let overscan = Overscan {
+            top: u32::MAX,
+            bottom: u32::MAX,
+            left: 200,
+            right: 200,
+        };
+        assert_eq!(overscan.size(), (1, 1));
+        assert_eq!(overscan.src_rect(WIDTH), (200, 239, 1, 1));
+
+        let overscan = Overscan::parse("8,8,8,8").unwrap();
+        assert_eq!(overscan.src_rect(WIDTH), (8, 8, 240, 224));
+        assert_eq!(overscan.src_rect(WIDTH * 3), (24, 8, 720, 224));
+
+        let scaling = Scaling {
+            overscan,
+            ..Scaling::default()
+        };
+        assert_eq!(scaling.window_size(), (480, 448));
+        assert_eq!(scaling.dest_rect((480, 448)), (0, 0, 480, 448));
+    }
+}
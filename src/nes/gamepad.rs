@@ -0,0 +1,120 @@
+use sdl2::controller::{Axis, Button as PadButton, GameController};
+use sdl2::GameControllerSubsystem;
+
+use nesoxyde::joypad::{Button, JoyPort};
+
+/// Default dead zone of the sticks. The axes go from -32768 to 32767
+pub const DEFAULT_DEAD_ZONE: i16 = 8000;
+
+/// Maps the buttons of a game controller to the NES buttons
+///
+/// Uses the position of the buttons: the right face button is A and the bottom one is B
+pub fn map_button(button: PadButton) -> Option<Button> {
+    match button {
+        PadButton::B => Some(Button::A),
+        PadButton::A | PadButton::X => Some(Button::B),
+        PadButton::Back => Some(Button::Select),
+        PadButton::Start => Some(Button::Start),
+        PadButton::DPadUp => Some(Button::Up),
+        PadButton::DPadDown => Some(Button::Down),
+        PadButton::DPadLeft => Some(Button::Left),
+        PadButton::DPadRight => Some(Button::Right),
+        _ => None,
+    }
+}
+
+/// Converts a left stick axis into D-pad presses
+///
+/// Returns the state of the 2 directions of the axis. Both are released inside the dead zone
+pub fn map_axis(axis: Axis, value: i16, dead_zone: i16) -> Option<[(Button, bool); 2]> {
+    let (negative, positive) = match axis {
+        Axis::LeftX => (Button::Left, Button::Right),
+        // Y goes down
+        Axis::LeftY => (Button::Up, Button::Down),
+        _ => return None,
+    };
+    // -32768 doesn't have a positive counterpart
+    let dead_zone = dead_zone.max(0);
+    Some([
+        (negative, value < -dead_zone),
+        (positive, value > dead_zone),
+    ])
+}
+
+/// Game controllers plugged in. The first one is player 1 and the second one player 2
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        Self {
+            subsystem,
+            controllers: Vec::new(),
+        }
+    }
+
+    /// Opens a new controller, from its joystick index
+    pub fn add(&mut self, index: u32) {
+        if !self.subsystem.is_game_controller(index) {
+            return;
+        }
+        match self.subsystem.open(index) {
+            Ok(controller) => {
+                println!("Controller connected: {}", controller.name());
+                self.controllers.push(controller);
+            }
+            Err(e) => println!("Error while opening controller: {}", e),
+        }
+    }
+
+    /// Closes a controller, from its instance id
+    pub fn remove(&mut self, id: u32) {
+        if let Some(index) = self.controllers.iter().position(|c| c.instance_id() == id) {
+            let controller = self.controllers.remove(index);
+            println!("Controller disconnected: {}", controller.name());
+        }
+    }
+
+    /// Port of a controller, from its instance id
+    pub fn port(&self, id: u32) -> Option<JoyPort> {
+        match self.controllers.iter().position(|c| c.instance_id() == id) {
+            Some(0) => Some(JoyPort::Port1),
+            Some(1) => Some(JoyPort::Port2),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_axis() {
+        let dead_zone = DEFAULT_DEAD_ZONE;
+        assert_eq!(
+            map_axis(Axis::LeftX, -20000, dead_zone),
+            Some([(Button::Left, true), (Button::Right, false)])
+        );
+        assert_eq!(
+            map_axis(Axis::LeftY, 20000, dead_zone),
+            Some([(Button::Up, false), (Button::Down, true)])
+        );
+        // Inside the dead zone
+        assert_eq!(
+            map_axis(Axis::LeftX, dead_zone, dead_zone),
+            Some([(Button::Left, false), (Button::Right, false)])
+        );
+        assert_eq!(
+            map_axis(Axis::LeftY, -100, dead_zone),
+            Some([(Button::Up, false), (Button::Down, false)])
+        );
+        assert_eq!(
+            map_axis(Axis::LeftX, i16::MIN, 0).unwrap()[0],
+            (Button::Left, true)
+        );
+        assert!(map_axis(Axis::RightX, 20000, dead_zone).is_none());
+    }
+}
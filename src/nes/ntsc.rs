@@ -0,0 +1,206 @@
+// NTSC composite video simulation
+//
+// The Ppu palette indices are turned into the composite signal the NES sends to the TV,
+// 8 samples per pixel with a color subcarrier period of 12 samples. The signal is then
+// decoded back to RGB like a TV would. The short luma window lets the subcarrier leak
+// into the picture (dot crawl) and the long chroma window bleeds colors between pixels
+
+use super::HEIGHT;
+
+/// Width of the filtered frame
+pub const NTSC_WIDTH: u32 = 602;
+
+/// Signal samples per NES pixel
+const SAMPLES_PER_PIXEL: usize = 8;
+/// Signal samples in a scanline
+const LINE_SAMPLES: usize = 256 * SAMPLES_PER_PIXEL;
+/// Samples in a period of the color subcarrier
+const SUBCARRIER: usize = 12;
+/// Luma filter width in samples
+const LUMA_WIDTH: usize = 8;
+/// Chroma filter width in samples
+const CHROMA_WIDTH: usize = 24;
+/// Black around the visible picture, so the filters don't read out of bounds
+const PADDING: usize = SUBCARRIER * 2;
+
+/// Signal voltages of the 4 luma levels, for the low and high parts of the wave
+const LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+/// Voltage factor of an emphasized color
+const ATTENUATION: f32 = 0.746;
+/// Phase offset of the decoder, so the hues match the usual NES palettes
+const HUE: f32 = 4.5;
+
+/// NTSC composite video filter
+pub struct Ntsc {
+    /// Signal level of each palette index (with emphasis) at each subcarrier phase
+    levels: Vec<[f32; SUBCARRIER]>,
+    /// Subcarrier reference used to decode the I and Q components
+    carrier: [(f32, f32); SUBCARRIER],
+    line: Vec<f32>,
+    output: Vec<u8>,
+}
+
+impl Ntsc {
+    pub fn new() -> Self {
+        let mut levels = vec![[0.0; SUBCARRIER]; 512];
+        for (pixel, phases) in levels.iter_mut().enumerate() {
+            for (phase, level) in phases.iter_mut().enumerate() {
+                *level = signal(pixel as u16, phase);
+            }
+        }
+
+        let mut carrier = [(0.0, 0.0); SUBCARRIER];
+        for (phase, c) in carrier.iter_mut().enumerate() {
+            let angle = std::f32::consts::PI * (phase as f32 + HUE) / 6.0;
+            *c = (angle.cos(), angle.sin());
+        }
+
+        Self {
+            levels,
+            carrier,
+            line: vec![0.0; LINE_SAMPLES + PADDING * 2],
+            output: vec![0; (NTSC_WIDTH * HEIGHT * 3) as usize],
+        }
+    }
+
+    /// Filters a frame of palette indices (see `Frame::indices`) into RGB
+    ///
+    /// `frame` is the frame number, the phase of the subcarrier changes every frame
+    pub fn filter(&mut self, indices: &[u16], frame: u128) -> &[u8] {
+        // Each scanline starts 4 samples later in the subcarrier. Odd frames are one Ppu dot
+        // shorter, so the pattern only repeats every 2 frames
+        let frame_phase = (frame % 2) as usize * 4;
+
+        for (y, pixels) in indices.chunks(256).enumerate() {
+            let phase = (frame_phase + y * 4) % SUBCARRIER;
+            self.encode_line(pixels, phase);
+            self.decode_line(y, phase);
+        }
+
+        &self.output
+    }
+
+    /// Generates the composite signal of a scanline
+    fn encode_line(&mut self, pixels: &[u16], phase: usize) {
+        let samples = &mut self.line[PADDING..PADDING + LINE_SAMPLES];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let pixel = pixels[i / SAMPLES_PER_PIXEL] as usize & 0x1FF;
+            *sample = self.levels[pixel][(phase + i) % SUBCARRIER];
+        }
+    }
+
+    /// Decodes the composite signal of a scanline to RGB
+    fn decode_line(&mut self, y: usize, phase: usize) {
+        let width = NTSC_WIDTH as usize;
+        let row = &mut self.output[y * width * 3..(y + 1) * width * 3];
+
+        for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+            // Position of the output pixel in the signal
+            let center = PADDING + (x * LINE_SAMPLES + LINE_SAMPLES / 2) / width;
+
+            let start = center - LUMA_WIDTH / 2;
+            let luma: f32 = self.line[start..start + LUMA_WIDTH].iter().sum();
+            let luma = luma / LUMA_WIDTH as f32;
+
+            let start = center - CHROMA_WIDTH / 2;
+            let (mut i, mut q) = (0.0, 0.0);
+            for (s, sample) in self.line[start..start + CHROMA_WIDTH].iter().enumerate() {
+                // The padding is a multiple of the subcarrier period, it doesn't shift the phase
+                let (cos, sin) = self.carrier[(phase + start + s) % SUBCARRIER];
+                i += sample * cos;
+                q += sample * sin;
+            }
+            // The product with the carrier halves the amplitude
+            let i = i * 2.0 / CHROMA_WIDTH as f32;
+            let q = q * 2.0 / CHROMA_WIDTH as f32;
+
+            rgb[0] = to_u8(luma + 0.946882 * i + 0.623557 * q);
+            rgb[1] = to_u8(luma - 0.274788 * i - 0.635691 * q);
+            rgb[2] = to_u8(luma - 1.108545 * i + 1.709007 * q);
+        }
+    }
+}
+
+/// Normalized signal level of a palette index (with the emphasis bits) at a subcarrier phase
+fn signal(pixel: u16, phase: usize) -> f32 {
+    let color = (pixel & 0x0F) as usize;
+    let emphasis = pixel >> 6;
+    // Colors 0xE and 0xF are black
+    let level = match color > 13 {
+        true => 1,
+        false => ((pixel >> 4) & 0x3) as usize,
+    };
+
+    // Color 0 is the high level only, colors 0xD-0xF are the low level only
+    let mut low = LEVELS[level];
+    let mut high = LEVELS[4 + level];
+    if color == 0 {
+        low = high;
+    }
+    if color > 12 {
+        high = low;
+    }
+
+    // The wave of a color is high for half of the subcarrier period
+    let in_phase = |c: usize| (c + phase) % SUBCARRIER < 6;
+    let mut level = match in_phase(color) {
+        true => high,
+        false => low,
+    };
+
+    // Emphasis attenuates the signal during the phase of its color
+    if (emphasis & 0x1 != 0 && in_phase(0))
+        || (emphasis & 0x2 != 0 && in_phase(4))
+        || (emphasis & 0x4 != 0 && in_phase(8))
+    {
+        level *= ATTENUATION;
+    }
+
+    (level - BLACK) / (WHITE - BLACK)
+}
+
+/// Converts a normalized color channel to a byte
+fn to_u8(v: f32) -> u8 {
+    (v * 255.0).clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Filters a frame of a single color and returns the pixel in the middle
+    fn flat_color(index: u16) -> (u8, u8, u8) {
+        let mut ntsc = Ntsc::new();
+        let indices = vec![index; 256 * HEIGHT as usize];
+        let output = ntsc.filter(&indices, 0);
+        let i = (100 * NTSC_WIDTH as usize + 300) * 3;
+        (output[i], output[i + 1], output[i + 2])
+    }
+
+    #[test]
+    fn test_flat_colors() {
+        // Greys have no chroma
+        let (r, g, b) = flat_color(0x30);
+        assert!(r > 250 && g > 250 && b > 250);
+        assert_eq!(flat_color(0x0F), (0, 0, 0));
+        let (r, g, b) = flat_color(0x2D);
+        assert!(r == g && g == b);
+
+        // Red, blue and green
+        let (r, g, b) = flat_color(0x16);
+        assert!(r > g && r > b);
+        let (r, g, b) = flat_color(0x12);
+        assert!(b > r && b > g);
+        let (r, g, b) = flat_color(0x1A);
+        assert!(g > r && g > b);
+    }
+
+    #[test]
+    fn test_emphasis() {
+        // Red emphasis dims green and blue on white
+        let (r, g, b) = flat_color(0x30 | 0x1 << 6);
+        assert!(r > g && r > b);
+    }
+}
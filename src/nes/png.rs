@@ -0,0 +1,111 @@
+// Minimal PNG writer for the screenshots of the headless runs
+//
+// The image data is not compressed, it is stored in raw deflate blocks.
+// This keeps the encoder small and the output is still a valid PNG file
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use nesoxyde::cartridge::crc32;
+
+/// PNG file signature
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+/// Max size of a stored deflate block
+const BLOCK_SIZE: usize = 0xFFFF;
+
+/// Adler-32 checksum of the zlib stream
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+/// Appends a chunk with its length and CRC
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps the data in a zlib stream of stored blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate, 32K window, no preset dictionary, fastest
+    let mut stream = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        // Single empty final block
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u8;
+        let len = block.len() as u16;
+        stream.push(last);
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+/// Encodes RGB24 pixels to a PNG image
+pub fn encode(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let row_len = width as usize * 3;
+    assert_eq!(pixels.len(), row_len * height as usize);
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, deflate, no filter, not interlaced
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Each row starts with its filter type (none)
+    let mut data = Vec::with_capacity((row_len + 1) * height as usize);
+    for row in pixels.chunks(row_len) {
+        data.push(0);
+        data.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&data));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Writes RGB24 pixels to a PNG file
+pub fn write<P: AsRef<Path>>(path: P, pixels: &[u8], width: u32, height: u32) -> io::Result<()> {
+    fs::write(path, encode(pixels, width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+
+        let pixels = vec![0x80; 300 * 300 * 3];
+        let png = encode(&pixels, 300, 300);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..20], 300u32.to_be_bytes());
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // 270300 bytes of image data need 5 stored blocks
+        let data_len = 300 * (300 * 3 + 1);
+        let idat_len = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        assert_eq!(idat_len, 2 + 5 * 5 + data_len + 4);
+    }
+}
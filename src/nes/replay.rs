@@ -0,0 +1,175 @@
+// Input script used to reproduce a run of the emulator deterministically
+//
+// Each line is `frame: port buttons`, for example `120: 1 A START`.
+// The listed buttons are held on the port from that frame on and the others are released.
+// Empty lines and lines starting with '#' are ignored
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+use nesoxyde::joypad::JoyPort;
+
+/// Names of the buttons in the script, in the controller shift order
+const BUTTON_NAMES: [&str; 8] = ["A", "B", "SELECT", "START", "UP", "DOWN", "LEFT", "RIGHT"];
+
+/// Controller state to apply at a given frame
+struct Input {
+    frame: u128,
+    port: usize,
+    buttons: u8,
+}
+
+/// Inputs fed to the controllers frame by frame
+pub struct Replay {
+    inputs: VecDeque<Input>,
+    last_frame: u128,
+}
+
+impl Replay {
+    pub fn new() -> Self {
+        Self {
+            inputs: VecDeque::new(),
+            last_frame: 0,
+        }
+    }
+
+    /// Reads and parses a replay script
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let script = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&script)
+    }
+
+    /// Parses a replay script
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let mut replay = Self::new();
+
+        for (i, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |msg: &str| format!("Line {}: {} -> \"{}\"", i + 1, msg, line);
+
+            let (frame, rest) = match line.find(':') {
+                Some(index) => (&line[..index], &line[index + 1..]),
+                None => return Err(error("missing ':' after the frame number")),
+            };
+            let frame = frame
+                .trim()
+                .parse::<u128>()
+                .map_err(|_| error("bad frame number"))?;
+
+            let mut tokens = rest.split_whitespace();
+            let port = match tokens.next() {
+                Some("1") => 0,
+                Some("2") => 1,
+                _ => return Err(error("port must be 1 or 2")),
+            };
+
+            let mut buttons = 0;
+            for token in tokens {
+                let name = token.to_uppercase();
+                match BUTTON_NAMES.iter().position(|&b| b == name) {
+                    Some(bit) => buttons |= 1 << bit,
+                    None => return Err(error(&format!("unknown button {}", token))),
+                }
+            }
+
+            replay.queue_input(frame, port, buttons);
+        }
+
+        Ok(replay)
+    }
+
+    /// Queues a controller state to apply at a frame
+    ///
+    /// `buttons` has the same layout as the controller shift register (A is bit 0)
+    pub fn queue_input(&mut self, frame: u128, port: usize, buttons: u8) {
+        // Keep the queue sorted by frame. Inputs on the same frame stay in order
+        let index = self
+            .inputs
+            .iter()
+            .position(|input| input.frame > frame)
+            .unwrap_or(self.inputs.len());
+        self.inputs.insert(
+            index,
+            Input {
+                frame,
+                port,
+                buttons,
+            },
+        );
+        self.last_frame = self.last_frame.max(frame);
+    }
+
+    /// Last frame with an input in the script
+    pub fn last_frame(&self) -> u128 {
+        self.last_frame
+    }
+
+    /// Applies the inputs queued up to `frame`
+    ///
+    /// `update` receives the state of the whole controller like `Cpu::set_joypad_state`
+    pub fn apply<F>(&mut self, frame: u128, mut update: F)
+    where
+        F: FnMut(JoyPort, u8),
+    {
+        while let Some(input) = self.inputs.front() {
+            if input.frame > frame {
+                break;
+            }
+
+            let port = match input.port {
+                0 => JoyPort::Port1,
+                _ => JoyPort::Port2,
+            };
+            update(port, input.buttons);
+
+            self.inputs.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let script = "# Start the game\n\
+                      10: 1 start\n\
+                      12: 1\n\
+                      \n\
+                      30: 2 A Right\n\
+                      20: 1 A B\n";
+        let mut replay = Replay::parse(script).unwrap();
+        assert_eq!(replay.last_frame(), 30);
+
+        let mut applied = Vec::new();
+        for frame in 0..=30 {
+            replay.apply(frame, |port, buttons| {
+                let port = match port {
+                    JoyPort::Port1 => 1,
+                    JoyPort::Port2 => 2,
+                };
+                applied.push((frame, port, buttons));
+            });
+        }
+
+        // Start, nothing, A + B, A + Right
+        assert_eq!(
+            applied,
+            vec![(10, 1, 0x08), (12, 1, 0x00), (20, 1, 0x03), (30, 2, 0x81)]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Replay::parse("10 1 A").is_err());
+        assert!(Replay::parse("x: 1 A").is_err());
+        assert!(Replay::parse("10: 3 A").is_err());
+        assert!(Replay::parse("10: 1 Turbo").is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::de::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::display::{Overscan, Scaling};
+use super::gamepad::DEFAULT_DEAD_ZONE;
+use super::Mode;
+use nesoxyde::ppu::frame::Region;
+
+/// Name of the directory in the config directory of the platform
+const CONFIG_DIR: &str = "nesoxyde";
+/// Name of the settings file
+const SETTINGS_FILE: &str = "settings.json";
+/// Name of the per-game settings file
+const PROFILES_FILE: &str = "profiles.json";
+
+/// User settings, kept between runs
+///
+/// Missing fields use their default value, so older files still load
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Volume between 0 and 1
+    pub volume: f32,
+    /// Audio buffers queued ahead in audio sync mode, from 1 to 16. More buffers add latency
+    /// but are less likely to run out when the emulation falls behind
+    pub audio_buffers: u32,
+    /// Emulation speed multiplier: 0.25, 0.5, 1 or 2. The audio pitch follows it
+    pub speed: f64,
+    /// Window size multiplier
+    pub scale: u32,
+    /// Only scale by whole numbers
+    pub integer_scaling: bool,
+    /// Use the 8:7 pixel aspect ratio
+    pub pixel_aspect: bool,
+    /// Borders cropped from the frame
+    pub overscan: Overscan,
+    /// Smooth the pixels when scaling
+    pub linear_filter: bool,
+    pub ntsc_filter: bool,
+    pub reverb: bool,
+    /// Soft clip the loud samples
+    pub limiter: bool,
+    /// Poll the input at the start of every frame in audio sync mode, right before the game
+    /// reads the joypads in its NMI
+    pub low_input_lag: bool,
+    pub mode: Mode,
+    pub region: Region,
+    /// Dead zone of the controller sticks, from 0 to 32767
+    pub dead_zone: i16,
+    /// Global values of the settings overridden by the game profile. Restored when saving
+    #[serde(skip)]
+    overridden: Map<String, Value>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            volume: 0.5,
+            audio_buffers: 4,
+            speed: 1.0,
+            scale: 2,
+            integer_scaling: false,
+            pixel_aspect: false,
+            overscan: Overscan::default(),
+            linear_filter: false,
+            ntsc_filter: false,
+            reverb: true,
+            limiter: true,
+            low_input_lag: false,
+            mode: Mode::AudioSync,
+            region: Region::default(),
+            dead_zone: DEFAULT_DEAD_ZONE,
+            overridden: Map::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the settings file. Uses the default settings if it is missing or malformed
+    pub fn load() -> Self {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Self::default(),
+        };
+        match fs::read_to_string(&path) {
+            Ok(json) => Self::from_json(&json).unwrap_or_else(|e| {
+                println!("Bad settings file: {} -> {}", e, path.display());
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Writes the settings file, creating its directory if needed
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No config directory found"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_json()?)
+    }
+
+    fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Global settings as json. The profile of the game does not leak into them
+    fn to_json(&self) -> serde_json::Result<String> {
+        let mut settings = serde_json::to_value(self)?;
+        if let Value::Object(settings) = &mut settings {
+            settings.extend(self.overridden.clone());
+        }
+        serde_json::to_string_pretty(&settings)
+    }
+
+    /// Scaling of the frame when the emulator starts
+    pub fn scaling(&self) -> Scaling {
+        let mut scaling = Scaling {
+            scale: self.scale,
+            integer: self.integer_scaling,
+            aspect: self.pixel_aspect,
+            overscan: self.overscan,
+        };
+        // Keeps the saved scale in range
+        scaling.step_scale(0);
+        scaling
+    }
+
+    /// Applies the profile of a game from the profiles file, if it has one
+    ///
+    /// The file maps the CRC32 of the ROMs to the settings they override, e.g.
+    /// `{ "1A2B3C4D": { "overscan": { "top": 8, "bottom": 8 }, "ntsc_filter": true } }`
+    pub fn apply_profile(&mut self, crc: u32) {
+        let path = match config_dir() {
+            Some(dir) => dir.join(CONFIG_DIR).join(PROFILES_FILE),
+            None => return,
+        };
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(_) => return,
+        };
+        match self.apply_profile_json(&json, crc) {
+            Ok(true) => println!("Game profile: {:08X}", crc),
+            Ok(false) => {}
+            Err(e) => println!("Bad profiles file: {} -> {}", e, path.display()),
+        }
+    }
+
+    /// Overrides the settings with the profile of `crc`. Returns if there was one
+    fn apply_profile_json(&mut self, json: &str, crc: u32) -> serde_json::Result<bool> {
+        let profiles: Map<String, Value> = serde_json::from_str(json)?;
+        let key = format!("{:08X}", crc);
+        let profile = match profiles.iter().find(|(k, _)| k.eq_ignore_ascii_case(&key)) {
+            Some((_, Value::Object(profile))) => profile,
+            Some(_) => return Err(serde_json::Error::custom("a profile must be an object")),
+            None => return Ok(false),
+        };
+
+        let mut settings = serde_json::to_value(&*self)?;
+        let mut overridden = Map::new();
+        for (key, value) in profile {
+            // Unknown settings are ignored, like in the settings file
+            if let Some(setting) = settings.get_mut(key) {
+                overridden.insert(key.clone(), std::mem::replace(setting, value.clone()));
+            }
+        }
+
+        *self = serde_json::from_value(settings)?;
+        self.overridden = overridden;
+        Ok(true)
+    }
+
+    /// Path of the settings file
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join(CONFIG_DIR).join(SETTINGS_FILE))
+    }
+}
+
+/// Config directory of the platform
+///
+/// %APPDATA% on Windows, ~/Library/Application Support on MacOS and $XDG_CONFIG_HOME or
+/// ~/.config on the others
+fn config_dir() -> Option<PathBuf> {
+    let home = || std::env::var_os("HOME").map(PathBuf::from);
+    if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home().map(|home| home.join(".config")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_json() {
+        let settings = Settings {
+            volume: 0.25,
+            scale: 4,
+            mode: Mode::VideoSync,
+            ..Settings::default()
+        };
+        let json = serde_json::to_string(&settings).unwrap();
+        assert_eq!(Settings::from_json(&json).unwrap(), settings);
+
+        // Missing fields are defaulted
+        let settings = Settings::from_json(r#"{ "scale": 3 }"#).unwrap();
+        assert_eq!(settings.scale, 3);
+        assert_eq!(settings.volume, Settings::default().volume);
+
+        assert!(Settings::from_json("{ scale: 3").is_err());
+        assert!(Settings::from_json(r#"{ "scale": "big" }"#).is_err());
+    }
+
+    #[test]
+    fn test_profile() {
+        let profiles = r#"{
+            "1a2b3c4d": { "scale": 4, "ntsc_filter": true, "unknown": 1 },
+            "00000001": 3
+        }"#;
+
+        let mut settings = Settings::default();
+        assert!(!settings.apply_profile_json(profiles, 0x12345678).unwrap());
+        assert!(settings.apply_profile_json(profiles, 0x1A2B3C4D).unwrap());
+        assert_eq!(settings.scale, 4);
+        assert!(settings.ntsc_filter);
+        assert_eq!(settings.volume, Settings::default().volume);
+
+        // The overridden settings keep their global value, the others are saved
+        settings.volume = 0.75;
+        settings.scale = 5;
+        let saved = Settings::from_json(&settings.to_json().unwrap()).unwrap();
+        assert_eq!(
+            saved,
+            Settings {
+                volume: 0.75,
+                ..Settings::default()
+            }
+        );
+
+        assert!(settings.apply_profile_json(profiles, 1).is_err());
+        assert!(settings.apply_profile_json("[]", 1).is_err());
+        let mut settings = Settings::default();
+        assert!(settings
+            .apply_profile_json(r#"{ "00000001": { "scale": "big" } }"#, 1)
+            .is_err());
+
+        // The profile reaches the scaling of the window
+        let mut settings = Settings::default();
+        let profiles = r#"{ "1A2B3C4D": { "scale": 3, "overscan": { "top": 8, "bottom": 8 } } }"#;
+        assert!(settings.apply_profile_json(profiles, 0x1A2B3C4D).unwrap());
+        let scaling = settings.scaling();
+        assert_eq!(scaling.scale, 3);
+        assert_eq!(scaling.overscan.size(), (256, 224));
+        assert_eq!(scaling.window_size(), (768, 672));
+    }
+}
@@ -0,0 +1,126 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use std::time::Duration;
+
+use super::NesError;
+use crate::timer::Timer;
+use nesoxyde::bus::SnakeBus;
+use nesoxyde::cpu::Cpu;
+
+/// The screen is 32x32 pixels
+const SIZE: u32 = 32;
+const SCALE: u32 = 10;
+/// About 70us per instruction, the speed the game was written for
+const INSTRUCTIONS_PER_FRAME: usize = 240;
+const SECS_PER_FRAME: f64 = 1.0 / 60.0;
+const BRK_OPCODE: u8 = 0x00;
+/// ASCII code of the last key pressed
+const KEY_ADDR: u16 = 0xFF;
+/// One byte per pixel
+const SCREEN_ADDR: u16 = 0x200;
+
+/// RGB color of a pixel of the screen
+fn color(byte: u8) -> [u8; 3] {
+    match byte {
+        0 => [0x00, 0x00, 0x00],
+        1 => [0xFF, 0xFF, 0xFF],
+        2 | 9 => [0x80, 0x80, 0x80],
+        3 | 10 => [0xFF, 0x00, 0x00],
+        4 | 11 => [0x00, 0xFF, 0x00],
+        5 | 12 => [0x00, 0x00, 0xFF],
+        6 | 13 => [0xFF, 0x00, 0xFF],
+        7 | 14 => [0xFF, 0xFF, 0x00],
+        _ => [0x00, 0xFF, 0xFF],
+    }
+}
+
+/// Runs the 6502 snake game loaded at 0x0600, with WASD or the arrows
+///
+/// The same seed gives the same apples. The game ends on a BRK
+pub fn run_snake(program: &[u8], seed: Option<u64>) -> Result<(), NesError> {
+    // Like a NES ROM passed by mistake
+    if program.len() > SnakeBus::MAX_PROGRAM_SIZE {
+        return Err(NesError::ProgramTooBig(program.len()));
+    }
+
+    let sdl_context = sdl2::init().map_err(NesError::Sdl)?;
+    let video_subsystem = sdl_context.video().map_err(NesError::Sdl)?;
+    let window = video_subsystem
+        .window("Snake", SIZE * SCALE, SIZE * SCALE)
+        .position_centered()
+        .build()?;
+    let mut canvas = window.into_canvas().build()?;
+    let mut event_pump = sdl_context.event_pump().map_err(NesError::Sdl)?;
+    let creator = canvas.texture_creator();
+    let mut texture = creator.create_texture_target(PixelFormatEnum::RGB24, SIZE, SIZE)?;
+
+    let mut bus = match seed {
+        Some(seed) => SnakeBus::with_seed(seed),
+        None => SnakeBus::new(),
+    };
+    bus.load(program.to_vec());
+    let mut cpu = Cpu::new(bus);
+    cpu.power_on();
+
+    let mut timer = Timer::new();
+    let mut pixels = vec![0; (SIZE * SIZE * 3) as usize];
+    'snake: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'snake,
+                Event::KeyDown {
+                    keycode: Some(key), ..
+                } => {
+                    let code = match key {
+                        Keycode::W | Keycode::Up => b'w',
+                        Keycode::A | Keycode::Left => b'a',
+                        Keycode::S | Keycode::Down => b's',
+                        Keycode::D | Keycode::Right => b'd',
+                        _ => continue,
+                    };
+                    cpu.poke(KEY_ADDR, code);
+                }
+                _ => {}
+            }
+        }
+
+        for _ in 0..INSTRUCTIONS_PER_FRAME {
+            if cpu.peek(cpu.pc()) == BRK_OPCODE || cpu.is_jammed() {
+                println!("Game over!");
+                break 'snake;
+            }
+            cpu.execute();
+        }
+
+        for (i, pixel) in pixels.chunks_exact_mut(3).enumerate() {
+            pixel.copy_from_slice(&color(cpu.peek(SCREEN_ADDR + i as u16)));
+        }
+        texture
+            .update(None, &pixels, (SIZE * 3) as usize)
+            .map_err(|e| NesError::Sdl(e.to_string()))?;
+        canvas.copy(&texture, None, None).map_err(NesError::Sdl)?;
+        canvas.present();
+
+        timer.wait(Duration::from_secs_f64(SECS_PER_FRAME));
+        timer.reset();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program_too_big() {
+        let program = vec![0; SnakeBus::MAX_PROGRAM_SIZE + 1];
+        let error = run_snake(&program, Some(1)).unwrap_err();
+        assert!(matches!(error, NesError::ProgramTooBig(size) if size == program.len()));
+    }
+}
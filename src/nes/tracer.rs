@@ -0,0 +1,77 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use nesoxyde::cpu::trace::trace;
+use nesoxyde::cpu::{Cpu, CpuInterface};
+
+/// Instructions kept in the log
+const TRACE_LINES: usize = 10000;
+
+/// Keeps the trace of the last instructions, to write it after a crash or a freeze
+pub struct Tracer {
+    path: String,
+    lines: VecDeque<String>,
+}
+
+impl Tracer {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            lines: VecDeque::with_capacity(TRACE_LINES),
+        }
+    }
+
+    /// Clocks the Cpu once, tracing the instruction if a new one starts
+    pub fn clock<B: CpuInterface>(&mut self, cpu: &mut Cpu<B>) {
+        if cpu.instruction_done() {
+            // Drop the oldest instruction if the log is full
+            if self.lines.len() == TRACE_LINES {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(trace(cpu));
+        }
+        cpu.clock();
+    }
+
+    /// Writes the log to the file, oldest instruction first
+    pub fn dump(&self) -> io::Result<()> {
+        let mut output = BufWriter::new(File::create(&self.path)?);
+        for line in self.lines.iter() {
+            writeln!(output, "{}", line)?;
+        }
+        output.flush()
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nesoxyde::bus::TestBus;
+
+    #[test]
+    fn test_clock() {
+        // NOPs from 0x2000, with the reset vector on them
+        let mut program = vec![0xEA; 0x10000 - 0x2000];
+        program[0xFFFC - 0x2000] = 0x00;
+        program[0xFFFD - 0x2000] = 0x20;
+        let mut cpu = Cpu::new(TestBus::new(program));
+        cpu.power_on();
+
+        let mut tracer = Tracer::new("trace.log");
+        // 2 cycles per NOP
+        for _ in 0..(TRACE_LINES + 5) * 2 {
+            tracer.clock(&mut cpu);
+        }
+        assert_eq!(tracer.lines.len(), TRACE_LINES);
+        assert!(tracer
+            .lines
+            .back()
+            .unwrap()
+            .starts_with("4714  EA        NOP"));
+    }
+}
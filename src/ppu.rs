@@ -1,21 +1,44 @@
-use std::{
-    fs::File,
-    io::{BufReader, BufWriter},
-};
+use std::io::{Read, Write};
 
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 use registers::{Controller, Loopy, Mask, Status};
 
+use crate::bus::RamPattern;
 use crate::savable::Savable;
 
-use self::frame::Frame;
+use self::frame::{Frame, FrameInfo, Region};
 
 pub mod frame;
-mod registers;
-
-#[derive(Clone, Copy)]
-pub struct Rgb(u8, u8, u8);
+pub mod registers;
+
+/// Color of a pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+/// Snapshot of the Ppu timing and scroll, for debug displays
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PpuState {
+    /// Current scanline (-1 is the pre render scanline)
+    pub scanline: i32,
+    /// Current cycle in the scanline
+    pub cycle: usize,
+    /// Frames rendered
+    pub frame_count: u128,
+    /// Current VRAM address (v)
+    pub v_addr: u16,
+    /// Horizontal scroll in pixels (0-511), from the nametable, coarse X and fine X
+    pub scroll_x: u16,
+    /// Vertical scroll in pixels (0-479), from the nametable, coarse Y and fine Y
+    pub scroll_y: u16,
+    /// Last value written to PPUCTRL (0x2000)
+    pub ctrl: Controller,
+    /// Last value written to PPUMASK (0x2001)
+    pub mask: Mask,
+    /// Flags of PPUSTATUS (0x2002), without the open bus bits
+    pub status: Status,
+}
 
 /// NES color palette
 #[rustfmt::skip]
@@ -33,6 +56,41 @@ static NES_PALETTE: [Rgb; 0x40] = [
     Rgb(204, 210, 120), Rgb(180, 222, 120), Rgb(168, 226, 144), Rgb(152, 226, 180), Rgb(160, 214, 228), Rgb(160, 162, 160), Rgb(0, 0, 0),       Rgb(0, 0, 0),
 ];
 
+/// Palettes of the RP2C04 VS System Ppus, as indices in the NES palette
+///
+/// Each chip has the same colors as the NES, but in a different order
+#[rustfmt::skip]
+static VS_PALETTES: [[u8; 0x40]; 4] = [
+    // RP2C04-0001
+    [
+        0x35, 0x23, 0x16, 0x22, 0x1C, 0x09, 0x1D, 0x15, 0x20, 0x00, 0x27, 0x05, 0x04, 0x28, 0x08, 0x20,
+        0x21, 0x3E, 0x1F, 0x29, 0x3C, 0x32, 0x36, 0x12, 0x3F, 0x2B, 0x2E, 0x1E, 0x3D, 0x2D, 0x24, 0x01,
+        0x0E, 0x31, 0x33, 0x2A, 0x2C, 0x0C, 0x1B, 0x14, 0x2E, 0x07, 0x34, 0x06, 0x13, 0x02, 0x26, 0x2E,
+        0x2E, 0x19, 0x10, 0x0A, 0x39, 0x03, 0x37, 0x17, 0x0F, 0x11, 0x0B, 0x0D, 0x38, 0x25, 0x18, 0x3A,
+    ],
+    // RP2C04-0002
+    [
+        0x2E, 0x27, 0x18, 0x39, 0x3A, 0x25, 0x1C, 0x31, 0x16, 0x13, 0x38, 0x34, 0x20, 0x23, 0x3C, 0x0B,
+        0x0F, 0x21, 0x06, 0x3D, 0x1B, 0x29, 0x1E, 0x22, 0x1D, 0x24, 0x0E, 0x2B, 0x32, 0x08, 0x2E, 0x03,
+        0x04, 0x36, 0x26, 0x33, 0x11, 0x1F, 0x10, 0x02, 0x14, 0x3F, 0x00, 0x09, 0x12, 0x2E, 0x28, 0x20,
+        0x3E, 0x0D, 0x2A, 0x17, 0x0C, 0x01, 0x15, 0x19, 0x2E, 0x2C, 0x07, 0x37, 0x35, 0x05, 0x0A, 0x2F,
+    ],
+    // RP2C04-0003
+    [
+        0x14, 0x25, 0x3A, 0x10, 0x0B, 0x20, 0x31, 0x09, 0x01, 0x2E, 0x36, 0x08, 0x15, 0x3D, 0x3E, 0x3C,
+        0x22, 0x1C, 0x05, 0x12, 0x19, 0x18, 0x17, 0x1B, 0x00, 0x03, 0x2E, 0x02, 0x16, 0x06, 0x34, 0x35,
+        0x23, 0x0F, 0x0E, 0x37, 0x0D, 0x27, 0x26, 0x20, 0x29, 0x04, 0x21, 0x24, 0x11, 0x2D, 0x2E, 0x1F,
+        0x2C, 0x1E, 0x39, 0x33, 0x07, 0x2A, 0x28, 0x1D, 0x0A, 0x2E, 0x32, 0x38, 0x13, 0x2B, 0x3F, 0x0C,
+    ],
+    // RP2C04-0004
+    [
+        0x18, 0x03, 0x1C, 0x28, 0x2E, 0x35, 0x01, 0x17, 0x10, 0x1F, 0x2A, 0x0E, 0x36, 0x37, 0x0B, 0x39,
+        0x25, 0x1E, 0x12, 0x34, 0x2E, 0x1D, 0x06, 0x26, 0x3E, 0x1B, 0x22, 0x19, 0x04, 0x2E, 0x3A, 0x21,
+        0x05, 0x0A, 0x07, 0x02, 0x13, 0x14, 0x00, 0x15, 0x0C, 0x3D, 0x11, 0x0F, 0x0D, 0x38, 0x2D, 0x24,
+        0x33, 0x20, 0x08, 0x16, 0x32, 0x29, 0x30, 0x3C, 0x2E, 0x27, 0x23, 0x31, 0x2E, 0x2F, 0x2E, 0x2C,
+    ],
+];
+
 /// Background tile
 #[derive(Clone, Copy, Default, Debug, Serialize, Deserialize)]
 struct Tile {
@@ -62,13 +120,45 @@ const PPU_ADDR: u16 = 0x6;
 const PPU_DATA: u16 = 0x7;
 
 const OAM_SIZE: usize = 0x100;
+/// Sprites per scanline of the real hardware
 const OAM2_SIZE: usize = 0x8;
+/// Sprites per scanline without the sprite limit (all of OAM)
+const MAX_SPRITES: usize = OAM_SIZE / 4;
+
+/// Number of Ppu cycles before a bit of the open bus decays to 0 (~600ms)
+const OPEN_BUS_DECAY: u32 = 5_369_318 * 6 / 10;
+
+/// Ppu cycles after power on or reset where writes to PPUCTRL, PPUMASK, PPUSCROLL and PPUADDR are
+/// ignored (29658 Cpu cycles, about one frame)
+const WARM_UP: u32 = 29658 * 3;
+/// Ppu cycles between the second PPUADDR write and the copy of the address to v
+const V_UPDATE_DELAY: u8 = 3;
+/// Bits of v changed by a coarse X increment
+const COARSE_X_BITS: u16 = 0x041F;
+/// Bits of v changed by a Y increment
+const Y_BITS: u16 = 0x7BE0;
+
+/// Called with each rendered frame
+type RenderFn<'a> = Box<dyn FnMut(&Frame, FrameInfo) + 'a>;
 
 /// Ppu memory interface
 pub trait Interface {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, data: u8);
     fn inc_scanline(&mut self);
+
+    /// Reads without side effects, for debuggers
+    fn peek(&self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// CHR banks mapped by the cartridge, for debuggers
+    fn chr_bank_layout(&self) -> Option<[usize; 8]> {
+        None
+    }
+
+    /// Resets the memory to its power on contents
+    fn power_on(&mut self, _ram_pattern: RamPattern) {}
 }
 
 pub trait PpuInterface: Interface + Savable {}
@@ -82,16 +172,16 @@ pub struct Ppu<'a> {
     bus: Box<dyn PpuInterface>,
     pending_nmi: Option<bool>,
     open_bus: u8,
-    open_bus_timer: u32,
+    open_bus_timers: [u32; 8],
 
     oam_data: [u8; OAM_SIZE],
-    oam2_data: [SpriteInfo; OAM2_SIZE],
+    oam2_data: [SpriteInfo; MAX_SPRITES],
     oam_addr: u8,
     clearing_oam: bool,
     sprite_0_rendering: bool,
     sprite_count: usize,
-    fg_lo_shift: [u8; OAM2_SIZE],
-    fg_hi_shift: [u8; OAM2_SIZE],
+    fg_lo_shift: [u8; MAX_SPRITES],
+    fg_hi_shift: [u8; MAX_SPRITES],
 
     addr_toggle: bool,
     read_buffer: u8,
@@ -110,94 +200,114 @@ pub struct Ppu<'a> {
     frame: Frame,
     frame_count: u128,
     odd_frame: bool,
-    render_fn: Box<dyn FnMut(&[u8]) + 'a>,
+    render_fn: RenderFn<'a>,
+    /// Frames skipped between 2 calls to the render function
+    frame_skip: u32,
+    skipped_frames: u32,
+    /// Ppu cycles left before the registers accept writes
+    warm_up: u32,
+    /// Scrambled palette of a VS System Ppu
+    vs_palette: Option<&'static [u8; 0x40]>,
+    /// Record the CHR banks of each scanline
+    chr_bank_capture: bool,
+    chr_banks: Vec<[usize; 8]>,
+    /// Only 8 sprites per scanline, like the real hardware
+    sprite_limit: bool,
+    /// Status was read right before the vblank flag is set, so it isn't set this frame
+    suppress_vblank: bool,
+    /// Address of a PPUADDR write made while rendering, and cycles left before it reaches v
+    v_update: Option<(u16, u8)>,
 }
 
 impl Savable for Ppu<'_> {
-    fn save(&self, output: &mut BufWriter<File>) -> bincode::Result<()> {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
         self.bus.save(output)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.ctrl.bits())?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.mask.bits())?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.status.bits())?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.pending_nmi)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.open_bus)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.open_bus_timer)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.oam_addr)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.clearing_oam)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.sprite_0_rendering)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.sprite_count)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.addr_toggle)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.read_buffer)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.xfine)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.v_addr.raw())?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.scroll.raw())?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.scanline)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.cycle)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.next_tile)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.bg_lo_shift)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.bg_hi_shift)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.bg_attr_lo_shift)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.bg_attr_hi_shift)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.ctrl.bits())?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.mask.bits())?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.status.bits())?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.pending_nmi)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.open_bus)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.open_bus_timers)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.oam_addr)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.clearing_oam)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.sprite_0_rendering)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.sprite_count)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.addr_toggle)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.read_buffer)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.xfine)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.v_addr.raw())?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.scroll.raw())?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.v_update)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.scanline)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.cycle)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.next_tile)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.bg_lo_shift)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.bg_hi_shift)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.bg_attr_lo_shift)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.bg_attr_hi_shift)?;
         for i in 0..OAM_SIZE {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.oam_data[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.oam_data[i])?;
         }
-        for i in 0..OAM2_SIZE {
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.fg_lo_shift[i])?;
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.fg_hi_shift[i])?;
-            bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.oam2_data[i])?;
+        for i in 0..MAX_SPRITES {
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.fg_lo_shift[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.fg_hi_shift[i])?;
+            bincode::serialize_into::<&mut dyn Write, _>(output, &self.oam2_data[i])?;
         }
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.frame_count)?;
-        bincode::serialize_into::<&mut BufWriter<File>, _>(output, &self.odd_frame)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.frame_count)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.odd_frame)?;
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.warm_up)?;
         Ok(())
     }
 
-    fn load(&mut self, input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
         self.bus.load(input)?;
-        let byte: u8 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        let byte: u8 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         self.ctrl.set_raw(byte);
-        let byte: u8 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        let byte: u8 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         self.mask.set_raw(byte);
-        let byte: u8 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        let byte: u8 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         self.status.set_raw(byte);
-        self.pending_nmi = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.open_bus = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.open_bus_timer = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.oam_addr = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.clearing_oam = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.sprite_0_rendering = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.sprite_count = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.addr_toggle = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.read_buffer = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.xfine = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        let word: u16 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        self.pending_nmi = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.open_bus = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.open_bus_timers = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.oam_addr = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.clearing_oam = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.sprite_0_rendering = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.sprite_count = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.addr_toggle = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.read_buffer = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.xfine = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        let word: u16 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         self.v_addr.set_raw(word);
-        let word: u16 = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        let word: u16 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         self.scroll.set_raw(word);
-        self.scanline = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.cycle = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.next_tile = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.bg_lo_shift = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.bg_hi_shift = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.bg_attr_lo_shift = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.bg_attr_hi_shift = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        self.v_update = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.scanline = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.cycle = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.next_tile = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.bg_lo_shift = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.bg_hi_shift = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.bg_attr_lo_shift = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.bg_attr_hi_shift = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         for i in 0..OAM_SIZE {
-            self.oam_data[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+            self.oam_data[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         }
-        for i in 0..OAM2_SIZE {
-            self.fg_lo_shift[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-            self.fg_hi_shift[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-            self.oam2_data[i] = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        for i in 0..MAX_SPRITES {
+            self.fg_lo_shift[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+            self.fg_hi_shift[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+            self.oam2_data[i] = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         }
-        self.frame_count = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
-        self.odd_frame = bincode::deserialize_from::<&mut BufReader<File>, _>(input)?;
+        self.frame_count = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.odd_frame = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        self.warm_up = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
         Ok(())
     }
 }
 
 impl<'a> Ppu<'a> {
-    pub fn new<F>(bus: Box<dyn PpuInterface>, render_fn: Box<F>) -> Self
+    pub fn new<F>(bus: Box<dyn PpuInterface>, mut render_fn: Box<F>) -> Self
     where
-        F: FnMut(&[u8]) + 'a,
+        F: FnMut(&Frame) + 'a,
     {
         Self {
             ctrl: Controller::from_bits_truncate(0),
@@ -207,16 +317,16 @@ impl<'a> Ppu<'a> {
             bus,
             pending_nmi: None,
             open_bus: 0,
-            open_bus_timer: 0,
+            open_bus_timers: [0; 8],
 
             oam_data: [0; OAM_SIZE],
-            oam2_data: [SpriteInfo::default(); OAM2_SIZE],
+            oam2_data: [SpriteInfo::default(); MAX_SPRITES],
             oam_addr: 0,
             clearing_oam: false,
             sprite_0_rendering: false,
             sprite_count: 0,
-            fg_lo_shift: [0; OAM2_SIZE],
-            fg_hi_shift: [0; OAM2_SIZE],
+            fg_lo_shift: [0; MAX_SPRITES],
+            fg_hi_shift: [0; MAX_SPRITES],
 
             addr_toggle: false,
             read_buffer: 0,
@@ -235,34 +345,68 @@ impl<'a> Ppu<'a> {
             frame: Frame::new(),
             frame_count: 0,
             odd_frame: false,
-            render_fn,
+            render_fn: Box::new(move |frame: &Frame, _: FrameInfo| render_fn(frame)),
+            frame_skip: 0,
+            skipped_frames: 0,
+            warm_up: 0,
+            vs_palette: None,
+            chr_bank_capture: false,
+            chr_banks: Vec::new(),
+            sprite_limit: true,
+            suppress_vblank: false,
+            v_update: None,
         }
     }
 
-    /// Resets the state of the Ppu
+    /// Soft resets the Ppu (reset button)
+    ///
+    /// Only the control, mask and scroll registers are cleared. VRAM, OAM and the
+    /// address register keep their values
     pub fn reset(&mut self) {
+        self.ctrl = Controller::from_bits_truncate(0);
+        self.mask = Mask::from_bits_truncate(0);
+        self.pending_nmi = None;
+        self.suppress_vblank = false;
+
+        self.addr_toggle = false;
+        self.read_buffer = 0;
+        self.xfine = 0;
+        self.scroll.set_raw(0);
+        self.v_update = None;
+
+        self.odd_frame = false;
+        self.warm_up = WARM_UP;
+    }
+
+    /// Resets the whole state of the Ppu (power on)
+    ///
+    /// `ram_pattern`: Contents of the VRAM at power on
+    pub fn power_on(&mut self, ram_pattern: RamPattern) {
+        self.bus.power_on(ram_pattern);
         self.ctrl = Controller::from_bits_truncate(0);
         self.mask = Mask::from_bits_truncate(0);
         self.status = Status::from_bits_truncate(0);
 
         self.pending_nmi = None;
+        self.suppress_vblank = false;
         self.open_bus = 0;
-        self.open_bus_timer = 0;
+        self.open_bus_timers = [0; 8];
 
         self.oam_data = [0; OAM_SIZE];
-        self.oam2_data = [SpriteInfo::default(); OAM2_SIZE];
+        self.oam2_data = [SpriteInfo::default(); MAX_SPRITES];
         self.oam_addr = 0;
         self.clearing_oam = false;
         self.sprite_0_rendering = false;
         self.sprite_count = 0;
-        self.fg_lo_shift = [0; OAM2_SIZE];
-        self.fg_hi_shift = [0; OAM2_SIZE];
+        self.fg_lo_shift = [0; MAX_SPRITES];
+        self.fg_hi_shift = [0; MAX_SPRITES];
 
         self.addr_toggle = false;
         self.read_buffer = 0;
         self.xfine = 0;
         self.v_addr.set_raw(0);
         self.scroll.set_raw(0);
+        self.v_update = None;
 
         self.scanline = 0;
         self.cycle = 0;
@@ -272,14 +416,16 @@ impl<'a> Ppu<'a> {
         self.bg_attr_lo_shift = 0;
         self.bg_attr_hi_shift = 0;
 
-        self.frame.clear();
+        // The screen shows the backdrop color until the first frame is drawn
+        let backdrop = self.backdrop_color();
+        self.frame.clear(backdrop);
         self.frame_count = 0;
         self.odd_frame = false;
+        self.warm_up = WARM_UP;
     }
 
     /// Debug function to show the cartridge CHR Patterns
-    #[allow(dead_code)]
-    fn render_chr_pattern(&mut self) {
+    pub fn render_chr_pattern(&mut self) {
         for tile_y in 0..16 {
             for tile_x in 0..16 {
                 let offset = tile_y * 256 + tile_x * 16;
@@ -330,8 +476,7 @@ impl<'a> Ppu<'a> {
     }
 
     /// Debug function to show the nametable 0
-    #[allow(dead_code)]
-    fn render_nametable_0(&mut self) {
+    pub fn render_nametable_0(&mut self) {
         for addr in 0..0x3C0 {
             let tile_id = self.mem_read(0x2000 | addr);
             let tile_addr = self.ctrl.bg_base_addr() + (tile_id as u16) * 16;
@@ -369,12 +514,153 @@ impl<'a> Ppu<'a> {
         }
     }
 
+    /// Also keep the palette index of each pixel, for filters like NTSC composite simulation
+    pub fn set_palette_indices(&mut self, enabled: bool) {
+        self.frame.keep_indices(enabled);
+    }
+
+    /// Replaces the render function with one that also receives information about the frame
+    pub fn set_render_fn<F>(&mut self, render_fn: F)
+    where
+        F: FnMut(&Frame, FrameInfo) + 'a,
+    {
+        self.render_fn = Box::new(render_fn);
+    }
+
+    /// Only call the render function every `skip + 1` frames. The frames are still computed
+    pub fn set_frame_skip(&mut self, skip: u32) {
+        self.frame_skip = skip;
+    }
+
+    /// Disabling the sprite limit draws every sprite of a scanline. Removes the flicker of games
+    /// with a lot of sprites, but isn't accurate
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+    }
+
+    /// Uses the palette of a RP2C04 VS System Ppu (0-3), or the normal one with `None`
+    pub fn set_vs_palette(&mut self, palette: Option<usize>) {
+        self.vs_palette = palette.map(|p| &VS_PALETTES[p]);
+    }
+
+    /// Records the CHR banks of the cartridge at the start of each visible scanline
+    ///
+    /// Shows the mid-frame bank switches of mappers like MMC3
+    pub fn set_chr_bank_capture(&mut self, enabled: bool) {
+        self.chr_bank_capture = enabled;
+        self.chr_banks.clear();
+    }
+
+    /// CHR banks of each scanline of the last frame, if the capture is enabled
+    ///
+    /// Empty if the mapper doesn't report its banks
+    pub fn debug_chr_banks_per_scanline(&self) -> Vec<[usize; 8]> {
+        self.chr_banks.clone()
+    }
+
+    /// Returns the palette indices of the frame, if enabled
+    ///
+    /// Each entry is the 6 bit palette index with the emphasis bits in bits 6-8
+    pub fn palette_indices(&self) -> Option<&[u16]> {
+        self.frame.indices()
+    }
+
+    /// Reads a Ppu register without side effects
+    ///
+    /// The status isn't cleared, the address doesn't move and the open bus isn't refreshed
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        match addr {
+            PPU_STATUS => (self.status.bits() & 0xE0) | (self.open_bus & 0x1F),
+            OAM_DATA => self.oam_data_value(),
+            PPU_DATA => self.read_buffer,
+            _ => self.open_bus,
+        }
+    }
+
+    /// Reads the Ppu memory without side effects
+    pub fn peek_vram(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+
+    /// Writes the Ppu memory without going through the registers
+    pub fn poke_vram(&mut self, addr: u16, data: u8) {
+        self.bus.write(addr, data);
+    }
+
+    /// Registers and timing of the Ppu, for debug dumps
+    pub fn snapshot(&self) -> Value {
+        json!({
+            "ctrl": self.ctrl.bits(),
+            "mask": self.mask.bits(),
+            "status": self.status.bits(),
+            "oam_addr": self.oam_addr,
+            "v_addr": self.v_addr.raw(),
+            "t_addr": self.scroll.raw(),
+            "fine_x": self.xfine,
+            "write_toggle": self.addr_toggle,
+            "read_buffer": self.read_buffer,
+            "open_bus": self.open_bus,
+            "scanline": self.scanline,
+            "cycle": self.cycle,
+            "frame_count": self.frame_count,
+            "odd_frame": self.odd_frame,
+        })
+    }
+
+    /// Returns the current timing, scroll and registers of the Ppu
+    pub fn state(&self) -> PpuState {
+        // The scroll set by the game lives in the temporary address (t)
+        let scroll = &self.scroll;
+        PpuState {
+            scanline: self.scanline,
+            cycle: self.cycle,
+            frame_count: self.frame_count,
+            v_addr: self.v_addr.raw(),
+            scroll_x: (scroll.nta_h() as u16) * 256
+                + (scroll.xcoarse() as u16) * 8
+                + self.xfine as u16,
+            scroll_y: (scroll.nta_v() as u16) * 240
+                + (scroll.ycoarse() as u16) * 8
+                + scroll.yfine() as u16,
+            ctrl: self.ctrl,
+            mask: self.mask,
+            status: self.status & !Status::UNUSED,
+        }
+    }
+
+    /// Returns the current scanline, -1 is the pre-render scanline
+    pub fn scanline(&self) -> i32 {
+        self.scanline
+    }
+
     /// Returns how many frames have been rendered
     pub fn frame_count(&self) -> u128 {
         self.frame_count
     }
 
+    /// Returns the frame being drawn. It is complete during vblank
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Returns the color of a pixel of the frame. During rendering, the lines under the
+    /// current scanline are still from the last frame
+    pub fn pixel_at(&self, x: usize, y: usize) -> Rgb {
+        self.frame.get_pixel(x, y)
+    }
+
+    /// Color drawn where there is no background or sprite pixel, and on the whole screen
+    /// while rendering is disabled. Palette entry 0x3F00, or the one the Ppu address points to
+    pub fn backdrop_color(&mut self) -> Rgb {
+        let addr = self.backdrop_override().unwrap_or(0x3F00);
+        let index = self.palette_index_at(addr);
+        self.index_color(index)
+    }
+
     /// Ppu register read
+    ///
+    /// The write-only registers return the open bus without refreshing it, nothing drives the
+    /// bus. Status, OAM data and Ppu data refresh the bits they drive
     pub fn read(&mut self, addr: u16) -> u8 {
         // The ppu bus would latch data for a few cycles, so there might
         // be data on the bus
@@ -386,26 +672,27 @@ impl<'a> Ppu<'a> {
                 // Only the upper 3 bits are the status data
                 // The rest is set to what was on the open bus
                 data = self.status.bits() | (self.open_bus & 0x1F);
+                // Only the status bits are driven on the bus
+                self.refresh_open_bus_bits(data, 0xE0);
                 // Reading status removes the vblank flag
                 self.status.remove(Status::IN_VBLANK);
-                self.pending_nmi = None;
+                // Races with the vblank flag being set on dot 1 of scanline 241. `cycle` is the
+                // next dot to run
+                if self.scanline == 241 {
+                    match self.cycle {
+                        // One dot before: reads as clear and the flag isn't set this frame
+                        1 => self.suppress_vblank = true,
+                        // Same dot or one after: reads as set but the NMI is cancelled
+                        2 | 3 => self.pending_nmi = None,
+                        _ => {}
+                    }
+                }
                 // Also resets the address toggle
                 self.addr_toggle = false;
             }
             OAM_ADDR => {}
-            OAM_DATA => match self.clearing_oam {
-                // Always returns 0xFF when clearing secondary OAM
-                true => data = 0xFF,
-                false => {
-                    // Bits 2, 3 and 4 do not exist in the Ppu if reading byte 2
-                    let mask = match self.oam_addr & 0x3 {
-                        2 => 0xE3,
-                        _ => 0xFF,
-                    };
-                    // Read from OAM and refresh open bus
-                    data = self.refresh_open_bus(self.oam_data[self.oam_addr as usize] & mask);
-                }
-            },
+            // Read from OAM and refresh open bus
+            OAM_DATA => data = self.refresh_open_bus(self.oam_data_value()),
             PPU_SCROLL => {}
             PPU_ADDR => {}
             PPU_DATA => {
@@ -423,9 +710,12 @@ impl<'a> Ppu<'a> {
                     data = (self.open_bus & 0xC0) | (self.read_buffer & 0x3F);
                     // Add the geryscale mask if enabled
                     data &= self.mask.greyscale_mask();
+                    // The upper 2 bits are not driven when reading palette RAM
+                    self.refresh_open_bus_bits(data, 0x3F);
+                } else {
+                    // Refresh the open bus value
+                    self.refresh_open_bus(data);
                 }
-                // Refresh the open bus value
-                self.refresh_open_bus(data);
                 // Every read automatically increments the Ppu address register
                 self.increment_vaddr();
             }
@@ -438,10 +728,25 @@ impl<'a> Ppu<'a> {
     pub fn write(&mut self, addr: u16, data: u8) {
         // Refresh the open bus value
         self.refresh_open_bus(data);
+
+        // Some registers ignore writes while the Ppu warms up. The toggle isn't updated either
+        let warm_up_reg = matches!(addr, PPU_CTRL | PPU_MASK | PPU_SCROLL | PPU_ADDR);
+        if self.warm_up > 0 && warm_up_reg {
+            return;
+        }
+
         match addr {
             PPU_CTRL => {
+                let nmi_enabled = self.ctrl.nmi_enabled();
                 // Set the register to data
                 self.ctrl.set_raw(data);
+                match (nmi_enabled, self.ctrl.nmi_enabled()) {
+                    // Enabling NMI while the vblank flag is set triggers one right away
+                    (false, true) if self.in_nmi_window() => self.pending_nmi = Some(true),
+                    // Disabling it withdraws the NMI if the Cpu didn't take it yet
+                    (true, false) => self.pending_nmi = None,
+                    _ => {}
+                }
                 // Update scroll nametable
                 self.scroll.set_nta_h(self.ctrl.nta_h());
                 self.scroll.set_nta_v(self.ctrl.nta_v());
@@ -455,6 +760,11 @@ impl<'a> Ppu<'a> {
                 // Set the OAM address to data
                 self.oam_addr = data;
             }
+            // While rendering, OAM is busy with the sprite evaluation. The write is lost and
+            // only the upper 6 bits of the address are incremented
+            OAM_DATA if self.rendering_scanline() => {
+                self.oam_addr = self.oam_addr.wrapping_add(4);
+            }
             OAM_DATA => {
                 // Write the data into OAM
                 self.oam_data[self.oam_addr as usize] = data;
@@ -488,7 +798,12 @@ impl<'a> Ppu<'a> {
                     // and then set the address register (v register) to the scroll
                     true => {
                         self.scroll.set_addr_lo(data);
-                        self.v_addr = self.scroll;
+                        // The copy to v happens a few cycles later. Only the rendering can
+                        // see the delay
+                        match self.rendering_scanline() {
+                            true => self.v_update = Some((self.scroll.raw(), V_UPDATE_DELAY)),
+                            false => self.v_addr = self.scroll,
+                        }
                     }
                     // Otherwise, set the high bits of the scroll
                     false => self.scroll.set_addr_hi(data & 0x3F),
@@ -499,8 +814,6 @@ impl<'a> Ppu<'a> {
             PPU_DATA => {
                 // Write on the memory bus at the current address
                 self.mem_write(self.v_addr.raw(), data);
-                // Refresh open bus
-                self.refresh_open_bus(data);
                 // Writing to Ppu data also auto increments the address
                 self.increment_vaddr();
             }
@@ -508,6 +821,12 @@ impl<'a> Ppu<'a> {
         }
     }
 
+    /// True while the vblank flag is set, except on the dot that clears it. `cycle` is the next
+    /// dot to run
+    fn in_nmi_window(&self) -> bool {
+        self.status.contains(Status::IN_VBLANK) && !(self.scanline == -1 && self.cycle == 1)
+    }
+
     /// Poll the NMI flag set by the Ppu
     pub fn poll_nmi(&mut self) -> bool {
         self.pending_nmi.take().is_some()
@@ -517,6 +836,7 @@ impl<'a> Ppu<'a> {
     pub fn clock(&mut self) {
         // Update the open bus timer
         self.update_open_bus();
+        self.warm_up = self.warm_up.saturating_sub(1);
 
         // Every odd frame on the first scanline, the first cycle is skipped if background rendering is enabled
         // A flag is updated every frame
@@ -540,22 +860,59 @@ impl<'a> Ppu<'a> {
             self.fg_hi_shift.fill(0);
         }
 
+        // Capture the CHR banks used by this scanline
+        if self.chr_bank_capture && (0..240).contains(&scanline) && cycle == 1 {
+            if scanline == 0 {
+                self.chr_banks.clear();
+            }
+            if let Some(layout) = self.bus.chr_bank_layout() {
+                self.chr_banks.push(layout);
+            }
+        }
+
         // 0..=240 -> rendering scanline
         if scanline < 240 && self.rendering_enabled() {
             self.process_rendering_scanline();
         }
 
+        // Delayed copy of a PPUADDR write
+        if let Some((addr, delay)) = self.v_update {
+            match delay {
+                1 => {
+                    self.v_update = None;
+                    self.update_vaddr(addr);
+                }
+                _ => self.v_update = Some((addr, delay - 1)),
+            }
+        }
+
         // Set NMI if enabled on cycle 241
         if scanline == 241 && cycle == 1 {
-            self.status.set_vblank(true);
-            if self.ctrl.nmi_enabled() {
-                self.pending_nmi = Some(true)
+            // Unless the status was read on the previous dot
+            if !self.suppress_vblank {
+                self.status.set_vblank(true);
+                if self.ctrl.nmi_enabled() {
+                    self.pending_nmi = Some(true)
+                }
             }
+            self.suppress_vblank = false;
 
             // A new frame is done rendering
             self.frame_count = self.frame_count.wrapping_add(1);
             // Render in window (in this case, using SDL2)
-            (self.render_fn)(self.frame.pixels());
+            if self.skipped_frames >= self.frame_skip {
+                let info = FrameInfo {
+                    frame_count: self.frame_count,
+                    odd_frame: self.odd_frame,
+                    skipped: self.skipped_frames,
+                    region: Region::Ntsc,
+                };
+                self.frame.update_hash();
+                (self.render_fn)(&self.frame, info);
+                self.skipped_frames = 0;
+            } else {
+                self.skipped_frames += 1;
+            }
         }
 
         // Calculate the pixel color
@@ -578,7 +935,9 @@ impl<'a> Ppu<'a> {
                 // Both are non zero
                 _ => {
                     // Collision is possible
-                    self.update_sprite_zero_hit();
+                    if self.sprite_zero_hit(cycle - 1) {
+                        self.status.set_sp_0_hit(true);
+                    }
                     // The result is choosen based on the sprite priority attribute
                     // If it is 0, output foreground
                     if fg_priority != 0 {
@@ -591,9 +950,16 @@ impl<'a> Ppu<'a> {
             };
 
             // Get the color from palette RAM
-            let color = self.get_color(palette, pixel);
+            let index = match self.backdrop_override() {
+                Some(addr) => self.palette_index_at(addr),
+                None => self.palette_index(palette, pixel),
+            };
+            let color = self.index_color(index);
             // Set the pixel
             self.frame.set_pixel(cycle - 1, scanline as usize, color);
+            let emphasis = (self.mask.emphasis() as u16) << 6;
+            self.frame
+                .set_index(cycle - 1, scanline as usize, index as u16 | emphasis);
         }
 
         // Update cycle count
@@ -621,39 +987,71 @@ impl<'a> Ppu<'a> {
         }
     }
 
+    /// Value read from OAM data
+    fn oam_data_value(&self) -> u8 {
+        // Always returns 0xFF when clearing secondary OAM
+        if self.clearing_oam {
+            return 0xFF;
+        }
+        // Bits 2, 3 and 4 do not exist in the Ppu if reading byte 2
+        let mask = match self.oam_addr & 0x3 {
+            2 => 0xE3,
+            _ => 0xFF,
+        };
+        self.oam_data[self.oam_addr as usize] & mask
+    }
+
     /// Refresh open bus latch value
     fn refresh_open_bus(&mut self, data: u8) -> u8 {
-        self.open_bus = data;
-        self.open_bus_timer = 7777;
+        self.refresh_open_bus_bits(data, 0xFF)
+    }
+
+    /// Refresh the open bus bits driven by `mask`
+    ///
+    /// Each bit has its own decay timer, only the driven bits are refreshed
+    fn refresh_open_bus_bits(&mut self, data: u8, mask: u8) -> u8 {
+        self.open_bus = (self.open_bus & !mask) | (data & mask);
+        for (bit, timer) in self.open_bus_timers.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *timer = OPEN_BUS_DECAY;
+            }
+        }
         data
     }
 
-    /// Refresh open bus latch timer
+    /// Decay the open bus bits one by one as their timer runs out
     fn update_open_bus(&mut self) {
-        match self.open_bus_timer > 0 {
-            true => self.open_bus_timer -= 1,
-            false => self.open_bus = 0,
-        }
-    }
-
-    /// Update the sprite 0 hit flag
-    fn update_sprite_zero_hit(&mut self) {
-        // Sprite 0 hit is a collision between a non 0 sprite pixel and bg pixel
-        // To be possible, we have to be drawing a sprite 0 pixel and both
-        // sprite and background rendering has to be enable
-        if self.sprite_0_rendering && self.mask.render_bg() && self.mask.render_sp() {
-            // If either bg or sprite left most pixels are disabled, don't check
-            // first 8 pixels
-            if !(self.mask.render_bg8() | self.mask.render_sp8()) {
-                if (9..256).contains(&self.cycle) {
-                    self.status.set_sp_0_hit(true);
-                }
-            } else if (1..256).contains(&self.cycle) {
-                self.status.set_sp_0_hit(true);
+        // Bits already at 0 can't decay further
+        if self.open_bus == 0 {
+            return;
+        }
+
+        for (bit, timer) in self.open_bus_timers.iter_mut().enumerate() {
+            match *timer > 0 {
+                true => *timer -= 1,
+                false => self.open_bus &= !(1 << bit),
             }
         }
     }
 
+    /// Returns if an opaque sprite 0 pixel over an opaque background pixel at `x` sets the hit flag
+    ///
+    /// Only called when both pixels are non 0, so the flag is set on the exact dot they're output
+    fn sprite_zero_hit(&self, x: usize) -> bool {
+        // The pixel has to come from sprite 0 and both layers have to be enabled
+        if !self.sprite_0_rendering || !self.mask.render_bg() || !self.mask.render_sp() {
+            return false;
+        }
+
+        // Never at x=255, the Ppu doesn't check the last pixel
+        if x == 255 {
+            return false;
+        }
+
+        // If either bg or sprite left most pixels are clipped, the first 8 pixels can't hit
+        x >= 8 || (self.mask.render_bg8() && self.mask.render_sp8())
+    }
+
     /// Process the current cycle of a rendering scanline
     fn process_rendering_scanline(&mut self) {
         // To not have to write self. every time
@@ -809,7 +1207,7 @@ impl<'a> Ppu<'a> {
                 if (0..sprite_size).contains(&diff) {
                     // If the sprite is visible and there is less than 8 sprite already visible,
                     // add it to secondary OAM
-                    if sprite_count < 8 {
+                    if sprite_count < OAM2_SIZE || !self.sprite_limit {
                         self.oam2_data[sprite_count].y = self.oam_data[index];
                         self.oam2_data[sprite_count].id = self.oam_data[index + 1];
                         self.oam2_data[sprite_count].attr = self.oam_data[index + 2];
@@ -821,10 +1219,18 @@ impl<'a> Ppu<'a> {
                 }
             }
 
-            // If more than 8 sprites, set the sprite overflow bit
-            self.status.set_sp_overflow(sprite_count > 8);
+            // If more than 8 sprites, set the sprite overflow bit, even without the sprite limit
+            self.status.set_sp_overflow(sprite_count > OAM2_SIZE);
             // Visible sprite count
-            self.sprite_count = if sprite_count > 8 { 8 } else { sprite_count };
+            self.sprite_count = match self.sprite_limit {
+                true => sprite_count.min(OAM2_SIZE),
+                false => sprite_count,
+            };
+        }
+
+        // The sprite tile fetches leave the OAM address at 0
+        if (257..=320).contains(&cycle) {
+            self.oam_addr = 0;
         }
 
         if cycle == 321 {
@@ -942,9 +1348,39 @@ impl<'a> Ppu<'a> {
 
     /// Returns the RBG value of the pixel with greyscale and color emphasis applied
     fn get_color(&mut self, palette: u8, pixel: u8) -> Rgb {
-        let index = self.mem_read(0x3F00 + ((palette as u16) << 2) + pixel as u16)
-            & self.mask.greyscale_mask();
-        let c = NES_PALETTE[(index as usize) & 0x3F];
+        let index = self.palette_index(palette, pixel);
+        self.index_color(index)
+    }
+
+    /// Returns the 6 bit palette index of the pixel with greyscale applied
+    fn palette_index(&mut self, palette: u8, pixel: u8) -> u8 {
+        self.palette_index_at(0x3F00 + ((palette as u16) << 2) + pixel as u16)
+    }
+
+    /// Returns the 6 bit palette index at an address of palette RAM with greyscale applied
+    fn palette_index_at(&mut self, addr: u16) -> u8 {
+        let index = self.mem_read(addr) & self.mask.greyscale_mask();
+        match self.vs_palette {
+            Some(palette) => palette[(index & 0x3F) as usize],
+            None => index & 0x3F,
+        }
+    }
+
+    /// Palette address drawn instead of the backdrop color
+    ///
+    /// When rendering is disabled and the Ppu address points in palette RAM, the Ppu outputs
+    /// that palette entry. Some games use it to draw colors outside of the palette
+    fn backdrop_override(&self) -> Option<u16> {
+        let addr = self.v_addr.raw() & 0x3FFF;
+        match !self.rendering_enabled() && addr >= 0x3F00 {
+            true => Some(addr),
+            false => None,
+        }
+    }
+
+    /// Returns the RBG value of a palette index with color emphasis applied
+    fn index_color(&self, index: u8) -> Rgb {
+        let c = NES_PALETTE[index as usize];
 
         match self.mask.color_emph_enabled() {
             false => c,
@@ -959,6 +1395,28 @@ impl<'a> Ppu<'a> {
         }
     }
 
+    /// Copies the address of a PPUADDR write to v
+    ///
+    /// If the scroll was incremented on this cycle, the increment and the copy both drive the
+    /// bits it changes and they end up ANDed together
+    fn update_vaddr(&mut self, addr: u16) {
+        let (scanline, cycle) = (self.scanline, self.cycle);
+        let mut conflict = 0;
+        if scanline < 240 && self.rendering_enabled() && self.mask.render_bg() {
+            let fetch = (8..=256).contains(&cycle) || (328..=336).contains(&cycle);
+            if fetch && cycle % 8 == 0 {
+                conflict |= COARSE_X_BITS;
+            }
+            if cycle == 256 {
+                conflict |= Y_BITS;
+            }
+        }
+
+        let v = self.v_addr.raw();
+        self.v_addr
+            .set_raw((addr & !conflict) | (v & addr & conflict));
+    }
+
     /// Increment horizontal scroll
     fn increment_xscroll(&mut self) {
         if self.mask.render_bg() {
@@ -1041,8 +1499,22 @@ impl<'a> Ppu<'a> {
         self.mask.render_sp() | self.mask.render_bg()
     }
 
+    /// On the pre-render or a visible scanline with the rendering enabled
+    fn rendering_scanline(&self) -> bool {
+        (-1..240).contains(&self.scanline) && self.rendering_enabled()
+    }
+
     /// Increments the VRAM address by 1 or 32 (based on the control register bit)
+    ///
+    /// While rendering, the address is busy fetching tiles and Ppu data accesses glitch the
+    /// increment: coarse X and Y are both incremented instead
     fn increment_vaddr(&mut self) {
+        if self.rendering_scanline() {
+            self.increment_xscroll();
+            self.increment_yscroll();
+            return;
+        }
+
         let new_addr = self.v_addr.raw().wrapping_add(self.ctrl.increment());
         self.v_addr.set_raw(new_addr);
     }
@@ -1057,3 +1529,565 @@ impl<'a> Ppu<'a> {
         self.bus.write(addr, data);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    struct NullBus;
+
+    impl Interface for NullBus {
+        fn read(&self, _addr: u16) -> u8 {
+            0x2D
+        }
+        fn write(&mut self, _addr: u16, _data: u8) {}
+        fn inc_scanline(&mut self) {}
+    }
+
+    impl Savable for NullBus {}
+    impl PpuInterface for NullBus {}
+
+    /// Bus with a cartridge reporting its CHR banks
+    struct BankBus;
+
+    impl Interface for BankBus {
+        fn read(&self, _addr: u16) -> u8 {
+            0
+        }
+        fn write(&mut self, _addr: u16, _data: u8) {}
+        fn inc_scanline(&mut self) {}
+        fn chr_bank_layout(&self) -> Option<[usize; 8]> {
+            Some([0, 1, 2, 3, 4, 5, 6, 7])
+        }
+    }
+
+    impl Savable for BankBus {}
+    impl PpuInterface for BankBus {}
+
+    #[test]
+    fn test_chr_bank_capture() {
+        let run_frame = |ppu: &mut Ppu| {
+            while ppu.scanline() != 241 {
+                ppu.clock();
+            }
+            while ppu.scanline() == 241 {
+                ppu.clock();
+            }
+        };
+
+        let mut ppu = Ppu::new(Box::new(BankBus), Box::new(|_: &Frame| {}));
+        run_frame(&mut ppu);
+        assert!(ppu.debug_chr_banks_per_scanline().is_empty());
+
+        ppu.set_chr_bank_capture(true);
+        run_frame(&mut ppu);
+        run_frame(&mut ppu);
+        let banks = ppu.debug_chr_banks_per_scanline();
+        assert_eq!(banks.len(), 240);
+        assert_eq!(banks[120], [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        // Mappers without CHR banks don't report anything
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.set_chr_bank_capture(true);
+        run_frame(&mut ppu);
+        assert!(ppu.debug_chr_banks_per_scanline().is_empty());
+    }
+
+    /// Bus with only the palette RAM
+    struct PaletteBus([u8; 0x20]);
+
+    impl Interface for PaletteBus {
+        fn read(&self, addr: u16) -> u8 {
+            self.0[(addr & 0x1F) as usize]
+        }
+        fn write(&mut self, addr: u16, data: u8) {
+            self.0[(addr & 0x1F) as usize] = data;
+        }
+        fn inc_scanline(&mut self) {}
+    }
+
+    impl Savable for PaletteBus {}
+    impl PpuInterface for PaletteBus {}
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.mask.set_raw(0x1E);
+
+        // Not drawing sprite 0
+        assert!(!ppu.sprite_zero_hit(100));
+
+        ppu.sprite_0_rendering = true;
+        assert!(ppu.sprite_zero_hit(0));
+        assert!(ppu.sprite_zero_hit(254));
+        assert!(!ppu.sprite_zero_hit(255));
+
+        // Clipping either layer on the left masks the first 8 pixels
+        ppu.mask.set_raw(0x1A);
+        assert!(!ppu.sprite_zero_hit(7));
+        assert!(ppu.sprite_zero_hit(8));
+        ppu.mask.set_raw(0x1C);
+        assert!(!ppu.sprite_zero_hit(7));
+        assert!(ppu.sprite_zero_hit(8));
+        ppu.mask.set_raw(0x18);
+        assert!(!ppu.sprite_zero_hit(0));
+        assert!(!ppu.sprite_zero_hit(7));
+        assert!(ppu.sprite_zero_hit(8));
+        assert!(!ppu.sprite_zero_hit(255));
+
+        // Both layers have to be enabled
+        ppu.mask.set_raw(0x0E);
+        assert!(!ppu.sprite_zero_hit(100));
+    }
+    #[test]
+    fn test_palette_indices() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        assert!(ppu.palette_indices().is_none());
+
+        ppu.set_palette_indices(true);
+        // Greyscale and red emphasis, rendering disabled so only the backdrop is drawn
+        ppu.mask.set_raw(0x21);
+        while ppu.scanline != 1 {
+            ppu.clock();
+        }
+
+        let indices = ppu.palette_indices().unwrap();
+        assert_eq!(indices[0], 0x20 | 0x1 << 6);
+        assert_eq!(indices[255], 0x20 | 0x1 << 6);
+        assert_eq!(indices[256], 0);
+
+        ppu.set_palette_indices(false);
+        assert!(ppu.palette_indices().is_none());
+    }
+    #[test]
+    fn test_state() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        for _ in 0..341 + 10 {
+            ppu.clock();
+        }
+        let state = ppu.state();
+        assert_eq!((state.scanline, state.cycle), (1, 10));
+
+        // Second nametable, X = 125 and Y = 94
+        ppu.write(PPU_CTRL, 0x01);
+        ppu.write(PPU_SCROLL, 0x7D);
+        ppu.write(PPU_SCROLL, 0x5E);
+        let state = ppu.state();
+        assert_eq!((state.scroll_x, state.scroll_y), (256 + 0x7D, 0x5E));
+        assert!(state.ctrl.contains(Controller::NAMETABLE_H));
+
+        ppu.write(PPU_MASK, 0x19);
+        let state = ppu.state();
+        assert!(state.mask.render_bg() && state.mask.render_sp());
+        assert!(state.mask.contains(Mask::GREYSCALE));
+        assert!(!state.status.contains(Status::IN_VBLANK));
+    }
+
+    #[test]
+    fn test_warm_up() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.power_on(RamPattern::Zeros);
+
+        ppu.write(PPU_CTRL, 0x80);
+        ppu.write(PPU_ADDR, 0x21);
+        assert_eq!(ppu.ctrl.bits(), 0);
+        assert!(!ppu.addr_toggle);
+        // OAM is still writable
+        ppu.write(OAM_ADDR, 0x10);
+        assert_eq!(ppu.oam_addr, 0x10);
+
+        for _ in 0..WARM_UP {
+            ppu.clock();
+        }
+        ppu.write(PPU_CTRL, 0x80);
+        assert_eq!(ppu.ctrl.bits(), 0x80);
+
+        // A reset starts it over
+        ppu.reset();
+        ppu.write(PPU_MASK, 0x1E);
+        assert_eq!(ppu.mask.bits(), 0);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.write(PPU_CTRL, 0x5A);
+        ppu.open_bus_timers = [5; 8];
+
+        // Write-only registers read back the open bus without refreshing it
+        for addr in [PPU_CTRL, PPU_MASK, OAM_ADDR, PPU_SCROLL, PPU_ADDR] {
+            assert_eq!(ppu.read(addr), 0x5A);
+        }
+        assert_eq!(ppu.open_bus_timers, [5; 8]);
+        for _ in 0..6 {
+            ppu.clock();
+        }
+        assert_eq!(ppu.read(PPU_MASK), 0);
+
+        // The status only drives bits 5-7
+        ppu.write(PPU_MASK, 0xFF);
+        ppu.open_bus_timers = [5; 8];
+        ppu.status = Status::from_bits_truncate(0x80);
+        assert_eq!(ppu.read(PPU_STATUS), 0x9F);
+        assert_eq!(ppu.open_bus_timers[..5], [5; 5]);
+        assert_eq!(ppu.open_bus_timers[5..], [OPEN_BUS_DECAY; 3]);
+        assert_eq!(ppu.read(PPU_CTRL), 0x9F);
+
+        // OAM data drives the whole byte, even while clearing secondary OAM
+        ppu.oam_addr = 2;
+        ppu.oam_data[2] = 0xFF;
+        assert_eq!(ppu.read(OAM_DATA), 0xE3);
+        assert_eq!(ppu.open_bus, 0xE3);
+        ppu.write(PPU_CTRL, 0x00);
+        ppu.open_bus_timers = [5; 8];
+        ppu.clearing_oam = true;
+        assert_eq!(ppu.peek_register(OAM_DATA), 0xFF);
+        assert_eq!(ppu.read(OAM_DATA), 0xFF);
+        assert_eq!(ppu.open_bus_timers, [OPEN_BUS_DECAY; 8]);
+        assert_eq!(ppu.read(PPU_SCROLL), 0xFF);
+    }
+
+    #[test]
+    fn test_open_bus_decay() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.write(PPU_CTRL, 0x5A);
+        // The bits are still there after ~600ms, and gone on the next cycle
+        for _ in 0..OPEN_BUS_DECAY {
+            ppu.update_open_bus();
+        }
+        assert_eq!(ppu.open_bus, 0x5A);
+        ppu.update_open_bus();
+        assert_eq!(ppu.open_bus, 0);
+    }
+
+    #[test]
+    fn test_oam_data_while_rendering() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.mask.set_raw(0x18);
+        ppu.scanline = 100;
+        ppu.cycle = 100;
+
+        // The write is dropped and the address skips to the next sprite
+        ppu.oam_addr = 0x11;
+        ppu.write(OAM_DATA, 0x42);
+        assert_eq!(ppu.oam_data[0x11], 0);
+        assert_eq!(ppu.oam_addr, 0x15);
+
+        // The sprite fetches reset the address
+        ppu.cycle = 257;
+        ppu.process_rendering_scanline();
+        assert_eq!(ppu.oam_addr, 0);
+
+        // Writes work in vblank or with the rendering disabled
+        ppu.oam_addr = 0x11;
+        ppu.scanline = 241;
+        ppu.write(OAM_DATA, 0x42);
+        ppu.scanline = 100;
+        ppu.mask.set_raw(0x00);
+        ppu.write(OAM_DATA, 0x43);
+        assert_eq!(ppu.oam_data[0x11..0x13], [0x42, 0x43]);
+        assert_eq!(ppu.oam_addr, 0x13);
+    }
+
+    #[test]
+    fn test_rendering_increment_glitch() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.write(PPU_ADDR, 0x20);
+        ppu.write(PPU_ADDR, 0x00);
+
+        // Normal increment in vblank, even with rendering enabled
+        ppu.mask.set_raw(0x08);
+        ppu.scanline = 241;
+        ppu.write(PPU_DATA, 0x00);
+        assert_eq!(ppu.v_addr.raw(), 0x2001);
+
+        // Coarse X and fine Y both move on a visible scanline
+        ppu.scanline = 10;
+        ppu.write(PPU_DATA, 0x00);
+        assert_eq!(ppu.v_addr.raw(), 0x3002);
+        ppu.read(PPU_DATA);
+        assert_eq!(ppu.v_addr.raw(), 0x4003);
+
+        // Rendering disabled
+        ppu.mask.set_raw(0x00);
+        ppu.write(PPU_DATA, 0x00);
+        assert_eq!(ppu.v_addr.raw(), 0x4004);
+    }
+
+    #[test]
+    fn test_vaddr_update_state() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.mask.set_raw(0x08);
+        ppu.scanline = 10;
+        ppu.cycle = 98;
+        ppu.write(PPU_ADDR, 0x23);
+        ppu.write(PPU_ADDR, 0x45);
+        let mut state = vec![];
+        ppu.save(&mut state).unwrap();
+
+        // The pending copy to v survives a state load
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.load(&mut state.as_slice()).unwrap();
+        assert_eq!(ppu.v_addr.raw(), 0x0000);
+        for _ in 0..V_UPDATE_DELAY {
+            ppu.clock();
+        }
+        assert_eq!(ppu.v_addr.raw(), 0x2345);
+    }
+
+    #[test]
+    fn test_vaddr_update_conflict() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.mask.set_raw(0x08);
+        ppu.scanline = 10;
+
+        // v gets the address a few cycles later while rendering
+        ppu.cycle = 98;
+        ppu.write(PPU_ADDR, 0x23);
+        ppu.write(PPU_ADDR, 0x45);
+        assert_eq!(ppu.v_addr.raw(), 0x0000);
+        for _ in 0..V_UPDATE_DELAY {
+            ppu.clock();
+        }
+        assert_eq!(ppu.v_addr.raw(), 0x2345);
+
+        // Landing on a coarse X increment (0x0000 -> 0x0001)
+        ppu.v_addr.set_raw(0x0000);
+        ppu.cycle = 246;
+        ppu.write(PPU_ADDR, 0x23);
+        ppu.write(PPU_ADDR, 0x45);
+        for _ in 0..V_UPDATE_DELAY {
+            ppu.clock();
+        }
+        assert_eq!(ppu.v_addr.raw(), 0x2341);
+
+        // Landing on the Y increment of cycle 256 (0x0000 -> 0x1001)
+        ppu.v_addr.set_raw(0x0000);
+        ppu.cycle = 254;
+        ppu.write(PPU_ADDR, 0x23);
+        ppu.write(PPU_ADDR, 0x45);
+        for _ in 0..V_UPDATE_DELAY {
+            ppu.clock();
+        }
+        assert_eq!(ppu.v_addr.raw(), 0x0001);
+
+        // Copied right away in vblank
+        ppu.scanline = 241;
+        ppu.write(PPU_ADDR, 0x23);
+        ppu.write(PPU_ADDR, 0x45);
+        assert_eq!(ppu.v_addr.raw(), 0x2345);
+    }
+
+    #[test]
+    fn test_vs_palette() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        // NullBus reads 0x2D everywhere
+        assert_eq!(ppu.palette_index(0, 1), 0x2D);
+        ppu.set_vs_palette(Some(0));
+        assert_eq!(ppu.palette_index(0, 1), 0x02);
+        ppu.set_vs_palette(None);
+        assert_eq!(ppu.palette_index(0, 1), 0x2D);
+
+        // Every chip shows all the NES colors except a few duplicates
+        for palette in VS_PALETTES.iter() {
+            let mut colors = palette.to_vec();
+            colors.sort_unstable();
+            colors.dedup();
+            assert!(colors.len() >= 58);
+        }
+    }
+
+    #[test]
+    fn test_frame_info() {
+        let infos = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&infos);
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.set_render_fn(move |_, info| log.borrow_mut().push(info));
+        ppu.set_frame_skip(1);
+
+        while ppu.frame_count() < 4 {
+            ppu.clock();
+        }
+        let infos = infos.borrow();
+        assert_eq!(infos.len(), 2);
+        assert_eq!((infos[0].frame_count, infos[0].skipped), (2, 1));
+        assert_eq!((infos[1].frame_count, infos[1].skipped), (4, 1));
+        assert_eq!(infos[1].region, Region::Ntsc);
+    }
+
+    #[test]
+    fn test_frame_skip() {
+        let rendered = Rc::new(Cell::new(0));
+        let count = Rc::clone(&rendered);
+        let mut ppu = Ppu::new(
+            Box::new(NullBus),
+            Box::new(move |_: &Frame| count.set(count.get() + 1)),
+        );
+        ppu.set_frame_skip(2);
+
+        while ppu.frame_count() < 6 {
+            ppu.clock();
+        }
+        assert_eq!(rendered.get(), 2);
+        assert_eq!(ppu.frame_count(), 6);
+    }
+
+    #[test]
+    fn test_sprite_limit() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.mask.set_raw(0x10);
+        // 12 sprites on scanline 10
+        for i in 0..12 {
+            ppu.oam_data[i * 4] = 10;
+            ppu.oam_data[i * 4 + 3] = i as u8 * 8;
+        }
+        for i in 12..MAX_SPRITES {
+            ppu.oam_data[i * 4] = 0xF0;
+        }
+        ppu.scanline = 10;
+        ppu.cycle = 257;
+
+        ppu.process_rendering_scanline();
+        assert_eq!(ppu.sprite_count, 8);
+        assert!(ppu.status.contains(Status::SP_OVERFLOW));
+
+        ppu.set_sprite_limit(false);
+        ppu.process_rendering_scanline();
+        assert_eq!(ppu.sprite_count, 12);
+        assert_eq!(ppu.oam2_data[11].x, 88);
+        // The overflow flag still behaves like the hardware
+        assert!(ppu.status.contains(Status::SP_OVERFLOW));
+    }
+
+    #[test]
+    fn test_vblank_read_race() {
+        let new_ppu = |cycle| {
+            let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+            ppu.write(PPU_CTRL, 0x80);
+            ppu.scanline = 241;
+            ppu.cycle = 1;
+            while ppu.cycle < cycle {
+                ppu.clock();
+            }
+            ppu
+        };
+
+        // Read one dot before the flag is set: no flag and no NMI for this frame
+        let mut ppu = new_ppu(1);
+        assert_eq!(ppu.read(PPU_STATUS) & 0x80, 0);
+        ppu.clock();
+        assert!(!ppu.status.contains(Status::IN_VBLANK));
+        assert!(!ppu.poll_nmi());
+
+        // Read on the same dot or the one after: the flag is seen but the NMI is cancelled
+        for cycle in [2, 3] {
+            let mut ppu = new_ppu(cycle);
+            assert_eq!(ppu.read(PPU_STATUS) & 0x80, 0x80);
+            assert!(!ppu.poll_nmi());
+        }
+
+        // Read later: the NMI already happened
+        let mut ppu = new_ppu(4);
+        assert_eq!(ppu.read(PPU_STATUS) & 0x80, 0x80);
+        assert!(ppu.poll_nmi());
+    }
+
+    #[test]
+    fn test_nmi_enable_in_vblank() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.scanline = 241;
+        ppu.cycle = 1;
+        ppu.clock();
+        assert!(!ppu.poll_nmi());
+
+        // Enabling the NMI in vblank triggers it
+        ppu.write(PPU_CTRL, 0x80);
+        assert!(ppu.poll_nmi());
+        // Only when the bit goes from 0 to 1
+        ppu.write(PPU_CTRL, 0x80);
+        assert!(!ppu.poll_nmi());
+
+        // Not once the flag is cleared
+        ppu.write(PPU_CTRL, 0x00);
+        ppu.read(PPU_STATUS);
+        ppu.write(PPU_CTRL, 0x80);
+        assert!(!ppu.poll_nmi());
+    }
+
+    #[test]
+    fn test_nmi_disable_in_vblank() {
+        let mut ppu = Ppu::new(Box::new(NullBus), Box::new(|_: &Frame| {}));
+        ppu.write(PPU_CTRL, 0x80);
+        ppu.scanline = 241;
+        ppu.cycle = 1;
+        ppu.clock();
+
+        // Disabling the NMI before the Cpu sees it cancels it
+        ppu.write(PPU_CTRL, 0x00);
+        assert!(!ppu.poll_nmi());
+        // The flag is still set, enabling again triggers a new one
+        ppu.write(PPU_CTRL, 0x80);
+        assert!(ppu.poll_nmi());
+
+        // Not on the dot that clears the flag
+        ppu.write(PPU_CTRL, 0x00);
+        ppu.scanline = -1;
+        ppu.cycle = 1;
+        ppu.write(PPU_CTRL, 0x80);
+        assert!(!ppu.poll_nmi());
+    }
+
+    #[test]
+    fn test_backdrop_override() {
+        let mut palette = [0; 0x20];
+        palette[0x00] = 0x0F;
+        palette[0x05] = 0x16;
+        let mut ppu = Ppu::new(Box::new(PaletteBus(palette)), Box::new(|_: &Frame| {}));
+        ppu.set_palette_indices(true);
+        let draw_line = |ppu: &mut Ppu| {
+            ppu.scanline = 10;
+            ppu.cycle = 0;
+            while ppu.scanline == 10 {
+                ppu.clock();
+            }
+            ppu.palette_indices().unwrap()[10 * frame::WIDTH as usize + 100]
+        };
+
+        // Normal backdrop color
+        ppu.write(PPU_ADDR, 0x20);
+        ppu.write(PPU_ADDR, 0x00);
+        assert_eq!(draw_line(&mut ppu), 0x0F);
+
+        // The address points in palette RAM
+        ppu.write(PPU_ADDR, 0x3F);
+        ppu.write(PPU_ADDR, 0x05);
+        assert_eq!(draw_line(&mut ppu), 0x16);
+
+        assert_eq!(ppu.pixel_at(100, 10), NES_PALETTE[0x16]);
+
+        // Not while rendering
+        ppu.mask.set_raw(0x08);
+        assert_eq!(draw_line(&mut ppu), 0x0F);
+        assert_eq!(ppu.pixel_at(100, 10), NES_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn test_backdrop_color() {
+        let mut palette = [0; 0x20];
+        palette[0x00] = 0x21;
+        palette[0x05] = 0x16;
+        let mut ppu = Ppu::new(Box::new(PaletteBus(palette)), Box::new(|_: &Frame| {}));
+        assert_eq!(ppu.backdrop_color(), NES_PALETTE[0x21]);
+
+        // The first frame starts with the backdrop color
+        ppu.power_on(RamPattern::Zeros);
+        assert_eq!(ppu.pixel_at(0, 0), NES_PALETTE[0x21]);
+        assert_eq!(ppu.pixel_at(255, 239), NES_PALETTE[0x21]);
+
+        // Follows the palette address while rendering is disabled
+        ppu.v_addr.set_raw(0x3F05);
+        assert_eq!(ppu.backdrop_color(), NES_PALETTE[0x16]);
+    }
+}
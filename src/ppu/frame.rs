@@ -1,18 +1,68 @@
 use serde::{Deserialize, Serialize};
 
 use super::Rgb;
-use crate::nes::{HEIGHT, WIDTH};
+
+/// NES screen width
+pub const WIDTH: u32 = 256;
+/// NES screen height
+pub const HEIGHT: u32 = 240;
+
+/// TV standard of the emulated console
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    /// Only the Apu has the PAL timings for now
+    Pal,
+}
+
+/// Information sent with a frame to the render function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameInfo {
+    /// Frames completed by the Ppu, this one included
+    pub frame_count: u128,
+    /// Odd frames are one Ppu cycle shorter when rendering is enabled
+    pub odd_frame: bool,
+    /// Frames computed but not sent to the render function since the last one (frame skip)
+    pub skipped: u32,
+    pub region: Region,
+}
+
+/// Small copy of a frame, stored with the save states
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    /// RGB, 3 bytes per pixel
+    pub pixels: Vec<u8>,
+}
 
 /// Helper struct for pixel buffer
 #[derive(Serialize, Deserialize)]
 pub struct Frame {
     pixels: Vec<u8>,
+    /// Palette index of each pixel, for filters working on the NES signal instead of RGB
+    indices: Option<Vec<u16>>,
+    /// Hash of the pixels when the frame was last completed, and the time before
+    #[serde(skip)]
+    hash: Option<u64>,
+    #[serde(skip)]
+    previous_hash: Option<u64>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Frame {
     pub fn new() -> Self {
         Self {
             pixels: vec![0; (WIDTH * HEIGHT * 3) as usize],
+            indices: None,
+            hash: None,
+            previous_hash: None,
         }
     }
 
@@ -29,8 +79,161 @@ impl Frame {
         self.pixels[index + 2] = pixel.2;
     }
 
-    /// Sets all pixels to black
-    pub fn clear(&mut self) {
-        self.pixels.fill(0);
+    /// Returns the pixel at coords x, y
+    pub fn get_pixel(&self, x: usize, y: usize) -> Rgb {
+        debug_assert!(x < WIDTH as usize && y < HEIGHT as usize);
+        let index = (y * 3 * WIDTH as usize) + (x * 3);
+        Rgb(
+            self.pixels[index],
+            self.pixels[index + 1],
+            self.pixels[index + 2],
+        )
+    }
+
+    /// Enables or disables the palette index buffer
+    pub fn keep_indices(&mut self, enabled: bool) {
+        self.indices = match enabled {
+            true => Some(vec![0; (WIDTH * HEIGHT) as usize]),
+            false => None,
+        };
+    }
+
+    /// Returns the palette index buffer, if enabled
+    ///
+    /// Each entry is the 6 bit palette index with the 3 emphasis bits on top (bits 6-8)
+    pub fn indices(&self) -> Option<&[u16]> {
+        self.indices.as_deref()
+    }
+
+    /// Set the palette index at coords x, y. Does nothing if the buffer is disabled
+    pub fn set_index(&mut self, x: usize, y: usize, index: u16) {
+        if let Some(indices) = &mut self.indices {
+            indices[y * WIDTH as usize + x] = index;
+        }
+    }
+
+    /// Frame shrunk `factor` times, each pixel is the average of a `factor` x `factor` block
+    pub fn thumbnail(&self, factor: u32) -> Thumbnail {
+        let factor = factor.max(1);
+        let (width, height) = (WIDTH / factor, HEIGHT / factor);
+        let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0u32; 3];
+                for dy in 0..factor {
+                    for dx in 0..factor {
+                        let Rgb(r, g, b) =
+                            self.get_pixel((x * factor + dx) as usize, (y * factor + dy) as usize);
+                        sum[0] += r as u32;
+                        sum[1] += g as u32;
+                        sum[2] += b as u32;
+                    }
+                }
+                pixels.extend(sum.iter().map(|c| (c / (factor * factor)) as u8));
+            }
+        }
+        Thumbnail {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// FNV-1a hash of all the pixels. Stable between builds, unlike the std hasher
+    pub fn hash(&self) -> u64 {
+        self.pixels.iter().fold(0xCBF29CE484222325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001B3)
+        })
+    }
+
+    /// Hashes the completed frame, to compare it with the next one
+    pub fn update_hash(&mut self) {
+        self.previous_hash = self.hash;
+        self.hash = Some(self.hash());
+    }
+
+    /// Returns false if the pixels are the same as the previous completed frame, so it
+    /// doesn't need to be drawn again. Always true before 2 frames are completed
+    pub fn changed_since_last(&self) -> bool {
+        self.hash.is_none() || self.hash != self.previous_hash
+    }
+
+    /// Sets all pixels to a color. The palette indices go back to 0
+    pub fn clear(&mut self, color: Rgb) {
+        for pixel in self.pixels.chunks_exact_mut(3) {
+            pixel.copy_from_slice(&[color.0, color.1, color.2]);
+        }
+        if let Some(indices) = &mut self.indices {
+            indices.fill(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_pixel() {
+        let mut frame = Frame::new();
+        frame.set_pixel(120, 80, Rgb(1, 2, 3));
+        assert_eq!(frame.get_pixel(120, 80), Rgb(1, 2, 3));
+        assert_eq!(frame.get_pixel(121, 80), Rgb(0, 0, 0));
+        assert_eq!(frame.get_pixel(255, 239), Rgb(0, 0, 0));
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn test_get_pixel_out_of_bounds() {
+        Frame::new().get_pixel(256, 0);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut frame = Frame::new();
+        frame.set_pixel(10, 10, Rgb(1, 2, 3));
+        frame.clear(Rgb(0x11, 0x22, 0x33));
+        assert_eq!(frame.get_pixel(0, 0), Rgb(0x11, 0x22, 0x33));
+        assert_eq!(frame.get_pixel(10, 10), Rgb(0x11, 0x22, 0x33));
+        assert_eq!(frame.get_pixel(255, 239), Rgb(0x11, 0x22, 0x33));
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, Rgb(100, 40, 8));
+        frame.set_pixel(1, 1, Rgb(100, 0, 0));
+        frame.set_pixel(255, 239, Rgb(255, 255, 255));
+
+        let thumbnail = frame.thumbnail(2);
+        assert_eq!((thumbnail.width, thumbnail.height), (128, 120));
+        assert_eq!(thumbnail.pixels.len(), 128 * 120 * 3);
+        assert_eq!(thumbnail.pixels[..3], [50, 10, 2]);
+        assert_eq!(thumbnail.pixels[thumbnail.pixels.len() - 3..], [63, 63, 63]);
+
+        // A factor of 1 is a copy of the frame
+        assert_eq!(frame.thumbnail(1).pixels, frame.pixels());
+    }
+
+    #[test]
+    fn test_changed_since_last() {
+        let mut frame = Frame::new();
+        assert!(frame.changed_since_last());
+        frame.update_hash();
+        assert!(frame.changed_since_last());
+        frame.update_hash();
+        assert!(!frame.changed_since_last());
+
+        // The last pixel of the buffer counts too
+        frame.set_pixel(255, 239, Rgb(0, 0, 1));
+        frame.update_hash();
+        assert!(frame.changed_since_last());
+        frame.update_hash();
+        assert!(!frame.changed_since_last());
+
+        frame.clear(Rgb(0, 0, 0));
+        frame.update_hash();
+        assert!(frame.changed_since_last());
     }
 }
@@ -2,6 +2,7 @@ use bitflags::bitflags;
 
 bitflags! {
     /// Ppu control register
+    #[derive(Default)]
     pub struct Controller: u8 {
         const NMI_ENABLED    = 0b10000000;
         const MASTER_SLAVE   = 0b01000000;
@@ -68,6 +69,7 @@ impl Controller {
 
 bitflags! {
     /// Ppu mask register
+    #[derive(Default)]
     pub struct Mask: u8 {
         const EMPH_BLUE  = 0b10000000;
         const EMPH_GREEN = 0b01000000;
@@ -135,6 +137,11 @@ impl Mask {
         (r_factor, g_factor, b_factor)
     }
 
+    /// Returns the emphasis bits (red, green, blue) as the 3 low bits
+    pub fn emphasis(&self) -> u8 {
+        self.bits >> 5
+    }
+
     /// Returns true if one of the color emphasis bits is set
     pub fn color_emph_enabled(&self) -> bool {
         self.intersects(Self::EMPH_RED | Self::EMPH_GREEN | Self::EMPH_BLUE)
@@ -143,6 +150,7 @@ impl Mask {
 
 bitflags! {
     /// Ppu status register
+    #[derive(Default)]
     pub struct Status: u8 {
         const IN_VBLANK   = 0b10000000;
         const SP_0_HIT    = 0b01000000;
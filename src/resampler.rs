@@ -0,0 +1,192 @@
+use std::f64::consts::PI;
+use std::io::{Read, Write};
+
+use crate::savable::Savable;
+
+/// Fractional positions the kernels are computed for
+const PHASES: usize = 64;
+/// The passband ends a bit before the Nyquist frequency of the output
+const CUTOFF: f64 = 0.45;
+
+/// How the Apu output is converted to the audio sample rate
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ResamplerQuality {
+    /// Keeps the last sample. Almost free, but high frequencies alias
+    Nearest,
+    /// Short windowed-sinc kernel
+    #[default]
+    Low,
+    /// Long windowed-sinc kernel. Costs about 3 times more Cpu than `Low`
+    High,
+}
+
+impl ResamplerQuality {
+    /// Zero crossings of the sinc on each side of the kernel
+    fn zero_crossings(&self) -> usize {
+        match self {
+            ResamplerQuality::Nearest => 0,
+            ResamplerQuality::Low => 4,
+            ResamplerQuality::High => 12,
+        }
+    }
+}
+
+/// Band-limited resampler from the Apu clock rate to the audio sample rate
+///
+/// Each output sample is the input convolved with a Blackman windowed sinc, so the frequencies
+/// above the output Nyquist are removed instead of folding back as aliasing
+pub struct Resampler {
+    /// Kernel for each fractional position of the output sample between 2 input samples
+    kernels: Vec<Vec<f32>>,
+    /// Input samples, written twice so the last `taps` are always contiguous
+    history: Vec<f32>,
+    pos: usize,
+    taps: usize,
+    /// Input samples per output sample
+    ratio: f64,
+    /// Input samples since the last output sample
+    time: f64,
+    last: f32,
+}
+
+impl Savable for Resampler {
+    fn save(&self, output: &mut dyn Write) -> bincode::Result<()> {
+        bincode::serialize_into::<&mut dyn Write, _>(output, &self.time)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut dyn Read) -> bincode::Result<()> {
+        self.time = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+        // The history isn't saved, it only holds a few ms of audio
+        self.history.iter_mut().for_each(|s| *s = 0.0);
+        Ok(())
+    }
+}
+
+impl Resampler {
+    pub fn new(input_rate: f64, output_rate: f64, quality: ResamplerQuality) -> Self {
+        let ratio = input_rate / output_rate;
+        // Distance between 2 zero crossings of the sinc, in input samples
+        let spacing = ratio / (2.0 * CUTOFF);
+        let taps = (2.0 * quality.zero_crossings() as f64 * spacing).ceil() as usize;
+
+        // Nearest doesn't filter
+        let phases = if taps > 0 { PHASES } else { 0 };
+        let kernels = (0..phases)
+            .map(|phase| kernel(taps, spacing, phase as f64 / PHASES as f64))
+            .collect();
+
+        Self {
+            kernels,
+            history: vec![0.0; taps * 2],
+            pos: 0,
+            taps,
+            ratio,
+            time: 0.0,
+            last: 0.0,
+        }
+    }
+
+    /// Adds an input sample. Returns an output sample when enough time has passed
+    pub fn push(&mut self, sample: f32) -> Option<f32> {
+        if self.taps > 0 {
+            self.history[self.pos] = sample;
+            self.history[self.pos + self.taps] = sample;
+            self.pos = (self.pos + 1) % self.taps;
+        }
+        self.last = sample;
+
+        self.time += 1.0;
+        if self.time < self.ratio {
+            return None;
+        }
+        // Keep the leftover, the output sample sits between 2 input samples
+        self.time -= self.ratio;
+
+        if self.taps == 0 {
+            return Some(self.last);
+        }
+
+        let phase = ((self.time * PHASES as f64) as usize).min(PHASES - 1);
+        let window = &self.history[self.pos..self.pos + self.taps];
+        let sample = window
+            .iter()
+            .zip(self.kernels[phase].iter())
+            .map(|(s, k)| s * k)
+            .sum();
+        Some(sample)
+    }
+
+    /// Forgets the previous input samples
+    pub fn clear(&mut self) {
+        self.history.iter_mut().for_each(|s| *s = 0.0);
+        self.time = 0.0;
+    }
+}
+
+/// Builds a kernel of `taps` samples, from the oldest to the newest input sample
+///
+/// `offset` is how many input samples ago the output sample is, between 0 and 1
+fn kernel(taps: usize, spacing: f64, offset: f64) -> Vec<f32> {
+    let center = (taps - 1) as f64 / 2.0 + offset;
+    let mut kernel: Vec<f64> = (0..taps)
+        .map(|i| {
+            // Age of the input sample, relative to the center of the kernel
+            let x = (taps - 1 - i) as f64 - center;
+            let sinc = match x == 0.0 {
+                true => 1.0,
+                false => (PI * x / spacing).sin() / (PI * x / spacing),
+            };
+            let w = 2.0 * PI * x / taps as f64;
+            let window = 0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+            sinc * window
+        })
+        .collect();
+
+    // Unity gain, so constant signals keep their level
+    let sum: f64 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|k| *k /= sum);
+    kernel.into_iter().map(|k| k as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT_RATE: f64 = 1789773.0;
+    const OUTPUT_RATE: f64 = 44100.0;
+
+    /// Resamples one second of a sine wave and returns its peak after the kernel warmed up
+    fn sine_peak(freq: f64, quality: ResamplerQuality) -> f32 {
+        let mut resampler = Resampler::new(INPUT_RATE, OUTPUT_RATE, quality);
+        let output: Vec<f32> = (0..INPUT_RATE as usize)
+            .filter_map(|i| {
+                let t = i as f64 * freq / INPUT_RATE;
+                resampler.push((2.0 * PI * t).sin() as f32)
+            })
+            .collect();
+
+        assert!((output.len() as f64 - OUTPUT_RATE).abs() <= 1.0);
+        output[1000..].iter().fold(0.0, |peak, s| s.abs().max(peak))
+    }
+
+    #[test]
+    fn test_constant() {
+        for quality in [ResamplerQuality::Low, ResamplerQuality::High] {
+            let mut resampler = Resampler::new(INPUT_RATE, OUTPUT_RATE, quality);
+            let output: Vec<f32> = (0..100000).filter_map(|_| resampler.push(0.5)).collect();
+            assert!((output.last().unwrap() - 0.5).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_aliasing() {
+        // An ultrasonic tone folds back into the audible range without filtering
+        assert!(sine_peak(30000.0, ResamplerQuality::Nearest) > 0.99);
+        assert!(sine_peak(30000.0, ResamplerQuality::Low) < 0.02);
+        assert!(sine_peak(30000.0, ResamplerQuality::High) < 0.001);
+
+        // Audible tones go through
+        assert!(sine_peak(440.0, ResamplerQuality::Low) > 0.99);
+    }
+}
@@ -1,16 +1,97 @@
-use std::{
-    fs::File,
-    io::{BufReader, BufWriter},
-};
+use std::io::{Read, Write};
 
+use crate::ppu::frame::Thumbnail;
+
+/// Start of every save state
+pub const STATE_MAGIC: [u8; 8] = *b"NESOXYDE";
+/// Version of the save state layout. Bump it when a component saves different fields
+pub const STATE_VERSION: u32 = 6;
+/// Starts the optional thumbnail block at the beginning of a save state
+pub const THUMBNAIL_TAG: [u8; 8] = *b"NOXTHUMB";
+
+/// Component with a save state. Works with any writer and reader, the frontend picks the storage
 pub trait Savable {
     /// Save state
-    fn save(&self, _output: &mut BufWriter<File>) -> bincode::Result<()> {
+    fn save(&self, _output: &mut dyn Write) -> bincode::Result<()> {
         Ok(())
     }
 
     /// Load state
-    fn load(&mut self, _input: &mut BufReader<File>) -> bincode::Result<()> {
+    fn load(&mut self, _input: &mut dyn Read) -> bincode::Result<()> {
         Ok(())
     }
 }
+
+/// Writes the magic number and the format version
+pub fn write_header(output: &mut dyn Write) -> bincode::Result<()> {
+    output.write_all(&STATE_MAGIC)?;
+    bincode::serialize_into::<&mut dyn Write, _>(output, &STATE_VERSION)
+}
+
+/// Checks the magic number and the format version. Fails with a readable message if the state
+/// comes from another version of the emulator
+pub fn check_header(input: &mut dyn Read) -> bincode::Result<()> {
+    let mut magic = [0; STATE_MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != STATE_MAGIC {
+        return Err(Box::new(bincode::ErrorKind::Custom(
+            "not a save state, or saved by an older version of the emulator".to_string(),
+        )));
+    }
+
+    let version: u32 = bincode::deserialize_from::<&mut dyn Read, _>(input)?;
+    match version {
+        STATE_VERSION => Ok(()),
+        _ => Err(Box::new(bincode::ErrorKind::Custom(format!(
+            "save state version {} is not supported, this version of the emulator uses {}",
+            version, STATE_VERSION
+        )))),
+    }
+}
+
+/// Writes a thumbnail block, after the header. The save state follows it
+pub fn write_thumbnail(output: &mut dyn Write, thumbnail: &Thumbnail) -> bincode::Result<()> {
+    output.write_all(&THUMBNAIL_TAG)?;
+    bincode::serialize_into::<&mut dyn Write, _>(output, thumbnail)
+}
+
+/// Reads the thumbnail at the start of a save state, without loading the state. None if it
+/// was saved without one
+pub fn read_thumbnail(input: &mut dyn Read) -> bincode::Result<Option<Thumbnail>> {
+    check_header(input)?;
+    let mut tag = [0; THUMBNAIL_TAG.len()];
+    input.read_exact(&mut tag)?;
+    match tag == THUMBNAIL_TAG {
+        true => Ok(Some(bincode::deserialize_from::<&mut dyn Read, _>(input)?)),
+        false => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let mut header = vec![];
+        write_header(&mut header).unwrap();
+        assert_eq!(header.len(), 12);
+        assert!(check_header(&mut header.as_slice()).is_ok());
+
+        let mut newer = header.clone();
+        newer[8] += 1;
+        let error = check_header(&mut newer.as_slice()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "save state version {} is not supported, this version of the emulator uses {}",
+                STATE_VERSION + 1,
+                STATE_VERSION
+            )
+        );
+
+        let error = check_header(&mut &[0u8; 16][..]).unwrap_err();
+        assert!(error.to_string().starts_with("not a save state"));
+        assert!(check_header(&mut &b"NES"[..]).is_err());
+    }
+}
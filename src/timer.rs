@@ -2,10 +2,17 @@ use std::time::{Duration, Instant};
 
 use spin_sleep::SpinSleeper;
 
+/// Time between 2 FPS measures
+const FPS_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Helper struct for video/audio timing
 pub struct Timer {
     start: Instant,
     sleeper: SpinSleeper,
+    /// Start of the current FPS measure
+    fps_start: Instant,
+    /// Frame count at the start of the current FPS measure
+    fps_frames: u128,
 }
 
 impl Timer {
@@ -13,6 +20,8 @@ impl Timer {
         Self {
             start: Instant::now(),
             sleeper: SpinSleeper::default(),
+            fps_start: Instant::now(),
+            fps_frames: 0,
         }
     }
 
@@ -20,6 +29,24 @@ impl Timer {
         self.start = Instant::now();
     }
 
+    /// Starts a new FPS measure from this frame count
+    pub fn reset_fps(&mut self, frame_count: u128) {
+        self.fps_start = Instant::now();
+        self.fps_frames = frame_count;
+    }
+
+    /// Returns the average FPS once every second, from the emulated frame count
+    pub fn measure_fps(&mut self, frame_count: u128) -> Option<f64> {
+        let elapsed = self.fps_start.elapsed();
+        if elapsed < FPS_INTERVAL {
+            return None;
+        }
+        // The count goes back on a power on or when loading a state
+        let frames = frame_count.saturating_sub(self.fps_frames);
+        self.reset_fps(frame_count);
+        Some(frames as f64 / elapsed.as_secs_f64())
+    }
+
     pub fn wait(&self, time: Duration) {
         let passed_time = Instant::now() - self.start;
         if time > passed_time {
@@ -0,0 +1,191 @@
+//! Runs test ROMs that report their result in PRG RAM, like the blargg ones
+//!
+//! 0x6000 holds the status: 0x80 while running, 0x81 when the reset button needs to be pressed,
+//! then the result code (0x00 is a pass). 0x6001-0x6003 hold the 0xDE 0xB0 0x61 signature and
+//! a null terminated message starts at 0x6004
+//!
+//! The ROMs aren't in the repository. The tests using them are ignored, put the ROMs in
+//! `roms/tests` and run them with `cargo test -- --ignored`
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use nesoxyde::bus::{MainBus, RamPattern};
+use nesoxyde::cartridge::Cartridge;
+use nesoxyde::cpu::{Cpu, CpuInterface};
+
+const STATUS_ADDR: u16 = 0x6000;
+const SIGNATURE_ADDR: u16 = 0x6001;
+const MESSAGE_ADDR: u16 = 0x6004;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET: u8 = 0x81;
+
+/// Gives up after 2 minutes of emulated time
+const MAX_FRAMES: u32 = 60 * 120;
+/// Frames to wait before pressing reset. The ROMs ask for at least 100ms
+const RESET_DELAY: u32 = 10;
+
+/// Status code and message written by a test ROM
+#[derive(Debug)]
+struct TestResult {
+    status: u8,
+    message: String,
+}
+
+/// Runs a test ROM until it writes its result
+fn run_test_rom(cartridge: Cartridge) -> TestResult {
+    let bus = MainBus::new(
+        Rc::new(RefCell::new(cartridge)),
+        |_| {},
+        44100.0,
+        RamPattern::default(),
+    );
+    let mut cpu = Cpu::new(bus);
+    cpu.power_on();
+
+    let mut started = false;
+    let mut reset_delay = None;
+    for _ in 0..MAX_FRAMES {
+        cpu.run_until_frame();
+        // Nobody listens to the audio
+        cpu.samples();
+
+        let signature = [0, 1, 2].map(|i| cpu.peek(SIGNATURE_ADDR + i));
+        if signature != SIGNATURE {
+            continue;
+        }
+
+        match cpu.peek(STATUS_ADDR) {
+            STATUS_RUNNING => started = true,
+            STATUS_RESET => match reset_delay {
+                Some(0) => {
+                    cpu.reset();
+                    reset_delay = None;
+                }
+                Some(frames) => reset_delay = Some(frames - 1),
+                None => reset_delay = Some(RESET_DELAY),
+            },
+            status if started => {
+                return TestResult {
+                    status,
+                    message: read_message(&cpu),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    panic!("Test ROM timed out: {}", read_message(&cpu));
+}
+
+/// Reads the null terminated message
+fn read_message<B: CpuInterface>(cpu: &Cpu<B>) -> String {
+    (MESSAGE_ADDR..0x8000)
+        .map(|addr| cpu.peek(addr))
+        .take_while(|&c| c != 0)
+        .map(|c| c as char)
+        .collect()
+}
+
+/// Runs a ROM of `roms/tests` and checks that it passes
+fn check_rom(path: &str) {
+    let path = format!("{}/roms/tests/{}", env!("CARGO_MANIFEST_DIR"), path);
+    let bytes = std::fs::read(&path).unwrap_or_else(|e| panic!("Can't read {}: {}", path, e));
+    let cartridge =
+        Cartridge::new(&path, &bytes).unwrap_or_else(|e| panic!("Can't load {}: {}", path, e));
+    let result = run_test_rom(cartridge);
+    assert_eq!(result.status, 0, "{} failed:\n{}", path, result.message);
+}
+
+/// Builds a NROM cartridge that reports `status` and `message` like a test ROM
+fn status_rom(status: u8, message: &str) -> Cartridge {
+    // LDA #data, STA addr
+    let store = |addr: u16, data: u8| [0xA9, data, 0x8D, addr as u8, (addr >> 8) as u8];
+
+    let mut code = Vec::new();
+    code.extend(store(STATUS_ADDR, STATUS_RUNNING));
+    for (i, &byte) in SIGNATURE.iter().enumerate() {
+        code.extend(store(SIGNATURE_ADDR + i as u16, byte));
+    }
+    for (i, byte) in message.bytes().chain([0]).enumerate() {
+        code.extend(store(MESSAGE_ADDR + i as u16, byte));
+    }
+    // Waste a few frames, like a real test
+    code.extend([0xA2, 0x00, 0xA0, 0x00, 0xC8, 0xD0, 0xFD, 0xE8, 0xD0, 0xFA]);
+    code.extend(store(STATUS_ADDR, status));
+    // JMP to itself
+    let end = 0x8000 + code.len() as u16;
+    code.extend([0x4C, end as u8, (end >> 8) as u8]);
+
+    let mut rom = vec![0; 16 + 0x4000 + 0x2000];
+    rom[..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+    rom[16..16 + code.len()].copy_from_slice(&code);
+    // Reset vector, 0x4000 PRG bank mirrored at 0xC000
+    rom[16 + 0x3FFC] = 0x00;
+    rom[16 + 0x3FFD] = 0x80;
+    Cartridge::from_bytes(&rom).unwrap()
+}
+
+#[test]
+fn test_harness_pass() {
+    let result = run_test_rom(status_rom(0, "Passed"));
+    assert_eq!(result.status, 0);
+    assert_eq!(result.message, "Passed");
+}
+
+#[test]
+fn test_harness_fail() {
+    let result = run_test_rom(status_rom(3, "Failed #3"));
+    assert_eq!(result.status, 3);
+    assert_eq!(result.message, "Failed #3");
+}
+
+#[test]
+#[ignore]
+fn instr_test_official() {
+    check_rom("instr_test-v5/official_only.nes");
+}
+
+#[test]
+#[ignore]
+fn cpu_dummy_reads() {
+    check_rom("cpu_dummy_reads.nes");
+}
+
+#[test]
+#[ignore]
+fn ppu_vbl_nmi() {
+    check_rom("ppu_vbl_nmi/ppu_vbl_nmi.nes");
+}
+
+#[test]
+#[ignore]
+fn ppu_open_bus() {
+    check_rom("ppu_open_bus/ppu_open_bus.nes");
+}
+
+#[test]
+#[ignore]
+fn oam_read() {
+    check_rom("oam_read/oam_read.nes");
+}
+
+#[test]
+#[ignore]
+fn oam_stress() {
+    check_rom("oam_stress/oam_stress.nes");
+}
+
+#[test]
+#[ignore]
+fn apu_irq_flag() {
+    check_rom("apu_test/rom_singles/3-irq_flag.nes");
+}
+
+#[test]
+#[ignore]
+fn apu_dmc_basics() {
+    check_rom("apu_test/rom_singles/7-dmc_basics.nes");
+}